@@ -1,4 +1,7 @@
-use crate::state::{Market, Oracle, ProtocolState, UserDeposit};
+use crate::state::{
+    Market, MarketIndex, MarketPointer, Oracle, ProtocolState, UserDeposit, UserPortfolio,
+};
+use crate::LendingError;
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
@@ -17,6 +20,18 @@ pub struct InitializeProtocol<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ProtocolAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump = protocol_state.bump,
+        has_one = admin @ LendingError::Unauthorized
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+    pub admin: Signer<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(market_id: u64)]
 pub struct CreateMarket<'info> {
@@ -28,8 +43,29 @@ pub struct CreateMarket<'info> {
         bump
     )]
     pub market: Account<'info, Market>,
-    #[account(mut)]
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump = protocol_state.bump
+    )]
     pub protocol_state: Account<'info, ProtocolState>,
+    #[account(
+        init,
+        payer = creator,
+        space = MarketIndex::SPACE,
+        seeds = [b"market_index", protocol_state.total_markets.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_index: Account<'info, MarketIndex>,
+    /// Only one market per mint pair: init fails if the pair already has one
+    #[account(
+        init,
+        payer = creator,
+        space = MarketPointer::SPACE,
+        seeds = [b"market_pointer", supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump
+    )]
+    pub market_pointer: Account<'info, MarketPointer>,
     pub supply_mint: InterfaceAccount<'info, Mint>,
     pub collateral_mint: InterfaceAccount<'info, Mint>,
     /// CHECK: Oracle account for supply asset pricing
@@ -71,8 +107,10 @@ pub struct Supply<'info> {
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
@@ -101,8 +139,10 @@ pub struct Withdraw<'info> {
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
@@ -118,15 +158,86 @@ pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
     /// CHECK: Supply oracle account
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
     pub supply_oracle: AccountInfo<'info>,
     /// CHECK: Collateral oracle account
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
     pub collateral_oracle: AccountInfo<'info>,
     pub token_program: Interface<'info, TokenInterface>,
 }
 
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct EmergencyWithdraw<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"user_deposit", user.key().as_ref(), market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = user_deposit.bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_supply_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetMaxWithdraw<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        seeds = [b"user_deposit", user.key().as_ref(), market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = user_deposit.bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Only used to derive the user_deposit PDA, anyone can query any user's limit
+    pub user: AccountInfo<'info>,
+    /// CHECK: Supply oracle account
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub supply_oracle: AccountInfo<'info>,
+    /// CHECK: Collateral oracle account
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
+    pub collateral_oracle: AccountInfo<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(market_id: u64)]
 pub struct Borrow<'info> {
+    #[account(
+        seeds = [b"protocol"],
+        bump = protocol_state.bump
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
     #[account(
         mut,
         seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
@@ -135,14 +246,18 @@ pub struct Borrow<'info> {
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
+        token::mint = collateral_mint,
+        token::authority = market,
         seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
-        bump
+        bump = market.collateral_vault_bump
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
@@ -160,8 +275,10 @@ pub struct Borrow<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
     /// CHECK: Oracle account for collateral pricing
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
     pub collateral_oracle: AccountInfo<'info>,
     /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
     pub borrow_oracle: AccountInfo<'info>,
     pub token_program: Interface<'info, TokenInterface>,
 }
@@ -177,8 +294,10 @@ pub struct WithdrawCollateral<'info> {
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = collateral_mint,
+        token::authority = market,
         seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
-        bump
+        bump = market.collateral_vault_bump
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
@@ -207,8 +326,10 @@ pub struct Repay<'info> {
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
@@ -230,20 +351,25 @@ pub struct Repay<'info> {
 #[instruction(market_id: u64)]
 pub struct Liquidate<'info> {
     #[account(
+        mut,
         seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
         bump = market.bump
     )]
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
+        token::mint = collateral_mint,
+        token::authority = market,
         seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
-        bump
+        bump = market.collateral_vault_bump
     )]
     pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
     pub supply_mint: InterfaceAccount<'info, Mint>,
@@ -252,12 +378,65 @@ pub struct Liquidate<'info> {
     pub borrower_deposit: Account<'info, UserDeposit>,
     #[account(mut)]
     pub liquidator_supply_account: InterfaceAccount<'info, TokenAccount>,
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = liquidator_collateral_account.mint == collateral_mint.key() @ LendingError::InvalidCollateralMint
+    )]
     pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
     pub liquidator: Signer<'info>,
     /// CHECK: Oracle account for pricing
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
     pub oracle: AccountInfo<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct LiquidateBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = collateral_mint,
+        token::authority = market,
+        seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.collateral_vault_bump
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    // Borrower deposits to liquidate are passed writable in remaining_accounts
+    #[account(mut)]
+    pub liquidator_supply_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = liquidator_collateral_account.mint == collateral_mint.key() @ LendingError::InvalidCollateralMint
+    )]
+    pub liquidator_collateral_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+    /// CHECK: Oracle account for collateral pricing
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
+    pub collateral_oracle: AccountInfo<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
     pub token_program: Interface<'info, TokenInterface>,
 }
 
@@ -265,22 +444,37 @@ pub struct Liquidate<'info> {
 #[instruction(market_id: u64)]
 pub struct FlashLoan<'info> {
     #[account(
+        seeds = [b"protocol"],
+        bump = protocol_state.bump
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+    #[account(
+        mut,
         seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
         bump = market.bump
     )]
     pub market: Account<'info, Market>,
     #[account(
         mut,
+        token::mint = supply_mint,
+        token::authority = market,
         seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
-        bump
+        bump = market.supply_vault_bump
     )]
     pub supply_vault: InterfaceAccount<'info, TokenAccount>,
     pub supply_mint: InterfaceAccount<'info, Mint>,
     pub collateral_mint: InterfaceAccount<'info, Mint>,
     #[account(mut)]
     pub user_supply_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = protocol_state.fee_recipient,
+    )]
+    pub fee_recipient_account: InterfaceAccount<'info, TokenAccount>,
     #[account(mut)]
     pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
@@ -323,11 +517,84 @@ pub struct CloseUninitializedAccount<'info> {
 
 #[derive(Accounts)]
 pub struct UpdateMarketParams<'info> {
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = market.market_admin == authority.key() @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct VerifyMarketVaults<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), market.supply_mint.as_ref(), market.collateral_mint.as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarketConfig<'info> {
+    #[account(
+        mut,
+        constraint = market.market_admin == authority.key() @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositFrozen<'info> {
+    #[account(
+        constraint = market.market_admin == authority.key() @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market @ LendingError::InvalidMarketState
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPortfolioFrozen<'info> {
+    #[account(
+        constraint = market.market_admin == authority.key() @ LendingError::Unauthorized
+    )]
     pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        has_one = market @ LendingError::InvalidMarketState
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct TransferMarketAdmin<'info> {
+    #[account(
+        mut,
+        has_one = market_admin @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    pub market_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptMarketAdmin<'info> {
+    #[account(
+        mut,
+        constraint = market.pending_admin == new_admin.key() @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    pub new_admin: Signer<'info>,
+}
+
 #[derive(Accounts)]
 #[instruction(source: Vec<u8>)]
 pub struct CreateOracle<'info> {
@@ -360,3 +627,483 @@ pub struct UpdateOraclePrice<'info> {
 
     pub authority: Signer<'info>,
 }
+
+#[derive(Accounts)]
+pub struct UpdateOraclePricesBatch<'info> {
+    // Oracles to update are passed writable in remaining_accounts
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetOracleStats<'info> {
+    #[account(
+        seeds = [b"oracle", oracle.mint.as_ref()],
+        bump = oracle.bump
+    )]
+    pub oracle: Account<'info, Oracle>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct AmISafe<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [b"user_deposit", user.key().as_ref(), market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = user_deposit.bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    pub user: Signer<'info>,
+    /// CHECK: Oracle account for collateral pricing
+    #[account(address = market.collateral_oracle)]
+    pub collateral_oracle: AccountInfo<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle)]
+    pub borrow_oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct FundReserves<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub funder_supply_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SweepSurplusCollateral<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump,
+        constraint = market.market_admin == authority.key() @ LendingError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = collateral_mint,
+        token::authority = market,
+        seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.collateral_vault_bump
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = destination.mint == collateral_mint.key() @ LendingError::InvalidCollateralMint
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetCurrentDebt<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [b"user_deposit", user.key().as_ref(), market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = user_deposit.bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Only used to derive the user_deposit PDA, anyone can query any user's debt
+    pub user: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetPosition<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        seeds = [b"user_deposit", user.key().as_ref(), market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = user_deposit.bump
+    )]
+    pub user_deposit: Account<'info, UserDeposit>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Only used to derive the user_deposit PDA, anyone can query any user's position
+    pub user: AccountInfo<'info>,
+    /// CHECK: Oracle account for collateral pricing
+    #[account(address = market.collateral_oracle @ LendingError::InvalidOracleData)]
+    pub collateral_oracle: AccountInfo<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetAvailableLiquidity<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetMarketStats<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetExchangeRate<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetMarketTvl<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        token::mint = collateral_mint,
+        token::authority = market,
+        seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.collateral_vault_bump
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Oracle account for supply asset pricing
+    #[account(address = market.supply_oracle)]
+    pub supply_oracle: AccountInfo<'info>,
+    /// CHECK: Oracle account for collateral pricing
+    #[account(address = market.collateral_oracle)]
+    pub collateral_oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CloseMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump,
+        constraint = market.market_admin == admin.key() @ LendingError::Unauthorized,
+        close = admin
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        seeds = [b"protocol"],
+        bump = protocol_state.bump
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+    #[account(
+        mut,
+        seeds = [b"market_index", market_index.index.to_le_bytes().as_ref()],
+        bump = market_index.bump,
+        constraint = market_index.market == market.key() @ LendingError::InvalidMarketState
+    )]
+    pub market_index: Account<'info, MarketIndex>,
+    /// Highest index entry, moved into the closed market's slot so indices stay dense.
+    /// Omitted when the closed market is itself the last entry.
+    #[account(
+        mut,
+        seeds = [b"market_index", protocol_state.total_markets.saturating_sub(1).to_le_bytes().as_ref()],
+        bump = last_market_index.bump
+    )]
+    pub last_market_index: Option<Account<'info, MarketIndex>>,
+    #[account(
+        mut,
+        seeds = [b"market_pointer", supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market_pointer.bump,
+        constraint = market_pointer.market == market.key() @ LendingError::InvalidMarketState,
+        close = admin
+    )]
+    pub market_pointer: Account<'info, MarketPointer>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        token::mint = collateral_mint,
+        token::authority = market,
+        seeds = [b"collateral_vault", market_id.to_le_bytes().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.collateral_vault_bump
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct InitializePortfolio<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = UserPortfolio::SPACE,
+        seeds = [b"portfolio", user.key().as_ref(), market.key().as_ref()],
+        bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct AddPortfolioCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"portfolio", user.key().as_ref(), market.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [b"oracle", asset_mint.key().as_ref()],
+        bump = asset_oracle.bump
+    )]
+    pub asset_oracle: Account<'info, Oracle>,
+    #[account(
+        init,
+        payer = user,
+        token::mint = asset_mint,
+        token::authority = market,
+        seeds = [b"portfolio_vault", portfolio.key().as_ref(), asset_mint.key().as_ref()],
+        bump
+    )]
+    pub asset_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct DepositPortfolioCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"portfolio", user.key().as_ref(), market.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"portfolio_vault", portfolio.key().as_ref(), asset_mint.key().as_ref()],
+        bump
+    )]
+    pub asset_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_asset_account: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PortfolioBorrow<'info> {
+    #[account(
+        seeds = [b"protocol"],
+        bump = protocol_state.bump
+    )]
+    pub protocol_state: Account<'info, ProtocolState>,
+    #[account(
+        mut,
+        seeds = [b"portfolio", user.key().as_ref(), market.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub user_supply_account: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct WithdrawPortfolioCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"portfolio", user.key().as_ref(), market.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"portfolio_vault", portfolio.key().as_ref(), asset_mint.key().as_ref()],
+        bump
+    )]
+    pub asset_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub user_asset_account: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct LiquidatePortfolio<'info> {
+    #[account(
+        mut,
+        seeds = [b"portfolio", portfolio.user.as_ref(), market.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, UserPortfolio>,
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref(), collateral_mint.key().as_ref()],
+        bump = market.bump
+    )]
+    pub market: Account<'info, Market>,
+    #[account(
+        mut,
+        token::mint = supply_mint,
+        token::authority = market,
+        seeds = [b"supply_vault", market_id.to_le_bytes().as_ref(), supply_mint.key().as_ref()],
+        bump = market.supply_vault_bump
+    )]
+    pub supply_vault: InterfaceAccount<'info, TokenAccount>,
+    pub supply_mint: InterfaceAccount<'info, Mint>,
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+    /// Portfolio collateral the liquidator takes in return for the repayment
+    pub asset_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [b"portfolio_vault", portfolio.key().as_ref(), asset_mint.key().as_ref()],
+        bump
+    )]
+    pub asset_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub liquidator_supply_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub liquidator_asset_account: InterfaceAccount<'info, TokenAccount>,
+    pub liquidator: Signer<'info>,
+    /// CHECK: Oracle account for borrow asset pricing
+    #[account(address = market.supply_oracle @ LendingError::InvalidOracleData)]
+    pub borrow_oracle: AccountInfo<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
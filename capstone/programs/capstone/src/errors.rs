@@ -42,4 +42,60 @@ pub enum LendingError {
     UserDepositAlreadyExists,
     #[msg("Invalid PDA")]
     InvalidPDA,
+    #[msg("Flash loans are disabled for this market")]
+    FlashLoansDisabled,
+    #[msg("Token account mint does not match the market collateral mint")]
+    InvalidCollateralMint,
+    #[msg("Basis points value exceeds 10000")]
+    InvalidBasisPoints,
+    #[msg("Borrow is below the market minimum")]
+    BorrowTooSmall,
+    #[msg("Number of prices does not match the number of oracle accounts")]
+    OraclePriceCountMismatch,
+    #[msg("Oracle staleness window must be non-zero and at most MAX_ORACLE_STALENESS_SLOTS")]
+    InvalidOracleStaleness,
+    #[msg("Supply amount is below the market minimum or too small to mint any cTokens")]
+    SupplyTooSmall,
+    #[msg("Portfolio already holds the maximum number of collateral assets")]
+    PortfolioFull,
+    #[msg("Collateral asset is not in the portfolio")]
+    CollateralNotInPortfolio,
+    #[msg("Flash loan vault balance does not match the expected repayment")]
+    FlashLoanRepaymentMismatch,
+    #[msg("Oracle price moved further than the allowed deviation band")]
+    PriceDeviationTooLarge,
+    #[msg("Liquidation bonus exceeds the maximum allowed")]
+    LiquidationBonusTooHigh,
+    #[msg("Liquidation batch is empty, too large, or doesn't match the borrower accounts")]
+    InvalidLiquidationBatch,
+    #[msg("Collateral oracle price is stale")]
+    CollateralOracleStale,
+    #[msg("Borrow oracle price is stale")]
+    BorrowOracleStale,
+    #[msg("Price ratio band minimum exceeds its maximum")]
+    InvalidPriceRatioBand,
+    #[msg("User deposit is frozen by the market admin")]
+    DepositFrozen,
+    #[msg("Oracle decimals exceed the supported maximum")]
+    InvalidOracleDecimals,
+    #[msg("Borrow destination is not the expected supply-mint token account")]
+    InvalidBorrowDestination,
+    #[msg("Withdrawal returns fewer tokens than the requested minimum")]
+    SlippageExceeded,
+    #[msg("Withdrawal would take the cToken balance below the amount locked to back debt")]
+    CTokensLocked,
+    #[msg("Oracle price updated again before its update interval elapsed")]
+    OracleUpdateTooFrequent,
+    #[msg("Flash loan callback can't be the lending program or a token program")]
+    InvalidCallbackProgram,
+    #[msg("Flash loan needs the callback program and token program in remaining accounts")]
+    MissingCallbackAccounts,
+    #[msg("Market id is not the next id allocated by the protocol")]
+    InvalidMarketId,
+    #[msg("Max borrow rate must be above zero and at most MAX_BORROW_RATE_CAP_BPS")]
+    InvalidBorrowRateCap,
+    #[msg("Collateral asset is already in the portfolio")]
+    CollateralAlreadyInPortfolio,
+    #[msg("Liquidation grace period exceeds MAX_GRACE_PERIOD_SLOTS")]
+    InvalidGracePeriod,
 }
@@ -0,0 +1,131 @@
+use anchor_lang::prelude::*;
+
+/// Emitted when a borrow leaves market utilization above the warning threshold
+#[event]
+pub struct HighUtilizationWarningEvent {
+    pub market_id: u64,
+    pub utilization_bps: u64,
+}
+
+/// Which property of a market vault a `MarketVaultCheckEvent` reports on
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VaultCheck {
+    SupplyVaultAuthority,
+    SupplyVaultMint,
+    CollateralVaultAuthority,
+    CollateralVaultMint,
+}
+
+/// Emitted by `verify_market_vaults` once per check with its pass/fail result
+#[event]
+pub struct MarketVaultCheckEvent {
+    pub market_id: u64,
+    pub vault: Pubkey,
+    pub check: VaultCheck,
+    pub passed: bool,
+}
+
+/// Emitted by `get_oracle_stats` with an oracle's update frequency
+#[event]
+pub struct OracleStatsEvent {
+    pub oracle: Pubkey,
+    pub update_count: u64,
+    pub last_update_slot: u64,
+    pub slots_since_update: u64,
+}
+
+/// Emitted by `am_i_safe` with the caller's current liquidation safety
+#[event]
+pub struct SafetyEvent {
+    pub is_safe: bool,
+    pub health_factor: u128,
+    pub liquidation_threshold: u64,
+}
+
+/// Emitted when a liquidation leaves debt with no collateral behind it
+#[event]
+pub struct BadDebtEvent {
+    pub market_id: u64,
+    pub borrower: Pubkey,
+    pub shortfall: u128,
+    pub covered_by_reserves: u128,
+    pub socialized: u128,
+}
+
+/// Emitted when a repayment exceeds the market's recorded total borrows
+#[event]
+pub struct BorrowAccountingMismatchEvent {
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub repay_amount: u64,
+    pub market_total_borrows: u128,
+}
+
+/// Emitted by `repay_all` with the exact debt that was cleared
+#[event]
+pub struct RepayAllEvent {
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub amount: u64,
+}
+
+/// Emitted by `borrow_to` with the account the borrowed tokens were sent to
+#[event]
+pub struct BorrowToEvent {
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+/// Emitted by `withdraw` with the interest the withdrawn principal earned since the
+/// user's supply index snapshot, paid out as part of the withdrawal
+#[event]
+pub struct SupplyInterestSettledEvent {
+    pub market_id: u64,
+    pub user: Pubkey,
+    pub principal: u128,
+    pub interest: u128,
+    pub user_supply_index: u128,
+    pub market_supply_index: u128,
+}
+
+/// Emitted by `get_available_liquidity` with what can be borrowed or withdrawn now
+#[event]
+pub struct LiquidityEvent {
+    pub available: u64,
+    pub total_supply_deposits: u128,
+    pub total_borrows: u128,
+    pub vault_balance: u64,
+}
+
+/// Emitted whenever a market's cumulative interest rates move forward
+#[event]
+pub struct InterestAccruedEvent {
+    pub market_id: u64,
+    pub slots_elapsed: u64,
+    pub borrow_increment: u128,
+    pub supply_increment: u128,
+    pub new_borrow_rate: u128,
+    pub new_supply_rate: u128,
+}
+
+/// Emitted by `liquidate` when a position is unhealthy but still inside the market's
+/// grace period, so the call succeeds without seizing anything
+#[event]
+pub struct LiquidationGracePeriodEvent {
+    pub market_id: u64,
+    pub borrower: Pubkey,
+    pub unhealthy_since_slot: u64,
+    pub unhealthy_slots: u64,
+    pub grace_period_slots: u64,
+}
+
+/// Emitted when an oracle authority overrides the price deviation band
+#[event]
+pub struct OraclePriceOverrideEvent {
+    pub oracle: Pubkey,
+    pub previous_price: u128,
+    pub new_price: u128,
+    pub deviation_bps: u128,
+}
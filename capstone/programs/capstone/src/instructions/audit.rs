@@ -0,0 +1,54 @@
+use crate::{contexts::VerifyMarketVaults, MarketVaultCheckEvent, VaultCheck};
+use anchor_lang::prelude::*;
+
+/// Verify both market vaults are owned by the market PDA and hold the market's mints
+pub fn verify_market_vaults(ctx: Context<VerifyMarketVaults>, market_id: u64) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let market_key = market.key();
+    let supply_vault = &ctx.accounts.supply_vault;
+    let collateral_vault = &ctx.accounts.collateral_vault;
+
+    let checks = [
+        (
+            supply_vault.key(),
+            VaultCheck::SupplyVaultAuthority,
+            supply_vault.owner == market_key,
+        ),
+        (
+            supply_vault.key(),
+            VaultCheck::SupplyVaultMint,
+            supply_vault.mint == market.supply_mint,
+        ),
+        (
+            collateral_vault.key(),
+            VaultCheck::CollateralVaultAuthority,
+            collateral_vault.owner == market_key,
+        ),
+        (
+            collateral_vault.key(),
+            VaultCheck::CollateralVaultMint,
+            collateral_vault.mint == market.collateral_mint,
+        ),
+    ];
+
+    let mut failures = 0;
+    for (vault, check, passed) in checks {
+        if !passed {
+            failures += 1;
+        }
+        emit!(MarketVaultCheckEvent {
+            market_id,
+            vault,
+            check,
+            passed,
+        });
+    }
+
+    msg!(
+        "Market {} vault verification: {} of {} checks failed",
+        market_id,
+        failures,
+        checks.len()
+    );
+    Ok(())
+}
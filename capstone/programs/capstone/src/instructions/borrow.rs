@@ -1,7 +1,11 @@
 use crate::{
     contexts::{Borrow, WithdrawCollateral},
-    utils::{get_asset_price, update_market_interest, SCALING_FACTOR},
-    LendingError,
+    utils::{
+        accrue_interest, calculate_utilization_bps, check_collateral_backing, check_price_ratio,
+        get_asset_price_scaled, is_healthy, require_fresh_oracle, to_common_units, token_value,
+        HIGH_UTILIZATION_WARNING_BPS, PRICE_DECIMALS,
+    },
+    BorrowToEvent, HighUtilizationWarningEvent, LendingError,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{self, Transfer};
@@ -13,45 +17,74 @@ pub fn borrow(
     collateral_amount: u64,
     borrow_amount: u64,
 ) -> Result<()> {
+    require!(
+        !ctx.accounts.protocol_state.borrows_paused,
+        LendingError::MarketPaused
+    );
+
     let market_account_info = ctx.accounts.market.to_account_info();
     let market = &mut ctx.accounts.market;
     let user_deposit = &mut ctx.accounts.user_deposit;
 
-    update_market_interest(market)?;
+    require!(!user_deposit.frozen, LendingError::DepositFrozen);
 
-    // Get asset prices from oracles, we use specific oracles for each asset to get the correct price
-    let collateral_price = get_asset_price(&ctx.accounts.collateral_oracle)?;
-    let borrow_price = get_asset_price(&ctx.accounts.borrow_oracle)?;
+    // Both prices must be fresh before anything moves, each with its own error
+    require_fresh_oracle(
+        &ctx.accounts.collateral_oracle,
+        market.max_oracle_staleness_slots,
+        LendingError::CollateralOracleStale,
+    )?;
+    require_fresh_oracle(
+        &ctx.accounts.borrow_oracle,
+        market.max_oracle_staleness_slots,
+        LendingError::BorrowOracleStale,
+    )?;
 
-    // First, deposit the collateral tokens to collateral vault
-    if collateral_amount > 0 {
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_collateral_account.to_account_info(),
-            to: ctx.accounts.collateral_vault.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token_interface::transfer(cpi_ctx, collateral_amount)?;
+    // Book interest on existing borrows (2% annual rate) before the collateral check,
+    // so the new borrow is measured against the debt actually owed now
+    accrue_interest(market, user_deposit)?;
 
-        // Update collateral balance
-        let collateral_amount_u128 = collateral_amount as u128;
-        user_deposit.collateral_deposited = user_deposit
-            .collateral_deposited
-            .checked_add(collateral_amount_u128)
-            .ok_or(LendingError::MathOverflow)?;
-
-        market.total_collateral_deposits = market
-            .total_collateral_deposits
-            .checked_add(collateral_amount_u128)
-            .ok_or(LendingError::MathOverflow)?;
+    // Opening a new loan restarts the first-repayment grace window and the
+    // interest-free window, and starts accruing from now
+    if user_deposit.borrowed_amount == 0 && borrow_amount > 0 {
+        user_deposit.loan_start_slot = user_deposit.last_update_slot;
+        user_deposit.first_repayment_made = false;
     }
 
-    // Calculate collateral value using the oracle price
-    // u128 calculations prevent overflow issues
-    let total_collateral_value = user_deposit
+    // Get asset prices from oracles, we use specific oracles for each asset to get the correct price
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+
+    // A zero collateral price is a broken feed, not a lack of collateral
+    require!(collateral_price > 0, LendingError::InvalidOracleData);
+    // Each feed can look fresh while their ratio is off, e.g. during a depeg
+    check_price_ratio(market, collateral_price, borrow_price)?;
+
+    // Checks run against the balances this borrow would leave, nothing is written
+    // until both transfers have gone through
+    let collateral_amount_u128 = collateral_amount as u128;
+    let new_collateral_deposited = user_deposit
         .collateral_deposited
-        .checked_mul(collateral_price)
-        .ok_or_else(|| LendingError::MathOverflow)?;
+        .checked_add(collateral_amount_u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    // Calculate collateral value using the oracle price, with both sides in common units
+    // so mints with different decimals compare correctly
+    // u128 calculations prevent overflow issues
+    let total_collateral_value =
+        to_common_units(new_collateral_deposited, market.collateral_decimals)?
+            .checked_mul(collateral_price)
+            .ok_or_else(|| LendingError::MathOverflow)?;
 
     let collateral_factor_u128 = market.collateral_factor as u128;
     let max_borrow_value = total_collateral_value
@@ -64,7 +97,17 @@ pub fn borrow(
         .borrowed_amount
         .checked_add(borrow_amount_u128)
         .ok_or_else(|| LendingError::MathOverflow)?;
-    let new_borrow_value = new_total_borrowed
+    // Avoid dust positions that cost more to liquidate than they're worth, judged by
+    // what the debt is worth rather than its token count
+    if market.min_borrow > 0 && borrow_amount > 0 {
+        require!(
+            token_value(new_total_borrowed, market.supply_decimals, borrow_price)?
+                >= market.min_borrow as u128,
+            LendingError::BorrowTooSmall
+        );
+    }
+
+    let new_borrow_value = to_common_units(new_total_borrowed, market.supply_decimals)?
         .checked_mul(borrow_price)
         .ok_or_else(|| LendingError::MathOverflow)?;
 
@@ -73,31 +116,31 @@ pub fn borrow(
         LendingError::InsufficientCollateral
     );
 
-    let available_liquidity = market.total_supply_deposits
+    // Keep a slice of supply deposits back so withdrawals always have liquidity
+    let liquidity_buffer = market
+        .total_supply_deposits
+        .checked_mul(market.min_liquidity_buffer_bps as u128)
+        .and_then(|v| v.checked_div(10000))
+        .ok_or(LendingError::MathOverflow)?;
+    let available_liquidity = market
+        .total_supply_deposits
         .checked_sub(market.total_borrows)
+        .and_then(|v| v.checked_sub(liquidity_buffer))
         .unwrap_or(0);
-    let borrow_amount_u128 = borrow_amount as u128;
     require!(
         borrow_amount_u128 <= available_liquidity,
         LendingError::InsufficientLiquidity
     );
 
-    // Apply simple interest to existing borrows (2% annual rate)
-    if user_deposit.borrowed_amount > 0 {
-        let current_slot = Clock::get()?.slot;
-        let slots_elapsed = current_slot.saturating_sub(user_deposit.last_update_slot);
-        let interest_rate_per_slot = 25u128; // ~2% annual / 800,000 slots
-        let slots_elapsed_u128 = slots_elapsed as u128;
-        let interest_increment = user_deposit
-            .borrowed_amount
-            .saturating_mul(interest_rate_per_slot)
-            .saturating_mul(slots_elapsed_u128)
-            / SCALING_FACTOR; // Scale down
-
-        user_deposit.borrowed_amount = user_deposit
-            .borrowed_amount
-            .saturating_add(interest_increment);
-        user_deposit.last_update_slot = current_slot;
+    // Deposit the collateral tokens to collateral vault
+    if collateral_amount > 0 {
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_collateral_account.to_account_info(),
+            to: ctx.accounts.collateral_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer(cpi_ctx, collateral_amount)?;
     }
 
     let market_bump = market.bump;
@@ -127,16 +170,41 @@ pub fn borrow(
     );
     token_interface::transfer(cpi_ctx, borrow_amount)?;
 
-    // Update borrow balances
-    let borrow_amount_u128 = borrow_amount as u128;
-    user_deposit.borrowed_amount = user_deposit
-        .borrowed_amount
-        .checked_add(borrow_amount_u128)
+    // Both transfers succeeded, record exactly the balances checked above
+    user_deposit.collateral_deposited = new_collateral_deposited;
+    market.total_collateral_deposits = market
+        .total_collateral_deposits
+        .checked_add(collateral_amount_u128)
         .ok_or(LendingError::MathOverflow)?;
+    user_deposit.borrowed_amount = new_total_borrowed;
     market.total_borrows = market
         .total_borrows
         .checked_add(borrow_amount_u128)
         .ok_or(LendingError::MathOverflow)?;
+    // A position this borrow leaves healthy restarts any liquidation grace period
+    if is_healthy(
+        total_collateral_value,
+        new_borrow_value,
+        market.liquidation_threshold,
+    ) {
+        user_deposit.unhealthy_since_slot = 0;
+    }
+
+    // Informational only: warn front-ends when withdrawal liquidity is getting thin
+    let utilization_bps =
+        calculate_utilization_bps(market.total_borrows, market.total_supply_deposits)?;
+    if utilization_bps > HIGH_UTILIZATION_WARNING_BPS {
+        emit!(HighUtilizationWarningEvent {
+            market_id,
+            utilization_bps,
+        });
+    }
+
+    ctx.accounts.collateral_vault.reload()?;
+    check_collateral_backing(
+        ctx.accounts.collateral_vault.amount,
+        ctx.accounts.market.total_collateral_deposits,
+    )?;
 
     msg!(
         "Borrow successful: {} collateral → {} supply tokens",
@@ -146,6 +214,39 @@ pub fn borrow(
     Ok(())
 }
 
+/// Borrow like `borrow`, but send the borrowed supply tokens straight to `destination`,
+/// passed as `user_supply_account`. Any holder may own it; it must hold the supply mint.
+pub fn borrow_to(
+    ctx: Context<Borrow>,
+    market_id: u64,
+    collateral_amount: u64,
+    borrow_amount: u64,
+    destination: Pubkey,
+) -> Result<()> {
+    let destination_account = &ctx.accounts.user_supply_account;
+    require_keys_eq!(
+        destination_account.key(),
+        destination,
+        LendingError::InvalidBorrowDestination
+    );
+    require_keys_eq!(
+        destination_account.mint,
+        ctx.accounts.supply_mint.key(),
+        LendingError::InvalidBorrowDestination
+    );
+    let user = ctx.accounts.user.key();
+
+    borrow(ctx, market_id, collateral_amount, borrow_amount)?;
+
+    emit!(BorrowToEvent {
+        market_id,
+        user,
+        destination,
+        amount: borrow_amount,
+    });
+    Ok(())
+}
+
 /// Withdraw collateral tokens (only allowed when no outstanding borrows)
 pub fn withdraw_collateral(
     ctx: Context<WithdrawCollateral>,
@@ -154,11 +255,13 @@ pub fn withdraw_collateral(
 ) -> Result<()> {
     let user_deposit = &mut ctx.accounts.user_deposit;
 
+    require!(!user_deposit.frozen, LendingError::DepositFrozen);
+
     // Ensure user has no outstanding borrows
-    require!(
-        user_deposit.borrowed_amount == 0,
-        LendingError::HasBorrows
-    );
+    require!(user_deposit.borrowed_amount == 0, LendingError::HasBorrows);
+
+    // With no debt there is no health to check, so collateral-only positions are
+    // withdrawn without reading any oracle and stay withdrawable when feeds go stale
 
     // Ensure user has enough collateral
     require!(
@@ -198,6 +301,8 @@ pub fn withdraw_collateral(
         .collateral_deposited
         .checked_sub(collateral_amount as u128)
         .ok_or(LendingError::MathOverflow)?;
+    // No debt means nothing to liquidate, so no grace period is running
+    user_deposit.unhealthy_since_slot = 0;
 
     // Update market totals
     let market = &mut ctx.accounts.market;
@@ -206,6 +311,12 @@ pub fn withdraw_collateral(
         .checked_sub(collateral_amount as u128)
         .ok_or(LendingError::MathOverflow)?;
 
+    ctx.accounts.collateral_vault.reload()?;
+    check_collateral_backing(
+        ctx.accounts.collateral_vault.amount,
+        market.total_collateral_deposits,
+    )?;
+
     msg!(
         "Collateral withdrawal successful: {} tokens",
         collateral_amount
@@ -1,4 +1,8 @@
-use crate::{contexts::FlashLoan, LendingError};
+use crate::{
+    contexts::FlashLoan,
+    utils::{calculate_flash_loan_fee, credit_suppliers},
+    LendingError,
+};
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{instruction::Instruction, program::invoke};
 use anchor_spl::token_interface::{self, Transfer};
@@ -11,7 +15,34 @@ pub fn flash_loan(
     amount: u64,
     callback_data: Vec<u8>, // User-provided data for callback
 ) -> Result<()> {
+    require!(
+        !ctx.accounts.protocol_state.borrows_paused,
+        LendingError::MarketPaused
+    );
     let market = &ctx.accounts.market;
+    require!(market.flash_loans_enabled, LendingError::FlashLoansDisabled);
+
+    // The callback program and the token program it gets are read by position below
+    require!(
+        ctx.remaining_accounts.len() >= 2,
+        LendingError::MissingCallbackAccounts
+    );
+    let callback_program = &ctx.remaining_accounts[0];
+    require!(
+        callback_program.executable,
+        LendingError::InvalidCallbackProgram
+    );
+
+    // A callback into this program could re-enter it mid-loan, and one into a token
+    // program could pass off a crafted transfer as the repayment
+    let callback_program_id = callback_program.key();
+    require!(
+        callback_program_id != *ctx.program_id
+            && callback_program_id != ctx.accounts.token_program.key()
+            && callback_program_id != anchor_spl::token::ID
+            && callback_program_id != anchor_spl::token_2022::ID,
+        LendingError::InvalidCallbackProgram
+    );
 
     let initial_balance = ctx.accounts.supply_vault.amount;
     let supply_mint = ctx.accounts.supply_mint.key();
@@ -46,9 +77,6 @@ pub fn flash_loan(
     );
     token_interface::transfer(cpi_ctx, amount)?;
 
-    // Use remaining accounts to call external program
-    let callback_program = &ctx.remaining_accounts[0];
-
     // Create accounts list for the callback - all remaining accounts except the first (callback program)
     let callback_accounts = &ctx.remaining_accounts[1..];
 
@@ -69,17 +97,66 @@ pub fn flash_loan(
     // Execute the callback CPI to external program
     invoke(&callback_ix, callback_accounts)?;
 
-    // Check final balance after callback execution
-    let final_balance = ctx.accounts.supply_vault.amount;
-    let fee = amount * 30 / 10000; // 0.3% fee
-    let required_balance = initial_balance + fee;
+    // Pull the repayment back explicitly rather than trusting the callback to route it
+    let fee = calculate_flash_loan_fee(amount, ctx.accounts.market.flash_loan_fee_bps)?;
+    let repayment = amount.checked_add(fee).ok_or(LendingError::MathOverflow)?;
 
-    // Verify flash loan was repaid with fee
+    ctx.accounts.user_supply_account.reload()?;
     require!(
-        final_balance >= required_balance,
+        ctx.accounts.user_supply_account.amount >= repayment,
         LendingError::FlashLoanNotRepaid
     );
 
-    msg!("Flash loan repaid with fee");
+    let cpi_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Transfer {
+            from: ctx.accounts.user_supply_account.to_account_info(),
+            to: ctx.accounts.supply_vault.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        },
+    );
+    token_interface::transfer(cpi_ctx, repayment)?;
+
+    // The vault must end up exactly `fee` above where it started: anything else means the
+    // callback moved vault funds on its own, and donations must not skew reserves
+    ctx.accounts.supply_vault.reload()?;
+    let final_balance = ctx.accounts.supply_vault.amount;
+    let expected_balance = initial_balance
+        .checked_add(fee)
+        .ok_or(LendingError::MathOverflow)?;
+    if final_balance != expected_balance {
+        msg!(
+            "Flash loan vault balance {} does not match expected {}",
+            final_balance,
+            expected_balance
+        );
+        return Err(LendingError::FlashLoanRepaymentMismatch.into());
+    }
+
+    // Split the fee: the protocol's share leaves the vault for the fee recipient,
+    // the rest stays behind and accrues to suppliers through the exchange rate
+    let protocol_fee = calculate_flash_loan_fee(fee, ctx.accounts.market.protocol_fee_share_bps)?;
+    let supplier_fee = fee - protocol_fee;
+    if protocol_fee > 0 {
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.supply_vault.to_account_info(),
+                to: ctx.accounts.fee_recipient_account.to_account_info(),
+                authority: ctx.accounts.market.to_account_info(),
+            },
+            signer_seeds,
+        );
+        token_interface::transfer(cpi_ctx, protocol_fee)?;
+    }
+
+    credit_suppliers(&mut ctx.accounts.market, supplier_fee as u128)?;
+
+    msg!(
+        "Flash loan repaid with fee {} ({} to protocol, {} to suppliers)",
+        fee,
+        protocol_fee,
+        supplier_fee
+    );
     Ok(())
 }
@@ -0,0 +1,142 @@
+use crate::{
+    contexts::{AmISafe, GetCurrentDebt, GetPosition},
+    utils::{
+        borrow_rate_per_slot, calculate_accrued_debt, calculate_health_factor,
+        get_asset_price_scaled, in_first_repayment_grace, interest_accrual_start, is_healthy,
+        to_common_units, PRICE_DECIMALS,
+    },
+    LendingError, Market, SafetyEvent, UserDeposit, UserPosition,
+};
+use anchor_lang::prelude::*;
+
+/// Report whether the caller's position is safe from liquidation
+pub fn am_i_safe(ctx: Context<AmISafe>, _market_id: u64) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let user_deposit = &ctx.accounts.user_deposit;
+
+    // Read path: include interest accrued since the last update without persisting it
+    let current_slot = Clock::get()?.slot;
+    let debt = calculate_accrued_debt(
+        user_deposit.borrowed_amount,
+        borrow_rate_per_slot(market),
+        interest_accrual_start(market, user_deposit),
+        current_slot,
+    )?;
+
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+
+    let collateral_value = to_common_units(
+        user_deposit.collateral_deposited,
+        market.collateral_decimals,
+    )?
+    .checked_mul(collateral_price)
+    .ok_or(LendingError::MathOverflow)?;
+    let borrow_value = to_common_units(debt, market.supply_decimals)?
+        .checked_mul(borrow_price)
+        .ok_or(LendingError::MathOverflow)?;
+
+    let health_factor =
+        calculate_health_factor(collateral_value, borrow_value, market.liquidation_threshold)?;
+    let is_safe = is_healthy(collateral_value, borrow_value, market.liquidation_threshold);
+
+    emit!(SafetyEvent {
+        is_safe,
+        health_factor,
+        liquidation_threshold: market.liquidation_threshold,
+    });
+
+    msg!(
+        "Position is {}: health factor {}",
+        if is_safe {
+            "safe"
+        } else {
+            "at risk of liquidation"
+        },
+        health_factor
+    );
+    Ok(())
+}
+
+/// Return a user's debt including interest pending up to the current slot,
+/// i.e. exactly what a full `repay` in this slot would consume. Nothing is persisted.
+pub fn get_current_debt(ctx: Context<GetCurrentDebt>, _market_id: u64) -> Result<u128> {
+    let current_slot = Clock::get()?.slot;
+    let debt = debt_owed_at(
+        &ctx.accounts.market,
+        &ctx.accounts.user_deposit,
+        current_slot,
+    )?;
+
+    msg!("Current debt: {} at slot {}", debt, current_slot);
+    Ok(debt)
+}
+
+/// Return a user's balances in one market, with debt and health factor as of the
+/// current slot, so clients can loop over known market ids. Nothing is persisted.
+pub fn get_position(ctx: Context<GetPosition>, _market_id: u64) -> Result<UserPosition> {
+    let market = &ctx.accounts.market;
+    let user_deposit = &ctx.accounts.user_deposit;
+    let borrowed_amount = debt_owed_at(market, user_deposit, Clock::get()?.slot)?;
+
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let collateral_value = to_common_units(
+        user_deposit.collateral_deposited,
+        market.collateral_decimals,
+    )?
+    .checked_mul(collateral_price)
+    .ok_or(LendingError::MathOverflow)?;
+    let borrow_value = to_common_units(borrowed_amount, market.supply_decimals)?
+        .checked_mul(borrow_price)
+        .ok_or(LendingError::MathOverflow)?;
+    let health_factor =
+        calculate_health_factor(collateral_value, borrow_value, market.liquidation_threshold)?;
+
+    msg!(
+        "Position: {} debt, health factor {}",
+        borrowed_amount,
+        health_factor
+    );
+    Ok(UserPosition {
+        supply_deposited: user_deposit.supply_deposited,
+        collateral_deposited: user_deposit.collateral_deposited,
+        borrowed_amount,
+        ctoken_balance: user_deposit.ctoken_balance,
+        health_factor,
+    })
+}
+
+/// Debt a full repayment at `current_slot` would consume
+fn debt_owed_at(market: &Market, user_deposit: &UserDeposit, current_slot: u64) -> Result<u128> {
+    // Mirror repay: interest is waived inside the first-repayment grace window
+    if in_first_repayment_grace(market, user_deposit, current_slot) {
+        return Ok(user_deposit.borrowed_amount);
+    }
+    calculate_accrued_debt(
+        user_deposit.borrowed_amount,
+        borrow_rate_per_slot(market),
+        interest_accrual_start(market, user_deposit),
+        current_slot,
+    )
+}
@@ -1,13 +1,24 @@
 use crate::{
-    contexts::Liquidate,
-    utils::{get_asset_price, update_market_interest},
-    LendingError,
+    contexts::{Liquidate, LiquidateBatch},
+    events::{BadDebtEvent, LiquidationGracePeriodEvent},
+    utils::{
+        absorb_bad_debt, accrue_interest, calculate_collateral_to_seize, check_collateral_backing,
+        get_asset_price_scaled, is_healthy, to_common_units, update_market_interest,
+        LIQUIDATION_CLOSE_FACTOR_BPS, MAX_LIQUIDATION_BATCH, PRICE_DECIMALS,
+    },
+    LendingError, LiquidationBatchSummary, UserDeposit,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{self, Transfer};
 
-/// Liquidate undercollateralized positions
-pub fn liquidate(ctx: Context<Liquidate>, market_id: u64, liquidation_amount: u64) -> Result<()> {
+/// Liquidate undercollateralized positions. Fails if the repayment would seize less than
+/// `min_collateral_out` collateral; 0 disables the check.
+pub fn liquidate(
+    ctx: Context<Liquidate>,
+    market_id: u64,
+    liquidation_amount: u64,
+    min_collateral_out: u64,
+) -> Result<()> {
     let market = &mut ctx.accounts.market;
     let borrower_deposit = &mut ctx.accounts.borrower_deposit;
 
@@ -16,31 +27,91 @@ pub fn liquidate(ctx: Context<Liquidate>, market_id: u64, liquidation_amount: u6
         LendingError::InvalidPDA
     );
 
-    update_market_interest(market)?;
+    // Book the borrower's interest first: both the health check and the debt
+    // reduction below must see what is actually owed, not the last stored debt
+    accrue_interest(market, borrower_deposit)?;
 
-    // Check if position is liquidatable
-    let asset_price = get_asset_price(&ctx.accounts.oracle)?;
-    let collateral_value = borrower_deposit
-        .collateral_deposited
-        .checked_mul(asset_price)
-        .ok_or(LendingError::MathOverflow)?;
-    let borrow_value = borrower_deposit
-        .borrowed_amount
-        .checked_mul(asset_price)
-        .ok_or(LendingError::MathOverflow)?;
-    let liquidation_threshold_value = collateral_value
-        .checked_mul(market.liquidation_threshold as u128)
-        .and_then(|v| v.checked_div(10000))
+    // Check if position is liquidatable, pricing each side with its own oracle
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let collateral_value = to_common_units(
+        borrower_deposit.collateral_deposited,
+        market.collateral_decimals,
+    )?
+    .checked_mul(collateral_price)
+    .ok_or(LendingError::MathOverflow)?;
+    let borrow_value = to_common_units(borrower_deposit.borrowed_amount, market.supply_decimals)?
+        .checked_mul(borrow_price)
         .ok_or(LendingError::MathOverflow)?;
 
-    require!(
-        borrow_value > liquidation_threshold_value,
-        LendingError::PositionHealthy
-    );
+    let current_slot = Clock::get()?.slot;
+    if is_healthy(collateral_value, borrow_value, market.liquidation_threshold) {
+        // Position recovered since it was last seen unhealthy: restart the grace period.
+        // Returning Ok keeps the reset, otherwise the error would roll it back.
+        if borrower_deposit.unhealthy_since_slot != 0 {
+            borrower_deposit.unhealthy_since_slot = 0;
+            msg!("Position is healthy again, liquidation grace period reset");
+            return Ok(());
+        }
+        return Err(LendingError::PositionHealthy.into());
+    }
+
+    // Only seize once the position has stayed unhealthy for the whole grace period,
+    // so a single-slot oracle blip can't be used to liquidate
+    if borrower_deposit.unhealthy_since_slot == 0 {
+        borrower_deposit.unhealthy_since_slot = current_slot;
+    }
+    let unhealthy_slots = current_slot.saturating_sub(borrower_deposit.unhealthy_since_slot);
+    if unhealthy_slots < market.grace_period_slots {
+        // Ok rather than an error so the recorded start of the grace period sticks;
+        // the event tells the liquidator nothing was seized and why
+        emit!(LiquidationGracePeriodEvent {
+            market_id,
+            borrower: borrower_deposit.user,
+            unhealthy_since_slot: borrower_deposit.unhealthy_since_slot,
+            unhealthy_slots,
+            grace_period_slots: market.grace_period_slots,
+        });
+        msg!(
+            "Position unhealthy for {} of {} grace slots, not yet liquidatable",
+            unhealthy_slots,
+            market.grace_period_slots
+        );
+        return Ok(());
+    }
 
-    // Calculate liquidation bonus
-    let liquidation_bonus = 1100; // 10% bonus
-    let collateral_to_seize = liquidation_amount * liquidation_bonus / 1000;
+    // Collateral worth the repaid debt plus the liquidation bonus
+    let collateral_to_seize = calculate_collateral_to_seize(
+        liquidation_amount,
+        market.liquidation_bonus_bps,
+        borrow_price,
+        collateral_price,
+        market.supply_decimals,
+        market.collateral_decimals,
+    )?;
+    // Can't seize more collateral than the borrower has left
+    let collateral_to_seize =
+        u64::try_from(collateral_to_seize.min(borrower_deposit.collateral_deposited))
+            .map_err(|_| LendingError::MathOverflow)?;
+    // Prices can move between quoting and landing: let the liquidator bound the outcome
+    if collateral_to_seize < min_collateral_out {
+        msg!(
+            "Liquidation would seize {} collateral, below the minimum {}",
+            collateral_to_seize,
+            min_collateral_out
+        );
+        return Err(LendingError::SlippageExceeded.into());
+    }
 
     // Validate liquidation amount
     require!(
@@ -83,13 +154,250 @@ pub fn liquidate(ctx: Context<Liquidate>, market_id: u64, liquidation_amount: u6
     token_interface::transfer(cpi_ctx, collateral_to_seize)?;
 
     // Update borrower balances
-    borrower_deposit.borrowed_amount -= liquidation_amount as u128;
-    borrower_deposit.collateral_deposited -= collateral_to_seize as u128;
+    borrower_deposit.borrowed_amount = borrower_deposit
+        .borrowed_amount
+        .checked_sub(liquidation_amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+    borrower_deposit.collateral_deposited = borrower_deposit
+        .collateral_deposited
+        .checked_sub(collateral_to_seize as u128)
+        .ok_or(LendingError::MathOverflow)?;
+    let market = &mut ctx.accounts.market;
+    market.total_borrows = market
+        .total_borrows
+        .saturating_sub(liquidation_amount as u128);
+    market.total_collateral_deposits = market
+        .total_collateral_deposits
+        .checked_sub(collateral_to_seize as u128)
+        .ok_or(LendingError::MathOverflow)?;
 
     msg!(
         "Liquidation successful: {} debt → {} collateral",
         liquidation_amount,
         collateral_to_seize
     );
+
+    // No collateral left behind the remaining debt: write it off as bad debt,
+    // drawing on reserves before socializing the loss to suppliers
+    if borrower_deposit.collateral_deposited == 0 && borrower_deposit.borrowed_amount > 0 {
+        let shortfall = borrower_deposit.borrowed_amount;
+        let (covered_by_reserves, socialized) = absorb_bad_debt(market, shortfall)?;
+        borrower_deposit.borrowed_amount = 0;
+
+        emit!(BadDebtEvent {
+            market_id,
+            borrower: borrower_deposit.user,
+            shortfall,
+            covered_by_reserves,
+            socialized,
+        });
+        msg!(
+            "Bad debt {}: {} covered by reserves, {} socialized",
+            shortfall,
+            covered_by_reserves,
+            socialized
+        );
+    }
+
+    ctx.accounts.collateral_vault.reload()?;
+    check_collateral_backing(
+        ctx.accounts.collateral_vault.amount,
+        ctx.accounts.market.total_collateral_deposits,
+    )?;
     Ok(())
 }
+
+/// Liquidate several positions in one market atomically. Borrower deposits are passed
+/// writable in `remaining_accounts`, in the same order as `amounts`. Each position repays
+/// at most its close factor; healthy positions and ones still inside the grace period
+/// are skipped rather than failing the batch.
+pub fn liquidate_batch<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidateBatch<'info>>,
+    market_id: u64,
+    amounts: Vec<u64>,
+) -> Result<LiquidationBatchSummary> {
+    require!(
+        !amounts.is_empty()
+            && amounts.len() <= MAX_LIQUIDATION_BATCH
+            && amounts.len() == ctx.remaining_accounts.len(),
+        LendingError::InvalidLiquidationBatch
+    );
+
+    let market_key = ctx.accounts.market.key();
+    let market = &mut ctx.accounts.market;
+    update_market_interest(market)?;
+
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let current_slot = Clock::get()?.slot;
+
+    let mut summary = LiquidationBatchSummary {
+        liquidated: 0,
+        skipped: 0,
+        debt_repaid: 0,
+        collateral_seized: 0,
+    };
+
+    for (deposit_info, &requested) in ctx.remaining_accounts.iter().zip(amounts.iter()) {
+        // Account::try_from checks the program owner and discriminator
+        let mut borrower_deposit = Account::<UserDeposit>::try_from(deposit_info)?;
+        require!(deposit_info.is_writable, LendingError::InvalidPDA);
+        require_keys_eq!(
+            borrower_deposit.market,
+            market_key,
+            LendingError::InvalidPDA
+        );
+        accrue_interest(market, &mut borrower_deposit)?;
+
+        let collateral_value = to_common_units(
+            borrower_deposit.collateral_deposited,
+            market.collateral_decimals,
+        )?
+        .checked_mul(collateral_price)
+        .ok_or(LendingError::MathOverflow)?;
+        let borrow_value =
+            to_common_units(borrower_deposit.borrowed_amount, market.supply_decimals)?
+                .checked_mul(borrow_price)
+                .ok_or(LendingError::MathOverflow)?;
+
+        if is_healthy(collateral_value, borrow_value, market.liquidation_threshold) {
+            // Same as `liquidate`: a recovered position restarts its grace period
+            borrower_deposit.unhealthy_since_slot = 0;
+            borrower_deposit.exit(ctx.program_id)?;
+            summary.skipped += 1;
+            continue;
+        }
+
+        if borrower_deposit.unhealthy_since_slot == 0 {
+            borrower_deposit.unhealthy_since_slot = current_slot;
+        }
+        let unhealthy_slots = current_slot.saturating_sub(borrower_deposit.unhealthy_since_slot);
+        let max_repay = borrower_deposit
+            .borrowed_amount
+            .checked_mul(LIQUIDATION_CLOSE_FACTOR_BPS as u128)
+            .map(|v| v / 10000)
+            .ok_or(LendingError::MathOverflow)?;
+        let liquidation_amount = (requested as u128).min(max_repay) as u64;
+
+        if unhealthy_slots < market.grace_period_slots || liquidation_amount == 0 {
+            borrower_deposit.exit(ctx.program_id)?;
+            summary.skipped += 1;
+            continue;
+        }
+
+        let collateral_to_seize = calculate_collateral_to_seize(
+            liquidation_amount,
+            market.liquidation_bonus_bps,
+            borrow_price,
+            collateral_price,
+            market.supply_decimals,
+            market.collateral_decimals,
+        )?;
+        let collateral_to_seize =
+            u64::try_from(collateral_to_seize.min(borrower_deposit.collateral_deposited))
+                .map_err(|_| LendingError::MathOverflow)?;
+
+        borrower_deposit.borrowed_amount = borrower_deposit
+            .borrowed_amount
+            .checked_sub(liquidation_amount as u128)
+            .ok_or(LendingError::MathOverflow)?;
+        borrower_deposit.collateral_deposited = borrower_deposit
+            .collateral_deposited
+            .checked_sub(collateral_to_seize as u128)
+            .ok_or(LendingError::MathOverflow)?;
+        market.total_borrows = market
+            .total_borrows
+            .saturating_sub(liquidation_amount as u128);
+        market.total_collateral_deposits = market
+            .total_collateral_deposits
+            .checked_sub(collateral_to_seize as u128)
+            .ok_or(LendingError::MathOverflow)?;
+
+        if borrower_deposit.collateral_deposited == 0 && borrower_deposit.borrowed_amount > 0 {
+            let shortfall = borrower_deposit.borrowed_amount;
+            let (covered_by_reserves, socialized) = absorb_bad_debt(market, shortfall)?;
+            borrower_deposit.borrowed_amount = 0;
+
+            emit!(BadDebtEvent {
+                market_id,
+                borrower: borrower_deposit.user,
+                shortfall,
+                covered_by_reserves,
+                socialized,
+            });
+        }
+        borrower_deposit.exit(ctx.program_id)?;
+
+        summary.liquidated += 1;
+        summary.debt_repaid = summary
+            .debt_repaid
+            .checked_add(liquidation_amount)
+            .ok_or(LendingError::MathOverflow)?;
+        summary.collateral_seized = summary
+            .collateral_seized
+            .checked_add(collateral_to_seize)
+            .ok_or(LendingError::MathOverflow)?;
+    }
+
+    // Settle the whole batch with one transfer in each direction
+    if summary.debt_repaid > 0 {
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.liquidator_supply_account.to_account_info(),
+            to: ctx.accounts.supply_vault.to_account_info(),
+            authority: ctx.accounts.liquidator.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer(cpi_ctx, summary.debt_repaid)?;
+    }
+
+    if summary.collateral_seized > 0 {
+        let supply_mint = ctx.accounts.supply_mint.key();
+        let collateral_mint = ctx.accounts.collateral_mint.key();
+        let market_id_bytes = market_id.to_le_bytes();
+        let market_seeds = &[
+            b"market",
+            market_id_bytes.as_ref(),
+            supply_mint.as_ref(),
+            collateral_mint.as_ref(),
+            &[ctx.accounts.market.bump],
+        ];
+        let signer_seeds = &[market_seeds.as_slice()];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.collateral_vault.to_account_info(),
+            to: ctx.accounts.liquidator_collateral_account.to_account_info(),
+            authority: ctx.accounts.market.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        );
+        token_interface::transfer(cpi_ctx, summary.collateral_seized)?;
+    }
+
+    ctx.accounts.collateral_vault.reload()?;
+    check_collateral_backing(
+        ctx.accounts.collateral_vault.amount,
+        ctx.accounts.market.total_collateral_deposits,
+    )?;
+
+    msg!(
+        "Batch liquidation: {} liquidated, {} skipped, {} debt → {} collateral",
+        summary.liquidated,
+        summary.skipped,
+        summary.debt_repaid,
+        summary.collateral_seized
+    );
+    Ok(summary)
+}
@@ -1,6 +1,18 @@
-use crate::contexts::CreateMarket;
-use crate::utils::SCALING_FACTOR;
+use crate::contexts::{
+    CloseMarket, CreateMarket, GetAvailableLiquidity, GetExchangeRate, GetMarketStats, GetMarketTvl,
+};
+use crate::errors::LendingError;
+use crate::events::LiquidityEvent;
+use crate::state::{MarketStats, MarketTvl, Oracle};
+use crate::utils::{
+    accrue_market_interest, book_borrow_interest, borrow_apr, borrow_apy, borrow_rate_per_slot,
+    calculate_exchange_rate, compound_interest, current_utilization, get_asset_price_scaled,
+    supply_apr, supply_apy, token_value, DEFAULT_DUST_THRESHOLD, DEFAULT_FLASH_LOAN_FEE_BPS,
+    DEFAULT_LIQUIDATION_BONUS_BPS, DEFAULT_MAX_BORROW_RATE_BPS, DEFAULT_MAX_CONFIDENCE_BPS,
+    DEFAULT_MAX_ORACLE_STALENESS_SLOTS, PRICE_DECIMALS, SCALING_FACTOR,
+};
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, CloseAccount};
 
 /// Create a new lending market with separate supply and collateral assets
 pub fn create_market(
@@ -8,10 +20,38 @@ pub fn create_market(
     market_id: u64,
     collateral_factor: u64, // Basis points (e.g., 8000 = 80%)
     liquidation_threshold: u64,
+    min_borrow: u64, // Smallest allowed outstanding borrow value (PRICE_DECIMALS), 0 disables the check
 ) -> Result<()> {
+    // Both feeds must be this program's oracles, each pricing its own side of the market
+    for (oracle_info, mint) in [
+        (&ctx.accounts.supply_oracle, ctx.accounts.supply_mint.key()),
+        (
+            &ctx.accounts.collateral_oracle,
+            ctx.accounts.collateral_mint.key(),
+        ),
+    ] {
+        require_keys_eq!(
+            *oracle_info.owner,
+            crate::ID,
+            LendingError::InvalidOracleData
+        );
+        // try_deserialize checks the Oracle discriminator
+        let oracle = Oracle::try_deserialize(&mut &oracle_info.data.borrow()[..])
+            .map_err(|_| LendingError::InvalidOracleData)?;
+        require_keys_eq!(oracle.mint, mint, LendingError::InvalidOracleData);
+    }
+
     let market = &mut ctx.accounts.market;
     let protocol_state = &mut ctx.accounts.protocol_state;
 
+    // Ids are handed out in order so the same id can't be created twice, not even
+    // after its market is closed and the PDA is free again
+    require!(
+        market_id == protocol_state.next_market_id,
+        LendingError::InvalidMarketId
+    );
+    protocol_state.next_market_id = market_id.checked_add(1).ok_or(LendingError::MathOverflow)?;
+
     market.market_id = market_id;
     market.supply_mint = ctx.accounts.supply_mint.key();
     market.collateral_mint = ctx.accounts.collateral_mint.key();
@@ -29,6 +69,45 @@ pub fn create_market(
     market.collateral_oracle = ctx.accounts.collateral_oracle.key();
     market.bump = ctx.bumps.market;
     market.is_active = true;
+    market.flash_loans_enabled = true;
+    market.first_repayment_grace_slots = 0;
+    market.grace_period_slots = 0;
+    market.min_liquidity_buffer_bps = 0;
+    market.pending_admin = Pubkey::default();
+    market.min_borrow = min_borrow;
+    market.total_reserves = 0;
+    market.max_oracle_staleness_slots = DEFAULT_MAX_ORACLE_STALENESS_SLOTS;
+    market.flash_loan_fee_bps = DEFAULT_FLASH_LOAN_FEE_BPS;
+    market.loyalty_threshold_slots = 0;
+    market.loyalty_bonus_bps = 0;
+    market.total_bad_debt = 0;
+    market.max_confidence_bps = DEFAULT_MAX_CONFIDENCE_BPS;
+    market.interest_free_slots = 0;
+    market.liquidation_bonus_bps = DEFAULT_LIQUIDATION_BONUS_BPS;
+    market.supply_decimals = ctx.accounts.supply_mint.decimals;
+    market.collateral_decimals = ctx.accounts.collateral_mint.decimals;
+    // Wide open until the admin configures a band
+    market.min_price_ratio_bps = 0;
+    market.max_price_ratio_bps = u64::MAX;
+    market.dust_threshold = DEFAULT_DUST_THRESHOLD;
+    market.min_supply = 0;
+    market.protocol_fee_share_bps = 0;
+    market.supply_vault_bump = ctx.bumps.supply_vault;
+    market.collateral_vault_bump = ctx.bumps.collateral_vault;
+    market.max_borrow_rate_bps = DEFAULT_MAX_BORROW_RATE_BPS;
+    market.supply_index = SCALING_FACTOR;
+
+    // Record the market in the enumerable index before bumping the counter
+    let market_index = &mut ctx.accounts.market_index;
+    market_index.index = protocol_state.total_markets;
+    market_index.market = market.key();
+    market_index.bump = ctx.bumps.market_index;
+
+    // Let clients resolve the market from its mint pair alone
+    let market_pointer = &mut ctx.accounts.market_pointer;
+    market_pointer.market_id = market_id;
+    market_pointer.market = market.key();
+    market_pointer.bump = ctx.bumps.market_pointer;
 
     protocol_state.total_markets += 1;
 
@@ -40,3 +119,189 @@ pub fn create_market(
     );
     Ok(())
 }
+
+/// Retire an empty market: close its vaults, account and mint-pair pointer and drop it from the index
+pub fn close_market(ctx: Context<CloseMarket>, market_id: u64) -> Result<()> {
+    let market = &ctx.accounts.market;
+    require!(
+        market.total_supply_deposits == 0
+            && market.total_borrows == 0
+            && market.total_collateral_deposits == 0,
+        LendingError::InvalidMarketState
+    );
+    require!(
+        ctx.accounts.supply_vault.amount == 0 && ctx.accounts.collateral_vault.amount == 0,
+        LendingError::InvalidMarketState
+    );
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market".as_ref(),
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    for vault in [
+        ctx.accounts.supply_vault.to_account_info(),
+        ctx.accounts.collateral_vault.to_account_info(),
+    ] {
+        let cpi_accounts = CloseAccount {
+            account: vault,
+            destination: ctx.accounts.admin.to_account_info(),
+            authority: ctx.accounts.market.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        );
+        token_interface::close_account(cpi_ctx)?;
+    }
+
+    // Swap-remove from the index: the last entry takes the closed market's slot,
+    // so the next create_market reuses the freed highest index
+    let protocol_state = &mut ctx.accounts.protocol_state;
+    let last_index = protocol_state
+        .total_markets
+        .checked_sub(1)
+        .ok_or(LendingError::MathOverflow)?;
+    let admin = ctx.accounts.admin.to_account_info();
+    if ctx.accounts.market_index.index == last_index {
+        ctx.accounts.market_index.close(admin)?;
+    } else {
+        let last_market_index = ctx
+            .accounts
+            .last_market_index
+            .as_mut()
+            .ok_or(LendingError::InvalidMarketState)?;
+        ctx.accounts.market_index.market = last_market_index.market;
+        last_market_index.close(admin)?;
+    }
+    protocol_state.total_markets = last_index;
+
+    msg!("Closed market {}", market_id);
+    Ok(())
+}
+
+/// Emit how much supply can be taken out of the market right now
+pub fn get_available_liquidity(ctx: Context<GetAvailableLiquidity>, market_id: u64) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let vault_balance = ctx.accounts.supply_vault.amount;
+
+    // Accounting can disagree with the vault (e.g. after bad debt), so the
+    // smaller of the two is what can actually leave
+    let accounting_liquidity = market
+        .total_supply_deposits
+        .saturating_sub(market.total_borrows);
+    let available = accounting_liquidity.min(vault_balance as u128) as u64;
+
+    emit!(LiquidityEvent {
+        available,
+        total_supply_deposits: market.total_supply_deposits,
+        total_borrows: market.total_borrows,
+        vault_balance,
+    });
+
+    msg!(
+        "Market {} available liquidity: {} (vault balance {})",
+        market_id,
+        available,
+        vault_balance
+    );
+    Ok(())
+}
+
+/// Return utilization and annualized rates for dashboards (via return data)
+pub fn get_market_stats(ctx: Context<GetMarketStats>, market_id: u64) -> Result<MarketStats> {
+    let market = &ctx.accounts.market;
+
+    let stats = MarketStats {
+        utilization: current_utilization(market),
+        borrow_apy: borrow_apy(market),
+        supply_apy: supply_apy(market),
+        borrow_apr: borrow_apr(market),
+        supply_apr: supply_apr(market),
+    };
+
+    msg!(
+        "Market {} utilization: {}, borrow APY: {} (APR {}), supply APY: {} (APR {})",
+        market_id,
+        stats.utilization,
+        stats.borrow_apy,
+        stats.borrow_apr,
+        stats.supply_apy,
+        stats.supply_apr
+    );
+    Ok(stats)
+}
+
+/// Return the live cToken exchange rate, scaled by SCALING_FACTOR (via return data).
+/// Interest is accrued on a copy of the market, so nothing is written.
+pub fn get_exchange_rate(ctx: Context<GetExchangeRate>, market_id: u64) -> Result<u128> {
+    let mut market = ctx.accounts.market.clone().into_inner();
+    let slots_elapsed = Clock::get()?.slot.saturating_sub(market.last_update_slot);
+    accrue_market_interest(&mut market)?;
+
+    // Borrow interest since the last update is already owed to suppliers, it just
+    // hasn't been booked by a user instruction yet
+    let owed = compound_interest(
+        market.total_borrows,
+        borrow_rate_per_slot(&market),
+        slots_elapsed,
+    )?;
+    let pending_interest = owed - market.total_borrows;
+    book_borrow_interest(&mut market, pending_interest)?;
+
+    let exchange_rate = calculate_exchange_rate(&market)?;
+    msg!("Market {} exchange rate: {}", market_id, exchange_rate);
+    Ok(exchange_rate)
+}
+
+/// Return the value held in both vaults and lent out, priced by the market's oracles
+/// in a common quote unit (via return data)
+pub fn get_market_tvl(ctx: Context<GetMarketTvl>, market_id: u64) -> Result<MarketTvl> {
+    let market = &ctx.accounts.market;
+
+    let supply_price = get_asset_price_scaled(
+        &ctx.accounts.supply_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let collateral_price = get_asset_price_scaled(
+        &ctx.accounts.collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let supply_decimals = ctx.accounts.supply_mint.decimals;
+    let collateral_decimals = ctx.accounts.collateral_mint.decimals;
+
+    let tvl = MarketTvl {
+        supply_value: token_value(
+            ctx.accounts.supply_vault.amount as u128,
+            supply_decimals,
+            supply_price,
+        )?,
+        collateral_value: token_value(
+            ctx.accounts.collateral_vault.amount as u128,
+            collateral_decimals,
+            collateral_price,
+        )?,
+        borrowed_value: token_value(market.total_borrows, supply_decimals, supply_price)?,
+    };
+
+    msg!(
+        "Market {} supply value: {}, collateral value: {}, borrowed value: {}",
+        market_id,
+        tvl.supply_value,
+        tvl.collateral_value,
+        tvl.borrowed_value
+    );
+    Ok(tvl)
+}
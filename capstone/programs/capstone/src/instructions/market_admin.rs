@@ -1,18 +1,366 @@
-use crate::contexts::UpdateMarketParams;
+use crate::{
+    contexts::{
+        AcceptMarketAdmin, SetDepositFrozen, SetPortfolioFrozen, SweepSurplusCollateral,
+        TransferMarketAdmin, UpdateMarketConfig, UpdateMarketParams,
+    },
+    utils::{
+        check_collateral_backing, check_max_borrow_rate_bps, update_market_interest,
+        MAX_GRACE_PERIOD_SLOTS, MAX_LIQUIDATION_BONUS_BPS, MAX_ORACLE_STALENESS_SLOTS,
+    },
+    LendingError,
+};
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Transfer};
 
-/// Update market parameters
+/// Update market parameters (market admin only)
 pub fn update_market_params(
     ctx: Context<UpdateMarketParams>,
     new_collateral_factor: u64,
     new_liquidation_threshold: u64,
+    new_grace_period_slots: u64,
+    new_flash_loan_fee_bps: u64,
+    new_max_confidence_bps: u64,
 ) -> Result<()> {
+    require!(
+        new_flash_loan_fee_bps <= 10000,
+        LendingError::InvalidBasisPoints
+    );
+    require!(
+        new_max_confidence_bps <= 10000,
+        LendingError::InvalidBasisPoints
+    );
+    require!(
+        new_grace_period_slots <= MAX_GRACE_PERIOD_SLOTS,
+        LendingError::InvalidGracePeriod
+    );
+
     let market = &mut ctx.accounts.market;
 
     // Update market parameters
     market.collateral_factor = new_collateral_factor;
     market.liquidation_threshold = new_liquidation_threshold;
+    market.grace_period_slots = new_grace_period_slots;
+    market.flash_loan_fee_bps = new_flash_loan_fee_bps;
+    market.max_confidence_bps = new_max_confidence_bps;
 
     msg!("Market parameters updated");
     Ok(())
 }
+
+/// Set the window (in slots after a loan opens) in which the first repayment is interest-free
+pub fn set_first_repayment_grace_slots(
+    ctx: Context<UpdateMarketConfig>,
+    grace_slots: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.first_repayment_grace_slots = grace_slots;
+
+    msg!(
+        "First repayment grace set to {} slots for market {}",
+        grace_slots,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set how many slots after a loan opens accrue no interest (0 disables the window)
+pub fn set_interest_free_slots(
+    ctx: Context<UpdateMarketConfig>,
+    interest_free_slots: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.interest_free_slots = interest_free_slots;
+
+    msg!(
+        "Interest-free window set to {} slots for market {}",
+        interest_free_slots,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the extra collateral (in basis points of the repaid amount) liquidators seize
+pub fn set_liquidation_bonus_bps(
+    ctx: Context<UpdateMarketConfig>,
+    liquidation_bonus_bps: u64,
+) -> Result<()> {
+    require!(
+        liquidation_bonus_bps <= MAX_LIQUIDATION_BONUS_BPS,
+        LendingError::LiquidationBonusTooHigh
+    );
+
+    let market = &mut ctx.accounts.market;
+    market.liquidation_bonus_bps = liquidation_bonus_bps;
+
+    msg!(
+        "Liquidation bonus set to {} bps for market {}",
+        liquidation_bonus_bps,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the ceiling (in basis points per year) the market's borrow rate is clamped to
+pub fn set_max_borrow_rate_bps(
+    ctx: Context<UpdateMarketConfig>,
+    max_borrow_rate_bps: u64,
+) -> Result<()> {
+    check_max_borrow_rate_bps(max_borrow_rate_bps)?;
+
+    let market = &mut ctx.accounts.market;
+    // Book interest up to now at the old ceiling before the new one applies
+    update_market_interest(market)?;
+    market.max_borrow_rate_bps = max_borrow_rate_bps;
+
+    msg!(
+        "Max borrow rate set to {} bps for market {}",
+        max_borrow_rate_bps,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the share of supply deposits (in basis points) kept back from borrowers
+pub fn set_min_liquidity_buffer_bps(
+    ctx: Context<UpdateMarketConfig>,
+    buffer_bps: u64,
+) -> Result<()> {
+    require!(buffer_bps <= 10000, LendingError::InvalidBasisPoints);
+
+    let market = &mut ctx.accounts.market;
+    market.min_liquidity_buffer_bps = buffer_bps;
+
+    msg!(
+        "Minimum liquidity buffer set to {} bps for market {}",
+        buffer_bps,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the residual debt (in supply token units) a repayment may leave and still settle the loan
+pub fn set_dust_threshold(ctx: Context<UpdateMarketConfig>, dust_threshold: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.dust_threshold = dust_threshold;
+
+    msg!(
+        "Dust threshold set to {} for market {}",
+        dust_threshold,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the share of each flash-loan fee (in bps) paid out to the protocol fee recipient
+pub fn set_protocol_fee_share_bps(
+    ctx: Context<UpdateMarketConfig>,
+    protocol_fee_share_bps: u64,
+) -> Result<()> {
+    require!(
+        protocol_fee_share_bps <= 10000,
+        LendingError::InvalidBasisPoints
+    );
+
+    let market = &mut ctx.accounts.market;
+    market.protocol_fee_share_bps = protocol_fee_share_bps;
+
+    msg!(
+        "Protocol fee share set to {} bps for market {}",
+        protocol_fee_share_bps,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the smallest amount (in supply token units) a single supply may deposit
+pub fn set_min_supply(ctx: Context<UpdateMarketConfig>, min_supply: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.min_supply = min_supply;
+
+    msg!(
+        "Minimum supply set to {} for market {}",
+        min_supply,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Freeze or unfreeze a single user's position. A frozen position can't borrow or
+/// withdraw, but can still be repaid.
+pub fn set_deposit_frozen(ctx: Context<SetDepositFrozen>, frozen: bool) -> Result<()> {
+    let user_deposit = &mut ctx.accounts.user_deposit;
+    user_deposit.frozen = frozen;
+
+    msg!(
+        "User deposit of {} in market {} frozen: {}",
+        user_deposit.user,
+        ctx.accounts.market.market_id,
+        frozen
+    );
+    Ok(())
+}
+
+/// Freeze or unfreeze a user's multi-collateral portfolio in this market
+pub fn set_portfolio_frozen(ctx: Context<SetPortfolioFrozen>, frozen: bool) -> Result<()> {
+    let portfolio = &mut ctx.accounts.portfolio;
+    portfolio.frozen = frozen;
+
+    msg!(
+        "Portfolio of {} in market {} frozen: {}",
+        portfolio.user,
+        ctx.accounts.market.market_id,
+        frozen
+    );
+    Ok(())
+}
+
+/// Set how many slots old an oracle price may be before this market rejects it
+pub fn set_max_oracle_staleness_slots(
+    ctx: Context<UpdateMarketConfig>,
+    max_staleness_slots: u64,
+) -> Result<()> {
+    require!(
+        max_staleness_slots > 0 && max_staleness_slots <= MAX_ORACLE_STALENESS_SLOTS,
+        LendingError::InvalidOracleStaleness
+    );
+
+    let market = &mut ctx.accounts.market;
+    market.max_oracle_staleness_slots = max_staleness_slots;
+
+    msg!(
+        "Max oracle staleness set to {} slots for market {}",
+        max_staleness_slots,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Set the band the collateral/borrow oracle price ratio must stay within for borrows
+pub fn set_price_ratio_band(
+    ctx: Context<UpdateMarketConfig>,
+    min_ratio_bps: u64,
+    max_ratio_bps: u64,
+) -> Result<()> {
+    require!(
+        min_ratio_bps <= max_ratio_bps,
+        LendingError::InvalidPriceRatioBand
+    );
+
+    let market = &mut ctx.accounts.market;
+    market.min_price_ratio_bps = min_ratio_bps;
+    market.max_price_ratio_bps = max_ratio_bps;
+
+    msg!(
+        "Price ratio band set to [{}, {}] bps for market {}",
+        min_ratio_bps,
+        max_ratio_bps,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Configure the supply loyalty bonus: positions held past `threshold_slots` earn up to
+/// `bonus_bps` extra on withdrawal, paid from reserves
+pub fn set_loyalty_bonus(
+    ctx: Context<UpdateMarketConfig>,
+    threshold_slots: u64,
+    bonus_bps: u64,
+) -> Result<()> {
+    require!(bonus_bps <= 10000, LendingError::InvalidBasisPoints);
+
+    let market = &mut ctx.accounts.market;
+    market.loyalty_threshold_slots = threshold_slots;
+    market.loyalty_bonus_bps = bonus_bps;
+
+    msg!(
+        "Loyalty bonus set to {} bps after {} slots for market {}",
+        bonus_bps,
+        threshold_slots,
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Enable or disable flash loans for a market
+pub fn set_flash_loans_enabled(ctx: Context<UpdateMarketConfig>, enabled: bool) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.flash_loans_enabled = enabled;
+
+    msg!(
+        "Flash loans {} for market {}",
+        if enabled { "enabled" } else { "disabled" },
+        market.market_id
+    );
+    Ok(())
+}
+
+/// Propose a new market admin; the transfer completes once they accept
+pub fn transfer_market_admin(ctx: Context<TransferMarketAdmin>, new_admin: Pubkey) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.pending_admin = new_admin;
+
+    msg!(
+        "Market {} admin transfer proposed to {}",
+        market.market_id,
+        new_admin
+    );
+    Ok(())
+}
+
+/// Accept a pending market admin transfer
+pub fn accept_market_admin(ctx: Context<AcceptMarketAdmin>) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    market.market_admin = market.pending_admin;
+    market.pending_admin = Pubkey::default();
+
+    msg!(
+        "Market {} admin transferred to {}",
+        market.market_id,
+        market.market_admin
+    );
+    Ok(())
+}
+
+/// Move collateral the vault holds beyond `total_collateral_deposits` (e.g. direct sends)
+/// to the admin's destination account
+pub fn sweep_surplus_collateral(
+    ctx: Context<SweepSurplusCollateral>,
+    market_id: u64,
+) -> Result<()> {
+    let market = &ctx.accounts.market;
+    let vault_amount = ctx.accounts.collateral_vault.amount;
+    check_collateral_backing(vault_amount, market.total_collateral_deposits)?;
+    // Backing check above guarantees the surplus fits in u64
+    let surplus = (vault_amount as u128 - market.total_collateral_deposits) as u64;
+    if surplus == 0 {
+        msg!("No surplus collateral to sweep");
+        return Ok(());
+    }
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market".as_ref(),
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.collateral_vault.to_account_info(),
+        to: ctx.accounts.destination.to_account_info(),
+        authority: ctx.accounts.market.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token_interface::transfer(cpi_ctx, surplus)?;
+
+    msg!("Swept {} surplus collateral", surplus);
+    Ok(())
+}
@@ -1,23 +1,31 @@
+pub mod audit;
 pub mod borrow;
 pub mod flash_loan;
+pub mod health;
 pub mod liquidate;
 pub mod market;
 pub mod market_admin;
 pub mod oracle;
+pub mod portfolio;
 pub mod protocol;
 pub mod repay;
+pub mod reserves;
 pub mod supply;
 pub mod user_deposit;
 pub mod withdraw;
 
+pub use audit::*;
 pub use borrow::*;
 pub use flash_loan::*;
+pub use health::*;
 pub use liquidate::*;
 pub use market::*;
 pub use market_admin::*;
 pub use oracle::*;
+pub use portfolio::*;
 pub use protocol::*;
 pub use repay::*;
+pub use reserves::*;
 pub use supply::*;
 pub use user_deposit::*;
 pub use withdraw::*;
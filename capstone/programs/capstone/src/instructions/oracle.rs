@@ -1,13 +1,28 @@
-use crate::contexts::{CreateOracle, UpdateOraclePrice};
+use crate::{
+    contexts::{CreateOracle, GetOracleStats, UpdateOraclePrice, UpdateOraclePricesBatch},
+    utils::{
+        max_confidence_interval, price_deviation_bps, DEFAULT_MAX_ORACLE_STALENESS_SLOTS,
+        MAX_ORACLE_DECIMALS, ORACLE_CONFIDENCE_BPS,
+    },
+    LendingError, Oracle, OraclePriceOverrideEvent, OracleStatsEvent,
+};
 use anchor_lang::prelude::*;
 
-/// Initialize a new Oracle account
+/// Initialize a new Oracle account. Later price updates must be at least
+/// `min_update_interval_slots` apart; 0 allows one every slot.
 pub fn create_oracle(
     ctx: Context<CreateOracle>,
     source: Vec<u8>,
     initial_price: u64,
     decimals: u8,
+    min_update_interval_slots: u64,
 ) -> Result<()> {
+    require!(initial_price > 0, LendingError::InvalidOracleData);
+    require!(
+        decimals <= MAX_ORACLE_DECIMALS,
+        LendingError::InvalidOracleDecimals
+    );
+
     let oracle = &mut ctx.accounts.oracle;
 
     oracle.mint = ctx.accounts.mint.key();
@@ -15,23 +30,32 @@ pub fn create_oracle(
     oracle.price = initial_price as u128;
     oracle.decimals = decimals;
     oracle.valid_slot = Clock::get()?.slot;
-    oracle.confidence = 5; // Initial confidence set to 5%
+    oracle.confidence = max_confidence_interval(initial_price as u128, ORACLE_CONFIDENCE_BPS)?;
     oracle.authority = ctx.accounts.authority.key();
     oracle.bump = ctx.bumps.oracle;
+    oracle.update_count = 0;
+    oracle.max_price_deviation_bps = 0;
+    oracle.deviation_window_slots = 0;
+    oracle.min_update_interval_slots = min_update_interval_slots;
 
     Ok(())
 }
 
 /// Update oracle price - FOR TESTING/CAPSTONE PURPOSES ONLY [CAPSTONE_SAFE]
 /// In production, this would be done by authorized price feeds like Pyth/Switchboard
-pub fn update_oracle_price(ctx: Context<UpdateOraclePrice>, new_price: u64) -> Result<()> {
+/// `admin_override` lets the authority push a price outside the deviation band.
+pub fn update_oracle_price(
+    ctx: Context<UpdateOraclePrice>,
+    new_price: u64,
+    admin_override: bool,
+) -> Result<()> {
+    let oracle_key = ctx.accounts.oracle.key();
     let oracle = &mut ctx.accounts.oracle;
     let current_slot = Clock::get()?.slot;
 
-    // Update price and timestamp
-    oracle.price = new_price as u128;
-    oracle.valid_slot = current_slot;
-    oracle.confidence = (new_price / 100) as u128; // 1% confidence interval
+    check_update_interval(oracle_key, oracle, current_slot)?;
+    check_price_band(oracle_key, oracle, new_price, current_slot, admin_override)?;
+    apply_price_update(oracle, new_price, current_slot)?;
 
     msg!(
         "Oracle price updated to: {} at slot: {}",
@@ -42,3 +66,168 @@ pub fn update_oracle_price(ctx: Context<UpdateOraclePrice>, new_price: u64) -> R
     msg!("In production, oracle updates would come from authorized price feeds!");
     Ok(())
 }
+
+/// Update several oracles in one transaction. Oracles are passed writable in
+/// `remaining_accounts`, in the same order as `prices`; if any of them isn't
+/// owned by the signing authority the whole batch reverts.
+pub fn update_oracle_prices_batch<'info>(
+    ctx: Context<'_, '_, 'info, 'info, UpdateOraclePricesBatch<'info>>,
+    prices: Vec<u64>,
+) -> Result<()> {
+    require!(
+        prices.len() == ctx.remaining_accounts.len(),
+        LendingError::OraclePriceCountMismatch
+    );
+    let authority = ctx.accounts.authority.key();
+    let current_slot = Clock::get()?.slot;
+
+    for (oracle_info, &new_price) in ctx.remaining_accounts.iter().zip(prices.iter()) {
+        // Same checks `UpdateOraclePrice` applies: program-owned oracle PDA with has_one = authority
+        let mut oracle = Account::<Oracle>::try_from(oracle_info)?;
+        require!(oracle_info.is_writable, LendingError::InvalidOracleData);
+        let expected = Pubkey::create_program_address(
+            &[b"oracle", oracle.mint.as_ref(), &[oracle.bump]],
+            ctx.program_id,
+        )
+        .map_err(|_| LendingError::InvalidPDA)?;
+        require_keys_eq!(oracle_info.key(), expected, LendingError::InvalidPDA);
+        require_keys_eq!(oracle.authority, authority, LendingError::Unauthorized);
+
+        // Batches never override the deviation band
+        check_update_interval(oracle_info.key(), &oracle, current_slot)?;
+        check_price_band(oracle_info.key(), &oracle, new_price, current_slot, false)?;
+        apply_price_update(&mut oracle, new_price, current_slot)?;
+        oracle.exit(ctx.program_id)?;
+
+        msg!(
+            "Oracle {} price updated to: {}",
+            oracle_info.key(),
+            new_price
+        );
+    }
+
+    msg!(
+        "Batch updated {} oracles at slot: {}",
+        prices.len(),
+        current_slot
+    );
+    Ok(())
+}
+
+/// Configure the deviation band applied to updates that land within
+/// `deviation_window_slots` of the previous one. A zero bps band disables the check.
+pub fn set_oracle_circuit_breaker(
+    ctx: Context<UpdateOraclePrice>,
+    max_price_deviation_bps: u64,
+    deviation_window_slots: u64,
+) -> Result<()> {
+    require!(
+        max_price_deviation_bps <= 10000,
+        LendingError::InvalidBasisPoints
+    );
+
+    let oracle = &mut ctx.accounts.oracle;
+    oracle.max_price_deviation_bps = max_price_deviation_bps;
+    oracle.deviation_window_slots = deviation_window_slots;
+
+    msg!(
+        "Oracle circuit breaker set to {} bps within {} slots",
+        max_price_deviation_bps,
+        deviation_window_slots
+    );
+    Ok(())
+}
+
+/// Reject an update that lands within the oracle's update interval of the previous one,
+/// unless the price has gone stale, so a long interval never pins an outdated price
+fn check_update_interval(oracle_key: Pubkey, oracle: &Oracle, current_slot: u64) -> Result<()> {
+    let slots_since_update = current_slot.saturating_sub(oracle.valid_slot);
+    if slots_since_update >= oracle.min_update_interval_slots
+        || !oracle.is_valid(current_slot, DEFAULT_MAX_ORACLE_STALENESS_SLOTS)
+    {
+        return Ok(());
+    }
+
+    msg!(
+        "Oracle {} updated {} slots ago, interval is {} slots",
+        oracle_key,
+        slots_since_update,
+        oracle.min_update_interval_slots
+    );
+    Err(LendingError::OracleUpdateTooFrequent.into())
+}
+
+/// Reject a price that moves beyond the oracle's deviation band too soon after the
+/// previous update, unless the authority explicitly overrides it
+fn check_price_band(
+    oracle_key: Pubkey,
+    oracle: &Oracle,
+    new_price: u64,
+    current_slot: u64,
+    admin_override: bool,
+) -> Result<()> {
+    let slots_since_update = current_slot.saturating_sub(oracle.valid_slot);
+    if oracle.max_price_deviation_bps == 0
+        || oracle.price == 0
+        || slots_since_update > oracle.deviation_window_slots
+    {
+        return Ok(());
+    }
+
+    let deviation_bps = price_deviation_bps(oracle.price, new_price as u128)?;
+    if deviation_bps <= oracle.max_price_deviation_bps as u128 {
+        return Ok(());
+    }
+
+    if !admin_override {
+        msg!(
+            "Price moved {} bps in {} slots, band is {} bps",
+            deviation_bps,
+            slots_since_update,
+            oracle.max_price_deviation_bps
+        );
+        return Err(LendingError::PriceDeviationTooLarge.into());
+    }
+
+    emit!(OraclePriceOverrideEvent {
+        oracle: oracle_key,
+        previous_price: oracle.price,
+        new_price: new_price as u128,
+        deviation_bps,
+    });
+    Ok(())
+}
+
+/// Write a new price, refresh its slot and confidence, and count the update
+fn apply_price_update(oracle: &mut Oracle, new_price: u64, current_slot: u64) -> Result<()> {
+    oracle.price = new_price as u128;
+    oracle.valid_slot = current_slot;
+    // A fraction of the price in the oracle's own units, so it holds at any decimals
+    oracle.confidence = max_confidence_interval(new_price as u128, ORACLE_CONFIDENCE_BPS)?;
+    oracle.update_count = oracle
+        .update_count
+        .checked_add(1)
+        .ok_or(LendingError::MathOverflow)?;
+    Ok(())
+}
+
+/// Emit how often an oracle has been updated and how fresh its price is
+pub fn get_oracle_stats(ctx: Context<GetOracleStats>) -> Result<()> {
+    let oracle = &ctx.accounts.oracle;
+    let current_slot = Clock::get()?.slot;
+
+    emit!(OracleStatsEvent {
+        oracle: oracle.key(),
+        update_count: oracle.update_count,
+        last_update_slot: oracle.valid_slot,
+        slots_since_update: current_slot.saturating_sub(oracle.valid_slot),
+    });
+
+    msg!(
+        "Oracle {} updated {} times, last at slot {}",
+        oracle.key(),
+        oracle.update_count,
+        oracle.valid_slot
+    );
+    Ok(())
+}
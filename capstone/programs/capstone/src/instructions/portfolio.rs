@@ -0,0 +1,498 @@
+use crate::{
+    contexts::{
+        AddPortfolioCollateral, DepositPortfolioCollateral, InitializePortfolio,
+        LiquidatePortfolio, PortfolioBorrow, WithdrawPortfolioCollateral,
+    },
+    events::BadDebtEvent,
+    utils::{
+        absorb_bad_debt, book_borrow_interest, borrow_rate_per_slot, calculate_collateral_to_seize,
+        compound_interest, get_asset_price_scaled, is_healthy, to_common_units,
+        update_market_interest, PRICE_DECIMALS,
+    },
+    CollateralEntry, LendingError, Market, UserPortfolio,
+};
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Transfer};
+use std::cmp;
+
+/// Create an empty multi-collateral portfolio for the caller in this market
+pub fn initialize_portfolio(ctx: Context<InitializePortfolio>, _market_id: u64) -> Result<()> {
+    let portfolio = &mut ctx.accounts.portfolio;
+    portfolio.user = ctx.accounts.user.key();
+    portfolio.market = ctx.accounts.market.key();
+    portfolio.borrowed_amount = 0;
+    portfolio.last_update_slot = Clock::get()?.slot;
+    portfolio.bump = ctx.bumps.portfolio;
+    portfolio.collaterals = Vec::new();
+    portfolio.frozen = false;
+
+    msg!("Portfolio initialized for {}", portfolio.user);
+    Ok(())
+}
+
+/// Register a new collateral asset in the portfolio and create its vault
+pub fn add_portfolio_collateral(
+    ctx: Context<AddPortfolioCollateral>,
+    _market_id: u64,
+) -> Result<()> {
+    let portfolio = &mut ctx.accounts.portfolio;
+    let mint = ctx.accounts.asset_mint.key();
+
+    require!(
+        portfolio.collaterals.len() < UserPortfolio::MAX_COLLATERALS,
+        LendingError::PortfolioFull
+    );
+    require!(
+        portfolio.entry_index(&mint).is_none(),
+        LendingError::CollateralAlreadyInPortfolio
+    );
+
+    portfolio.collaterals.push(CollateralEntry {
+        mint,
+        amount: 0,
+        oracle: ctx.accounts.asset_oracle.key(),
+        decimals: ctx.accounts.asset_mint.decimals,
+    });
+
+    msg!("Added collateral {} to portfolio", mint);
+    Ok(())
+}
+
+/// Post collateral of an asset already registered in the portfolio
+pub fn deposit_portfolio_collateral(
+    ctx: Context<DepositPortfolioCollateral>,
+    _market_id: u64,
+    amount: u64,
+) -> Result<()> {
+    let index = ctx
+        .accounts
+        .portfolio
+        .entry_index(&ctx.accounts.asset_mint.key())
+        .ok_or(LendingError::CollateralNotInPortfolio)?;
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.user_asset_account.to_account_info(),
+        to: ctx.accounts.asset_vault.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token_interface::transfer(cpi_ctx, amount)?;
+
+    let entry = &mut ctx.accounts.portfolio.collaterals[index];
+    entry.amount = entry
+        .amount
+        .checked_add(amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!("Deposited {} of {} into portfolio", amount, entry.mint);
+    Ok(())
+}
+
+/// Borrow supply tokens against the combined value of every portfolio collateral.
+/// Expects one oracle per collateral entry in `remaining_accounts`, in entry order.
+pub fn borrow_against_portfolio<'info>(
+    ctx: Context<'_, '_, 'info, 'info, PortfolioBorrow<'info>>,
+    market_id: u64,
+    borrow_amount: u64,
+) -> Result<()> {
+    require!(
+        !ctx.accounts.protocol_state.borrows_paused,
+        LendingError::MarketPaused
+    );
+
+    let market_account_info = ctx.accounts.market.to_account_info();
+    let market = &mut ctx.accounts.market;
+    let portfolio = &mut ctx.accounts.portfolio;
+
+    // Same gates as single-collateral borrows
+    require!(market.is_active, LendingError::MarketNotActive);
+    require!(!portfolio.frozen, LendingError::DepositFrozen);
+
+    accrue_portfolio_debt(market, portfolio)?;
+
+    let total_collateral_value =
+        portfolio_collateral_value(market, portfolio, ctx.remaining_accounts)?;
+
+    let borrow_amount_u128 = borrow_amount as u128;
+    let new_total_borrowed = portfolio
+        .borrowed_amount
+        .checked_add(borrow_amount_u128)
+        .ok_or(LendingError::MathOverflow)?;
+    require_within_borrow_limit(
+        market,
+        total_collateral_value,
+        new_total_borrowed,
+        &ctx.accounts.borrow_oracle,
+    )?;
+
+    // Same liquidity rules as single-collateral borrows
+    let liquidity_buffer = market
+        .total_supply_deposits
+        .checked_mul(market.min_liquidity_buffer_bps as u128)
+        .and_then(|v| v.checked_div(10000))
+        .ok_or(LendingError::MathOverflow)?;
+    let available_liquidity = market
+        .total_supply_deposits
+        .checked_sub(market.total_borrows)
+        .and_then(|v| v.checked_sub(liquidity_buffer))
+        .unwrap_or(0);
+    require!(
+        borrow_amount_u128 <= available_liquidity,
+        LendingError::InsufficientLiquidity
+    );
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market".as_ref(),
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.supply_vault.to_account_info(),
+        to: ctx.accounts.user_supply_account.to_account_info(),
+        authority: market_account_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token_interface::transfer(cpi_ctx, borrow_amount)?;
+
+    portfolio.borrowed_amount = new_total_borrowed;
+    market.total_borrows = market
+        .total_borrows
+        .checked_add(borrow_amount_u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!(
+        "Portfolio borrow successful: {} supply tokens against {} collateral assets",
+        borrow_amount,
+        portfolio.collaterals.len()
+    );
+    Ok(())
+}
+
+/// Repay portfolio debt (capped at what is owed)
+pub fn repay_portfolio(ctx: Context<PortfolioBorrow>, _market_id: u64, amount: u64) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let portfolio = &mut ctx.accounts.portfolio;
+
+    accrue_portfolio_debt(market, portfolio)?;
+
+    let repay_amount_u128 = cmp::min(amount as u128, portfolio.borrowed_amount);
+    require!(
+        repay_amount_u128 <= u64::MAX as u128,
+        LendingError::MathOverflow
+    );
+    let repay_amount = repay_amount_u128 as u64;
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.user_supply_account.to_account_info(),
+        to: ctx.accounts.supply_vault.to_account_info(),
+        authority: ctx.accounts.user.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token_interface::transfer(cpi_ctx, repay_amount)?;
+
+    portfolio.borrowed_amount -= repay_amount_u128;
+    market.total_borrows = market
+        .total_borrows
+        .checked_sub(repay_amount_u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!("Portfolio repay successful: {} tokens", repay_amount);
+    Ok(())
+}
+
+/// Withdraw collateral from the portfolio. While debt is open the combined borrow limit
+/// is re-checked without the withdrawn amount, with one oracle per collateral entry in
+/// `remaining_accounts`, in entry order.
+pub fn withdraw_portfolio_collateral<'info>(
+    ctx: Context<'_, '_, 'info, 'info, WithdrawPortfolioCollateral<'info>>,
+    market_id: u64,
+    amount: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let portfolio = &mut ctx.accounts.portfolio;
+
+    require!(!portfolio.frozen, LendingError::DepositFrozen);
+
+    let index = portfolio
+        .entry_index(&ctx.accounts.asset_mint.key())
+        .ok_or(LendingError::CollateralNotInPortfolio)?;
+    let entry = &mut portfolio.collaterals[index];
+    entry.amount = entry
+        .amount
+        .checked_sub(amount as u128)
+        .ok_or(LendingError::InsufficientBalance)?;
+
+    accrue_portfolio_debt(market, portfolio)?;
+
+    // Debt-free portfolios withdraw without reading any oracle
+    if portfolio.borrowed_amount > 0 {
+        let total_collateral_value =
+            portfolio_collateral_value(market, portfolio, ctx.remaining_accounts)?;
+        require_within_borrow_limit(
+            market,
+            total_collateral_value,
+            portfolio.borrowed_amount,
+            &ctx.accounts.borrow_oracle,
+        )?;
+    }
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market".as_ref(),
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.asset_vault.to_account_info(),
+        to: ctx.accounts.user_asset_account.to_account_info(),
+        authority: ctx.accounts.market.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token_interface::transfer(cpi_ctx, amount)?;
+
+    msg!(
+        "Withdrew {} of {} from portfolio",
+        amount,
+        ctx.accounts.asset_mint.key()
+    );
+    Ok(())
+}
+
+/// Liquidate a portfolio whose debt exceeds its combined collateral at the liquidation
+/// threshold: the liquidator repays `repay_amount` and seizes `asset_mint` collateral
+/// worth it plus the liquidation bonus. Expects one oracle per collateral entry in
+/// `remaining_accounts`, in entry order. `min_collateral_out` of 0 disables the slippage check.
+pub fn liquidate_portfolio<'info>(
+    ctx: Context<'_, '_, 'info, 'info, LiquidatePortfolio<'info>>,
+    market_id: u64,
+    repay_amount: u64,
+    min_collateral_out: u64,
+) -> Result<()> {
+    let market = &mut ctx.accounts.market;
+    let portfolio = &mut ctx.accounts.portfolio;
+
+    accrue_portfolio_debt(market, portfolio)?;
+
+    let collateral_value = portfolio_collateral_value(market, portfolio, ctx.remaining_accounts)?;
+    let borrow_price = get_asset_price_scaled(
+        &ctx.accounts.borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let borrow_value = to_common_units(portfolio.borrowed_amount, market.supply_decimals)?
+        .checked_mul(borrow_price)
+        .ok_or(LendingError::MathOverflow)?;
+    require!(
+        !is_healthy(collateral_value, borrow_value, market.liquidation_threshold),
+        LendingError::PositionHealthy
+    );
+    require!(
+        (repay_amount as u128) <= portfolio.borrowed_amount,
+        LendingError::ExcessiveLiquidation
+    );
+
+    // Seize from the chosen entry, priced by its own oracle (validated above)
+    let index = portfolio
+        .entry_index(&ctx.accounts.asset_mint.key())
+        .ok_or(LendingError::CollateralNotInPortfolio)?;
+    let entry = &portfolio.collaterals[index];
+    let asset_price = get_asset_price_scaled(
+        &ctx.remaining_accounts[index],
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let collateral_to_seize = calculate_collateral_to_seize(
+        repay_amount,
+        market.liquidation_bonus_bps,
+        borrow_price,
+        asset_price,
+        market.supply_decimals,
+        entry.decimals,
+    )?;
+    // Can't seize more of the asset than the portfolio holds
+    let collateral_to_seize = u64::try_from(collateral_to_seize.min(entry.amount))
+        .map_err(|_| LendingError::MathOverflow)?;
+    if collateral_to_seize < min_collateral_out {
+        msg!(
+            "Liquidation would seize {} collateral, below the minimum {}",
+            collateral_to_seize,
+            min_collateral_out
+        );
+        return Err(LendingError::SlippageExceeded.into());
+    }
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.liquidator_supply_account.to_account_info(),
+        to: ctx.accounts.supply_vault.to_account_info(),
+        authority: ctx.accounts.liquidator.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token_interface::transfer(cpi_ctx, repay_amount)?;
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market".as_ref(),
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.asset_vault.to_account_info(),
+        to: ctx.accounts.liquidator_asset_account.to_account_info(),
+        authority: market.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token_interface::transfer(cpi_ctx, collateral_to_seize)?;
+
+    let entry = &mut portfolio.collaterals[index];
+    entry.amount = entry
+        .amount
+        .checked_sub(collateral_to_seize as u128)
+        .ok_or(LendingError::MathOverflow)?;
+    portfolio.borrowed_amount = portfolio
+        .borrowed_amount
+        .checked_sub(repay_amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+    market.total_borrows = market
+        .total_borrows
+        .checked_sub(repay_amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!(
+        "Portfolio liquidation successful: {} debt → {} of {}",
+        repay_amount,
+        collateral_to_seize,
+        ctx.accounts.asset_mint.key()
+    );
+
+    // Nothing left behind the remaining debt: write it off like a single-collateral
+    // liquidation, reserves first, then suppliers
+    let collateral_left = portfolio.collaterals.iter().any(|entry| entry.amount > 0);
+    if !collateral_left && portfolio.borrowed_amount > 0 {
+        let shortfall = portfolio.borrowed_amount;
+        let (covered_by_reserves, socialized) = absorb_bad_debt(market, shortfall)?;
+        portfolio.borrowed_amount = 0;
+
+        emit!(BadDebtEvent {
+            market_id,
+            borrower: portfolio.user,
+            shortfall,
+            covered_by_reserves,
+            socialized,
+        });
+    }
+    Ok(())
+}
+
+/// Fail with `InsufficientCollateral` unless `debt` fits under the collateral factor
+/// applied to `total_collateral_value`
+fn require_within_borrow_limit(
+    market: &Market,
+    total_collateral_value: u128,
+    debt: u128,
+    borrow_oracle: &AccountInfo,
+) -> Result<()> {
+    let borrow_price = get_asset_price_scaled(
+        borrow_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+
+    let max_borrow_value = total_collateral_value
+        .checked_mul(market.collateral_factor as u128)
+        .and_then(|v| v.checked_div(10000))
+        .ok_or(LendingError::MathOverflow)?;
+    let borrow_value = to_common_units(debt, market.supply_decimals)?
+        .checked_mul(borrow_price)
+        .ok_or(LendingError::MathOverflow)?;
+
+    require!(
+        borrow_value <= max_borrow_value,
+        LendingError::InsufficientCollateral
+    );
+    Ok(())
+}
+
+/// Combined value of every portfolio collateral, in common units times a PRICE_DECIMALS
+/// price like `borrow`. Expects one oracle per entry in `oracles`, in entry order.
+fn portfolio_collateral_value(
+    market: &Market,
+    portfolio: &UserPortfolio,
+    oracles: &[AccountInfo],
+) -> Result<u128> {
+    require!(
+        oracles.len() == portfolio.collaterals.len(),
+        LendingError::InvalidOracleData
+    );
+
+    let mut total_collateral_value: u128 = 0;
+    for (entry, oracle) in portfolio.collaterals.iter().zip(oracles) {
+        require_keys_eq!(oracle.key(), entry.oracle, LendingError::InvalidOracleData);
+        let price = get_asset_price_scaled(
+            oracle,
+            PRICE_DECIMALS,
+            market.max_oracle_staleness_slots,
+            market.max_confidence_bps,
+        )?;
+        let value = to_common_units(entry.amount, entry.decimals)?
+            .checked_mul(price)
+            .ok_or(LendingError::MathOverflow)?;
+        total_collateral_value = total_collateral_value
+            .checked_add(value)
+            .ok_or(LendingError::MathOverflow)?;
+    }
+    Ok(total_collateral_value)
+}
+
+/// Accrue the market and compound the portfolio's debt since its last update, booking
+/// the interest on the market like `accrue_interest` does for single-collateral debt
+fn accrue_portfolio_debt(market: &mut Market, portfolio: &mut UserPortfolio) -> Result<()> {
+    update_market_interest(market)?;
+
+    let current_slot = Clock::get()?.slot;
+    if portfolio.borrowed_amount > 0 {
+        let slots_elapsed = current_slot.saturating_sub(portfolio.last_update_slot);
+        let accrued_debt = compound_interest(
+            portfolio.borrowed_amount,
+            borrow_rate_per_slot(market),
+            slots_elapsed,
+        )?;
+        book_borrow_interest(market, accrued_debt - portfolio.borrowed_amount)?;
+        portfolio.borrowed_amount = accrued_debt;
+    }
+    portfolio.last_update_slot = current_slot;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::contexts::InitializeProtocol;
+use crate::contexts::{InitializeProtocol, ProtocolAdmin};
 use anchor_lang::prelude::*;
 
 /// Initialize the global lending protocol state
@@ -6,7 +6,10 @@ pub fn initialize_protocol(ctx: Context<InitializeProtocol>) -> Result<()> {
     let protocol_state = &mut ctx.accounts.protocol_state;
     protocol_state.admin = ctx.accounts.admin.key();
     protocol_state.total_markets = 0;
+    protocol_state.next_market_id = 0;
     protocol_state.is_paused = false;
+    protocol_state.borrows_paused = false;
+    protocol_state.fee_recipient = ctx.accounts.admin.key();
     protocol_state.bump = ctx.bumps.protocol_state;
 
     msg!(
@@ -15,3 +18,47 @@ pub fn initialize_protocol(ctx: Context<InitializeProtocol>) -> Result<()> {
     );
     Ok(())
 }
+
+/// Hand protocol admin rights to a new key
+pub fn transfer_protocol_admin(ctx: Context<ProtocolAdmin>, new_admin: Pubkey) -> Result<()> {
+    let protocol_state = &mut ctx.accounts.protocol_state;
+    let old_admin = protocol_state.admin;
+    protocol_state.admin = new_admin;
+
+    msg!(
+        "Protocol admin transferred from {} to {}",
+        old_admin,
+        new_admin
+    );
+    Ok(())
+}
+
+/// Route protocol fees to token accounts owned by `fee_recipient` (admin only)
+pub fn set_fee_recipient(ctx: Context<ProtocolAdmin>, fee_recipient: Pubkey) -> Result<()> {
+    let protocol_state = &mut ctx.accounts.protocol_state;
+    protocol_state.fee_recipient = fee_recipient;
+
+    msg!("Protocol fee recipient set to {}", fee_recipient);
+    Ok(())
+}
+
+/// Pause or unpause the protocol (admin only)
+pub fn set_protocol_paused(ctx: Context<ProtocolAdmin>, paused: bool) -> Result<()> {
+    let protocol_state = &mut ctx.accounts.protocol_state;
+    protocol_state.is_paused = paused;
+
+    msg!("Protocol {}", if paused { "paused" } else { "unpaused" });
+    Ok(())
+}
+
+/// Pause or unpause new borrows across every market (admin only)
+pub fn set_borrows_paused(ctx: Context<ProtocolAdmin>, paused: bool) -> Result<()> {
+    let protocol_state = &mut ctx.accounts.protocol_state;
+    protocol_state.borrows_paused = paused;
+
+    msg!(
+        "Protocol borrows {}",
+        if paused { "paused" } else { "unpaused" }
+    );
+    Ok(())
+}
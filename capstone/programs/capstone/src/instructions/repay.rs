@@ -1,63 +1,125 @@
 use crate::{
     contexts::Repay,
-    utils::{update_market_interest, SCALING_FACTOR},
-    LendingError,
+    utils::{accrue_interest, in_first_repayment_grace},
+    BorrowAccountingMismatchEvent, LendingError, Market, RepayAllEvent, UserDeposit,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{self, Transfer};
 use std::cmp;
 
 /// Repay borrowed tokens
-pub fn repay(ctx: Context<Repay>, _market_id: u64, amount: u64) -> Result<()> {
-    let market = &mut ctx.accounts.market;
-    let user_deposit = &mut ctx.accounts.user_deposit;
-
-    update_market_interest(market)?;
-
-    // Apply simple interest to existing debt (2% annual rate)
-    if user_deposit.borrowed_amount > 0 {
-        let current_slot = Clock::get()?.slot;
-        let slots_elapsed = current_slot.saturating_sub(user_deposit.last_update_slot);
-        let interest_rate_per_slot = 25u128; // ~2% annual / 800,000 slots
-        let slots_elapsed_u128 = slots_elapsed as u128;
-        let interest_increment = user_deposit
-            .borrowed_amount
-            .saturating_mul(interest_rate_per_slot)
-            .saturating_mul(slots_elapsed_u128)
-            / SCALING_FACTOR; // Scale down
-
-        user_deposit.borrowed_amount = user_deposit
-            .borrowed_amount
-            .saturating_add(interest_increment);
-        user_deposit.last_update_slot = current_slot;
-    }
+pub fn repay(ctx: Context<Repay>, market_id: u64, amount: u64) -> Result<()> {
+    accrue_user_debt(&mut ctx.accounts.market, &mut ctx.accounts.user_deposit)?;
 
-    let repay_amount_u128 = cmp::min(amount as u128, user_deposit.borrowed_amount);
+    let repay_amount_u128 = cmp::min(amount as u128, ctx.accounts.user_deposit.borrowed_amount);
     require!(
         repay_amount_u128 <= u64::MAX as u128,
         LendingError::MathOverflow
     );
     let repay_amount = repay_amount_u128 as u64;
+    // Fail with a clear error here rather than an opaque one from the token program
+    let balance = ctx.accounts.user_supply_account.amount;
+    if balance < repay_amount {
+        msg!(
+            "Repaying {} tokens but the account only holds {}",
+            repay_amount,
+            balance
+        );
+        return Err(LendingError::InsufficientBalance.into());
+    }
+
+    apply_repayment(ctx.accounts, market_id, repay_amount)?;
+
+    msg!("Repay successful: {} tokens", repay_amount);
+    Ok(())
+}
+
+/// Repay the exact outstanding debt, including interest accrued up to this slot
+pub fn repay_all(ctx: Context<Repay>, market_id: u64) -> Result<()> {
+    accrue_user_debt(&mut ctx.accounts.market, &mut ctx.accounts.user_deposit)?;
+
+    let debt = ctx.accounts.user_deposit.borrowed_amount;
+    require!(debt <= u64::MAX as u128, LendingError::MathOverflow);
+    let repay_amount = debt as u64;
+    require!(
+        ctx.accounts.user_supply_account.amount >= repay_amount,
+        LendingError::InsufficientBalance
+    );
+
+    apply_repayment(ctx.accounts, market_id, repay_amount)?;
+
+    emit!(RepayAllEvent {
+        market_id,
+        user: ctx.accounts.user.key(),
+        amount: repay_amount,
+    });
+
+    msg!("Repaid all debt: {} tokens", repay_amount);
+    Ok(())
+}
+
+/// Bring the user's debt up to date with interest accrued since their last update
+fn accrue_user_debt(market: &mut Market, user_deposit: &mut UserDeposit) -> Result<()> {
+    let current_slot = Clock::get()?.slot;
+
+    // Interest accrued so far is waived, the first repayment only pays principal
+    if in_first_repayment_grace(market, user_deposit, current_slot) {
+        user_deposit.last_update_slot = current_slot;
+    }
+
+    // Apply compound interest to existing debt (2% annual rate)
+    accrue_interest(market, user_deposit)
+}
 
+/// Move `repay_amount` into the supply vault and take it off the user's and market's debt
+fn apply_repayment(accounts: &mut Repay, market_id: u64, repay_amount: u64) -> Result<()> {
     // Transfer supply tokens from user to supply vault
     let cpi_accounts = Transfer {
-        from: ctx.accounts.user_supply_account.to_account_info(),
-        to: ctx.accounts.supply_vault.to_account_info(),
-        authority: ctx.accounts.user.to_account_info(),
+        from: accounts.user_supply_account.to_account_info(),
+        to: accounts.supply_vault.to_account_info(),
+        authority: accounts.user.to_account_info(),
     };
-    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    let cpi_ctx = CpiContext::new(accounts.token_program.to_account_info(), cpi_accounts);
     token_interface::transfer(cpi_ctx, repay_amount)?;
 
+    let market = &mut accounts.market;
+    let user_deposit = &mut accounts.user_deposit;
+
     // Update balances
     user_deposit.borrowed_amount = user_deposit
         .borrowed_amount
         .checked_sub(repay_amount as u128)
         .ok_or(LendingError::MathOverflow)?;
-    market.total_borrows = market
-        .total_borrows
-        .checked_sub(repay_amount as u128)
-        .ok_or(LendingError::MathOverflow)?;
+    // Debt that grew without being booked on the market (e.g. before interest was
+    // realized into total_borrows) can exceed it: clamp at zero and report it instead of failing
+    if market.total_borrows < repay_amount as u128 {
+        msg!(
+            "Warning: repaying {} exceeds market total borrows {}, clamping to zero",
+            repay_amount,
+            market.total_borrows
+        );
+        emit!(BorrowAccountingMismatchEvent {
+            market_id,
+            user: accounts.user.key(),
+            repay_amount,
+            market_total_borrows: market.total_borrows,
+        });
+    }
+    market.total_borrows = market.total_borrows.saturating_sub(repay_amount as u128);
 
-    msg!("Repay successful: {} tokens", repay_amount);
+    // Interest rounding can strand a few units the user can't practically clear,
+    // which would block closing the position: forgive it below the market's threshold
+    let residual = user_deposit.borrowed_amount;
+    if residual > 0 && residual < market.dust_threshold as u128 {
+        msg!("Forgiving {} units of dust debt", residual);
+        user_deposit.borrowed_amount = 0;
+        market.total_borrows = market.total_borrows.saturating_sub(residual);
+    }
+    user_deposit.first_repayment_made = true;
+    // Repay reads no prices, so only a cleared debt is known to be healthy here;
+    // `liquidate` resets the grace period once it sees a partly repaid position recover
+    if user_deposit.borrowed_amount == 0 {
+        user_deposit.unhealthy_since_slot = 0;
+    }
     Ok(())
 }
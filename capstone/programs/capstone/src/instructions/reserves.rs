@@ -0,0 +1,27 @@
+use crate::{contexts::FundReserves, LendingError};
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Transfer};
+
+/// Top up the market's reserves, which absorb bad debt before suppliers do
+pub fn fund_reserves(ctx: Context<FundReserves>, _market_id: u64, amount: u64) -> Result<()> {
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.funder_supply_account.to_account_info(),
+        to: ctx.accounts.supply_vault.to_account_info(),
+        authority: ctx.accounts.funder.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+    token_interface::transfer(cpi_ctx, amount)?;
+
+    let market = &mut ctx.accounts.market;
+    market.total_reserves = market
+        .total_reserves
+        .checked_add(amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!(
+        "Reserves funded with {}, total {}",
+        amount,
+        market.total_reserves
+    );
+    Ok(())
+}
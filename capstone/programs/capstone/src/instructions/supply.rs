@@ -1,6 +1,9 @@
 use crate::{
     contexts::Supply,
-    utils::{calculate_ctokens_to_mint, calculate_exchange_rate, update_market_interest},
+    utils::{
+        accrue_interest, blend_opened_slot, blend_supply_index, calculate_ctokens_to_mint,
+        calculate_exchange_rate,
+    },
     LendingError,
 };
 use anchor_lang::prelude::*;
@@ -11,19 +14,19 @@ pub fn supply(ctx: Context<Supply>, _market_id: u64, amount: u64) -> Result<()>
     let market = &mut ctx.accounts.market;
     let user_deposit = &mut ctx.accounts.user_deposit;
 
-    // Update interest first
-    update_market_interest(market)?;
+    require!(amount >= market.min_supply, LendingError::SupplyTooSmall);
 
-    // Add a tiny bit of supply interest to make cTokens appreciate over time
-    // [CAPSTONE_SAFE: THIS IS NOT A BUG BECAUSE IT IS INTENDED FOR MOCKING INTEREST]
-    if market.total_supply_deposits > 0 {
-        let interest_earned = market.total_supply_deposits / 100000; // 0.001% per interaction
-        market.total_supply_deposits = market.total_supply_deposits.saturating_add(interest_earned);
-    }
+    // Update interest first, the user's debt along with the market. This has to come
+    // before the exchange rate is read: the new cTokens are priced at a rate that
+    // already includes the interest booked so far, so the deposit can't share in it
+    accrue_interest(market, user_deposit)?;
 
     // Calculate proper exchange rate based on accumulated interest
     let exchange_rate = calculate_exchange_rate(market)?;
     let ctokens_to_mint = calculate_ctokens_to_mint(amount, exchange_rate)?;
+    // Dust can floor to zero cTokens once the rate is above 1:1, which would
+    // move tokens into the vault without giving the supplier anything back
+    require!(ctokens_to_mint > 0, LendingError::SupplyTooSmall);
 
     // Transfer supply tokens from user to supply vault
     let cpi_accounts = Transfer {
@@ -34,12 +37,27 @@ pub fn supply(ctx: Context<Supply>, _market_id: u64, amount: u64) -> Result<()>
     let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
     token_interface::transfer(cpi_ctx, amount)?;
 
+    // Snapshot the supply index before the principal grows, so withdraw can settle
+    // exactly what this position earned
+    user_deposit.supply_index = blend_supply_index(
+        user_deposit.supply_index,
+        user_deposit.supply_deposited,
+        market.supply_index,
+        amount as u128,
+    )?;
+
     // Update balances
     user_deposit.supply_deposited = user_deposit
         .supply_deposited
         .checked_add(amount as u128)
         .ok_or(LendingError::MathOverflow)?;
 
+    user_deposit.supply_opened_slot = blend_opened_slot(
+        user_deposit.supply_opened_slot,
+        user_deposit.ctoken_balance,
+        Clock::get()?.slot,
+        ctokens_to_mint,
+    )?;
     user_deposit.ctoken_balance = user_deposit
         .ctoken_balance
         .checked_add(ctokens_to_mint)
@@ -78,6 +78,13 @@ pub fn initialize_user_deposit(ctx: Context<InitializeUserDeposit>, market_id: u
         ctoken_balance: 0,
         last_update_slot: Clock::get()?.slot,
         bump,
+        loan_start_slot: 0,
+        first_repayment_made: false,
+        unhealthy_since_slot: 0,
+        supply_opened_slot: 0,
+        frozen: false,
+        locked_ctokens: 0,
+        supply_index: 0,
     };
 
     let serialized = user_deposit_data.try_to_vec()?;
@@ -1,21 +1,31 @@
 use crate::{
-    contexts::Withdraw,
+    contexts::{EmergencyWithdraw, GetMaxWithdraw, Withdraw},
     utils::{
-        calculate_exchange_rate, calculate_underlying_from_ctokens, get_asset_price,
-        update_market_interest,
+        accrue_interest, accrue_market_interest, calculate_ctokens_to_cover,
+        calculate_exchange_rate, calculate_index_interest, calculate_loyalty_bonus,
+        calculate_underlying_from_ctokens, get_asset_price_scaled, max_amount_where, scale_price,
+        to_common_units, COMMON_DECIMALS, PRICE_DECIMALS,
     },
-    LendingError,
+    LendingError, Market, SupplyInterestSettledEvent, UserDeposit,
 };
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{self, Transfer};
 
-/// Withdraw supplied tokens (burn cTokens)
-pub fn withdraw(ctx: Context<Withdraw>, market_id: u64, ctoken_amount: u64) -> Result<()> {
+/// Withdraw supplied tokens (burn cTokens). Fails if the cTokens redeem for fewer than
+/// `min_tokens_out` underlying tokens; 0 disables the check.
+pub fn withdraw(
+    ctx: Context<Withdraw>,
+    market_id: u64,
+    ctoken_amount: u64,
+    min_tokens_out: u64,
+) -> Result<()> {
     let market_account_info = ctx.accounts.market.to_account_info();
     let market = &mut ctx.accounts.market;
     let user_deposit = &mut ctx.accounts.user_deposit;
 
-    update_market_interest(market)?;
+    require!(!user_deposit.frozen, LendingError::DepositFrozen);
+
+    accrue_interest(market, user_deposit)?;
 
     // Calculate proper exchange rate based on accumulated interest
     let exchange_rate = calculate_exchange_rate(market)?;
@@ -23,40 +33,75 @@ pub fn withdraw(ctx: Context<Withdraw>, market_id: u64, ctoken_amount: u64) -> R
     // Calculate how many underlying tokens to return
     let tokens_to_withdraw =
         calculate_underlying_from_ctokens(ctoken_amount as u128, exchange_rate)?;
+    // The rate can move between signing and landing, e.g. when bad debt is socialized
+    if tokens_to_withdraw < min_tokens_out as u128 {
+        msg!(
+            "Withdraw would return {} tokens, below the minimum {}",
+            tokens_to_withdraw,
+            min_tokens_out
+        );
+        return Err(LendingError::SlippageExceeded.into());
+    }
 
     require!(
         user_deposit.ctoken_balance >= (ctoken_amount as u128),
         LendingError::InsufficientBalance
     );
 
-    // Check if account remains properly collateralized after withdrawal
-    // Get prices from separate oracles
-    let supply_price = get_asset_price(&ctx.accounts.supply_oracle)?;
-    let collateral_price = get_asset_price(&ctx.accounts.collateral_oracle)?;
-
-    // Calculate collateral value
-    let collateral_value = user_deposit
-        .collateral_deposited
-        .checked_mul(collateral_price)
-        .ok_or(LendingError::MathOverflow)?;
-
-    // Calculate borrow value in supply asset terms
-    let borrow_value = user_deposit
-        .borrowed_amount
-        .checked_mul(supply_price)
-        .ok_or(LendingError::MathOverflow)?;
+    // Supplied cTokens back whatever debt the collateral no longer covers, and
+    // can only be withdrawn down to that floor
+    user_deposit.locked_ctokens = calculate_locked_ctokens(
+        market,
+        user_deposit,
+        &ctx.accounts.supply_oracle,
+        &ctx.accounts.collateral_oracle,
+        exchange_rate,
+    )?;
+    let ctokens_left = user_deposit.ctoken_balance - ctoken_amount as u128;
+    if ctokens_left < user_deposit.locked_ctokens {
+        msg!(
+            "Withdraw would leave {} cTokens, below the {} locked to back debt",
+            ctokens_left,
+            user_deposit.locked_ctokens
+        );
+        return Err(LendingError::CTokensLocked.into());
+    }
 
-    // Calculate maximum allowed borrow based on collateral
-    let max_borrow_value = collateral_value
-        .checked_mul(market.collateral_factor as u128)
-        .and_then(|v| v.checked_div(10000))
+    // Long-held positions earn a loyalty bonus, paid from protocol reserves
+    let held_slots = Clock::get()?
+        .slot
+        .saturating_sub(user_deposit.supply_opened_slot);
+    let loyalty_bonus = calculate_loyalty_bonus(
+        tokens_to_withdraw,
+        held_slots,
+        market.loyalty_threshold_slots,
+        market.loyalty_bonus_bps,
+    )?
+    .min(market.total_reserves);
+    market.total_reserves -= loyalty_bonus;
+    let tokens_to_transfer = tokens_to_withdraw
+        .checked_add(loyalty_bonus)
         .ok_or(LendingError::MathOverflow)?;
 
+    // The vault must still cover outstanding borrows once this withdrawal leaves it
+    let vault_after = (ctx.accounts.supply_vault.amount as u128)
+        .checked_sub(tokens_to_transfer)
+        .ok_or(LendingError::InsufficientLiquidity)?;
     require!(
-        borrow_value <= max_borrow_value,
-        LendingError::InsufficientCollateral
+        vault_after >= market.total_borrows,
+        LendingError::InsufficientLiquidity
     );
 
+    // Split the payout into the withdrawn share of the principal and what it earned since
+    // the user's supply index snapshot. The index moves with the exchange rate, so the
+    // interest is what `tokens_to_withdraw` pays on top of the principal
+    let principal_withdrawn = principal_share(user_deposit, ctoken_amount);
+    let interest_earned = calculate_index_interest(
+        principal_withdrawn,
+        user_deposit.supply_index,
+        market.supply_index,
+    )?;
+
     let market_bump = market.bump;
     let supply_mint = ctx.accounts.supply_mint.key();
     let collateral_mint = ctx.accounts.collateral_mint.key();
@@ -84,16 +129,16 @@ pub fn withdraw(ctx: Context<Withdraw>, market_id: u64, ctoken_amount: u64) -> R
     );
 
     require!(
-        tokens_to_withdraw <= u64::MAX as u128,
+        tokens_to_transfer <= u64::MAX as u128,
         LendingError::MathOverflow
     );
 
-    token_interface::transfer(cpi_ctx, tokens_to_withdraw as u64)?;
+    token_interface::transfer(cpi_ctx, tokens_to_transfer as u64)?;
 
-    // Update balances
+    // Update balances: the interest was never part of the user's principal
     user_deposit.supply_deposited = user_deposit
         .supply_deposited
-        .checked_sub(tokens_to_withdraw)
+        .checked_sub(principal_withdrawn)
         .ok_or(LendingError::MathOverflow)?;
     user_deposit.ctoken_balance = user_deposit
         .ctoken_balance
@@ -110,10 +155,217 @@ pub fn withdraw(ctx: Context<Withdraw>, market_id: u64, ctoken_amount: u64) -> R
         .checked_sub(ctoken_amount as u128)
         .ok_or(LendingError::MathOverflow)?;
 
+    emit!(SupplyInterestSettledEvent {
+        market_id,
+        user: ctx.accounts.user.key(),
+        principal: principal_withdrawn,
+        interest: interest_earned,
+        user_supply_index: user_deposit.supply_index,
+        market_supply_index: market.supply_index,
+    });
+
     msg!(
-        "Withdraw successful: {} cTokens → {} tokens",
+        "Withdraw successful: {} cTokens → {} tokens (+{} loyalty bonus)",
+        ctoken_amount,
+        tokens_to_withdraw,
+        loyalty_bonus
+    );
+    Ok(())
+}
+
+/// Withdraw supplied tokens when the oracles can't be read. Only debt-free positions
+/// qualify: with nothing borrowed there is no solvency to check, and no debt to accrue.
+/// Skips the loyalty bonus, which isn't worth blocking an exit over.
+pub fn emergency_withdraw(
+    ctx: Context<EmergencyWithdraw>,
+    market_id: u64,
+    ctoken_amount: u64,
+) -> Result<()> {
+    let market_account_info = ctx.accounts.market.to_account_info();
+    let market = &mut ctx.accounts.market;
+    let user_deposit = &mut ctx.accounts.user_deposit;
+
+    require!(!user_deposit.frozen, LendingError::DepositFrozen);
+    require!(user_deposit.borrowed_amount == 0, LendingError::HasBorrows);
+    require!(
+        user_deposit.ctoken_balance >= (ctoken_amount as u128),
+        LendingError::InsufficientBalance
+    );
+
+    let exchange_rate = calculate_exchange_rate(market)?;
+    let tokens_to_withdraw =
+        calculate_underlying_from_ctokens(ctoken_amount as u128, exchange_rate)?;
+    require!(
+        tokens_to_withdraw <= u64::MAX as u128,
+        LendingError::MathOverflow
+    );
+
+    // The vault must still cover outstanding borrows once this withdrawal leaves it
+    let vault_after = (ctx.accounts.supply_vault.amount as u128)
+        .checked_sub(tokens_to_withdraw)
+        .ok_or(LendingError::InsufficientLiquidity)?;
+    require!(
+        vault_after >= market.total_borrows,
+        LendingError::InsufficientLiquidity
+    );
+
+    let supply_mint = ctx.accounts.supply_mint.key();
+    let collateral_mint = ctx.accounts.collateral_mint.key();
+    let market_id_bytes = market_id.to_le_bytes();
+    let market_seeds = &[
+        b"market",
+        market_id_bytes.as_ref(),
+        supply_mint.as_ref(),
+        collateral_mint.as_ref(),
+        &[market.bump],
+    ];
+    let signer_seeds = &[market_seeds.as_slice()];
+
+    let cpi_accounts = Transfer {
+        from: ctx.accounts.supply_vault.to_account_info(),
+        to: ctx.accounts.user_supply_account.to_account_info(),
+        authority: market_account_info,
+    };
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        cpi_accounts,
+        signer_seeds,
+    );
+    token_interface::transfer(cpi_ctx, tokens_to_withdraw as u64)?;
+
+    // Update balances: booked interest makes the payout larger than the principal it retires
+    let principal_withdrawn = principal_share(user_deposit, ctoken_amount);
+    user_deposit.supply_deposited = user_deposit
+        .supply_deposited
+        .checked_sub(principal_withdrawn)
+        .ok_or(LendingError::MathOverflow)?;
+    user_deposit.ctoken_balance = user_deposit
+        .ctoken_balance
+        .checked_sub(ctoken_amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+    // Nothing borrowed, so nothing locked
+    user_deposit.locked_ctokens = 0;
+    market.total_supply_deposits = market
+        .total_supply_deposits
+        .checked_sub(tokens_to_withdraw)
+        .ok_or(LendingError::MathOverflow)?;
+    market.total_ctoken_supply = market
+        .total_ctoken_supply
+        .checked_sub(ctoken_amount as u128)
+        .ok_or(LendingError::MathOverflow)?;
+
+    msg!(
+        "Emergency withdraw successful: {} cTokens → {} tokens",
         ctoken_amount,
         tokens_to_withdraw
     );
     Ok(())
 }
+
+/// Share of the user's supplied principal backing `ctoken_amount` of their cTokens
+fn principal_share(user_deposit: &UserDeposit, ctoken_amount: u64) -> u128 {
+    user_deposit
+        .supply_deposited
+        .checked_mul(ctoken_amount as u128)
+        .and_then(|v| v.checked_div(user_deposit.ctoken_balance))
+        .unwrap_or(0)
+}
+
+/// Largest cToken amount `withdraw` would accept for this user right now
+pub fn get_max_withdraw(ctx: Context<GetMaxWithdraw>, _market_id: u64) -> Result<u64> {
+    // Accrue on a copy so the query stays read-only and can run as a view
+    let market = &mut ctx.accounts.market.clone().into_inner();
+    let user_deposit = &ctx.accounts.user_deposit;
+
+    accrue_market_interest(market)?;
+    let exchange_rate = calculate_exchange_rate(market)?;
+
+    // Withdraw keeps the cTokens backing debt the collateral doesn't cover
+    let locked_ctokens = calculate_locked_ctokens(
+        market,
+        user_deposit,
+        &ctx.accounts.supply_oracle,
+        &ctx.accounts.collateral_oracle,
+        exchange_rate,
+    )?;
+    let unlocked_ctokens = user_deposit.ctoken_balance.saturating_sub(locked_ctokens);
+
+    // Redeemed tokens plus any loyalty bonus must leave the vault covering total borrows
+    let available = (ctx.accounts.supply_vault.amount as u128).saturating_sub(market.total_borrows);
+    let held_slots = Clock::get()?
+        .slot
+        .saturating_sub(user_deposit.supply_opened_slot);
+    let max_ctokens = max_amount_where(unlocked_ctokens, |ctoken_amount| {
+        let tokens = calculate_underlying_from_ctokens(ctoken_amount, exchange_rate)?;
+        let loyalty_bonus = calculate_loyalty_bonus(
+            tokens,
+            held_slots,
+            market.loyalty_threshold_slots,
+            market.loyalty_bonus_bps,
+        )?
+        .min(market.total_reserves);
+        let tokens_to_transfer = tokens
+            .checked_add(loyalty_bonus)
+            .ok_or(LendingError::MathOverflow)?;
+        Ok(tokens_to_transfer <= available && tokens_to_transfer <= u64::MAX as u128)
+    })?;
+    let max_ctokens = max_ctokens.min(u64::MAX as u128) as u64;
+
+    msg!(
+        "Max withdraw: {} cTokens at exchange rate {}",
+        max_ctokens,
+        exchange_rate
+    );
+    Ok(max_ctokens)
+}
+
+/// cTokens that must stay supplied to back the part of the user's debt beyond the
+/// collateral factor of their posted collateral, with each asset priced by its own oracle
+fn calculate_locked_ctokens(
+    market: &Market,
+    user_deposit: &UserDeposit,
+    supply_oracle: &AccountInfo,
+    collateral_oracle: &AccountInfo,
+    exchange_rate: u128,
+) -> Result<u128> {
+    let supply_price = get_asset_price_scaled(
+        supply_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+    let collateral_price = get_asset_price_scaled(
+        collateral_oracle,
+        PRICE_DECIMALS,
+        market.max_oracle_staleness_slots,
+        market.max_confidence_bps,
+    )?;
+
+    // Calculate collateral value
+    let collateral_value = to_common_units(
+        user_deposit.collateral_deposited,
+        market.collateral_decimals,
+    )?
+    .checked_mul(collateral_price)
+    .ok_or(LendingError::MathOverflow)?;
+
+    // Calculate maximum allowed borrow based on collateral
+    let max_borrow_value = collateral_value
+        .checked_mul(market.collateral_factor as u128)
+        .and_then(|v| v.checked_div(10000))
+        .ok_or(LendingError::MathOverflow)?;
+
+    // Convert it back to supply tokens, rounding down so the lock errs on the high side
+    let collateral_backed_debt = scale_price(
+        max_borrow_value
+            .checked_div(supply_price)
+            .ok_or(LendingError::MathOverflow)?,
+        COMMON_DECIMALS,
+        market.supply_decimals,
+    )?;
+    let uncovered_debt = user_deposit
+        .borrowed_amount
+        .saturating_sub(collateral_backed_debt);
+
+    calculate_ctokens_to_cover(uncovered_debt, exchange_rate)
+}
@@ -4,10 +4,12 @@ declare_id!("AYye92emHVPgnxDHnTEkuuWVLUKF7JHKgWsXysZBZ3qe");
 
 pub use contexts::*;
 pub use errors::*;
+pub use events::*;
 pub use state::*;
 
 pub mod contexts;
 pub mod errors;
+pub mod events;
 pub mod instructions;
 pub mod state;
 pub mod utils;
@@ -23,14 +25,69 @@ pub mod meta_lend {
         instructions::initialize_protocol(ctx)
     }
 
+    /// Transfer protocol admin rights (current admin only)
+    pub fn transfer_protocol_admin(ctx: Context<ProtocolAdmin>, new_admin: Pubkey) -> Result<()> {
+        instructions::transfer_protocol_admin(ctx, new_admin)
+    }
+
+    /// Pause or unpause the protocol (admin only)
+    pub fn set_protocol_paused(ctx: Context<ProtocolAdmin>, paused: bool) -> Result<()> {
+        instructions::set_protocol_paused(ctx, paused)
+    }
+
+    /// Pause or unpause new borrows and flash loans in every market (admin only)
+    pub fn set_borrows_paused(ctx: Context<ProtocolAdmin>, paused: bool) -> Result<()> {
+        instructions::set_borrows_paused(ctx, paused)
+    }
+
+    /// Set the owner of the token accounts that receive protocol fees (admin only)
+    pub fn set_fee_recipient(ctx: Context<ProtocolAdmin>, fee_recipient: Pubkey) -> Result<()> {
+        instructions::set_fee_recipient(ctx, fee_recipient)
+    }
+
     /// Create a new lending market for any SPL token
     pub fn create_market(
         ctx: Context<CreateMarket>,
         market_id: u64,
         collateral_factor: u64,
         liquidation_threshold: u64,
+        min_borrow: u64,
     ) -> Result<()> {
-        instructions::create_market(ctx, market_id, collateral_factor, liquidation_threshold)
+        instructions::create_market(
+            ctx,
+            market_id,
+            collateral_factor,
+            liquidation_threshold,
+            min_borrow,
+        )
+    }
+
+    /// Close an empty market and its vaults (market admin only)
+    pub fn close_market(ctx: Context<CloseMarket>, market_id: u64) -> Result<()> {
+        instructions::close_market(ctx, market_id)
+    }
+
+    /// Emit the supply currently available to borrow or withdraw
+    pub fn get_available_liquidity(
+        ctx: Context<GetAvailableLiquidity>,
+        market_id: u64,
+    ) -> Result<()> {
+        instructions::get_available_liquidity(ctx, market_id)
+    }
+
+    /// Return utilization, borrow APY and supply APY (via return data)
+    pub fn get_market_stats(ctx: Context<GetMarketStats>, market_id: u64) -> Result<MarketStats> {
+        instructions::get_market_stats(ctx, market_id)
+    }
+
+    /// Return the live cToken exchange rate scaled by SCALING_FACTOR (via return data)
+    pub fn get_exchange_rate(ctx: Context<GetExchangeRate>, market_id: u64) -> Result<u128> {
+        instructions::get_exchange_rate(ctx, market_id)
+    }
+
+    /// Return supply, collateral and borrowed value in a common quote unit (via return data)
+    pub fn get_market_tvl(ctx: Context<GetMarketTvl>, market_id: u64) -> Result<MarketTvl> {
+        instructions::get_market_tvl(ctx, market_id)
     }
 
     /// Supply tokens to earn interest (mint cTokens)
@@ -39,8 +96,27 @@ pub mod meta_lend {
     }
 
     /// Withdraw supplied tokens (burn cTokens)
-    pub fn withdraw(ctx: Context<Withdraw>, market_id: u64, ctoken_amount: u64) -> Result<()> {
-        instructions::withdraw(ctx, market_id, ctoken_amount)
+    pub fn withdraw(
+        ctx: Context<Withdraw>,
+        market_id: u64,
+        ctoken_amount: u64,
+        min_tokens_out: u64,
+    ) -> Result<()> {
+        instructions::withdraw(ctx, market_id, ctoken_amount, min_tokens_out)
+    }
+
+    /// Withdraw supplied tokens without reading oracles, only for positions with no debt
+    pub fn emergency_withdraw(
+        ctx: Context<EmergencyWithdraw>,
+        market_id: u64,
+        ctoken_amount: u64,
+    ) -> Result<()> {
+        instructions::emergency_withdraw(ctx, market_id, ctoken_amount)
+    }
+
+    /// Return the most cTokens the user could withdraw right now (via return data)
+    pub fn get_max_withdraw(ctx: Context<GetMaxWithdraw>, market_id: u64) -> Result<u64> {
+        instructions::get_max_withdraw(ctx, market_id)
     }
 
     /// Borrow supply tokens by depositing collateral tokens
@@ -53,6 +129,23 @@ pub mod meta_lend {
         instructions::borrow(ctx, market_id, collateral_amount, borrow_amount)
     }
 
+    /// Borrow and send the borrowed supply tokens to any supply-mint token account
+    pub fn borrow_to(
+        ctx: Context<Borrow>,
+        market_id: u64,
+        collateral_amount: u64,
+        borrow_amount: u64,
+        destination: Pubkey,
+    ) -> Result<()> {
+        instructions::borrow_to(
+            ctx,
+            market_id,
+            collateral_amount,
+            borrow_amount,
+            destination,
+        )
+    }
+
     /// Withdraw collateral tokens (only allowed when no outstanding borrows)
     pub fn withdraw_collateral(
         ctx: Context<WithdrawCollateral>,
@@ -67,13 +160,95 @@ pub mod meta_lend {
         instructions::repay(ctx, market_id, amount)
     }
 
+    /// Repay the full outstanding debt, including accrued interest
+    pub fn repay_all(ctx: Context<Repay>, market_id: u64) -> Result<()> {
+        instructions::repay_all(ctx, market_id)
+    }
+
+    /// Create a multi-collateral portfolio for the caller
+    pub fn initialize_portfolio(ctx: Context<InitializePortfolio>, market_id: u64) -> Result<()> {
+        instructions::initialize_portfolio(ctx, market_id)
+    }
+
+    /// Register another collateral asset in the caller's portfolio
+    pub fn add_portfolio_collateral(
+        ctx: Context<AddPortfolioCollateral>,
+        market_id: u64,
+    ) -> Result<()> {
+        instructions::add_portfolio_collateral(ctx, market_id)
+    }
+
+    /// Post collateral of a registered asset into the portfolio
+    pub fn deposit_portfolio_collateral(
+        ctx: Context<DepositPortfolioCollateral>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        instructions::deposit_portfolio_collateral(ctx, market_id, amount)
+    }
+
+    /// Borrow against the combined value of all portfolio collateral
+    /// (one oracle per collateral entry in remaining accounts)
+    pub fn borrow_against_portfolio<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PortfolioBorrow<'info>>,
+        market_id: u64,
+        borrow_amount: u64,
+    ) -> Result<()> {
+        instructions::borrow_against_portfolio(ctx, market_id, borrow_amount)
+    }
+
+    /// Repay portfolio debt
+    pub fn repay_portfolio(
+        ctx: Context<PortfolioBorrow>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        instructions::repay_portfolio(ctx, market_id, amount)
+    }
+
+    /// Withdraw portfolio collateral, re-checking the combined borrow limit
+    /// (one oracle per collateral entry in remaining accounts while debt is open)
+    pub fn withdraw_portfolio_collateral<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawPortfolioCollateral<'info>>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        instructions::withdraw_portfolio_collateral(ctx, market_id, amount)
+    }
+
+    /// Liquidate an undercollateralized portfolio, seizing one of its collaterals
+    /// (one oracle per collateral entry in remaining accounts)
+    pub fn liquidate_portfolio<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidatePortfolio<'info>>,
+        market_id: u64,
+        repay_amount: u64,
+        min_collateral_out: u64,
+    ) -> Result<()> {
+        instructions::liquidate_portfolio(ctx, market_id, repay_amount, min_collateral_out)
+    }
+
     /// Liquidate undercollateralized positions
     pub fn liquidate(
         ctx: Context<Liquidate>,
         market_id: u64,
         liquidation_amount: u64,
+        min_collateral_out: u64,
     ) -> Result<()> {
-        instructions::liquidate(ctx, market_id, liquidation_amount)
+        instructions::liquidate(ctx, market_id, liquidation_amount, min_collateral_out)
+    }
+
+    /// Liquidate several positions at once (borrower deposits in remaining_accounts)
+    pub fn liquidate_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LiquidateBatch<'info>>,
+        market_id: u64,
+        amounts: Vec<u64>,
+    ) -> Result<LiquidationBatchSummary> {
+        instructions::liquidate_batch(ctx, market_id, amounts)
+    }
+
+    /// Add supply tokens to the market's bad-debt reserves
+    pub fn fund_reserves(ctx: Context<FundReserves>, market_id: u64, amount: u64) -> Result<()> {
+        instructions::fund_reserves(ctx, market_id, amount)
     }
 
     /// Flash loan functionality with external callback
@@ -99,13 +274,163 @@ pub mod meta_lend {
         instructions::close_user_deposit(ctx)
     }
 
-    /// Update market parameters
+    /// Check whether the caller's position is safe from liquidation
+    pub fn am_i_safe(ctx: Context<AmISafe>, market_id: u64) -> Result<()> {
+        instructions::am_i_safe(ctx, market_id)
+    }
+
+    /// Return a user's debt including pending interest (via return data)
+    pub fn get_current_debt(ctx: Context<GetCurrentDebt>, market_id: u64) -> Result<u128> {
+        instructions::get_current_debt(ctx, market_id)
+    }
+
+    /// Return a user's balances, debt and health factor in one market (via return data)
+    pub fn get_position(ctx: Context<GetPosition>, market_id: u64) -> Result<UserPosition> {
+        instructions::get_position(ctx, market_id)
+    }
+
+    /// Update market parameters (market admin only)
     pub fn update_market_params(
         ctx: Context<UpdateMarketParams>,
         new_collateral_factor: u64,
         new_liquidation_threshold: u64,
+        new_grace_period_slots: u64,
+        new_flash_loan_fee_bps: u64,
+        new_max_confidence_bps: u64,
+    ) -> Result<()> {
+        instructions::update_market_params(
+            ctx,
+            new_collateral_factor,
+            new_liquidation_threshold,
+            new_grace_period_slots,
+            new_flash_loan_fee_bps,
+            new_max_confidence_bps,
+        )
+    }
+
+    /// Enable or disable flash loans for a market
+    pub fn set_flash_loans_enabled(ctx: Context<UpdateMarketConfig>, enabled: bool) -> Result<()> {
+        instructions::set_flash_loans_enabled(ctx, enabled)
+    }
+
+    /// Set the interest-free window for a loan's first repayment
+    pub fn set_first_repayment_grace_slots(
+        ctx: Context<UpdateMarketConfig>,
+        grace_slots: u64,
+    ) -> Result<()> {
+        instructions::set_first_repayment_grace_slots(ctx, grace_slots)
+    }
+
+    /// Set how many slots after a loan opens accrue no interest
+    pub fn set_interest_free_slots(
+        ctx: Context<UpdateMarketConfig>,
+        interest_free_slots: u64,
+    ) -> Result<()> {
+        instructions::set_interest_free_slots(ctx, interest_free_slots)
+    }
+
+    /// Set the liquidation bonus, capped at MAX_LIQUIDATION_BONUS_BPS
+    pub fn set_liquidation_bonus_bps(
+        ctx: Context<UpdateMarketConfig>,
+        liquidation_bonus_bps: u64,
+    ) -> Result<()> {
+        instructions::set_liquidation_bonus_bps(ctx, liquidation_bonus_bps)
+    }
+
+    /// Set the annual borrow rate ceiling, capped at MAX_BORROW_RATE_CAP_BPS
+    pub fn set_max_borrow_rate_bps(
+        ctx: Context<UpdateMarketConfig>,
+        max_borrow_rate_bps: u64,
+    ) -> Result<()> {
+        instructions::set_max_borrow_rate_bps(ctx, max_borrow_rate_bps)
+    }
+
+    /// Set the share of supply deposits that can never be borrowed
+    pub fn set_min_liquidity_buffer_bps(
+        ctx: Context<UpdateMarketConfig>,
+        buffer_bps: u64,
+    ) -> Result<()> {
+        instructions::set_min_liquidity_buffer_bps(ctx, buffer_bps)
+    }
+
+    /// Set the residual debt below which a repayment settles the loan in full
+    pub fn set_dust_threshold(ctx: Context<UpdateMarketConfig>, dust_threshold: u64) -> Result<()> {
+        instructions::set_dust_threshold(ctx, dust_threshold)
+    }
+
+    /// Set the smallest amount a single supply may deposit
+    pub fn set_min_supply(ctx: Context<UpdateMarketConfig>, min_supply: u64) -> Result<()> {
+        instructions::set_min_supply(ctx, min_supply)
+    }
+
+    /// Set the share of flash-loan fees paid to the protocol fee recipient
+    pub fn set_protocol_fee_share_bps(
+        ctx: Context<UpdateMarketConfig>,
+        protocol_fee_share_bps: u64,
     ) -> Result<()> {
-        instructions::update_market_params(ctx, new_collateral_factor, new_liquidation_threshold)
+        instructions::set_protocol_fee_share_bps(ctx, protocol_fee_share_bps)
+    }
+
+    /// Freeze or unfreeze a user's position in this market (market admin only)
+    pub fn set_deposit_frozen(ctx: Context<SetDepositFrozen>, frozen: bool) -> Result<()> {
+        instructions::set_deposit_frozen(ctx, frozen)
+    }
+
+    /// Freeze or unfreeze a user's portfolio in this market (market admin only)
+    pub fn set_portfolio_frozen(ctx: Context<SetPortfolioFrozen>, frozen: bool) -> Result<()> {
+        instructions::set_portfolio_frozen(ctx, frozen)
+    }
+
+    /// Set the oldest oracle price (in slots) the market accepts
+    pub fn set_max_oracle_staleness_slots(
+        ctx: Context<UpdateMarketConfig>,
+        max_staleness_slots: u64,
+    ) -> Result<()> {
+        instructions::set_max_oracle_staleness_slots(ctx, max_staleness_slots)
+    }
+
+    /// Set the collateral/borrow price ratio band (in bps) borrows must stay within
+    pub fn set_price_ratio_band(
+        ctx: Context<UpdateMarketConfig>,
+        min_ratio_bps: u64,
+        max_ratio_bps: u64,
+    ) -> Result<()> {
+        instructions::set_price_ratio_band(ctx, min_ratio_bps, max_ratio_bps)
+    }
+
+    /// Configure the supply loyalty bonus threshold and cap
+    pub fn set_loyalty_bonus(
+        ctx: Context<UpdateMarketConfig>,
+        threshold_slots: u64,
+        bonus_bps: u64,
+    ) -> Result<()> {
+        instructions::set_loyalty_bonus(ctx, threshold_slots, bonus_bps)
+    }
+
+    /// Propose a new market admin (two-step, completed by accept_market_admin)
+    pub fn transfer_market_admin(
+        ctx: Context<TransferMarketAdmin>,
+        new_admin: Pubkey,
+    ) -> Result<()> {
+        instructions::transfer_market_admin(ctx, new_admin)
+    }
+
+    /// Accept a pending market admin transfer
+    pub fn accept_market_admin(ctx: Context<AcceptMarketAdmin>) -> Result<()> {
+        instructions::accept_market_admin(ctx)
+    }
+
+    /// Sweep collateral held beyond recorded deposits to the market admin
+    pub fn sweep_surplus_collateral(
+        ctx: Context<SweepSurplusCollateral>,
+        market_id: u64,
+    ) -> Result<()> {
+        instructions::sweep_surplus_collateral(ctx, market_id)
+    }
+
+    /// Verify market vault authorities and mints, emitting a result per check
+    pub fn verify_market_vaults(ctx: Context<VerifyMarketVaults>, market_id: u64) -> Result<()> {
+        instructions::verify_market_vaults(ctx, market_id)
     }
 
     /// Create oracle (simplified for demo)
@@ -114,12 +439,49 @@ pub mod meta_lend {
         source: Vec<u8>,
         initial_price: u64,
         decimals: u8,
+        min_update_interval_slots: u64,
     ) -> Result<()> {
-        instructions::create_oracle(ctx, source, initial_price, decimals)
+        instructions::create_oracle(
+            ctx,
+            source,
+            initial_price,
+            decimals,
+            min_update_interval_slots,
+        )
     }
 
     /// Update oracle price
-    pub fn update_oracle_price(ctx: Context<UpdateOraclePrice>, new_price: u64) -> Result<()> {
-        instructions::update_oracle_price(ctx, new_price)
+    pub fn update_oracle_price(
+        ctx: Context<UpdateOraclePrice>,
+        new_price: u64,
+        admin_override: bool,
+    ) -> Result<()> {
+        instructions::update_oracle_price(ctx, new_price, admin_override)
+    }
+
+    /// Configure the oracle's price deviation circuit breaker
+    pub fn set_oracle_circuit_breaker(
+        ctx: Context<UpdateOraclePrice>,
+        max_price_deviation_bps: u64,
+        deviation_window_slots: u64,
+    ) -> Result<()> {
+        instructions::set_oracle_circuit_breaker(
+            ctx,
+            max_price_deviation_bps,
+            deviation_window_slots,
+        )
+    }
+
+    /// Update several oracle prices atomically (oracles in remaining_accounts)
+    pub fn update_oracle_prices_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateOraclePricesBatch<'info>>,
+        prices: Vec<u64>,
+    ) -> Result<()> {
+        instructions::update_oracle_prices_batch(ctx, prices)
+    }
+
+    /// Emit oracle update frequency stats
+    pub fn get_oracle_stats(ctx: Context<GetOracleStats>) -> Result<()> {
+        instructions::get_oracle_stats(ctx)
     }
 }
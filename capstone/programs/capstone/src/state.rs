@@ -5,12 +5,15 @@ use anchor_lang::prelude::*;
 pub struct ProtocolState {
     pub admin: Pubkey,
     pub total_markets: u64,
+    pub next_market_id: u64, // Id the next create_market must use, never reused after a close
     pub is_paused: bool,
+    pub borrows_paused: bool, // Blocks new debt in every market, repayments stay open
+    pub fee_recipient: Pubkey, // Owner of the token accounts that receive protocol fees
     pub bump: u8,
 }
 
 impl ProtocolState {
-    pub const SPACE: usize = 8 + 32 + 8 + 1 + 1; // discriminator + admin + total_markets + is_paused + bump
+    pub const SPACE: usize = 8 + 32 + 8 + 8 + 1 + 1 + 32 + 1; // discriminator + admin + total_markets + next_market_id + is_paused + borrows_paused + fee_recipient + bump
 }
 
 /// Individual lending markets with supply and collateral assets
@@ -33,6 +36,32 @@ pub struct Market {
     pub collateral_oracle: Pubkey,    // Oracle for collateral asset price
     pub bump: u8,
     pub is_active: bool,
+    pub flash_loans_enabled: bool,
+    pub first_repayment_grace_slots: u64, // Interest-free window for the first repayment, 0 = disabled
+    pub grace_period_slots: u64, // Slots a position must stay unhealthy before it can be liquidated
+    pub min_liquidity_buffer_bps: u64, // Share of supply deposits that can never be borrowed
+    pub pending_admin: Pubkey,   // Proposed new market admin, default = no transfer pending
+    pub min_borrow: u64, // Smallest allowed outstanding borrow value (quote units, PRICE_DECIMALS), 0 = disabled
+    pub total_reserves: u128, // Protocol-owned supply tokens held in the vault, absorb bad debt first
+    pub max_oracle_staleness_slots: u64, // Oldest oracle price (in slots) this market accepts
+    pub flash_loan_fee_bps: u64, // Fee charged on flash loans, 0 = free
+    pub loyalty_threshold_slots: u64, // Supply positions held longer start earning a bonus, 0 = disabled
+    pub loyalty_bonus_bps: u64,       // Cap on the loyalty bonus, reached at twice the threshold
+    pub total_bad_debt: u128,         // Cumulative debt written off in liquidations
+    pub max_confidence_bps: u64, // Widest oracle confidence interval accepted, relative to price
+    pub interest_free_slots: u64, // Slots after a loan opens that accrue no interest, 0 = disabled
+    pub liquidation_bonus_bps: u64, // Extra collateral liquidators seize on top of the repaid amount
+    pub supply_decimals: u8,        // Decimals of supply_mint, cached at creation
+    pub collateral_decimals: u8,    // Decimals of collateral_mint, cached at creation
+    pub min_price_ratio_bps: u64,   // Lowest collateral/borrow oracle price ratio borrows accept
+    pub max_price_ratio_bps: u64,   // Highest collateral/borrow oracle price ratio borrows accept
+    pub dust_threshold: u64, // Debt left after a repayment below this is forgiven (supply token units)
+    pub min_supply: u64, // Smallest accepted supply (supply token units), 0 = only zero-mint supplies are rejected
+    pub protocol_fee_share_bps: u64, // Share of each flash-loan fee sent to the fee recipient, the rest goes to suppliers
+    pub supply_vault_bump: u8,       // Bump of the supply vault PDA, cached at creation
+    pub collateral_vault_bump: u8,   // Bump of the collateral vault PDA, cached at creation
+    pub max_borrow_rate_bps: u64,    // Ceiling on the annual borrow rate, clamps runaway rates
+    pub supply_index: u128, // Scaled by 10^9, moves with what suppliers actually earn (tracks the cToken exchange rate)
 }
 
 impl Market {
@@ -54,10 +83,62 @@ impl Market {
         32 + // supply_oracle
         32 + // collateral_oracle
         1 + // bump
-        1 // is_active
+        1 + // is_active
+        1 + // flash_loans_enabled
+        8 + // first_repayment_grace_slots
+        8 + // grace_period_slots
+        8 + // min_liquidity_buffer_bps
+        32 + // pending_admin
+        8 + // min_borrow
+        16 + // total_reserves (u128)
+        8 + // max_oracle_staleness_slots
+        8 + // flash_loan_fee_bps
+        8 + // loyalty_threshold_slots
+        8 + // loyalty_bonus_bps
+        16 + // total_bad_debt (u128)
+        8 + // max_confidence_bps
+        8 + // interest_free_slots
+        8 + // liquidation_bonus_bps
+        1 + // supply_decimals
+        1 + // collateral_decimals
+        8 + // min_price_ratio_bps
+        8 + // max_price_ratio_bps
+        8 + // dust_threshold
+        8 + // min_supply
+        8 + // protocol_fee_share_bps
+        1 + // supply_vault_bump
+        1 + // collateral_vault_bump
+        8 + // max_borrow_rate_bps
+        16 // supply_index (u128)
     }
 }
 
+/// Registry entry mapping a sequential market number to its market PDA,
+/// derived at `[b"market_index", index]` so clients can enumerate every market
+#[account]
+pub struct MarketIndex {
+    pub index: u64,
+    pub market: Pubkey,
+    pub bump: u8,
+}
+
+impl MarketIndex {
+    pub const SPACE: usize = 8 + 8 + 32 + 1; // discriminator + index + market + bump
+}
+
+/// Lookup entry for the one market of a (supply mint, collateral mint) pair,
+/// derived at `[b"market_pointer", supply_mint, collateral_mint]`
+#[account]
+pub struct MarketPointer {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub bump: u8,
+}
+
+impl MarketPointer {
+    pub const SPACE: usize = 8 + 8 + 32 + 1; // discriminator + market_id + market + bump
+}
+
 /// Per-user account tracking supply deposits, collateral deposits, borrows, and cToken balances
 #[account]
 pub struct UserDeposit {
@@ -69,10 +150,90 @@ pub struct UserDeposit {
     pub ctoken_balance: u128,   // cTokens from supply deposits
     pub last_update_slot: u64,
     pub bump: u8,
+    pub loan_start_slot: u64,       // Slot the current loan was opened
+    pub first_repayment_made: bool, // Whether the current loan has been repaid at least once
+    pub unhealthy_since_slot: u64,  // First slot the position was seen unhealthy, 0 = healthy
+    pub supply_opened_slot: u64,    // Size-weighted slot the supply position was opened
+    pub frozen: bool,               // Set by the market admin, blocks borrows and withdrawals
+    pub locked_ctokens: u128, // cTokens held back for debt collateral doesn't cover, set by withdraw
+    pub supply_index: u128, // Market supply_index when the supply position was opened, size-weighted
 }
 
 impl UserDeposit {
-    pub const SPACE: usize = 8 + 32 + 32 + 16 + 16 + 16 + 16 + 8 + 1; // Updated for u128 fields
+    pub const SPACE: usize = 8 + 32 + 32 + 16 + 16 + 16 + 16 + 8 + 1 + 8 + 1 + 8 + 8 + 1 + 16 + 16; // Updated for u128 fields
+}
+
+/// One collateral asset posted into a `UserPortfolio`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct CollateralEntry {
+    pub mint: Pubkey,
+    pub amount: u128,
+    pub oracle: Pubkey,
+    pub decimals: u8, // The mint's decimals, so entries price in common units
+}
+
+/// Aggregated borrow position: one debt in the market's supply asset backed by
+/// several collateral mints, derived at `[b"portfolio", user, market]`
+#[account]
+pub struct UserPortfolio {
+    pub user: Pubkey,
+    pub market: Pubkey,
+    pub borrowed_amount: u128,
+    pub last_update_slot: u64,
+    pub bump: u8,
+    pub collaterals: Vec<CollateralEntry>,
+    pub frozen: bool, // Set by the market admin: blocks new borrows and withdrawals, repayments stay open
+}
+
+impl UserPortfolio {
+    pub const MAX_COLLATERALS: usize = 4;
+    pub const SPACE: usize =
+        8 + 32 + 32 + 16 + 8 + 1 + 4 + Self::MAX_COLLATERALS * (32 + 16 + 32 + 1) + 1; // discriminator + user + market + borrowed_amount + last_update_slot + bump + collaterals + frozen
+
+    /// Position of `mint` in the collateral list, if it has been added
+    pub fn entry_index(&self, mint: &Pubkey) -> Option<usize> {
+        self.collaterals
+            .iter()
+            .position(|entry| entry.mint == *mint)
+    }
+}
+
+/// Read-only market rates returned by `get_market_stats`, all scaled by SCALING_FACTOR.
+/// APYs compound every slot; APRs are the simple annual rates they compound from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MarketStats {
+    pub utilization: u128,
+    pub borrow_apy: u128,
+    pub supply_apy: u128,
+    pub borrow_apr: u128,
+    pub supply_apr: u128,
+}
+
+/// Market value locked returned by `get_market_tvl`, in quote units scaled by PRICE_DECIMALS
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MarketTvl {
+    pub supply_value: u128,
+    pub collateral_value: u128,
+    pub borrowed_value: u128,
+}
+
+/// What a `liquidate_batch` call processed, returned via return data
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LiquidationBatchSummary {
+    pub liquidated: u32,
+    pub skipped: u32,
+    pub debt_repaid: u64,
+    pub collateral_seized: u64,
+}
+
+/// One user's position in one market returned by `get_position`, debt including pending interest
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct UserPosition {
+    pub supply_deposited: u128,
+    pub collateral_deposited: u128,
+    pub borrowed_amount: u128,
+    pub ctoken_balance: u128,
+    pub health_factor: u128, // Scaled by SCALING_FACTOR, u128::MAX with no debt
 }
 
 /// Oracle account for price feeds with proper validation
@@ -86,6 +247,10 @@ pub struct Oracle {
     pub confidence: u128, // Price confidence interval (u128 for consistency)
     pub authority: Pubkey, // Authority that can update this oracle
     pub bump: u8,
+    pub update_count: u64,            // Number of price updates since creation
+    pub max_price_deviation_bps: u64, // Largest move allowed within deviation_window_slots, 0 = no band
+    pub deviation_window_slots: u64,  // Window after an update in which the deviation band applies
+    pub min_update_interval_slots: u64, // Fewest slots between accepted price updates, 0 = no limit
 }
 
 impl Oracle {
@@ -98,11 +263,15 @@ impl Oracle {
         8 +                     // valid_slot
         16 +                    // confidence (u128)
         32 +                    // authority
-        1 // bump
+        1 +                     // bump
+        8 +                     // update_count
+        8 +                     // max_price_deviation_bps
+        8 +                     // deviation_window_slots
+        8 // min_update_interval_slots
     }
 
     /// Check if the oracle data is still valid (within acceptable staleness)
     pub fn is_valid(&self, current_slot: u64, max_staleness_slots: u64) -> bool {
-        current_slot <= self.valid_slot + max_staleness_slots
+        current_slot <= self.valid_slot.saturating_add(max_staleness_slots)
     }
 }
@@ -1,27 +1,113 @@
-use crate::{LendingError, Market, Oracle};
+use crate::{InterestAccruedEvent, LendingError, Market, Oracle, UserDeposit};
 use anchor_lang::prelude::*;
 
 /// Scaling factor for exchange rate calculations (1e9)
 /// This is used to scale the exchange rate to a whole number
 pub const SCALING_FACTOR: u128 = 1_000_000_000;
 
-/// Update market interest rates with simple flat rates: 1% supply, 2% borrow
+/// Common decimal base that all oracle prices are normalized to before comparison
+pub const PRICE_DECIMALS: u8 = 9;
+
+/// Common decimal base token amounts are normalized to before they are priced,
+/// so mints with different decimals compare correctly
+pub const COMMON_DECIMALS: u8 = 9;
+
+/// Utilization (in basis points) above which borrows emit a liquidity warning
+pub const HIGH_UTILIZATION_WARNING_BPS: u64 = 9000;
+
+/// Slots per year assumed by the interest model; every per-slot rate is derived from it
+pub const SLOTS_PER_YEAR: u128 = 800_000;
+
+/// Annual interest rate charged on user borrows (2%)
+pub const BORROW_RATE_ANNUAL_BPS: u64 = 200;
+
+/// Annual interest rate credited to the supply index (1%)
+pub const SUPPLY_RATE_ANNUAL_BPS: u64 = 100;
+
+/// Per-slot interest rate charged on user borrows, scaled by SCALING_FACTOR
+pub const BORROW_RATE_PER_SLOT: u128 = annual_bps_to_per_slot_scaled(BORROW_RATE_ANNUAL_BPS);
+
+/// Per-slot interest rate credited to the supply index, scaled by SCALING_FACTOR
+pub const SUPPLY_RATE_PER_SLOT: u128 = annual_bps_to_per_slot_scaled(SUPPLY_RATE_ANNUAL_BPS);
+
+/// Maximum slots of interest accrued in one update (~1 day), bounds compounding cost
+pub const MAX_INTEREST_SLOTS: u64 = 216_000;
+
+/// Oracle staleness window new markets start with
+pub const DEFAULT_MAX_ORACLE_STALENESS_SLOTS: u64 = 100;
+
+/// Widest oracle staleness window a market may be configured with (~1 day)
+pub const MAX_ORACLE_STALENESS_SLOTS: u64 = 216_000;
+
+/// Widest oracle confidence interval new markets accept, relative to price (5%)
+pub const DEFAULT_MAX_CONFIDENCE_BPS: u64 = 500;
+
+/// Confidence interval the mock oracle attaches to each price it's given, relative to price (1%)
+pub const ORACLE_CONFIDENCE_BPS: u64 = 100;
+
+/// Most decimals an oracle price may carry: 10^18 keeps scaled prices well inside u128
+pub const MAX_ORACLE_DECIMALS: u8 = 18;
+
+/// Flash loan fee new markets start with (0.3%)
+pub const DEFAULT_FLASH_LOAN_FEE_BPS: u64 = 30;
+
+/// Liquidation bonus new markets start with (10%)
+pub const DEFAULT_LIQUIDATION_BONUS_BPS: u64 = 1000;
+
+/// Residual debt below which new markets treat a repayment as settling the loan
+pub const DEFAULT_DUST_THRESHOLD: u64 = 100;
+
+/// Largest liquidation bonus a market may be configured with (20%)
+pub const MAX_LIQUIDATION_BONUS_BPS: u64 = 2000;
+
+/// Annual borrow rate ceiling new markets start with: the model's own rate (2%), so a
+/// rate model that charges more only applies once the market admin raises the ceiling
+pub const DEFAULT_MAX_BORROW_RATE_BPS: u64 = BORROW_RATE_ANNUAL_BPS;
+
+/// Highest borrow rate ceiling a market may be configured with (1000%)
+pub const MAX_BORROW_RATE_CAP_BPS: u64 = 100_000;
+
+/// Longest liquidation grace period a market may be configured with (~1 hour)
+pub const MAX_GRACE_PERIOD_SLOTS: u64 = 9_000;
+
+/// Share of a position's debt one batched liquidation may repay (50%)
+pub const LIQUIDATION_CLOSE_FACTOR_BPS: u64 = 5000;
+
+/// Most borrower positions `liquidate_batch` handles in one call, keeps it within compute limits
+pub const MAX_LIQUIDATION_BATCH: usize = 5;
+
+/// Convert an annual rate in basis points to a per-slot rate scaled by SCALING_FACTOR,
+/// spreading it over SLOTS_PER_YEAR (rounds down)
+pub const fn annual_bps_to_per_slot_scaled(bps: u64) -> u128 {
+    bps as u128 * SCALING_FACTOR / 10000 / SLOTS_PER_YEAR
+}
+
+/// Update market interest rates with simple flat rates: 1% supply, 2% borrow,
+/// emitting an `InterestAccruedEvent` whenever the rates actually move
 pub fn update_market_interest(market: &mut Market) -> Result<()> {
+    if let Some(accrued) = accrue_market_interest(market)? {
+        emit!(accrued);
+    }
+    Ok(())
+}
+
+/// Advance the market's cumulative rates to the current slot without emitting anything,
+/// for read paths that accrue on a copy. Returns `None` when no slots have passed.
+pub fn accrue_market_interest(market: &mut Market) -> Result<Option<InterestAccruedEvent>> {
     let current_slot = Clock::get()?.slot;
     let slots_elapsed = current_slot - market.last_update_slot;
 
     if slots_elapsed == 0 {
-        return Ok(());
+        return Ok(None);
     }
 
     // Simple flat rates: 2% borrow, 1% supply (annual rates)
     // Convert to per-slot rates (very small increments)
-    // Assuming ~800,000 slots per year (400ms slots), rates per slot:
-    let borrow_rate_per_slot = 25u128; // ~2% annual / 800,000 slots * 1e9 scale
-    let supply_rate_per_slot = 12u128; // ~1% annual / 800,000 slots * 1e9 scale
+    let borrow_rate_per_slot = borrow_rate_per_slot(market);
+    let supply_rate_per_slot = SUPPLY_RATE_PER_SLOT;
 
     // Limit slots to prevent any overflow (max 1 day worth of slots)
-    let slots_elapsed = slots_elapsed.min(216000); // ~1 day of slots
+    let slots_elapsed = slots_elapsed.min(MAX_INTEREST_SLOTS);
     let slots_elapsed_u128 = slots_elapsed as u128;
 
     // Update cumulative borrow rate
@@ -37,7 +123,14 @@ pub fn update_market_interest(market: &mut Market) -> Result<()> {
         .saturating_add(supply_increment);
 
     market.last_update_slot = current_slot;
-    Ok(())
+    Ok(Some(InterestAccruedEvent {
+        market_id: market.market_id,
+        slots_elapsed,
+        borrow_increment,
+        supply_increment,
+        new_borrow_rate: market.cumulative_borrow_rate,
+        new_supply_rate: market.cumulative_supply_rate,
+    }))
 }
 
 pub fn update_market_interest_readonly(_market: &Market) -> Result<()> {
@@ -46,28 +139,493 @@ pub fn update_market_interest_readonly(_market: &Market) -> Result<()> {
 }
 
 /// Get asset price from oracle with proper validation
-pub fn get_asset_price(oracle_account: &AccountInfo) -> Result<u128> {
+pub fn get_asset_price(
+    oracle_account: &AccountInfo,
+    max_staleness_slots: u64,
+    max_confidence_bps: u64,
+) -> Result<u128> {
+    Ok(load_valid_oracle(oracle_account, max_staleness_slots, max_confidence_bps)?.price)
+}
+
+/// Get asset price from oracle rescaled to `target_decimals`, so prices from
+/// oracles with different decimal bases can be compared directly
+pub fn get_asset_price_scaled(
+    oracle_account: &AccountInfo,
+    target_decimals: u8,
+    max_staleness_slots: u64,
+    max_confidence_bps: u64,
+) -> Result<u128> {
+    let oracle = load_valid_oracle(oracle_account, max_staleness_slots, max_confidence_bps)?;
+    scale_price(oracle.price, oracle.decimals, target_decimals)
+}
+
+/// Reject an oracle whose price is older than the staleness window with `stale_error`,
+/// so callers can report which of their feeds is lagging
+pub fn require_fresh_oracle(
+    oracle_account: &AccountInfo,
+    max_staleness_slots: u64,
+    stale_error: LendingError,
+) -> Result<()> {
+    let oracle = Oracle::try_deserialize(&mut &oracle_account.data.borrow()[..])?;
+    let current_slot = Clock::get()?.slot;
+    if !oracle.is_valid(current_slot, max_staleness_slots) {
+        msg!(
+            "Oracle {} is stale: last updated at slot {}",
+            oracle_account.key(),
+            oracle.valid_slot
+        );
+        return Err(stale_error.into());
+    }
+    Ok(())
+}
+
+/// Value of `amount` native units of a token with `decimals` at a PRICE_DECIMALS price,
+/// in quote units scaled by PRICE_DECIMALS (rounds down)
+pub fn token_value(amount: u128, decimals: u8, price: u128) -> Result<u128> {
+    let unit = 10u128
+        .checked_pow(decimals as u32)
+        .ok_or(LendingError::MathOverflow)?;
+    amount
+        .checked_mul(price)
+        .map(|v| v / unit)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Rescale a token amount from its mint's decimals to `COMMON_DECIMALS`
+/// (rounds down for mints with more decimals)
+pub fn to_common_units(amount: u128, mint_decimals: u8) -> Result<u128> {
+    scale_price(amount, mint_decimals, COMMON_DECIMALS)
+}
+
+/// Rescale a price from one decimal base to another (rounds down when reducing precision)
+pub fn scale_price(price: u128, from_decimals: u8, to_decimals: u8) -> Result<u128> {
+    if from_decimals == to_decimals {
+        return Ok(price);
+    }
+
+    let diff = from_decimals.abs_diff(to_decimals) as u32;
+    let factor = 10u128.checked_pow(diff).ok_or(LendingError::MathOverflow)?;
+
+    if to_decimals > from_decimals {
+        price
+            .checked_mul(factor)
+            .ok_or(LendingError::MathOverflow.into())
+    } else {
+        Ok(price / factor)
+    }
+}
+
+/// Deserialize an oracle account and reject stale or low-confidence prices
+fn load_valid_oracle(
+    oracle_account: &AccountInfo,
+    max_staleness_slots: u64,
+    max_confidence_bps: u64,
+) -> Result<Oracle> {
     // Deserialize oracle account
     let oracle = Oracle::try_deserialize(&mut &oracle_account.data.borrow()[..])?;
-    // Check if oracle data is still valid (within 100 slots)
+    // Check if oracle data is still valid (within the market's staleness window)
     let current_slot = Clock::get()?.slot;
-    if !oracle.is_valid(current_slot, 100) {
+    if !oracle.is_valid(current_slot, max_staleness_slots) {
         msg!("Oracle data is stale");
         return Err(LendingError::InvalidOracleData.into());
     }
 
     // Additional validation: check confidence is within acceptable bounds
-    // Reject price if confidence interval is too wide (>5% of price)
-    if oracle.confidence > oracle.price / 20 {
+    // Reject price if confidence interval is wider than the market allows
+    let max_confidence = max_confidence_interval(oracle.price, max_confidence_bps)?;
+    if oracle.confidence > max_confidence {
         msg!(
             "Oracle confidence too wide: {} > {}",
             oracle.confidence,
-            oracle.price / 20
+            max_confidence
         );
         return Err(LendingError::InvalidOracleData.into());
     }
 
-    Ok(oracle.price)
+    Ok(oracle)
+}
+
+/// `Oracle.source` prefix marking a Pyth-backed oracle; the feed's price account address follows it
+pub const PYTH_SOURCE_TAG: &[u8] = b"pyth:";
+
+/// Magic number at the start of every Pyth price account
+pub const PYTH_MAGIC: u32 = 0xa1b2_c3d4;
+
+// Pyth price account layout (little endian): only the fields read here
+const PYTH_EXPO_OFFSET: usize = 20; // i32
+const PYTH_AGG_PRICE_OFFSET: usize = 208; // i64
+const PYTH_AGG_CONF_OFFSET: usize = 216; // u64
+const PYTH_AGG_STATUS_OFFSET: usize = 224; // u32
+const PYTH_AGG_PUB_SLOT_OFFSET: usize = 232; // u64
+const PYTH_PRICE_ACCOUNT_MIN_LEN: usize = 240;
+const PYTH_STATUS_TRADING: u32 = 1;
+
+/// Price normalized to PRICE_DECIMALS. Oracles whose `source` is tagged as Pyth are
+/// read from `external_account` (which must be the tagged feed), anything else falls
+/// back to the program's own oracle account.
+pub fn get_external_price(
+    oracle_account: &AccountInfo,
+    external_account: Option<&AccountInfo>,
+    max_staleness_slots: u64,
+    max_confidence_bps: u64,
+) -> Result<u128> {
+    let oracle = Oracle::try_deserialize(&mut &oracle_account.data.borrow()[..])?;
+
+    let Some(feed) = oracle.source.strip_prefix(PYTH_SOURCE_TAG) else {
+        return get_asset_price_scaled(
+            oracle_account,
+            PRICE_DECIMALS,
+            max_staleness_slots,
+            max_confidence_bps,
+        );
+    };
+
+    let feed = Pubkey::try_from(feed).map_err(|_| LendingError::InvalidOracleData)?;
+    let external_account = external_account.ok_or(LendingError::InvalidOracleData)?;
+    require_keys_eq!(
+        external_account.key(),
+        feed,
+        LendingError::InvalidOracleData
+    );
+
+    parse_pyth_price(
+        &external_account.data.borrow(),
+        Clock::get()?.slot,
+        max_staleness_slots,
+        max_confidence_bps,
+    )
+}
+
+/// Read the aggregate price from a Pyth price account and normalize it to PRICE_DECIMALS,
+/// rejecting prices that aren't trading, are stale, or have too wide a confidence interval
+pub fn parse_pyth_price(
+    data: &[u8],
+    current_slot: u64,
+    max_staleness_slots: u64,
+    max_confidence_bps: u64,
+) -> Result<u128> {
+    require!(
+        data.len() >= PYTH_PRICE_ACCOUNT_MIN_LEN,
+        LendingError::InvalidOracleData
+    );
+    let read = |offset: usize, len: usize| &data[offset..offset + len];
+    let read_u32 = |offset: usize| u32::from_le_bytes(read(offset, 4).try_into().unwrap());
+    let read_u64 = |offset: usize| u64::from_le_bytes(read(offset, 8).try_into().unwrap());
+
+    require!(read_u32(0) == PYTH_MAGIC, LendingError::InvalidOracleData);
+
+    let expo = i32::from_le_bytes(read(PYTH_EXPO_OFFSET, 4).try_into().unwrap());
+    let price = read_u64(PYTH_AGG_PRICE_OFFSET) as i64;
+    let conf = read_u64(PYTH_AGG_CONF_OFFSET);
+    let status = read_u32(PYTH_AGG_STATUS_OFFSET);
+    let publish_slot = read_u64(PYTH_AGG_PUB_SLOT_OFFSET);
+
+    if status != PYTH_STATUS_TRADING || price <= 0 {
+        msg!("Pyth price unavailable: status {}, price {}", status, price);
+        return Err(LendingError::InvalidOracleData.into());
+    }
+    if current_slot > publish_slot.saturating_add(max_staleness_slots) {
+        msg!("Pyth price is stale: published at slot {}", publish_slot);
+        return Err(LendingError::InvalidOracleData.into());
+    }
+
+    let price = price as u128;
+    let max_confidence = max_confidence_interval(price, max_confidence_bps)?;
+    if conf as u128 > max_confidence {
+        msg!("Pyth confidence too wide: {} > {}", conf, max_confidence);
+        return Err(LendingError::InvalidOracleData.into());
+    }
+
+    // A negative exponent is a decimal count, a positive one scales the integer price up
+    if expo <= 0 {
+        let decimals = u8::try_from(-(expo as i64)).map_err(|_| LendingError::MathOverflow)?;
+        scale_price(price, decimals, PRICE_DECIMALS)
+    } else {
+        let whole = 10u128
+            .checked_pow(expo as u32)
+            .and_then(|factor| price.checked_mul(factor))
+            .ok_or(LendingError::MathOverflow)?;
+        scale_price(whole, 0, PRICE_DECIMALS)
+    }
+}
+
+/// Widest confidence interval accepted for `price` at `max_confidence_bps`
+pub fn max_confidence_interval(price: u128, max_confidence_bps: u64) -> Result<u128> {
+    price
+        .checked_mul(max_confidence_bps as u128)
+        .map(|v| v / 10000)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// How far `new_price` is from `previous_price`, in basis points of the previous price
+pub fn price_deviation_bps(previous_price: u128, new_price: u128) -> Result<u128> {
+    require!(previous_price > 0, LendingError::InvalidOracleData);
+    previous_price
+        .abs_diff(new_price)
+        .checked_mul(10000)
+        .map(|v| v / previous_price)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Collateral price relative to the borrow price, in basis points
+pub fn price_ratio_bps(collateral_price: u128, borrow_price: u128) -> Result<u128> {
+    require!(borrow_price > 0, LendingError::InvalidOracleData);
+    collateral_price
+        .checked_mul(10000)
+        .map(|v| v / borrow_price)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Reject oracle prices whose collateral/borrow ratio falls outside the market's band,
+/// which catches one feed going bad while both still look fresh
+pub fn check_price_ratio(
+    market: &Market,
+    collateral_price: u128,
+    borrow_price: u128,
+) -> Result<()> {
+    let ratio = price_ratio_bps(collateral_price, borrow_price)?;
+    require!(
+        ratio >= market.min_price_ratio_bps as u128 && ratio <= market.max_price_ratio_bps as u128,
+        LendingError::InvalidOracleData
+    );
+    Ok(())
+}
+
+/// Collateral (in collateral mint units) a liquidator seizes for repaying `liquidation_amount`
+/// of debt: the repaid value plus the bonus, converted through both oracle prices.
+/// Left in u128 so callers cap it at the borrower's collateral before narrowing.
+pub fn calculate_collateral_to_seize(
+    liquidation_amount: u64,
+    bonus_bps: u64,
+    borrow_price: u128,
+    collateral_price: u128,
+    supply_decimals: u8,
+    collateral_decimals: u8,
+) -> Result<u128> {
+    require!(collateral_price > 0, LendingError::InvalidOracleData);
+    let seize_value = to_common_units(liquidation_amount as u128, supply_decimals)?
+        .checked_mul(borrow_price)
+        .and_then(|v| v.checked_mul(10000 + bonus_bps as u128))
+        .map(|v| v / 10000)
+        .ok_or(LendingError::MathOverflow)?;
+    scale_price(
+        seize_value / collateral_price,
+        COMMON_DECIMALS,
+        collateral_decimals,
+    )
+}
+
+/// Fee owed on a flash loan of `amount` at `fee_bps`
+pub fn calculate_flash_loan_fee(amount: u64, fee_bps: u64) -> Result<u64> {
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(LendingError::MathOverflow)?
+        / 10000;
+    u64::try_from(fee).map_err(|_| LendingError::MathOverflow.into())
+}
+
+/// Loyalty bonus on `amount` withdrawn from a supply position held for `held_slots`.
+/// The bonus rate ramps linearly from 0 at `threshold_slots` up to `max_bonus_bps`
+/// at twice the threshold, and stays capped there.
+pub fn calculate_loyalty_bonus(
+    amount: u128,
+    held_slots: u64,
+    threshold_slots: u64,
+    max_bonus_bps: u64,
+) -> Result<u128> {
+    if threshold_slots == 0 || held_slots <= threshold_slots {
+        return Ok(0);
+    }
+
+    let ramp_slots = (held_slots - threshold_slots).min(threshold_slots) as u128;
+    amount
+        .checked_mul(max_bonus_bps as u128)
+        .and_then(|v| v.checked_mul(ramp_slots))
+        .map(|v| v / 10000 / threshold_slots as u128)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Open slot of a supply position after `added` cTokens join `existing` ones,
+/// weighted by size so topping up an old position can't inherit its loyalty
+pub fn blend_opened_slot(
+    opened_slot: u64,
+    existing: u128,
+    current_slot: u64,
+    added: u128,
+) -> Result<u64> {
+    let total = existing
+        .checked_add(added)
+        .ok_or(LendingError::MathOverflow)?;
+    if existing == 0 || total == 0 {
+        return Ok(current_slot);
+    }
+
+    let weighted = (opened_slot as u128)
+        .checked_mul(existing)
+        .and_then(|v| v.checked_add((current_slot as u128).checked_mul(added)?))
+        .ok_or(LendingError::MathOverflow)?;
+    Ok((weighted / total) as u64)
+}
+
+/// Supply index of a position after `added` principal joins `existing` principal at
+/// `current_index`, weighted by size like `blend_opened_slot`
+pub fn blend_supply_index(
+    supply_index: u128,
+    existing: u128,
+    current_index: u128,
+    added: u128,
+) -> Result<u128> {
+    let total = existing
+        .checked_add(added)
+        .ok_or(LendingError::MathOverflow)?;
+    if existing == 0 || total == 0 {
+        return Ok(current_index);
+    }
+
+    let weighted = supply_index
+        .checked_mul(existing)
+        .and_then(|v| v.checked_add(current_index.checked_mul(added)?))
+        .ok_or(LendingError::MathOverflow)?;
+    Ok(weighted / total)
+}
+
+/// Interest `principal` earned while the supply index grew from `user_index` to `market_index`
+pub fn calculate_index_interest(
+    principal: u128,
+    user_index: u128,
+    market_index: u128,
+) -> Result<u128> {
+    if user_index == 0 {
+        return Ok(0);
+    }
+    principal
+        .checked_mul(market_index.saturating_sub(user_index))
+        .and_then(|v| v.checked_div(user_index))
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Calculate market utilization (borrows / supply) in basis points
+pub fn calculate_utilization_bps(total_borrows: u128, total_supply_deposits: u128) -> Result<u64> {
+    if total_supply_deposits == 0 {
+        return Ok(0);
+    }
+
+    let utilization = total_borrows
+        .checked_mul(10000)
+        .and_then(|v| v.checked_div(total_supply_deposits))
+        .ok_or(LendingError::MathOverflow)?;
+
+    Ok(utilization.min(u64::MAX as u128) as u64)
+}
+
+/// Current utilization (borrows / supply) scaled by SCALING_FACTOR, capped at 100%
+pub fn current_utilization(market: &Market) -> u128 {
+    if market.total_supply_deposits == 0 {
+        return 0;
+    }
+
+    market
+        .total_borrows
+        .saturating_mul(SCALING_FACTOR)
+        .checked_div(market.total_supply_deposits)
+        .unwrap_or(0)
+        .min(SCALING_FACTOR)
+}
+
+/// Reject a borrow rate ceiling of zero (no borrow would ever accrue) or above
+/// MAX_BORROW_RATE_CAP_BPS
+pub fn check_max_borrow_rate_bps(max_borrow_rate_bps: u64) -> Result<()> {
+    require!(
+        max_borrow_rate_bps > 0 && max_borrow_rate_bps <= MAX_BORROW_RATE_CAP_BPS,
+        LendingError::InvalidBorrowRateCap
+    );
+    Ok(())
+}
+
+/// Per-slot borrow rate scaled by SCALING_FACTOR, with the annual rate clamped to the
+/// market's `max_borrow_rate_bps` ceiling
+pub fn borrow_rate_per_slot(market: &Market) -> u128 {
+    annual_bps_to_per_slot_scaled(BORROW_RATE_ANNUAL_BPS.min(market.max_borrow_rate_bps))
+}
+
+/// Simple annual borrow rate scaled by SCALING_FACTOR: the per-slot rate times a year
+/// of slots, without compounding
+pub fn borrow_apr(market: &Market) -> u128 {
+    borrow_rate_per_slot(market) * SLOTS_PER_YEAR
+}
+
+/// Simple annual supply rate scaled by SCALING_FACTOR: the borrow APR spread over all
+/// supplied liquidity
+pub fn supply_apr(market: &Market) -> u128 {
+    borrow_apr(market).saturating_mul(current_utilization(market)) / SCALING_FACTOR
+}
+
+/// Borrow APY scaled by SCALING_FACTOR: the per-slot rate compounded every slot for a
+/// year, the way `compound_interest` grows debt
+pub fn borrow_apy(market: &Market) -> u128 {
+    apr_to_apy(borrow_apr(market), SLOTS_PER_YEAR as u64)
+}
+
+/// Supply APY scaled by SCALING_FACTOR: suppliers earn what borrowers pay, so the
+/// compounded borrow yield spread over all supplied liquidity
+pub fn supply_apy(market: &Market) -> u128 {
+    borrow_apy(market).saturating_mul(current_utilization(market)) / SCALING_FACTOR
+}
+
+/// `base^exponent` for a base scaled by SCALING_FACTOR, saturating at u128::MAX.
+/// Bases are at least 1.0 here, so once a step overflows the true power does too.
+fn pow_scaled_saturating(mut base: u128, mut exponent: u64) -> u128 {
+    let mut result = SCALING_FACTOR;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = match result.checked_mul(base) {
+                Some(v) => v / SCALING_FACTOR,
+                None => return u128::MAX,
+            };
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = match base.checked_mul(base) {
+                Some(v) => v / SCALING_FACTOR,
+                None => return u128::MAX,
+            };
+        }
+    }
+    result
+}
+
+/// Compounded yearly yield for a simple annual rate, both scaled by SCALING_FACTOR:
+/// (1 + apr / n)^n - 1. Zero compounds means no compounding; saturates at u128::MAX.
+pub fn apr_to_apy(apr_scaled: u128, compounds_per_year: u64) -> u128 {
+    if compounds_per_year == 0 {
+        return apr_scaled;
+    }
+    let periodic_rate = apr_scaled / compounds_per_year as u128;
+    match pow_scaled_saturating(
+        SCALING_FACTOR.saturating_add(periodic_rate),
+        compounds_per_year,
+    ) {
+        u128::MAX => u128::MAX,
+        growth => growth - SCALING_FACTOR,
+    }
+}
+
+/// Inverse of `apr_to_apy`: the simple annual rate that compounds `compounds_per_year`
+/// times to `apy_scaled`, rounded down
+pub fn apy_to_apr(apy_scaled: u128, compounds_per_year: u64) -> u128 {
+    if compounds_per_year == 0 {
+        return apy_scaled;
+    }
+    let target = SCALING_FACTOR.saturating_add(apy_scaled);
+    // Compounding only adds yield, so the periodic rate never exceeds the APY
+    let periodic_rate = max_amount_where(apy_scaled, |rate| {
+        Ok(
+            pow_scaled_saturating(SCALING_FACTOR.saturating_add(rate), compounds_per_year)
+                <= target,
+        )
+    })
+    .unwrap_or(0);
+    periodic_rate.saturating_mul(compounds_per_year as u128)
 }
 
 /// Calculate exchange rate for cTokens - simplified version
@@ -77,22 +635,19 @@ pub fn calculate_exchange_rate(market: &Market) -> Result<u128> {
     }
 
     // Simple exchange rate: total_supply_deposits / total_ctoken_supply
-    // This naturally appreciates as interest is added to total_supply_deposits
+    // This naturally appreciates as interest is added to total_supply_deposits,
+    // and drops below 1:1 when bad debt is socialized to suppliers
     let scaled_deposits = market.total_supply_deposits.checked_mul(SCALING_FACTOR);
     let exchange_rate = scaled_deposits
         .and_then(|v| v.checked_div(market.total_ctoken_supply))
         .unwrap_or(SCALING_FACTOR);
-
-    // Ensure rate never goes below 1:1
-    let final_rate = if exchange_rate > SCALING_FACTOR {
-        exchange_rate
-    } else {
-        SCALING_FACTOR
-    };
-    Ok(final_rate)
+    Ok(exchange_rate)
 }
 
 /// Calculate how many cTokens to mint for a given supply amount
+///
+/// Rounds down: rounding up would hand the supplier a fraction of a cToken they
+/// didn't pay for, which an immediate withdraw turns into extracted tokens.
 pub fn calculate_ctokens_to_mint(supply_amount: u64, exchange_rate: u128) -> Result<u128> {
     let supply_amount_u128 = supply_amount as u128;
     let numerator = supply_amount_u128
@@ -100,15 +655,16 @@ pub fn calculate_ctokens_to_mint(supply_amount: u64, exchange_rate: u128) -> Res
         .ok_or(LendingError::MathOverflow)?;
 
     let ctokens = numerator
-        .checked_add(exchange_rate)
-        .and_then(|v| v.checked_sub(1))
-        .and_then(|v| v.checked_div(exchange_rate))
+        .checked_div(exchange_rate)
         .ok_or(LendingError::MathOverflow)?;
 
     Ok(ctokens)
 }
 
 /// Calculate how many underlying tokens to return for cToken redemption
+///
+/// Rounds down, so together with minting a supply/withdraw round trip never
+/// returns more than was supplied.
 pub fn calculate_underlying_from_ctokens(ctoken_amount: u128, exchange_rate: u128) -> Result<u128> {
     ctoken_amount
         .checked_mul(exchange_rate)
@@ -116,6 +672,32 @@ pub fn calculate_underlying_from_ctokens(ctoken_amount: u128, exchange_rate: u12
         .ok_or(LendingError::MathOverflow.into())
 }
 
+/// cTokens that redeem for at least `underlying_amount` tokens
+///
+/// Rounds up, so the cTokens held back to cover an amount always redeem for all of it.
+pub fn calculate_ctokens_to_cover(underlying_amount: u128, exchange_rate: u128) -> Result<u128> {
+    let numerator = underlying_amount
+        .checked_mul(SCALING_FACTOR)
+        .ok_or(LendingError::MathOverflow)?;
+    require!(exchange_rate > 0, LendingError::MathOverflow);
+    Ok(numerator.div_ceil(exchange_rate))
+}
+
+/// Largest amount in `0..=upper` for which `fits` holds, assuming `fits` is monotonic
+/// (true up to some amount, false beyond it) and true at zero
+pub fn max_amount_where(upper: u128, fits: impl Fn(u128) -> Result<bool>) -> Result<u128> {
+    let (mut low, mut high) = (0u128, upper);
+    while low < high {
+        let mid = low + (high - low).div_ceil(2);
+        if fits(mid)? {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Ok(low)
+}
+
 /// Calculate health factor for liquidation
 pub fn calculate_health_factor(
     collateral_value: u128,
@@ -133,6 +715,131 @@ pub fn calculate_health_factor(
     Ok(threshold_value * SCALING_FACTOR / borrow_value)
 }
 
+/// Compound `principal` by `rate_per_slot` (scaled by SCALING_FACTOR) for `slots` slots:
+/// principal * (1 + rate)^slots, using fixed-point exponentiation by squaring.
+/// Slots are capped at MAX_INTEREST_SLOTS like the market index.
+pub fn compound_interest(principal: u128, rate_per_slot: u128, slots: u64) -> Result<u128> {
+    let mut exponent = slots.min(MAX_INTEREST_SLOTS);
+    let mut base = SCALING_FACTOR
+        .checked_add(rate_per_slot)
+        .ok_or(LendingError::MathOverflow)?;
+    let mut factor = SCALING_FACTOR;
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            factor = factor.checked_mul(base).ok_or(LendingError::MathOverflow)? / SCALING_FACTOR;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = base.checked_mul(base).ok_or(LendingError::MathOverflow)? / SCALING_FACTOR;
+        }
+    }
+
+    principal
+        .checked_mul(factor)
+        .map(|v| v / SCALING_FACTOR)
+        .ok_or(LendingError::MathOverflow.into())
+}
+
+/// Debt including per-slot user interest accrued since `last_update_slot`,
+/// computed without writing anything back (for read-only queries)
+pub fn calculate_accrued_debt(
+    borrowed_amount: u128,
+    rate_per_slot: u128,
+    last_update_slot: u64,
+    current_slot: u64,
+) -> Result<u128> {
+    let slots_elapsed = current_slot.saturating_sub(last_update_slot);
+    compound_interest(borrowed_amount, rate_per_slot, slots_elapsed)
+}
+
+/// Single interest entry point for instructions acting on a user position: advances the
+/// market's cumulative rates, then books the user's debt interest up to the same slot.
+///
+/// Invariant: `user_deposit.last_update_slot <= market.last_update_slot`. Accruing both
+/// together keeps it, so no ordering of calls can move the market forward while the
+/// user's debt is left behind.
+///
+/// The interest booked on the debt is the only source of supplier yield: it is added to
+/// both `total_borrows` and `total_supply_deposits`, so deposits stay backed by the vault
+/// plus what borrowers owe.
+pub fn accrue_interest(market: &mut Market, user_deposit: &mut UserDeposit) -> Result<()> {
+    update_market_interest(market)?;
+
+    let current_slot = Clock::get()?.slot;
+    if user_deposit.borrowed_amount > 0 {
+        let slots_elapsed =
+            current_slot.saturating_sub(interest_accrual_start(market, user_deposit));
+        let accrued_debt = compound_interest(
+            user_deposit.borrowed_amount,
+            borrow_rate_per_slot(market),
+            slots_elapsed,
+        )?;
+        book_borrow_interest(market, accrued_debt - user_deposit.borrowed_amount)?;
+        user_deposit.borrowed_amount = accrued_debt;
+    }
+    user_deposit.last_update_slot = current_slot;
+
+    require!(
+        user_deposit.last_update_slot <= market.last_update_slot,
+        LendingError::InvalidMarketState
+    );
+    Ok(())
+}
+
+/// Realize borrow interest: the debt grows, and suppliers earn exactly that amount
+pub fn book_borrow_interest(market: &mut Market, interest: u128) -> Result<()> {
+    market.total_borrows = market
+        .total_borrows
+        .checked_add(interest)
+        .ok_or(LendingError::MathOverflow)?;
+    credit_suppliers(market, interest)
+}
+
+/// Add `earnings` to supply deposits and grow the supply index by the same proportion,
+/// so the index moves exactly with the cToken exchange rate
+pub fn credit_suppliers(market: &mut Market, earnings: u128) -> Result<()> {
+    if market.total_supply_deposits > 0 {
+        let growth = market
+            .supply_index
+            .checked_mul(earnings)
+            .and_then(|v| v.checked_div(market.total_supply_deposits))
+            .ok_or(LendingError::MathOverflow)?;
+        market.supply_index = market
+            .supply_index
+            .checked_add(growth)
+            .ok_or(LendingError::MathOverflow)?;
+    }
+    market.total_supply_deposits = market
+        .total_supply_deposits
+        .checked_add(earnings)
+        .ok_or(LendingError::MathOverflow)?;
+    Ok(())
+}
+
+/// Slot from which the user's debt accrues interest: the last update, pushed past
+/// the market's interest-free window that starts when the loan opens
+pub fn interest_accrual_start(market: &Market, user_deposit: &UserDeposit) -> u64 {
+    let free_until = user_deposit
+        .loan_start_slot
+        .saturating_add(market.interest_free_slots);
+    user_deposit.last_update_slot.max(free_until)
+}
+
+/// Whether a repayment at `current_slot` falls in the loan's interest-free first-repayment window
+pub fn in_first_repayment_grace(
+    market: &Market,
+    user_deposit: &UserDeposit,
+    current_slot: u64,
+) -> bool {
+    market.first_repayment_grace_slots > 0
+        && !user_deposit.first_repayment_made
+        && current_slot
+            <= user_deposit
+                .loan_start_slot
+                .saturating_add(market.first_repayment_grace_slots)
+}
+
 /// Check if position is liquidatable
 pub fn is_liquidatable(
     collateral_value: u128,
@@ -144,6 +851,12 @@ pub fn is_liquidatable(
     borrow_value > threshold_value
 }
 
+/// Whether a position is safe from liquidation: the exact complement of `is_liquidatable`,
+/// so every health check and the liquidation path agree at the threshold
+pub fn is_healthy(collateral_value: u128, borrow_value: u128, liquidation_threshold: u64) -> bool {
+    !is_liquidatable(collateral_value, borrow_value, liquidation_threshold)
+}
+
 /// Calculate maximum borrowable amount
 pub fn calculate_max_borrow(
     collateral_value: u128,
@@ -158,3 +871,800 @@ pub fn calculate_max_borrow(
         0
     }
 }
+
+/// Fail if the collateral vault holds less than the collateral the market has recorded.
+/// Surplus (e.g. direct sends) is allowed and can be swept by the market admin.
+pub fn check_collateral_backing(vault_amount: u64, total_collateral_deposits: u128) -> Result<()> {
+    require!(
+        vault_amount as u128 >= total_collateral_deposits,
+        LendingError::InvalidMarketState
+    );
+    Ok(())
+}
+
+/// Split a bad-debt shortfall into the part reserves cover and the part
+/// socialized to suppliers: `(covered_by_reserves, socialized)`
+pub fn split_bad_debt(shortfall: u128, total_reserves: u128) -> (u128, u128) {
+    let covered = shortfall.min(total_reserves);
+    (covered, shortfall - covered)
+}
+
+/// Write off a shortfall against reserves first, then supplier deposits
+pub fn absorb_bad_debt(market: &mut Market, shortfall: u128) -> Result<(u128, u128)> {
+    let (covered, socialized) = split_bad_debt(shortfall, market.total_reserves);
+    market.total_reserves = market
+        .total_reserves
+        .checked_sub(covered)
+        .ok_or(LendingError::MathOverflow)?;
+    // Only the uncovered remainder lowers the cToken exchange rate, and the supply index with it
+    if market.total_supply_deposits > 0 {
+        let loss = market
+            .supply_index
+            .checked_mul(socialized.min(market.total_supply_deposits))
+            .and_then(|v| v.checked_div(market.total_supply_deposits))
+            .ok_or(LendingError::MathOverflow)?;
+        market.supply_index -= loss;
+    }
+    market.total_supply_deposits = market.total_supply_deposits.saturating_sub(socialized);
+    market.total_borrows = market.total_borrows.saturating_sub(shortfall);
+    market.total_bad_debt = market
+        .total_bad_debt
+        .checked_add(shortfall)
+        .ok_or(LendingError::MathOverflow)?;
+    Ok((covered, socialized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_price_same_decimals_is_identity() {
+        assert_eq!(scale_price(1_234_567, 6, 6).unwrap(), 1_234_567);
+    }
+
+    #[test]
+    fn scale_price_up_to_more_decimals() {
+        // $3000 at 6 decimals -> 9 decimals
+        assert_eq!(scale_price(3_000_000_000, 6, 9).unwrap(), 3_000_000_000_000);
+        // $1 at 0 decimals -> 18 decimals
+        assert_eq!(scale_price(1, 0, 18).unwrap(), 1_000_000_000_000_000_000);
+    }
+
+    #[test]
+    fn scale_price_down_to_fewer_decimals_rounds_down() {
+        // $1.23456789 at 8 decimals -> 6 decimals
+        assert_eq!(scale_price(123_456_789, 8, 6).unwrap(), 1_234_567);
+        // 18-decimal price -> 9 decimals
+        assert_eq!(
+            scale_price(2_500_000_000_000_000_000, 18, 9).unwrap(),
+            2_500_000_000
+        );
+    }
+
+    #[test]
+    fn scale_price_prices_become_comparable() {
+        // The same $1.50 reported with different decimal bases normalizes to the same value
+        let six = scale_price(1_500_000, 6, PRICE_DECIMALS).unwrap();
+        let eight = scale_price(150_000_000, 8, PRICE_DECIMALS).unwrap();
+        let eighteen = scale_price(1_500_000_000_000_000_000, 18, PRICE_DECIMALS).unwrap();
+        assert_eq!(six, eight);
+        assert_eq!(six, eighteen);
+    }
+
+    #[test]
+    fn scale_price_overflow_is_an_error() {
+        assert!(scale_price(u128::MAX, 0, 1).is_err());
+    }
+
+    #[test]
+    fn accrued_debt_adds_per_slot_interest() {
+        // 25 / 1e9 per slot on 1e9 borrowed for 4 slots
+        assert_eq!(
+            calculate_accrued_debt(1_000_000_000, BORROW_RATE_PER_SLOT, 100, 104).unwrap(),
+            1_000_000_100
+        );
+        // No time elapsed (or a clock behind the last update) accrues nothing
+        assert_eq!(
+            calculate_accrued_debt(1_000_000_000, BORROW_RATE_PER_SLOT, 100, 100).unwrap(),
+            1_000_000_000
+        );
+        assert_eq!(
+            calculate_accrued_debt(1_000_000_000, BORROW_RATE_PER_SLOT, 100, 90).unwrap(),
+            1_000_000_000
+        );
+    }
+
+    fn simple_interest(principal: u128, rate_per_slot: u128, slots: u64) -> u128 {
+        principal + principal * rate_per_slot * slots as u128 / SCALING_FACTOR
+    }
+
+    #[test]
+    fn compound_interest_exceeds_simple_over_100k_slots() {
+        let principal = 1_000_000_000_000u128; // 1M tokens at 6 decimals
+        let simple = simple_interest(principal, BORROW_RATE_PER_SLOT, 100_000);
+        let compound = compound_interest(principal, BORROW_RATE_PER_SLOT, 100_000).unwrap();
+        assert!(
+            compound > simple,
+            "compound {} <= simple {}",
+            compound,
+            simple
+        );
+        // ...but only by the small second-order term at this rate
+        assert!(compound - simple < simple / 1_000);
+    }
+
+    #[test]
+    fn compound_interest_does_not_overflow_on_large_balances() {
+        // u64::MAX principal with a full day of slots stays in range
+        let principal = u64::MAX as u128;
+        let compound =
+            compound_interest(principal, BORROW_RATE_PER_SLOT, MAX_INTEREST_SLOTS).unwrap();
+        assert!(compound > principal);
+    }
+
+    #[test]
+    fn compound_interest_is_identity_for_zero_slots_or_rate() {
+        assert_eq!(
+            compound_interest(12_345, BORROW_RATE_PER_SLOT, 0).unwrap(),
+            12_345
+        );
+        assert_eq!(compound_interest(12_345, 0, 100_000).unwrap(), 12_345);
+    }
+
+    #[test]
+    fn compound_interest_caps_slots() {
+        let principal = 1_000_000_000u128;
+        assert_eq!(
+            compound_interest(principal, BORROW_RATE_PER_SLOT, 10 * MAX_INTEREST_SLOTS).unwrap(),
+            compound_interest(principal, BORROW_RATE_PER_SLOT, MAX_INTEREST_SLOTS).unwrap()
+        );
+    }
+
+    #[test]
+    fn split_bad_debt_reserves_cover_small_shortfall() {
+        assert_eq!(split_bad_debt(100, 500), (100, 0));
+        assert_eq!(split_bad_debt(500, 500), (500, 0));
+    }
+
+    #[test]
+    fn split_bad_debt_socializes_what_reserves_cannot_cover() {
+        assert_eq!(split_bad_debt(100, 30), (30, 70));
+        assert_eq!(split_bad_debt(100, 0), (0, 100));
+    }
+
+    #[test]
+    fn check_collateral_backing_allows_exact_and_surplus() {
+        assert!(check_collateral_backing(1_000, 1_000).is_ok());
+        assert!(check_collateral_backing(1_500, 1_000).is_ok());
+    }
+
+    #[test]
+    fn check_collateral_backing_rejects_drift() {
+        // Vault lost collateral the market still thinks it holds
+        assert!(check_collateral_backing(999, 1_000).is_err());
+        assert!(check_collateral_backing(0, 1).is_err());
+    }
+
+    #[test]
+    fn price_deviation_is_relative_to_previous_price() {
+        assert_eq!(price_deviation_bps(1_000, 1_050).unwrap(), 500);
+        assert_eq!(price_deviation_bps(1_000, 950).unwrap(), 500);
+        assert_eq!(price_deviation_bps(1_000, 1_000).unwrap(), 0);
+        // Doubling is a 100% move, a crash to zero is too
+        assert_eq!(price_deviation_bps(1_000, 2_000).unwrap(), 10000);
+        assert_eq!(price_deviation_bps(1_000, 0).unwrap(), 10000);
+        assert!(price_deviation_bps(0, 1_000).is_err());
+    }
+
+    #[test]
+    fn token_value_normalizes_mint_decimals() {
+        // 750 USDC (6 decimals) at $1 and 1 ETH (9 decimals) at $3000
+        assert_eq!(
+            token_value(750_000_000, 6, 1_000_000_000).unwrap(),
+            750_000_000_000
+        );
+        assert_eq!(
+            token_value(1_000_000_000, 9, 3_000_000_000_000).unwrap(),
+            3_000_000_000_000
+        );
+        // Dust below one quote unit rounds down
+        assert_eq!(token_value(1, 6, 999).unwrap(), 0);
+        assert!(token_value(u128::MAX, 6, 2).is_err());
+    }
+
+    #[test]
+    fn collateral_to_seize_applies_market_bonus() {
+        // Same price and decimals on both sides, so only the bonus applies
+        let seize_at_par = |amount, bonus| {
+            calculate_collateral_to_seize(amount, bonus, 1_000_000_000, 1_000_000_000, 6, 6)
+        };
+        // The default 10% matches the old hardcoded bonus
+        assert_eq!(
+            seize_at_par(1_000_000, DEFAULT_LIQUIDATION_BONUS_BPS).unwrap(),
+            1_100_000
+        );
+        assert_eq!(seize_at_par(1_000_000, 500).unwrap(), 1_050_000);
+        assert_eq!(seize_at_par(1_000_000, 1500).unwrap(), 1_150_000);
+        assert_eq!(seize_at_par(1_000_000, 0).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn collateral_to_seize_converts_debt_value_into_collateral_units() {
+        // 50 USDC (6 decimals, $1) repaid against ETH (9 decimals, $2000) at a 10% bonus:
+        // $55 of ETH is 0.0275 ETH
+        assert_eq!(
+            calculate_collateral_to_seize(50_000_000, 1000, 1_000_000_000, 2_000_000_000_000, 6, 9)
+                .unwrap(),
+            27_500_000
+        );
+
+        // A huge repayment on a $0.0001 collateral: the old u64 math overflowed here,
+        // now the u128 amount is returned for the caller to cap
+        let seize =
+            calculate_collateral_to_seize(u64::MAX, 1000, 1_000_000_000, 100_000, 6, 6).unwrap();
+        assert!(seize > u64::MAX as u128);
+        assert!(calculate_collateral_to_seize(1, 1000, 1_000_000_000, 0, 6, 6).is_err());
+    }
+
+    #[test]
+    fn health_checks_agree_at_the_liquidation_threshold() {
+        // $200 of collateral at 85% backs exactly $170 of debt
+        let collateral_value = 200 * SCALING_FACTOR;
+        let at_threshold = 170 * SCALING_FACTOR;
+
+        assert!(is_healthy(collateral_value, at_threshold, 8500));
+        assert!(!is_liquidatable(collateral_value, at_threshold, 8500));
+        assert_eq!(
+            calculate_health_factor(collateral_value, at_threshold, 8500).unwrap(),
+            SCALING_FACTOR
+        );
+
+        // One unit of debt past the threshold flips every check together
+        assert!(!is_healthy(collateral_value, at_threshold + 1, 8500));
+        assert!(is_liquidatable(collateral_value, at_threshold + 1, 8500));
+        assert!(
+            calculate_health_factor(collateral_value, at_threshold + 1, 8500).unwrap()
+                < SCALING_FACTOR
+        );
+    }
+
+    #[test]
+    fn flash_loan_fee_uses_market_bps() {
+        assert_eq!(calculate_flash_loan_fee(1_000_000, 30).unwrap(), 3_000);
+        assert_eq!(calculate_flash_loan_fee(1_000_000, 100).unwrap(), 10_000);
+        // Zero bps makes flash loans free
+        assert_eq!(calculate_flash_loan_fee(1_000_000, 0).unwrap(), 0);
+        // Rounds down, and doesn't overflow on large amounts
+        assert_eq!(calculate_flash_loan_fee(99, 100).unwrap(), 0);
+        assert_eq!(calculate_flash_loan_fee(u64::MAX, 10000).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn loyalty_bonus_ramps_after_threshold_and_caps() {
+        let amount = 1_000_000u128;
+        // Disabled, or not yet held past the threshold
+        assert_eq!(calculate_loyalty_bonus(amount, 500, 0, 500).unwrap(), 0);
+        assert_eq!(calculate_loyalty_bonus(amount, 100, 100, 500).unwrap(), 0);
+        // Halfway up the ramp earns half the cap
+        assert_eq!(
+            calculate_loyalty_bonus(amount, 150, 100, 500).unwrap(),
+            25_000
+        );
+        // Capped from twice the threshold onwards
+        assert_eq!(
+            calculate_loyalty_bonus(amount, 200, 100, 500).unwrap(),
+            50_000
+        );
+        assert_eq!(
+            calculate_loyalty_bonus(amount, 10_000, 100, 500).unwrap(),
+            50_000
+        );
+    }
+
+    #[test]
+    fn booked_borrow_interest_keeps_deposits_backed() {
+        // 1000 supplied, 400 lent out: the vault holds 600
+        let mut market = market_with(1_000, 400);
+        market.total_ctoken_supply = 1_000;
+        let vault = 600u128;
+        let rate_before = calculate_exchange_rate(&market).unwrap();
+
+        book_borrow_interest(&mut market, 25).unwrap();
+        assert_eq!(market.total_borrows, 425);
+        assert_eq!(market.total_supply_deposits, 1_025);
+        assert!(market.total_supply_deposits <= vault + market.total_borrows);
+        // Suppliers earn exactly what borrowers owe
+        assert!(calculate_exchange_rate(&market).unwrap() > rate_before);
+    }
+
+    #[test]
+    fn price_ratio_band_rejects_depegged_prices() {
+        let mut market = market_with(0, 0);
+        // Wide open by default
+        assert!(check_price_ratio(&market, 1, 1_000_000).is_ok());
+
+        // Collateral expected to trade at 0.9x-1.1x the borrow asset
+        market.min_price_ratio_bps = 9_000;
+        market.max_price_ratio_bps = 11_000;
+        assert!(check_price_ratio(&market, 1_000_000, 1_000_000).is_ok());
+        assert!(check_price_ratio(&market, 1_100_000, 1_000_000).is_ok());
+        assert!(check_price_ratio(&market, 500_000, 1_000_000).is_err());
+        assert!(check_price_ratio(&market, 1_200_000, 1_000_000).is_err());
+        // A zero borrow price is a broken feed
+        assert!(check_price_ratio(&market, 1_000_000, 0).is_err());
+    }
+
+    #[test]
+    fn blend_opened_slot_weights_by_size() {
+        // A fresh position opens now
+        assert_eq!(blend_opened_slot(0, 0, 1_000, 50).unwrap(), 1_000);
+        // Doubling a position opened at slot 100 moves it halfway to now
+        assert_eq!(blend_opened_slot(100, 50, 300, 50).unwrap(), 200);
+        // A small top-up barely moves it
+        assert_eq!(blend_opened_slot(100, 99, 200, 1).unwrap(), 101);
+    }
+
+    #[test]
+    fn supply_index_interest_depends_on_entry_index() {
+        let start = SCALING_FACTOR;
+        let later = SCALING_FACTOR * 101 / 100;
+        let now = SCALING_FACTOR * 102 / 100;
+        // The same principal entering earlier earns about twice as much
+        assert_eq!(
+            calculate_index_interest(1_000_000, start, now).unwrap(),
+            20_000
+        );
+        assert_eq!(
+            calculate_index_interest(1_000_000, later, now).unwrap(),
+            9_900
+        );
+        // Positions from before the index was tracked report nothing
+        assert_eq!(calculate_index_interest(1_000_000, 0, now).unwrap(), 0);
+        // Topping up blends the snapshot by size
+        assert_eq!(blend_supply_index(0, 0, later, 100).unwrap(), later);
+        assert_eq!(
+            blend_supply_index(start, 100, start * 3, 100).unwrap(),
+            start * 2
+        );
+    }
+
+    #[test]
+    fn supply_index_moves_with_the_exchange_rate() {
+        let mut market = market_with(1_000_000, 500_000);
+        market.total_ctoken_supply = 1_000_000;
+
+        // 2% of deposits booked as borrow interest: both grow by 2%
+        book_borrow_interest(&mut market, 20_000).unwrap();
+        assert_eq!(market.supply_index, SCALING_FACTOR * 102 / 100);
+        assert_eq!(
+            calculate_exchange_rate(&market).unwrap(),
+            market.supply_index
+        );
+        // So a position opened at the start is paid exactly principal + index interest
+        let paid = calculate_underlying_from_ctokens(100_000, market.supply_index).unwrap();
+        let interest =
+            calculate_index_interest(100_000, SCALING_FACTOR, market.supply_index).unwrap();
+        assert_eq!(paid, 100_000 + interest);
+        assert_eq!(interest, 2_000);
+
+        // Socialized bad debt takes both back down by the same share
+        absorb_bad_debt(&mut market, 102_000).unwrap();
+        assert_eq!(market.total_supply_deposits, 918_000);
+        assert_eq!(
+            calculate_exchange_rate(&market).unwrap(),
+            market.supply_index
+        );
+    }
+
+    fn market_with(total_supply_deposits: u128, total_borrows: u128) -> Market {
+        Market {
+            market_id: 0,
+            supply_mint: Pubkey::default(),
+            collateral_mint: Pubkey::default(),
+            market_admin: Pubkey::default(),
+            total_supply_deposits,
+            total_borrows,
+            total_collateral_deposits: 0,
+            total_ctoken_supply: 0,
+            collateral_factor: 8000,
+            liquidation_threshold: 8500,
+            last_update_slot: 0,
+            cumulative_borrow_rate: SCALING_FACTOR,
+            cumulative_supply_rate: SCALING_FACTOR,
+            supply_oracle: Pubkey::default(),
+            collateral_oracle: Pubkey::default(),
+            bump: 0,
+            is_active: true,
+            flash_loans_enabled: false,
+            first_repayment_grace_slots: 0,
+            grace_period_slots: 0,
+            min_liquidity_buffer_bps: 0,
+            pending_admin: Pubkey::default(),
+            min_borrow: 0,
+            total_reserves: 0,
+            max_oracle_staleness_slots: DEFAULT_MAX_ORACLE_STALENESS_SLOTS,
+            flash_loan_fee_bps: DEFAULT_FLASH_LOAN_FEE_BPS,
+            loyalty_threshold_slots: 0,
+            loyalty_bonus_bps: 0,
+            total_bad_debt: 0,
+            max_confidence_bps: DEFAULT_MAX_CONFIDENCE_BPS,
+            interest_free_slots: 0,
+            liquidation_bonus_bps: DEFAULT_LIQUIDATION_BONUS_BPS,
+            supply_decimals: 6,
+            collateral_decimals: 9,
+            min_price_ratio_bps: 0,
+            max_price_ratio_bps: u64::MAX,
+            dust_threshold: DEFAULT_DUST_THRESHOLD,
+            min_supply: 0,
+            protocol_fee_share_bps: 0,
+            supply_vault_bump: 0,
+            collateral_vault_bump: 0,
+            max_borrow_rate_bps: DEFAULT_MAX_BORROW_RATE_BPS,
+            supply_index: SCALING_FACTOR,
+        }
+    }
+
+    #[test]
+    fn borrow_apy_compounds_the_per_slot_rate() {
+        // 25 per slot compounded over 800,000 slots: e^0.02 - 1, about 2.0201%
+        let apy = borrow_apy(&market_with(1_000, 500));
+        assert_eq!(apy, 20_200_564);
+        assert!(apy.abs_diff(20_201_340) < 1_000, "apy = {apy}");
+        assert_eq!(borrow_apy(&market_with(0, 0)), apy);
+        // The simple rate is the 2% the per-slot rate was derived from
+        assert_eq!(borrow_apr(&market_with(1_000, 500)), 20_000_000);
+    }
+
+    #[test]
+    fn apr_to_apy_matches_known_compounding() {
+        // 100% APR compounded daily is about 171.4567% APY
+        let apy = apr_to_apy(SCALING_FACTOR, 365);
+        assert!(apy.abs_diff(1_714_567_482) < 1_000, "apy = {apy}");
+        // 5% APR compounded monthly is about 5.1162% APY
+        let apy = apr_to_apy(50_000_000, 12);
+        assert!(apy.abs_diff(51_161_898) < 1_000, "apy = {apy}");
+        // A single compound, or none, leaves the rate unchanged
+        assert_eq!(apr_to_apy(50_000_000, 1), 50_000_000);
+        assert_eq!(apr_to_apy(50_000_000, 0), 50_000_000);
+        assert_eq!(apr_to_apy(0, 365), 0);
+    }
+
+    #[test]
+    fn apr_to_apy_saturates_for_high_aprs() {
+        // 100,000% APR compounded every slot would overflow any fixed-point value
+        assert_eq!(
+            apr_to_apy(1_000 * SCALING_FACTOR, SLOTS_PER_YEAR as u64),
+            u128::MAX
+        );
+        assert_eq!(apr_to_apy(u128::MAX, 365), u128::MAX);
+    }
+
+    #[test]
+    fn apy_to_apr_inverts_apr_to_apy() {
+        for (apr, compounds) in [
+            (SCALING_FACTOR, 365),
+            (50_000_000, 12),
+            (20_000_000, 800_000),
+        ] {
+            let back = apy_to_apr(apr_to_apy(apr, compounds), compounds);
+            assert!(back <= apr && apr - back < 1_000, "{apr} -> {back}");
+        }
+        assert_eq!(apy_to_apr(50_000_000, 1), 50_000_000);
+        assert_eq!(apy_to_apr(0, 365), 0);
+    }
+
+    #[test]
+    fn borrow_rate_is_clamped_to_the_market_ceiling() {
+        // The default ceiling is the flat model rate itself
+        let mut market = market_with(1_000, 500);
+        assert_eq!(borrow_rate_per_slot(&market), BORROW_RATE_PER_SLOT);
+        assert!(check_max_borrow_rate_bps(DEFAULT_MAX_BORROW_RATE_BPS).is_ok());
+
+        // A 1% ceiling clamps it to 12 per slot, and debt compounds at the clamped rate
+        market.max_borrow_rate_bps = 100;
+        assert_eq!(borrow_rate_per_slot(&market), 12);
+        assert_eq!(borrow_apy(&market), 9_645_883);
+        assert_eq!(
+            compound_interest(1_000_000_000, borrow_rate_per_slot(&market), 4).unwrap(),
+            1_000_000_048
+        );
+
+        // A ceiling above the model rate leaves the flat 2% alone
+        market.max_borrow_rate_bps = MAX_BORROW_RATE_CAP_BPS;
+        assert_eq!(borrow_rate_per_slot(&market), BORROW_RATE_PER_SLOT);
+
+        assert!(check_max_borrow_rate_bps(0).is_err());
+        assert!(check_max_borrow_rate_bps(MAX_BORROW_RATE_CAP_BPS + 1).is_err());
+    }
+
+    #[test]
+    fn annual_bps_convert_to_the_original_per_slot_rates() {
+        // The rates used to be hardcoded as 25 (2%) and 12 (1%) per slot
+        assert_eq!(annual_bps_to_per_slot_scaled(200), 25);
+        assert_eq!(annual_bps_to_per_slot_scaled(100), 12);
+        assert_eq!(BORROW_RATE_PER_SLOT, 25);
+        assert_eq!(SUPPLY_RATE_PER_SLOT, 12);
+        assert_eq!(annual_bps_to_per_slot_scaled(0), 0);
+        // A full year at the per-slot rate gives back the annual rate (less rounding)
+        assert_eq!(
+            annual_bps_to_per_slot_scaled(10000) * SLOTS_PER_YEAR,
+            SCALING_FACTOR
+        );
+    }
+
+    #[test]
+    fn market_stats_at_several_utilization_levels() {
+        // Empty market: nothing borrowed, suppliers earn nothing
+        let empty = market_with(0, 0);
+        assert_eq!(current_utilization(&empty), 0);
+        assert_eq!(supply_apy(&empty), 0);
+
+        // 0% utilization
+        let idle = market_with(1_000_000, 0);
+        assert_eq!(current_utilization(&idle), 0);
+        assert_eq!(supply_apy(&idle), 0);
+
+        // 50% utilization earns suppliers half the compounded borrow yield
+        let half = market_with(1_000_000, 500_000);
+        assert_eq!(current_utilization(&half), SCALING_FACTOR / 2);
+        assert_eq!(supply_apy(&half), 10_100_282);
+        assert_eq!(supply_apr(&half), 10_000_000);
+
+        // 80% utilization
+        let busy = market_with(1_000_000, 800_000);
+        assert_eq!(current_utilization(&busy), 800_000_000);
+        assert_eq!(supply_apy(&busy), 16_160_451);
+        assert_eq!(supply_apr(&busy), 16_000_000);
+
+        // Fully borrowed, and borrows past supply (e.g. after bad debt) cap at 100%
+        let full = market_with(1_000_000, 1_000_000);
+        assert_eq!(current_utilization(&full), SCALING_FACTOR);
+        assert_eq!(supply_apy(&full), borrow_apy(&full));
+        let over = market_with(1_000_000, 3_000_000);
+        assert_eq!(current_utilization(&over), SCALING_FACTOR);
+        assert_eq!(supply_apy(&over), borrow_apy(&over));
+    }
+
+    #[test]
+    fn supply_then_withdraw_never_returns_more_than_supplied() {
+        // Deterministic LCG so the property runs over many pool states without extra deps
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = |bound: u64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..20_000 {
+            let ctoken_supply = next(1_000_000_000_000) as u128;
+            // Exchange rates from well below to well above 1:1
+            let deposits = ctoken_supply * (1 + next(4_000) as u128) / 1_000;
+            let supplied = 1 + next(1_000_000_000);
+
+            let mut market = market_with(deposits, 0);
+            market.total_ctoken_supply = ctoken_supply;
+
+            // Same rate on both legs
+            let rate = calculate_exchange_rate(&market).unwrap();
+            let minted = calculate_ctokens_to_mint(supplied, rate).unwrap();
+            let returned = calculate_underlying_from_ctokens(minted, rate).unwrap();
+            assert!(
+                returned <= supplied as u128,
+                "rate {} supplied {} returned {}",
+                rate,
+                supplied,
+                returned
+            );
+
+            // Through the pool: the deposit lands, then the fresh cTokens are redeemed
+            market.total_supply_deposits += supplied as u128;
+            market.total_ctoken_supply += minted;
+            let rate_after = calculate_exchange_rate(&market).unwrap();
+            let returned = calculate_underlying_from_ctokens(minted, rate_after).unwrap();
+            assert!(
+                returned <= supplied as u128,
+                "deposits {} ctokens {} supplied {} returned {}",
+                deposits,
+                ctoken_supply,
+                supplied,
+                returned
+            );
+        }
+    }
+
+    #[test]
+    fn ctoken_minting_rounds_down() {
+        // 3 underlying per cToken: supplying 1 must not mint a whole cToken worth 3
+        let rate = 3 * SCALING_FACTOR;
+        assert_eq!(calculate_ctokens_to_mint(1, rate).unwrap(), 0);
+        assert_eq!(calculate_ctokens_to_mint(5, rate).unwrap(), 1);
+        assert_eq!(calculate_underlying_from_ctokens(1, rate).unwrap(), 3);
+    }
+
+    #[test]
+    fn ctokens_to_cover_round_up() {
+        // 3 underlying per cToken: covering 4 takes 2 cTokens, which redeem for 6
+        let rate = 3 * SCALING_FACTOR;
+        assert_eq!(calculate_ctokens_to_cover(4, rate).unwrap(), 2);
+        assert_eq!(calculate_ctokens_to_cover(6, rate).unwrap(), 2);
+        assert_eq!(calculate_ctokens_to_cover(0, rate).unwrap(), 0);
+        for amount in [1u128, 7, 1_000_001] {
+            let ctokens = calculate_ctokens_to_cover(amount, rate).unwrap();
+            assert!(calculate_underlying_from_ctokens(ctokens, rate).unwrap() >= amount);
+        }
+    }
+
+    #[test]
+    fn oracle_confidence_is_the_same_fraction_at_any_decimals() {
+        // $1.50 at 6 and at 18 decimals gets a 1% interval in its own units
+        let six = max_confidence_interval(1_500_000, ORACLE_CONFIDENCE_BPS).unwrap();
+        let eighteen =
+            max_confidence_interval(1_500_000_000_000_000_000, ORACLE_CONFIDENCE_BPS).unwrap();
+        assert_eq!(six, 15_000);
+        assert_eq!(eighteen, 15_000_000_000_000_000);
+        assert_eq!(
+            scale_price(six, 6, PRICE_DECIMALS).unwrap(),
+            scale_price(eighteen, 18, PRICE_DECIMALS).unwrap()
+        );
+        // And both pass the default market check
+        assert!(six <= max_confidence_interval(1_500_000, DEFAULT_MAX_CONFIDENCE_BPS).unwrap());
+    }
+
+    #[test]
+    fn max_confidence_interval_scales_with_bps() {
+        // The default 5% matches the old fixed price / 20 bound
+        assert_eq!(
+            max_confidence_interval(3_000_000_000, DEFAULT_MAX_CONFIDENCE_BPS).unwrap(),
+            3_000_000_000 / 20
+        );
+        assert_eq!(max_confidence_interval(1_000_000, 50).unwrap(), 5_000);
+        assert_eq!(max_confidence_interval(1_000_000, 0).unwrap(), 0);
+        assert!(max_confidence_interval(u128::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn interest_accrual_start_skips_the_interest_free_window() {
+        let mut market = market_with(0, 0);
+        let mut deposit = UserDeposit {
+            user: Pubkey::default(),
+            market: Pubkey::default(),
+            supply_deposited: 0,
+            collateral_deposited: 0,
+            borrowed_amount: 1_000,
+            ctoken_balance: 0,
+            last_update_slot: 1_000,
+            bump: 0,
+            loan_start_slot: 1_000,
+            first_repayment_made: false,
+            unhealthy_since_slot: 0,
+            supply_opened_slot: 0,
+            frozen: false,
+            locked_ctokens: 0,
+            supply_index: 0,
+        };
+
+        // Disabled: accrue from the last update
+        assert_eq!(interest_accrual_start(&market, &deposit), 1_000);
+
+        // Enabled: nothing accrues until the window closes
+        market.interest_free_slots = 50;
+        assert_eq!(interest_accrual_start(&market, &deposit), 1_050);
+
+        // Updates after the window are not pushed back
+        deposit.last_update_slot = 1_200;
+        assert_eq!(interest_accrual_start(&market, &deposit), 1_200);
+    }
+
+    fn pyth_account(expo: i32, price: i64, conf: u64, status: u32, publish_slot: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 3312];
+        data[0..4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[PYTH_EXPO_OFFSET..PYTH_EXPO_OFFSET + 4].copy_from_slice(&expo.to_le_bytes());
+        data[PYTH_AGG_PRICE_OFFSET..PYTH_AGG_PRICE_OFFSET + 8]
+            .copy_from_slice(&price.to_le_bytes());
+        data[PYTH_AGG_CONF_OFFSET..PYTH_AGG_CONF_OFFSET + 8].copy_from_slice(&conf.to_le_bytes());
+        data[PYTH_AGG_STATUS_OFFSET..PYTH_AGG_STATUS_OFFSET + 4]
+            .copy_from_slice(&status.to_le_bytes());
+        data[PYTH_AGG_PUB_SLOT_OFFSET..PYTH_AGG_PUB_SLOT_OFFSET + 8]
+            .copy_from_slice(&publish_slot.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parse_pyth_price_normalizes_exponents() {
+        // $3000.12345678 with expo -8
+        let data = pyth_account(-8, 300_012_345_678, 1_000_000, PYTH_STATUS_TRADING, 100);
+        assert_eq!(
+            parse_pyth_price(&data, 110, 100, DEFAULT_MAX_CONFIDENCE_BPS).unwrap(),
+            3_000_123_456_780
+        );
+
+        // $1.00 with expo -6 and a whole-dollar price with a positive exponent
+        let data = pyth_account(-6, 1_000_000, 0, PYTH_STATUS_TRADING, 100);
+        assert_eq!(
+            parse_pyth_price(&data, 100, 100, 500).unwrap(),
+            1_000_000_000
+        );
+        let data = pyth_account(2, 5, 0, PYTH_STATUS_TRADING, 100);
+        assert_eq!(
+            parse_pyth_price(&data, 100, 100, 500).unwrap(),
+            500_000_000_000
+        );
+    }
+
+    #[test]
+    fn parse_pyth_price_rejects_stale_prices() {
+        let data = pyth_account(-8, 100_000_000, 0, PYTH_STATUS_TRADING, 1_000);
+        assert!(parse_pyth_price(&data, 1_100, 100, 500).is_ok());
+        assert!(parse_pyth_price(&data, 1_101, 100, 500).is_err());
+    }
+
+    #[test]
+    fn oracle_staleness_check_saturates_instead_of_overflowing() {
+        let oracle = Oracle {
+            mint: Pubkey::default(),
+            source: Vec::new(),
+            price: 1_000_000,
+            decimals: 6,
+            valid_slot: u64::MAX - 10,
+            confidence: 0,
+            authority: Pubkey::default(),
+            bump: 0,
+            update_count: 1,
+            max_price_deviation_bps: 0,
+            deviation_window_slots: 0,
+            min_update_interval_slots: 0,
+        };
+        assert!(oracle.is_valid(u64::MAX, 100));
+        assert!(oracle.is_valid(u64::MAX - 10, u64::MAX));
+    }
+
+    #[test]
+    fn parse_pyth_price_rejects_bad_feeds() {
+        // Too-wide confidence (6% of price against a 5% limit)
+        let data = pyth_account(-8, 100_000_000, 6_000_000, PYTH_STATUS_TRADING, 100);
+        assert!(parse_pyth_price(&data, 100, 100, 500).is_err());
+        // Not trading, or a non-positive price
+        let data = pyth_account(-8, 100_000_000, 0, 0, 100);
+        assert!(parse_pyth_price(&data, 100, 100, 500).is_err());
+        let data = pyth_account(-8, -1, 0, PYTH_STATUS_TRADING, 100);
+        assert!(parse_pyth_price(&data, 100, 100, 500).is_err());
+        // Wrong magic or a truncated account
+        let mut data = pyth_account(-8, 100_000_000, 0, PYTH_STATUS_TRADING, 100);
+        data[0] = 0;
+        assert!(parse_pyth_price(&data, 100, 100, 500).is_err());
+        assert!(parse_pyth_price(&[0u8; 64], 100, 100, 500).is_err());
+    }
+
+    #[test]
+    fn common_units_price_mismatched_decimals_alike() {
+        // 1 token of a 6-decimal mint and of a 9-decimal mint are the same common amount
+        assert_eq!(to_common_units(1_000_000, 6).unwrap(), 1_000_000_000);
+        assert_eq!(to_common_units(1_000_000_000, 9).unwrap(), 1_000_000_000);
+        // More precise mints round down
+        assert_eq!(to_common_units(1_999_999_999, 12).unwrap(), 1_999_999);
+
+        // 1 ETH (9 decimals) at $3000 backs 2400 USDC (6 decimals) at 80%, not 2400 raw units
+        let collateral_value = to_common_units(1_000_000_000, 9).unwrap() * 3_000_000_000_000;
+        let max_borrow_value = collateral_value * 8000 / 10000;
+        let borrow_value = |usdc: u128| to_common_units(usdc, 6).unwrap() * 1_000_000_000;
+        assert!(borrow_value(2_400_000_000) <= max_borrow_value);
+        assert!(borrow_value(2_400_000_001) > max_borrow_value);
+    }
+
+    #[test]
+    fn max_amount_where_finds_the_boundary() {
+        // Redeeming at 1.5 tokens per cToken with 100 tokens available
+        let fits = |ctokens: u128| Ok(ctokens * 3 / 2 <= 100);
+        assert_eq!(max_amount_where(1_000, fits).unwrap(), 67);
+        // Capped by the balance when everything fits
+        assert_eq!(max_amount_where(50, fits).unwrap(), 50);
+        assert_eq!(max_amount_where(0, fits).unwrap(), 0);
+        assert_eq!(
+            max_amount_where(u128::MAX, |_| Ok(true)).unwrap(),
+            u128::MAX
+        );
+        assert_eq!(max_amount_where(u128::MAX, |v| Ok(v == 0)).unwrap(), 0);
+    }
+}
@@ -9,6 +9,7 @@ import {
   mintTo,
   getAccount,
   createTransferInstruction,
+  transfer,
 } from "@solana/spl-token";
 const { expect } = require("chai");
 
@@ -202,7 +203,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     [market] = PublicKey.findProgramAddressSync(
       [
         Buffer.from("market"),
-        new anchor.BN(1).toArrayLike(Buffer, "le", 8),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
         usdcMint.toBuffer(),
         ethMint.toBuffer(),
       ],
@@ -212,7 +213,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     [supplyVault] = PublicKey.findProgramAddressSync(
       [
         Buffer.from("supply_vault"),
-        new anchor.BN(1).toArrayLike(Buffer, "le", 8),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
         usdcMint.toBuffer(),
       ],
       program.programId
@@ -221,7 +222,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     [collateralVault] = PublicKey.findProgramAddressSync(
       [
         Buffer.from("collateral_vault"),
-        new anchor.BN(1).toArrayLike(Buffer, "le", 8),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
         ethMint.toBuffer(),
       ],
       program.programId
@@ -231,7 +232,7 @@ describe("MetaLend Dual-Asset Tests", () => {
       [
         Buffer.from("user_deposit"),
         user1.publicKey.toBuffer(),
-        new anchor.BN(1).toArrayLike(Buffer, "le", 8),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
         usdcMint.toBuffer(),
         ethMint.toBuffer(),
       ],
@@ -242,7 +243,7 @@ describe("MetaLend Dual-Asset Tests", () => {
       [
         Buffer.from("user_deposit"),
         user2.publicKey.toBuffer(),
-        new anchor.BN(1).toArrayLike(Buffer, "le", 8),
+        new anchor.BN(0).toArrayLike(Buffer, "le", 8),
         usdcMint.toBuffer(),
         ethMint.toBuffer(),
       ],
@@ -265,6 +266,345 @@ describe("MetaLend Dual-Asset Tests", () => {
     console.log("Collateral asset (ETH):", ethMint.toString());
   });
 
+  // ============================================
+  // Helpers for tests that need an isolated market
+  // ============================================
+
+  type TestMarket = {
+    id: anchor.BN;
+    supplyMint: PublicKey;
+    collateralMint: PublicKey;
+    supplyOracle: PublicKey;
+    collateralOracle: PublicKey;
+    market: PublicKey;
+    supplyVault: PublicKey;
+    collateralVault: PublicKey;
+  };
+
+  type TestUser = {
+    keypair: Keypair;
+    supplyAccount: PublicKey;
+    collateralAccount: PublicKey;
+    deposit: PublicKey;
+  };
+
+  const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+  // Id the protocol will accept for the next create_market call
+  const nextMarketId = async () => {
+    const state = await program.account.protocolState.fetch(protocolState);
+    return state.nextMarketId;
+  };
+
+  // Index PDA the next create_market call will record its market in
+  const nextMarketIndexPda = async () => {
+    const state = await program.account.protocolState.fetch(protocolState);
+    return PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("market_index"),
+        state.totalMarkets.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+  };
+
+  const marketPointerPda = (supplyMint: PublicKey, collateralMint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("market_pointer"), supplyMint.toBuffer(), collateralMint.toBuffer()],
+      program.programId
+    )[0];
+
+  const createTestMarket = async (
+    supplyPrice = 1_000_000, // $1.00 with 6 decimals
+    collateralPrice = 3000_000_000, // $3000.00 with 6 decimals
+    minBorrow = 0, // Minimum borrow value, quote units with 9 decimals
+    supplyDecimals = 6,
+    collateralDecimals = 9
+  ): Promise<TestMarket> => {
+    const id = await nextMarketId();
+    const supplyMint = await createMint(
+      provider.connection,
+      admin,
+      admin.publicKey,
+      admin.publicKey,
+      supplyDecimals
+    );
+    const collateralMint = await createMint(
+      provider.connection,
+      admin,
+      admin.publicKey,
+      admin.publicKey,
+      collateralDecimals
+    );
+    const idBytes = id.toArrayLike(Buffer, "le", 8);
+    const [market] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("market"),
+        idBytes,
+        supplyMint.toBuffer(),
+        collateralMint.toBuffer(),
+      ],
+      program.programId
+    );
+    const [supplyVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("supply_vault"), idBytes, supplyMint.toBuffer()],
+      program.programId
+    );
+    const [collateralVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("collateral_vault"), idBytes, collateralMint.toBuffer()],
+      program.programId
+    );
+    const [supplyOracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle"), supplyMint.toBuffer()],
+      program.programId
+    );
+    const [collateralOracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle"), collateralMint.toBuffer()],
+      program.programId
+    );
+
+    for (const [oracle, mint, price] of [
+      [supplyOracle, supplyMint, supplyPrice],
+      [collateralOracle, collateralMint, collateralPrice],
+    ] as [PublicKey, PublicKey, number][]) {
+      await program.methods
+        .createOracle(
+          Buffer.from("mock_pyth_source_data"),
+          new anchor.BN(price),
+          6,
+          new anchor.BN(0)
+        )
+        .accounts({
+          oracle,
+          mint,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    await program.methods
+      .createMarket(
+        id,
+        new anchor.BN(8000),
+        new anchor.BN(8500),
+        new anchor.BN(minBorrow)
+      )
+      .accounts({
+        market,
+        protocolState,
+        marketIndex: await nextMarketIndexPda(),
+        marketPointer: marketPointerPda(supplyMint, collateralMint),
+        supplyMint,
+        collateralMint,
+        supplyOracle,
+        collateralOracle,
+        supplyVault,
+        collateralVault,
+        creator: admin.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    return {
+      id,
+      supplyMint,
+      collateralMint,
+      supplyOracle,
+      collateralOracle,
+      market,
+      supplyVault,
+      collateralVault,
+    };
+  };
+
+  const createTestUser = async (
+    tm: TestMarket,
+    supplyTokens: number,
+    collateralTokens: number
+  ): Promise<TestUser> => {
+    const keypair = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        keypair.publicKey,
+        10 * anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+    const supplyAccount = await createAccount(
+      provider.connection,
+      keypair,
+      tm.supplyMint,
+      keypair.publicKey
+    );
+    const collateralAccount = await createAccount(
+      provider.connection,
+      keypair,
+      tm.collateralMint,
+      keypair.publicKey
+    );
+    if (supplyTokens > 0) {
+      await mintTo(
+        provider.connection,
+        admin,
+        tm.supplyMint,
+        supplyAccount,
+        admin,
+        supplyTokens
+      );
+    }
+    if (collateralTokens > 0) {
+      await mintTo(
+        provider.connection,
+        admin,
+        tm.collateralMint,
+        collateralAccount,
+        admin,
+        collateralTokens
+      );
+    }
+    const [deposit] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("user_deposit"),
+        keypair.publicKey.toBuffer(),
+        tm.id.toArrayLike(Buffer, "le", 8),
+        tm.supplyMint.toBuffer(),
+        tm.collateralMint.toBuffer(),
+      ],
+      program.programId
+    );
+    await program.methods
+      .initializeUserDeposit(tm.id)
+      .accounts({
+        userDeposit: deposit,
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        user: keypair.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([keypair])
+      .rpc();
+
+    return { keypair, supplyAccount, collateralAccount, deposit };
+  };
+
+  const supplyTo = (tm: TestMarket, user: TestUser, amount: number) =>
+    program.methods
+      .supply(tm.id, new anchor.BN(amount))
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        userDeposit: user.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: user.supplyAccount,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user.keypair])
+      .rpc({ commitment: "confirmed" });
+
+  const borrowFrom = (
+    tm: TestMarket,
+    user: TestUser,
+    collateralAmount: number,
+    borrowAmount: number
+  ) =>
+    program.methods
+      .borrow(
+        tm.id,
+        new anchor.BN(collateralAmount),
+        new anchor.BN(borrowAmount)
+      )
+      .accounts({
+        protocolState,
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        collateralVault: tm.collateralVault,
+        userDeposit: user.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: user.supplyAccount,
+        userCollateralAccount: user.collateralAccount,
+        user: user.keypair.publicKey,
+        collateralOracle: tm.collateralOracle,
+        borrowOracle: tm.supplyOracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user.keypair])
+      .rpc({ commitment: "confirmed" });
+
+  const repayTo = (tm: TestMarket, user: TestUser, amount: number) =>
+    program.methods
+      .repay(tm.id, new anchor.BN(amount))
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        userDeposit: user.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: user.supplyAccount,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user.keypair])
+      .rpc({ commitment: "confirmed" });
+
+  const setOraclePrice = (oracle: PublicKey, price: number) =>
+    program.methods
+      .updateOraclePrice(new anchor.BN(price), false)
+      .accounts({ oracle, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+  const liquidateIn = (
+    tm: TestMarket,
+    liquidatorUser: TestUser,
+    borrower: TestUser,
+    amount: number,
+    minCollateralOut = 0
+  ) =>
+    program.methods
+      .liquidate(tm.id, new anchor.BN(amount), new anchor.BN(minCollateralOut))
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        collateralVault: tm.collateralVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        borrowerDeposit: borrower.deposit,
+        liquidatorSupplyAccount: liquidatorUser.supplyAccount,
+        liquidatorCollateralAccount: liquidatorUser.collateralAccount,
+        liquidator: liquidatorUser.keypair.publicKey,
+        oracle: tm.collateralOracle,
+        borrowOracle: tm.supplyOracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([liquidatorUser.keypair])
+      .rpc({ commitment: "confirmed" });
+
+  const getEvents = async (txSig: string) => {
+    const tx = await provider.connection.getTransaction(txSig, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, program.coder);
+    return Array.from(parser.parseLogs(tx?.meta?.logMessages ?? []));
+  };
+
+  const expectError = async (promise: Promise<unknown>, code: string) => {
+    try {
+      await promise;
+    } catch (error) {
+      expect(error.toString()).to.include(code);
+      return;
+    }
+    expect.fail(`Expected transaction to fail with ${code}`);
+  };
+
   it("Initialize protocol", async () => {
     console.log("Testing protocol initialization...");
 
@@ -285,7 +625,11 @@ describe("MetaLend Dual-Asset Tests", () => {
       admin.publicKey.toString()
     );
     expect(protocolStateAccount.totalMarkets.toNumber()).to.equal(0);
+    expect(protocolStateAccount.nextMarketId.toNumber()).to.equal(0);
     expect(protocolStateAccount.isPaused).to.equal(false);
+    expect(protocolStateAccount.feeRecipient.toString()).to.equal(
+      admin.publicKey.toString()
+    );
     console.log("✓ Protocol initialized successfully");
   });
 
@@ -297,7 +641,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     // Create USDC Oracle (supply asset) - $1 with 6 decimals
     const usdcPrice = new anchor.BN(1_000_000); // $1.00
     await program.methods
-      .createOracle(sourceData, usdcPrice, 6)
+      .createOracle(sourceData, usdcPrice, 6, new anchor.BN(0))
       .accounts({
         oracle: usdcOracle,
         mint: usdcMint,
@@ -310,7 +654,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     // Create ETH Oracle (collateral asset)
     const ethPrice = new anchor.BN(3000_000_000); // $3000.00 with 6 decimals
     await program.methods
-      .createOracle(sourceData, ethPrice, 6)
+      .createOracle(sourceData, ethPrice, 6, new anchor.BN(0))
       .accounts({
         oracle: ethOracle,
         mint: ethMint,
@@ -336,13 +680,16 @@ describe("MetaLend Dual-Asset Tests", () => {
 
     await program.methods
       .createMarket(
-        new anchor.BN(1),
+        new anchor.BN(0),
         new anchor.BN(8000), // 80% collateral factor
-        new anchor.BN(8500) // 85% liquidation threshold
+        new anchor.BN(8500), // 85% liquidation threshold
+        new anchor.BN(0) // no minimum borrow
       )
       .accounts({
         market,
         protocolState,
+        marketIndex: await nextMarketIndexPda(),
+        marketPointer: marketPointerPda(usdcMint, ethMint),
         supplyMint: usdcMint,
         collateralMint: ethMint,
         supplyOracle: usdcOracle,
@@ -376,7 +723,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     console.log("Testing user deposit initialization...");
 
     await program.methods
-      .initializeUserDeposit(new anchor.BN(1))
+      .initializeUserDeposit(new anchor.BN(0))
       .accounts({
         userDeposit: user1Deposit,
         market,
@@ -404,7 +751,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     const supplyAmount = 500 * 1e6; // 500 USDC
 
     await program.methods
-      .supply(new anchor.BN(1), new anchor.BN(supplyAmount))
+      .supply(new anchor.BN(0), new anchor.BN(supplyAmount))
       .accounts({
         market,
         supplyVault,
@@ -494,11 +841,12 @@ describe("MetaLend Dual-Asset Tests", () => {
 
     await program.methods
       .borrow(
-        new anchor.BN(1),
+        new anchor.BN(0),
         new anchor.BN(collateralAmount),
         new anchor.BN(borrowAmount)
       )
       .accounts({
+        protocolState,
         market,
         supplyVault,
         collateralVault,
@@ -551,7 +899,7 @@ describe("MetaLend Dual-Asset Tests", () => {
 
     // Initialize second user deposit
     await program.methods
-      .initializeUserDeposit(new anchor.BN(1))
+      .initializeUserDeposit(new anchor.BN(0))
       .accounts({
         userDeposit: user2Deposit,
         market,
@@ -566,7 +914,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     // User2 supplies USDC first
     const supplyAmount = 300 * 1e6;
     await program.methods
-      .supply(new anchor.BN(1), new anchor.BN(supplyAmount))
+      .supply(new anchor.BN(0), new anchor.BN(supplyAmount))
       .accounts({
         market,
         supplyVault,
@@ -588,11 +936,12 @@ describe("MetaLend Dual-Asset Tests", () => {
 
     await program.methods
       .borrow(
-        new anchor.BN(1),
+        new anchor.BN(0),
         new anchor.BN(collateralAmount),
         new anchor.BN(borrowAmount)
       )
       .accounts({
+        protocolState,
         market,
         supplyVault,
         collateralVault,
@@ -621,7 +970,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     );
     const lowerEthPrice = new anchor.BN(1800_000_000); // $1800 (down from $3000)
     await program.methods
-      .updateOraclePrice(lowerEthPrice)
+      .updateOraclePrice(lowerEthPrice, false)
       .accounts({
         oracle: ethOracle,
         authority: admin.publicKey,
@@ -659,7 +1008,7 @@ describe("MetaLend Dual-Asset Tests", () => {
     );
 
     await program.methods
-      .liquidate(new anchor.BN(1), new anchor.BN(liquidationAmount))
+      .liquidate(new anchor.BN(0), new anchor.BN(liquidationAmount), new anchor.BN(0))
       .accounts({
         market,
         supplyVault,
@@ -671,6 +1020,7 @@ describe("MetaLend Dual-Asset Tests", () => {
         liquidatorCollateralAccount: liquidatorEthAccount,
         liquidator: liquidator.publicKey,
         oracle: ethOracle,
+        borrowOracle: usdcOracle,
         tokenProgram: TOKEN_PROGRAM_ID,
       })
       .signers([liquidator])
@@ -689,4 +1039,3819 @@ describe("MetaLend Dual-Asset Tests", () => {
     console.log(`Liquidated ${liquidationAmount / 1e6} USDC worth of debt`);
     console.log("Liquidator received ETH collateral with bonus");
   });
+
+  it("Emits a high-utilization warning when a borrow pushes utilization past 90%", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    // 80% utilization: no warning
+    let sig = await borrowFrom(tm, borrower, 1 * 1e9, 800 * 1e6);
+    let events = await getEvents(sig);
+    expect(events.some((e) => e.name === "highUtilizationWarningEvent")).to.equal(
+      false
+    );
+
+    // 95% utilization: informational warning, borrow still succeeds
+    sig = await borrowFrom(tm, borrower, 0, 150 * 1e6);
+    events = await getEvents(sig);
+    const warning = events.find((e) => e.name === "highUtilizationWarningEvent");
+    expect(warning).to.not.equal(undefined);
+    expect(warning.data.utilizationBps.toNumber()).to.be.greaterThan(9000);
+
+    const depositAccount = await program.account.userDeposit.fetch(
+      borrower.deposit
+    );
+    expect(depositAccount.borrowedAmount.toNumber()).to.be.at.least(950 * 1e6);
+  });
+
+  it("Rejects borrows against a zero-priced collateral oracle with InvalidOracleData", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1_000_000_000, 0);
+    const borrower = await createTestUser(tm, 0, 1_000_000_000);
+
+    await supplyTo(tm, supplier, 1_000_000_000);
+
+    await program.methods
+      .updateOraclePrice(new anchor.BN(0), false)
+      .accounts({
+        oracle: tm.collateralOracle,
+        authority: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+
+    await expectError(
+      borrowFrom(tm, borrower, 1_000_000_000, 1_000_000),
+      "InvalidOracleData"
+    );
+
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+  });
+
+  it("Rejects flash loans on a market with flash loans disabled", async () => {
+    const tm = await createTestMarket();
+    const user = await createTestUser(tm, 0, 0);
+
+    let marketAccount = await program.account.market.fetch(tm.market);
+    expect(marketAccount.flashLoansEnabled).to.be.true;
+
+    // Only the market admin may toggle flash loans
+    await expectError(
+      program.methods
+        .setFlashLoansEnabled(false)
+        .accounts({ market: tm.market, authority: user.keypair.publicKey })
+        .signers([user.keypair])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods
+      .setFlashLoansEnabled(false)
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    marketAccount = await program.account.market.fetch(tm.market);
+    expect(marketAccount.flashLoansEnabled).to.be.false;
+
+    await setFeeRecipientTo(user);
+    await expectError(
+      program.methods
+        .flashLoan(tm.id, new anchor.BN(1_000), Buffer.from([]))
+        .accounts({
+          protocolState,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: user.supplyAccount,
+          feeRecipientAccount: user.supplyAccount,
+          user: user.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.keypair])
+        .rpc(),
+      "FlashLoansDisabled"
+    );
+  });
+
+  it("Verifies market vault authorities and mints", async () => {
+    const tm = await createTestMarket();
+    const verify = (supplyVault: PublicKey, collateralVault: PublicKey) =>
+      program.methods
+        .verifyMarketVaults(tm.id)
+        .accounts({ market: tm.market, supplyVault, collateralVault })
+        .rpc({ commitment: "confirmed" });
+
+    // Correctly configured market: every check passes
+    let events = (await getEvents(await verify(tm.supplyVault, tm.collateralVault))).filter(
+      (e) => e.name === "marketVaultCheckEvent"
+    );
+    expect(events).to.have.length(4);
+    expect(events.every((e) => e.data.passed)).to.equal(true);
+
+    // Passing the collateral vault as the supply vault breaks the supply mint check
+    events = (await getEvents(await verify(tm.collateralVault, tm.collateralVault))).filter(
+      (e) => e.name === "marketVaultCheckEvent"
+    );
+    expect(events).to.have.length(4);
+    const failed = events.filter((e) => !e.data.passed);
+    expect(failed).to.have.length(1);
+    expect(failed[0].data.check).to.have.property("supplyVaultMint");
+    expect(failed[0].data.vault.toString()).to.equal(tm.collateralVault.toString());
+  });
+
+  it("Waives interest on the first repayment inside the grace window only", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    await program.methods
+      .setFirstRepaymentGraceSlots(new anchor.BN(10_000))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await borrowFrom(tm, borrower, 1 * 1e9, 500 * 1e6);
+    await sleep(2000);
+
+    // First repayment in-window: only principal comes off, no interest is added
+    let before = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(before.firstRepaymentMade).to.be.false;
+    await repayTo(tm, borrower, 100 * 1e6);
+    let after = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(after.borrowedAmount.toNumber()).to.equal(
+      before.borrowedAmount.toNumber() - 100 * 1e6
+    );
+    expect(after.firstRepaymentMade).to.be.true;
+
+    await sleep(2000);
+
+    // Subsequent repayments accrue interest as usual
+    before = after;
+    await repayTo(tm, borrower, 100 * 1e6);
+    after = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(after.borrowedAmount.toNumber()).to.be.greaterThan(
+      before.borrowedAmount.toNumber() - 100 * 1e6
+    );
+  });
+
+  it("Rejects liquidations paying collateral into a wrong-mint account", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1 * 1e9, 500 * 1e6);
+
+    await expectError(
+      program.methods
+        .liquidate(tm.id, new anchor.BN(50 * 1e6), new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          collateralVault: tm.collateralVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          borrowerDeposit: borrower.deposit,
+          liquidatorSupplyAccount: liquidatorUser.supplyAccount,
+          // Supply-mint account passed where a collateral-mint account is required
+          liquidatorCollateralAccount: liquidatorUser.supplyAccount,
+          liquidator: liquidatorUser.keypair.publicKey,
+          oracle: tm.collateralOracle,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([liquidatorUser.keypair])
+        .rpc(),
+      "InvalidCollateralMint"
+    );
+  });
+
+  it("Only liquidates positions that stay unhealthy for the grace period", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    // Holds a little extra to cover interest when repaying in full
+    const borrower2 = await createTestUser(tm, 10 * 1e6, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    await program.methods
+      .updateMarketParams(
+        new anchor.BN(8000),
+        new anchor.BN(8500),
+        new anchor.BN(5),
+        new anchor.BN(30),
+        new anchor.BN(500)
+      )
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+    await borrowFrom(tm, borrower2, 1e8, 200 * 1e6);
+    // Collateral crashes to $1: the position is now unhealthy
+    await setOraclePrice(tm.collateralOracle, 1_000_000);
+
+    // Inside the grace period: observed and reported, but nothing is seized
+    const graceSig = await liquidateIn(tm, liquidatorUser, borrower, 50 * 1e6);
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.be.greaterThan(0);
+    const grace = (await getEvents(graceSig)).find(
+      (e) => e.name === "liquidationGracePeriodEvent"
+    );
+    expect(grace).to.not.be.undefined;
+    expect(grace.data.borrower.toString()).to.equal(borrower.keypair.publicKey.toString());
+    expect(grace.data.unhealthySinceSlot.toNumber()).to.equal(
+      deposit.unhealthySinceSlot.toNumber()
+    );
+    expect(grace.data.gracePeriodSlots.toNumber()).to.equal(5);
+    expect(deposit.borrowedAmount.toNumber()).to.be.at.least(200 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e8);
+
+    // Still unhealthy once the grace period has passed: seizure goes through
+    await sleep(3000);
+    await setOraclePrice(tm.collateralOracle, 1_000_000);
+    await liquidateIn(tm, liquidatorUser, borrower, 50 * 1e6);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.be.lessThan(1e8);
+
+    // Recovered position: the next check resets the grace period
+    await setOraclePrice(tm.collateralOracle, 3000_000_000);
+    await liquidateIn(tm, liquidatorUser, borrower, 1);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.equal(0);
+
+    await expectError(
+      liquidateIn(tm, liquidatorUser, borrower, 1),
+      "PositionHealthy"
+    );
+
+    // A borrow that leaves the position healthy also resets a grace period in progress
+    await setOraclePrice(tm.collateralOracle, 1_000_000);
+    await liquidateIn(tm, liquidatorUser, borrower2, 1);
+    deposit = await program.account.userDeposit.fetch(borrower2.deposit);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.be.greaterThan(0);
+    await setOraclePrice(tm.collateralOracle, 3000_000_000);
+    await borrowFrom(tm, borrower2, 0, 1e6);
+    deposit = await program.account.userDeposit.fetch(borrower2.deposit);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.equal(0);
+
+    // So does repaying the debt in full, even though repay reads no prices
+    await setOraclePrice(tm.collateralOracle, 1_000_000);
+    await liquidateIn(tm, liquidatorUser, borrower2, 1);
+    deposit = await program.account.userDeposit.fetch(borrower2.deposit);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.be.greaterThan(0);
+    await repayTo(tm, borrower2, 1000 * 1e6);
+    deposit = await program.account.userDeposit.fetch(borrower2.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+    expect(deposit.unhealthySinceSlot.toNumber()).to.equal(0);
+  });
+
+  it("Tracks how many times an oracle has been updated", async () => {
+    const tm = await createTestMarket();
+
+    let oracleAccount = await program.account.oracle.fetch(tm.collateralOracle);
+    expect(oracleAccount.updateCount.toNumber()).to.equal(0);
+
+    await setOraclePrice(tm.collateralOracle, 3100_000_000);
+    await setOraclePrice(tm.collateralOracle, 3200_000_000);
+
+    oracleAccount = await program.account.oracle.fetch(tm.collateralOracle);
+    expect(oracleAccount.updateCount.toNumber()).to.equal(2);
+
+    const sig = await program.methods
+      .getOracleStats()
+      .accounts({ oracle: tm.collateralOracle })
+      .rpc({ commitment: "confirmed" });
+    const stats = (await getEvents(sig)).find((e) => e.name === "oracleStatsEvent");
+    expect(stats).to.not.equal(undefined);
+    expect(stats.data.updateCount.toNumber()).to.equal(2);
+    expect(stats.data.lastUpdateSlot.toNumber()).to.equal(
+      oracleAccount.validSlot.toNumber()
+    );
+  });
+
+  it("Caps borrows so the minimum liquidity buffer is never lent out", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    await expectError(
+      program.methods
+        .setMinLiquidityBufferBps(new anchor.BN(10001))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc(),
+      "InvalidBasisPoints"
+    );
+
+    // Keep 10% of supply deposits back from borrowers
+    await program.methods
+      .setMinLiquidityBufferBps(new anchor.BN(1000))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    // Taking the entire supply is rejected
+    await expectError(
+      borrowFrom(tm, borrower, 1 * 1e9, 1000 * 1e6),
+      "InsufficientLiquidity"
+    );
+
+    // Everything above the buffer can still be borrowed, but nothing more
+    await borrowFrom(tm, borrower, 1 * 1e9, 900 * 1e6);
+    await expectError(borrowFrom(tm, borrower, 0, 1), "InsufficientLiquidity");
+
+    const vault = await getAccount(provider.connection, tm.supplyVault);
+    expect(Number(vault.amount)).to.equal(100 * 1e6);
+  });
+
+  it("Transfers market admin with a two-step propose/accept", async () => {
+    const tm = await createTestMarket();
+    const newAdmin = Keypair.generate();
+    const outsider = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        outsider.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        newAdmin.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+
+    // A non-admin cannot propose a transfer
+    await expectError(
+      program.methods
+        .transferMarketAdmin(outsider.publicKey)
+        .accounts({ market: tm.market, marketAdmin: outsider.publicKey })
+        .signers([outsider])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods
+      .transferMarketAdmin(newAdmin.publicKey)
+      .accounts({ market: tm.market, marketAdmin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let marketAccount = await program.account.market.fetch(tm.market);
+    expect(marketAccount.pendingAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+    expect(marketAccount.marketAdmin.toString()).to.equal(admin.publicKey.toString());
+
+    // Only the proposed admin can accept
+    await expectError(
+      program.methods
+        .acceptMarketAdmin()
+        .accounts({ market: tm.market, newAdmin: outsider.publicKey })
+        .signers([outsider])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods
+      .acceptMarketAdmin()
+      .accounts({ market: tm.market, newAdmin: newAdmin.publicKey })
+      .signers([newAdmin])
+      .rpc();
+
+    marketAccount = await program.account.market.fetch(tm.market);
+    expect(marketAccount.marketAdmin.toString()).to.equal(newAdmin.publicKey.toString());
+    expect(marketAccount.pendingAdmin.toString()).to.equal(PublicKey.default.toString());
+  });
+
+  it("am_i_safe flips around the liquidation threshold", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e8, 100 * 1e6);
+
+    const checkSafety = async () => {
+      const sig = await program.methods
+        .amISafe(tm.id)
+        .accounts({
+          market: tm.market,
+          userDeposit: borrower.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          user: borrower.keypair.publicKey,
+          collateralOracle: tm.collateralOracle,
+          borrowOracle: tm.supplyOracle,
+        })
+        .signers([borrower.keypair])
+        .rpc({ commitment: "confirmed" });
+      const event = (await getEvents(sig)).find((e) => e.name === "safetyEvent");
+      expect(event).to.not.equal(undefined);
+      expect(event.data.liquidationThreshold.toNumber()).to.equal(8500);
+      return event.data;
+    };
+
+    // The position sits at the 85% threshold when collateral is worth ~$1176
+    await setOraclePrice(tm.collateralOracle, 1200_000_000);
+    let safety = await checkSafety();
+    expect(safety.isSafe).to.be.true;
+    expect(safety.healthFactor.gte(new anchor.BN(1e9))).to.be.true;
+
+    await setOraclePrice(tm.collateralOracle, 1150_000_000);
+    safety = await checkSafety();
+    expect(safety.isSafe).to.be.false;
+    expect(safety.healthFactor.lt(new anchor.BN(1e9))).to.be.true;
+  });
+
+  it("Transfers protocol admin and locks the old admin out of pausing", async () => {
+    const newAdmin = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        newAdmin.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+
+    await program.methods
+      .transferProtocolAdmin(newAdmin.publicKey)
+      .accounts({ protocolState, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let protocolAccount = await program.account.protocolState.fetch(protocolState);
+    expect(protocolAccount.admin.toString()).to.equal(newAdmin.publicKey.toString());
+
+    // The old admin can no longer pause
+    await expectError(
+      program.methods
+        .setProtocolPaused(true)
+        .accounts({ protocolState, admin: admin.publicKey })
+        .signers([admin])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    // The new admin can
+    await program.methods
+      .setProtocolPaused(true)
+      .accounts({ protocolState, admin: newAdmin.publicKey })
+      .signers([newAdmin])
+      .rpc();
+    protocolAccount = await program.account.protocolState.fetch(protocolState);
+    expect(protocolAccount.isPaused).to.equal(true);
+
+    // Restore the original state for the remaining tests
+    await program.methods
+      .setProtocolPaused(false)
+      .accounts({ protocolState, admin: newAdmin.publicKey })
+      .signers([newAdmin])
+      .rpc();
+    await program.methods
+      .transferProtocolAdmin(admin.publicKey)
+      .accounts({ protocolState, admin: newAdmin.publicKey })
+      .signers([newAdmin])
+      .rpc();
+  });
+
+  it("Withdraws collateral with a stale oracle when nothing is borrowed", async () => {
+    const tm = await createTestMarket();
+    const depositor = await createTestUser(tm, 0, 1 * 1e9);
+
+    // Collateral-only deposit: borrow nothing
+    await borrowFrom(tm, depositor, 1 * 1e9, 0);
+
+    // Let the oracles go stale (older than 100 slots)
+    const oracleAccount = await program.account.oracle.fetch(tm.collateralOracle);
+    while (
+      (await provider.connection.getSlot()) <=
+      oracleAccount.validSlot.toNumber() + 100
+    ) {
+      await sleep(1000);
+    }
+    await expectError(
+      borrowFrom(tm, depositor, 0, 1),
+      "CollateralOracleStale"
+    );
+
+    await program.methods
+      .withdrawCollateral(tm.id, new anchor.BN(1 * 1e9))
+      .accounts({
+        market: tm.market,
+        collateralVault: tm.collateralVault,
+        userDeposit: depositor.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userCollateralAccount: depositor.collateralAccount,
+        user: depositor.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([depositor.keypair])
+      .rpc();
+
+    const deposit = await program.account.userDeposit.fetch(depositor.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(0);
+    const collateralAccount = await getAccount(
+      provider.connection,
+      depositor.collateralAccount
+    );
+    expect(Number(collateralAccount.amount)).to.equal(1 * 1e9);
+  });
+
+  it("Records every created market in the market index", async () => {
+    const before = await program.account.protocolState.fetch(protocolState);
+    const firstIndex = before.totalMarkets.toNumber();
+
+    const created = [];
+    for (let i = 0; i < 3; i++) {
+      created.push(await createTestMarket());
+    }
+
+    const after = await program.account.protocolState.fetch(protocolState);
+    expect(after.totalMarkets.toNumber()).to.equal(firstIndex + 3);
+
+    for (let i = 0; i < 3; i++) {
+      const [indexPda] = PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("market_index"),
+          new anchor.BN(firstIndex + i).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+      const entry = await program.account.marketIndex.fetch(indexPda);
+      expect(entry.index.toNumber()).to.equal(firstIndex + i);
+      expect(entry.market.toString()).to.equal(created[i].market.toString());
+    }
+  });
+
+  it("Rejects borrows that would leave a position below the market minimum", async () => {
+    // $100 minimum, in quote units with PRICE_DECIMALS (9)
+    const tm = await createTestMarket(1_000_000, 3000_000_000, 100 * 1e9);
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const marketAccount = await program.account.market.fetch(tm.market);
+    expect(marketAccount.minBorrow.toNumber()).to.equal(100 * 1e9);
+
+    // At $1 a token the minimum is 100 tokens
+    await expectError(
+      borrowFrom(tm, borrower, 1 * 1e9, 100 * 1e6 - 1),
+      "BorrowTooSmall"
+    );
+
+    // The failed borrow rolled back its collateral deposit too
+    await borrowFrom(tm, borrower, 1 * 1e9, 100 * 1e6 + 1);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6 + 1);
+
+    // The threshold is a value: at $2 a token, 60 tokens clear it and 40 don't
+    const pricier = await createTestMarket(2_000_000, 3000_000_000, 100 * 1e9);
+    const pricierSupplier = await createTestUser(pricier, 1000 * 1e6, 0);
+    const pricierBorrower = await createTestUser(pricier, 0, 1 * 1e9);
+    await supplyTo(pricier, pricierSupplier, 1000 * 1e6);
+    await expectError(
+      borrowFrom(pricier, pricierBorrower, 1 * 1e9, 40 * 1e6),
+      "BorrowTooSmall"
+    );
+    await borrowFrom(pricier, pricierBorrower, 1 * 1e9, 60 * 1e6);
+  });
+
+  const fundReservesIn = (tm: TestMarket, funder: TestUser, amount: number) =>
+    program.methods
+      .fundReserves(tm.id, new anchor.BN(amount))
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        funderSupplyAccount: funder.supplyAccount,
+        funder: funder.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([funder.keypair])
+      .rpc({ commitment: "confirmed" });
+
+  // Borrow 200 against 0.1 collateral, crash collateral to $1 and liquidate
+  // 100: all collateral is seized and 100 of debt is left uncollateralized
+  const liquidateIntoShortfall = async (tm: TestMarket) => {
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    // Interest-free, so the shortfall is exactly the unpaid principal
+    await setInterestFreeSlots(tm, 1_000_000);
+    await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+    await setOraclePrice(tm.collateralOracle, 1_000_000);
+
+    const before = await program.account.market.fetch(tm.market);
+    const sig = await liquidateIn(tm, liquidatorUser, borrower, 100 * 1e6);
+    const after = await program.account.market.fetch(tm.market);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(0);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+
+    const badDebt = (await getEvents(sig)).find((e) => e.name === "badDebtEvent");
+    expect(badDebt).to.not.be.undefined;
+    expect(badDebt.data.shortfall.toNumber()).to.equal(100 * 1e6);
+    return { before, after, badDebt };
+  };
+
+  it("Covers a small bad-debt shortfall entirely from reserves", async () => {
+    const tm = await createTestMarket();
+    const funder = await createTestUser(tm, 500 * 1e6, 0);
+    await fundReservesIn(tm, funder, 500 * 1e6);
+
+    const { before, after, badDebt } = await liquidateIntoShortfall(tm);
+
+    expect(badDebt.data.coveredByReserves.toNumber()).to.equal(100 * 1e6);
+    expect(badDebt.data.socialized.toNumber()).to.equal(0);
+    expect(after.totalReserves.toNumber()).to.equal(400 * 1e6);
+    // Suppliers are untouched, so the cToken exchange rate doesn't move
+    expect(after.totalSupplyDeposits.toString()).to.equal(
+      before.totalSupplyDeposits.toString()
+    );
+    expect(after.totalCtokenSupply.toString()).to.equal(
+      before.totalCtokenSupply.toString()
+    );
+  });
+
+  it("Socializes the part of a bad-debt shortfall reserves can't cover", async () => {
+    const tm = await createTestMarket();
+    const funder = await createTestUser(tm, 30 * 1e6, 0);
+    await fundReservesIn(tm, funder, 30 * 1e6);
+
+    const { before, after, badDebt } = await liquidateIntoShortfall(tm);
+
+    expect(badDebt.data.coveredByReserves.toNumber()).to.equal(30 * 1e6);
+    expect(badDebt.data.socialized.toNumber()).to.equal(70 * 1e6);
+    expect(after.totalReserves.toNumber()).to.equal(0);
+    expect(
+      before.totalSupplyDeposits.sub(after.totalSupplyDeposits).toNumber()
+    ).to.equal(70 * 1e6);
+  });
+
+  const updateOraclesBatch = (
+    authority: Keypair,
+    oracles: PublicKey[],
+    prices: number[]
+  ) =>
+    program.methods
+      .updateOraclePricesBatch(prices.map((p) => new anchor.BN(p)))
+      .accounts({ authority: authority.publicKey })
+      .remainingAccounts(
+        oracles.map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+      )
+      .signers([authority])
+      .rpc();
+
+  it("Batch-updates several oracle prices in one transaction", async () => {
+    const tmA = await createTestMarket();
+    const tmB = await createTestMarket();
+    const oracles = [tmA.supplyOracle, tmA.collateralOracle, tmB.collateralOracle];
+    const prices = [1_010_000, 3100_000_000, 2900_000_000];
+
+    await updateOraclesBatch(admin, oracles, prices);
+
+    for (let i = 0; i < oracles.length; i++) {
+      const oracleAccount = await program.account.oracle.fetch(oracles[i]);
+      expect(oracleAccount.price.toNumber()).to.equal(prices[i]);
+      expect(oracleAccount.updateCount.toNumber()).to.equal(1);
+    }
+    // Untouched oracle keeps its original price
+    const untouched = await program.account.oracle.fetch(tmB.supplyOracle);
+    expect(untouched.price.toNumber()).to.equal(1_000_000);
+    expect(untouched.updateCount.toNumber()).to.equal(0);
+  });
+
+  it("Reverts the whole oracle batch if one oracle has another authority", async () => {
+    const tm = await createTestMarket();
+
+    // Oracle owned by a different authority
+    const otherAuthority = Keypair.generate();
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(
+        otherAuthority.publicKey,
+        anchor.web3.LAMPORTS_PER_SOL
+      )
+    );
+    const otherMint = await createMint(
+      provider.connection,
+      admin,
+      admin.publicKey,
+      null,
+      6
+    );
+    const [otherOracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle"), otherMint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createOracle(
+        Buffer.from("mock_pyth_source_data"),
+        new anchor.BN(1_000_000),
+        6,
+        new anchor.BN(0)
+      )
+      .accounts({
+        oracle: otherOracle,
+        mint: otherMint,
+        authority: otherAuthority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([otherAuthority])
+      .rpc();
+
+    await expectError(
+      updateOraclesBatch(
+        admin,
+        [tm.supplyOracle, otherOracle, tm.collateralOracle],
+        [1_010_000, 1_010_000, 3100_000_000]
+      ),
+      "Unauthorized"
+    );
+
+    // Nothing in the batch was applied
+    const supplyOracle = await program.account.oracle.fetch(tm.supplyOracle);
+    expect(supplyOracle.price.toNumber()).to.equal(1_000_000);
+    expect(supplyOracle.updateCount.toNumber()).to.equal(0);
+    const other = await program.account.oracle.fetch(otherOracle);
+    expect(other.updateCount.toNumber()).to.equal(0);
+  });
+
+  it("Rejects oracles that don't match the market's configured oracles", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    // Attacker-controlled oracle PDA reporting an inflated collateral price
+    const fakeMint = await createMint(
+      provider.connection,
+      borrower.keypair,
+      borrower.keypair.publicKey,
+      null,
+      9
+    );
+    const [fakeOracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle"), fakeMint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createOracle(
+        Buffer.from("fake"),
+        new anchor.BN(1_000_000_000_000),
+        6,
+        new anchor.BN(0)
+      )
+      .accounts({
+        oracle: fakeOracle,
+        mint: fakeMint,
+        authority: borrower.keypair.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([borrower.keypair])
+      .rpc();
+
+    await expectError(
+      program.methods
+        .borrow(tm.id, new anchor.BN(1e8), new anchor.BN(900 * 1e6))
+        .accounts({
+          protocolState,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          collateralVault: tm.collateralVault,
+          userDeposit: borrower.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: borrower.supplyAccount,
+          userCollateralAccount: borrower.collateralAccount,
+          user: borrower.keypair.publicKey,
+          collateralOracle: fakeOracle,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([borrower.keypair])
+        .rpc(),
+      "InvalidOracleData"
+    );
+
+    // Liquidation priced off the wrong feed is rejected too
+    await borrowFrom(tm, borrower, 1e8, 100 * 1e6);
+    await expectError(
+      program.methods
+        .liquidate(tm.id, new anchor.BN(10 * 1e6), new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          collateralVault: tm.collateralVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          borrowerDeposit: borrower.deposit,
+          liquidatorSupplyAccount: liquidatorUser.supplyAccount,
+          liquidatorCollateralAccount: liquidatorUser.collateralAccount,
+          liquidator: liquidatorUser.keypair.publicKey,
+          oracle: fakeOracle,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([liquidatorUser.keypair])
+        .rpc(),
+      "InvalidOracleData"
+    );
+  });
+
+  it("Keeps the collateral vault backing recorded deposits and sweeps surplus", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    const outsider = await createTestUser(tm, 0, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // A direct send leaves the vault holding more than the market recorded
+    await mintTo(
+      provider.connection,
+      admin,
+      tm.collateralMint,
+      tm.collateralVault,
+      admin,
+      5e8
+    );
+    let market = await program.account.market.fetch(tm.market);
+    let vault = await getAccount(provider.connection, tm.collateralVault);
+    expect(Number(vault.amount)).to.equal(market.totalCollateralDeposits.toNumber() + 5e8);
+
+    const sweep = (authority: Keypair) =>
+      program.methods
+        .sweepSurplusCollateral(tm.id)
+        .accounts({
+          market: tm.market,
+          collateralVault: tm.collateralVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          destination: outsider.collateralAccount,
+          authority: authority.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([authority])
+        .rpc();
+
+    await expectError(sweep(outsider.keypair), "Unauthorized");
+    await sweep(admin);
+
+    vault = await getAccount(provider.connection, tm.collateralVault);
+    market = await program.account.market.fetch(tm.market);
+    expect(Number(vault.amount)).to.equal(market.totalCollateralDeposits.toNumber());
+    const swept = await getAccount(provider.connection, outsider.collateralAccount);
+    expect(Number(swept.amount)).to.equal(5e8);
+
+    // Repaying and withdrawing still pass the backing check with no surplus left
+    await repayTo(tm, borrower, 110 * 1e6);
+    await program.methods
+      .withdrawCollateral(tm.id, new anchor.BN(1e9))
+      .accounts({
+        market: tm.market,
+        collateralVault: tm.collateralVault,
+        userDeposit: borrower.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userCollateralAccount: borrower.collateralAccount,
+        user: borrower.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([borrower.keypair])
+      .rpc();
+    vault = await getAccount(provider.connection, tm.collateralVault);
+    expect(Number(vault.amount)).to.equal(0);
+  });
+
+  it("Accepts older oracle prices under a generous per-market staleness window", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    let market = await program.account.market.fetch(tm.market);
+    expect(market.maxOracleStalenessSlots.toNumber()).to.equal(100);
+
+    await expectError(
+      program.methods
+        .setMaxOracleStalenessSlots(new anchor.BN(0))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc(),
+      "InvalidOracleStaleness"
+    );
+    // A window past MAX_ORACLE_STALENESS_SLOTS would accept arbitrarily old prices
+    await expectError(
+      program.methods
+        .setMaxOracleStalenessSlots(new anchor.BN("18446744073709551615"))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc(),
+      "InvalidOracleStaleness"
+    );
+    await program.methods
+      .setMaxOracleStalenessSlots(new anchor.BN(10_000))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    market = await program.account.market.fetch(tm.market);
+    expect(market.maxOracleStalenessSlots.toNumber()).to.equal(10_000);
+
+    // Age the prices past the default 100-slot window
+    const oracleAccount = await program.account.oracle.fetch(tm.collateralOracle);
+    while (
+      (await provider.connection.getSlot()) <=
+      oracleAccount.validSlot.toNumber() + 100
+    ) {
+      await sleep(1000);
+    }
+
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6);
+  });
+
+  it("Configures a per-market flash loan fee through update_market_params", async () => {
+    const tm = await createTestMarket();
+
+    let market = await program.account.market.fetch(tm.market);
+    expect(market.flashLoanFeeBps.toNumber()).to.equal(30);
+
+    const updateFee = (feeBps: number) =>
+      program.methods
+        .updateMarketParams(
+          new anchor.BN(8000),
+          new anchor.BN(8500),
+          new anchor.BN(0),
+          new anchor.BN(feeBps),
+          new anchor.BN(500)
+        )
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    await updateFee(100);
+    market = await program.account.market.fetch(tm.market);
+    expect(market.flashLoanFeeBps.toNumber()).to.equal(100);
+
+    // Free flash loans are allowed, fees above 100% are not
+    await updateFee(0);
+    market = await program.account.market.fetch(tm.market);
+    expect(market.flashLoanFeeBps.toNumber()).to.equal(0);
+    await expectError(updateFee(10001), "InvalidBasisPoints");
+  });
+
+  it("Books borrow interest into total borrows so a full repayment clears them exactly", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // The interest on the only borrower's debt is booked on the market as well,
+    // so repaying all of it leaves nothing to clamp
+    await sleep(2000);
+    const sig = await repayTo(tm, borrower, 110 * 1e6);
+
+    const mismatch = (await getEvents(sig)).find(
+      (e) => e.name === "borrowAccountingMismatchEvent"
+    );
+    expect(mismatch).to.be.undefined;
+
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.totalBorrows.toNumber()).to.equal(0);
+    // Suppliers earned the interest the borrower paid
+    expect(market.totalSupplyDeposits.toNumber()).to.be.greaterThan(1000 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+  });
+
+  it("Pays long-held supply positions a loyalty bonus over fresh ones", async () => {
+    const tm = await createTestMarket();
+    const funder = await createTestUser(tm, 50 * 1e6, 0);
+    const loyal = await createTestUser(tm, 100 * 1e6, 0);
+    const fresh = await createTestUser(tm, 100 * 1e6, 0);
+    await fundReservesIn(tm, funder, 50 * 1e6);
+
+    // Bonus starts after 5 slots and reaches the 5% cap at 10
+    await program.methods
+      .setLoyaltyBonus(new anchor.BN(5), new anchor.BN(500))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    const withdrawAll = async (user: TestUser) => {
+      const deposit = await program.account.userDeposit.fetch(user.deposit);
+      const before = await getAccount(provider.connection, user.supplyAccount);
+      await program.methods
+        .withdraw(tm.id, deposit.ctokenBalance, new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: user.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: user.supplyAccount,
+          user: user.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.keypair])
+        .rpc();
+      const after = await getAccount(provider.connection, user.supplyAccount);
+      return Number(after.amount) - Number(before.amount);
+    };
+
+    await supplyTo(tm, loyal, 100 * 1e6);
+    const loyalDeposit = await program.account.userDeposit.fetch(loyal.deposit);
+    while (
+      (await provider.connection.getSlot()) <
+      loyalDeposit.supplyOpenedSlot.toNumber() + 12
+    ) {
+      await sleep(500);
+    }
+    await supplyTo(tm, fresh, 100 * 1e6);
+
+    const loyalReceived = await withdrawAll(loyal);
+    const freshReceived = await withdrawAll(fresh);
+
+    // Same-sized positions: the long-held one also collects the capped 5% bonus
+    expect(loyalReceived).to.be.greaterThan(freshReceived);
+    expect(loyalReceived - freshReceived).to.be.at.least(4 * 1e6);
+
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.totalReserves.toNumber()).to.be.lessThan(50 * 1e6);
+  });
+
+  it("get_current_debt returns the debt a full repay consumes", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // Let interest accrue
+    await sleep(3000);
+
+    const debt = await program.methods
+      .getCurrentDebt(tm.id)
+      .accounts({
+        market: tm.market,
+        userDeposit: borrower.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        user: borrower.keypair.publicKey,
+      })
+      .view();
+    expect(debt.toNumber()).to.be.greaterThan(100 * 1e6);
+    // Nothing was persisted by the query
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6);
+
+    const before = await getAccount(provider.connection, borrower.supplyAccount);
+    await repayTo(tm, borrower, 110 * 1e6);
+    const after = await getAccount(provider.connection, borrower.supplyAccount);
+    const consumed = Number(before.amount) - Number(after.amount);
+
+    // The repay lands a slot or two later, so it may include a few more units of interest
+    expect(consumed).to.be.at.least(debt.toNumber());
+    expect(consumed - debt.toNumber()).to.be.at.most(50);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+  });
+
+  it("Reports available liquidity as the smaller of accounting and vault balance", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    const funder = await createTestUser(tm, 50 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 200 * 1e6);
+    await borrowFrom(tm, borrower, 0, 100 * 1e6);
+    // Reserves sit in the vault without being supplier liquidity
+    await fundReservesIn(tm, funder, 50 * 1e6);
+
+    const sig = await program.methods
+      .getAvailableLiquidity(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+      })
+      .rpc({ commitment: "confirmed" });
+    const event = (await getEvents(sig)).find((e) => e.name === "liquidityEvent");
+    expect(event).to.not.be.undefined;
+
+    const market = await program.account.market.fetch(tm.market);
+    const vault = await getAccount(provider.connection, tm.supplyVault);
+    const accounting = market.totalSupplyDeposits.sub(market.totalBorrows).toNumber();
+    // Includes the interest booked on the first borrow when the second one accrued
+    expect(event.data.totalBorrows.toNumber()).to.equal(market.totalBorrows.toNumber());
+    expect(event.data.totalBorrows.toNumber()).to.be.at.least(300 * 1e6);
+    expect(event.data.vaultBalance.toNumber()).to.equal(Number(vault.amount));
+    expect(Number(vault.amount)).to.be.greaterThan(accounting);
+    expect(event.data.available.toNumber()).to.equal(
+      Math.min(accounting, Number(vault.amount))
+    );
+  });
+
+  it("Blocks supplier withdrawals that would leave outstanding borrows unbacked", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 800 * 1e6);
+
+    const withdrawCTokens = (ctokens: anchor.BN) =>
+      program.methods
+        .withdraw(tm.id, ctokens, new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: supplier.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: supplier.supplyAccount,
+          user: supplier.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([supplier.keypair])
+        .rpc();
+
+    // Most of the pool is borrowed: the vault can't give up supply it needs for borrows
+    const deposit = await program.account.userDeposit.fetch(supplier.deposit);
+    await expectError(withdrawCTokens(deposit.ctokenBalance), "InsufficientLiquidity");
+    await expectError(withdrawCTokens(new anchor.BN(100 * 1e6)), "InsufficientLiquidity");
+
+    // Once the borrow is repaid the supplier can leave
+    await repayTo(tm, borrower, 810 * 1e6);
+    await withdrawCTokens(deposit.ctokenBalance);
+    const after = await program.account.userDeposit.fetch(supplier.deposit);
+    expect(after.ctokenBalance.toNumber()).to.equal(0);
+  });
+
+  it("Socializes bad debt to suppliers by lowering the cToken exchange rate", async () => {
+    const tm = await createTestMarket();
+    const exchangeRate = (m: { totalSupplyDeposits: anchor.BN; totalCtokenSupply: anchor.BN }) =>
+      m.totalSupplyDeposits.mul(new anchor.BN(1e9)).div(m.totalCtokenSupply).toNumber();
+
+    // No reserves: the whole 100 shortfall falls on suppliers
+    const { before, after, badDebt } = await liquidateIntoShortfall(tm);
+    expect(badDebt.data.socialized.toNumber()).to.equal(100 * 1e6);
+    expect(after.totalBadDebt.toNumber()).to.equal(100 * 1e6);
+    expect(before.totalBadDebt.toNumber()).to.equal(0);
+
+    // 1000 supplied, 100 written off: each cToken is now worth ~10% less
+    expect(exchangeRate(after)).to.be.lessThan(exchangeRate(before));
+    expect(exchangeRate(after)).to.be.lessThan(1e9);
+    expect(exchangeRate(after)).to.be.closeTo(0.9e9, 0.001e9);
+    // The written-off debt no longer counts as outstanding
+    expect(after.totalBorrows.toNumber()).to.equal(0);
+  });
+
+  it("get_market_stats returns utilization and annualized rates", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const query = () =>
+      program.methods
+        .getMarketStats(tm.id)
+        .accounts({
+          market: tm.market,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+        })
+        .view();
+
+    let stats = await query();
+    expect(stats.utilization.toNumber()).to.equal(0);
+    // 25 per slot compounded over 800,000 slots: about 2.0201% scaled by 1e9
+    expect(stats.borrowApy.toNumber()).to.equal(20_200_564);
+    expect(stats.borrowApr.toNumber()).to.equal(20_000_000); // the simple 2%
+    expect(stats.supplyApy.toNumber()).to.equal(0);
+    expect(stats.supplyApr.toNumber()).to.equal(0);
+
+    await borrowFrom(tm, borrower, 1e9, 250 * 1e6);
+
+    stats = await query();
+    const market = await program.account.market.fetch(tm.market);
+    const expectedUtilization = market.totalBorrows
+      .mul(new anchor.BN(1e9))
+      .div(market.totalSupplyDeposits)
+      .toNumber();
+    expect(stats.utilization.toNumber()).to.equal(expectedUtilization);
+    expect(stats.utilization.toNumber()).to.be.closeTo(250_000_000, 1_000_000);
+    // Suppliers earn the compounded borrow yield scaled down by utilization
+    expect(stats.supplyApy.toNumber()).to.equal(
+      Math.floor((20_200_564 * expectedUtilization) / 1e9)
+    );
+    expect(stats.supplyApr.toNumber()).to.equal(
+      Math.floor((20_000_000 * expectedUtilization) / 1e9)
+    );
+  });
+
+  it("repay_all clears the full debt, interest included, in one call", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // Let interest accrue
+    await sleep(3000);
+
+    const before = await getAccount(provider.connection, borrower.supplyAccount);
+    const sig = await program.methods
+      .repayAll(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        userDeposit: borrower.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: borrower.supplyAccount,
+        user: borrower.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([borrower.keypair])
+      .rpc({ commitment: "confirmed" });
+    const after = await getAccount(provider.connection, borrower.supplyAccount);
+
+    const event = (await getEvents(sig)).find((e) => e.name === "repayAllEvent");
+    expect(event).to.not.be.undefined;
+    const repaid = event.data.amount.toNumber();
+    expect(repaid).to.be.greaterThan(100 * 1e6);
+    expect(Number(before.amount) - Number(after.amount)).to.equal(repaid);
+
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.totalBorrows.toNumber()).to.equal(0);
+  });
+
+  it("Applies the market's max_confidence_bps to oracle prices", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    let market = await program.account.market.fetch(tm.market);
+    expect(market.maxConfidenceBps.toNumber()).to.equal(500);
+
+    const setMaxConfidence = (bps: number) =>
+      program.methods
+        .updateMarketParams(
+          new anchor.BN(8000),
+          new anchor.BN(8500),
+          new anchor.BN(0),
+          new anchor.BN(30),
+          new anchor.BN(bps)
+        )
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    // Fresh prices carry a 1% confidence interval
+    await setOraclePrice(tm.collateralOracle, 3000 * 1e6);
+    await setOraclePrice(tm.supplyOracle, 1e6);
+
+    // Strict 0.5% setting rejects them
+    await setMaxConfidence(50);
+    await expectError(borrowFrom(tm, borrower, 1e9, 10 * 1e6), "InvalidOracleData");
+
+    // Loose 2% setting accepts the same prices
+    await setMaxConfidence(200);
+    await borrowFrom(tm, borrower, 1e9, 10 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(10 * 1e6);
+
+    market = await program.account.market.fetch(tm.market);
+    expect(market.maxConfidenceBps.toNumber()).to.equal(200);
+  });
+
+  const marketIndexPda = (index: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("market_index"), index.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+
+  const closeMarketIn = (
+    tm: TestMarket,
+    marketIndex: PublicKey,
+    lastMarketIndex: PublicKey | null
+  ) =>
+    program.methods
+      .closeMarket(tm.id)
+      .accounts({
+        market: tm.market,
+        protocolState,
+        marketIndex,
+        lastMarketIndex,
+        marketPointer: marketPointerPda(tm.supplyMint, tm.collateralMint),
+        supplyVault: tm.supplyVault,
+        collateralVault: tm.collateralVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        admin: admin.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([admin])
+      .rpc();
+
+  it("Closes an empty market and keeps the market index dense", async () => {
+    const closedIndexPda = await nextMarketIndexPda();
+    const tm = await createTestMarket();
+    const survivor = await createTestMarket();
+
+    const before = await program.account.protocolState.fetch(protocolState);
+    const lastIndexPda = marketIndexPda(before.totalMarkets.subn(1));
+    expect(
+      (await program.account.marketIndex.fetch(lastIndexPda)).market.toBase58()
+    ).to.equal(survivor.market.toBase58());
+
+    await closeMarketIn(tm, closedIndexPda, lastIndexPda);
+
+    const after = await program.account.protocolState.fetch(protocolState);
+    expect(after.totalMarkets.toNumber()).to.equal(before.totalMarkets.toNumber() - 1);
+    expect(await provider.connection.getAccountInfo(tm.market)).to.be.null;
+    expect(await provider.connection.getAccountInfo(tm.supplyVault)).to.be.null;
+    expect(await provider.connection.getAccountInfo(tm.collateralVault)).to.be.null;
+    expect(
+      await provider.connection.getAccountInfo(marketPointerPda(tm.supplyMint, tm.collateralMint))
+    ).to.be.null;
+
+    // The last entry moved into the freed slot
+    const moved = await program.account.marketIndex.fetch(closedIndexPda);
+    expect(moved.market.toBase58()).to.equal(survivor.market.toBase58());
+    expect(await provider.connection.getAccountInfo(lastIndexPda)).to.be.null;
+
+    // The freed highest index is reused by the next market
+    expect((await nextMarketIndexPda()).toBase58()).to.equal(lastIndexPda.toBase58());
+  });
+
+  it("Refuses to close a market that still holds deposits", async () => {
+    const indexPda = await nextMarketIndexPda();
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 100 * 1e6, 0);
+    await supplyTo(tm, supplier, 100 * 1e6);
+
+    await expectError(closeMarketIn(tm, indexPda, null), "InvalidMarketState");
+    expect(await provider.connection.getAccountInfo(tm.market)).to.not.be.null;
+  });
+
+  const portfolioPda = (tm: TestMarket, user: TestUser) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("portfolio"), user.keypair.publicKey.toBuffer(), tm.market.toBuffer()],
+      program.programId
+    )[0];
+
+  const portfolioVaultPda = (portfolio: PublicKey, mint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("portfolio_vault"), portfolio.toBuffer(), mint.toBuffer()],
+      program.programId
+    )[0];
+
+  const initializePortfolioIn = async (tm: TestMarket, user: TestUser) => {
+    const portfolio = portfolioPda(tm, user);
+    await program.methods
+      .initializePortfolio(tm.id)
+      .accounts({
+        portfolio,
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        user: user.keypair.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.keypair])
+      .rpc();
+    return portfolio;
+  };
+
+  const addPortfolioCollateralIn = (
+    tm: TestMarket,
+    user: TestUser,
+    mint: PublicKey,
+    oracle: PublicKey
+  ) =>
+    program.methods
+      .addPortfolioCollateral(tm.id)
+      .accounts({
+        portfolio: portfolioPda(tm, user),
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        assetMint: mint,
+        assetOracle: oracle,
+        assetVault: portfolioVaultPda(portfolioPda(tm, user), mint),
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user.keypair])
+      .rpc();
+
+  const depositPortfolioCollateralIn = (
+    tm: TestMarket,
+    user: TestUser,
+    mint: PublicKey,
+    from: PublicKey,
+    amount: number
+  ) =>
+    program.methods
+      .depositPortfolioCollateral(tm.id, new anchor.BN(amount))
+      .accounts({
+        portfolio: portfolioPda(tm, user),
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        assetMint: mint,
+        assetVault: portfolioVaultPda(portfolioPda(tm, user), mint),
+        userAssetAccount: from,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user.keypair])
+      .rpc();
+
+  const portfolioBorrowAccounts = (tm: TestMarket, user: TestUser) => ({
+    protocolState,
+    portfolio: portfolioPda(tm, user),
+    market: tm.market,
+    supplyVault: tm.supplyVault,
+    supplyMint: tm.supplyMint,
+    collateralMint: tm.collateralMint,
+    userSupplyAccount: user.supplyAccount,
+    user: user.keypair.publicKey,
+    borrowOracle: tm.supplyOracle,
+    tokenProgram: TOKEN_PROGRAM_ID,
+  });
+
+  const oracleMetas = (oracles: PublicKey[]) =>
+    oracles.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false }));
+
+  const borrowAgainstPortfolioIn = (
+    tm: TestMarket,
+    user: TestUser,
+    amount: number,
+    oracles: PublicKey[]
+  ) =>
+    program.methods
+      .borrowAgainstPortfolio(tm.id, new anchor.BN(amount))
+      .accounts(portfolioBorrowAccounts(tm, user))
+      .remainingAccounts(oracleMetas(oracles))
+      .signers([user.keypair])
+      .rpc();
+
+  const repayPortfolioIn = (tm: TestMarket, user: TestUser, amount: number) =>
+    program.methods
+      .repayPortfolio(tm.id, new anchor.BN(amount))
+      .accounts(portfolioBorrowAccounts(tm, user))
+      .signers([user.keypair])
+      .rpc();
+
+  const withdrawPortfolioCollateralIn = (
+    tm: TestMarket,
+    user: TestUser,
+    amount: number,
+    oracles: PublicKey[]
+  ) =>
+    program.methods
+      .withdrawPortfolioCollateral(tm.id, new anchor.BN(amount))
+      .accounts({
+        portfolio: portfolioPda(tm, user),
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        assetMint: tm.collateralMint,
+        assetVault: portfolioVaultPda(portfolioPda(tm, user), tm.collateralMint),
+        userAssetAccount: user.collateralAccount,
+        user: user.keypair.publicKey,
+        borrowOracle: tm.supplyOracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(oracleMetas(oracles))
+      .signers([user.keypair])
+      .rpc();
+
+  // Portfolio borrowing 400 of 1000 supplied against 1 collateral token at $3000
+  const openPortfolioLoan = async (tm: TestMarket) => {
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await initializePortfolioIn(tm, borrower);
+    await addPortfolioCollateralIn(tm, borrower, tm.collateralMint, tm.collateralOracle);
+    await depositPortfolioCollateralIn(
+      tm,
+      borrower,
+      tm.collateralMint,
+      borrower.collateralAccount,
+      1e9
+    );
+    await borrowAgainstPortfolioIn(tm, borrower, 400 * 1e6, [tm.collateralOracle]);
+    return borrower;
+  };
+
+  it("Borrows against the combined value of two portfolio collaterals", async () => {
+    const tm = await createTestMarket();
+    // Second collateral asset: borrow the 6-decimal collateral mint and oracle of another
+    // market ($2000), so the portfolio mixes decimals
+    const other = await createTestMarket(1_000_000, 2000_000_000, 0, 6, 6);
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 0.01 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const otherAccount = await createAccount(
+      provider.connection,
+      borrower.keypair,
+      other.collateralMint,
+      borrower.keypair.publicKey
+    );
+    await mintTo(provider.connection, admin, other.collateralMint, otherAccount, admin, 0.01 * 1e6);
+
+    const portfolio = await initializePortfolioIn(tm, borrower);
+    const addAndDeposit = async (
+      mint: PublicKey,
+      oracle: PublicKey,
+      from: PublicKey,
+      amount: number
+    ) => {
+      await addPortfolioCollateralIn(tm, borrower, mint, oracle);
+      await depositPortfolioCollateralIn(tm, borrower, mint, from, amount);
+    };
+
+    const borrowAgainst = async (amount: number, oracles: PublicKey[]) => {
+      // Keep every feed fresh for the borrow
+      await setOraclePrice(tm.supplyOracle, 1_000_000);
+      await setOraclePrice(tm.collateralOracle, 3000_000_000);
+      await setOraclePrice(other.collateralOracle, 2000_000_000);
+      return program.methods
+        .borrowAgainstPortfolio(tm.id, new anchor.BN(amount))
+        .accounts({
+          portfolio,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: borrower.supplyAccount,
+          user: borrower.keypair.publicKey,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(
+          oracles.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false }))
+        )
+        .signers([borrower.keypair])
+        .rpc();
+    };
+
+    // 0.01 of each at $3000 with an 80% factor backs 24 tokens, at $2000 it backs 16
+    await addAndDeposit(
+      tm.collateralMint,
+      tm.collateralOracle,
+      borrower.collateralAccount,
+      0.01 * 1e9
+    );
+    await expectError(
+      borrowAgainst(30 * 1e6, [tm.collateralOracle]),
+      "InsufficientCollateral"
+    );
+
+    await addAndDeposit(other.collateralMint, other.collateralOracle, otherAccount, 0.01 * 1e6);
+    // More than either collateral backs alone, within their combined 40
+    await borrowAgainst(30 * 1e6, [tm.collateralOracle, other.collateralOracle]);
+
+    const state = await program.account.userPortfolio.fetch(portfolio);
+    expect(state.collaterals.length).to.equal(2);
+    expect(state.collaterals.map((entry) => entry.decimals)).to.deep.equal([9, 6]);
+    expect(state.borrowedAmount.toNumber()).to.equal(30 * 1e6);
+    const received = await getAccount(provider.connection, borrower.supplyAccount);
+    expect(Number(received.amount)).to.equal(30 * 1e6);
+
+    // The combined limit still applies
+    await expectError(
+      borrowAgainst(15 * 1e6, [tm.collateralOracle, other.collateralOracle]),
+      "InsufficientCollateral"
+    );
+    // Each entry must be priced by its own oracle
+    await expectError(
+      borrowAgainst(1e6, [tm.collateralOracle, tm.collateralOracle]),
+      "InvalidOracleData"
+    );
+  });
+
+  it("Gives every portfolio its own vault for a collateral mint", async () => {
+    const tm = await createTestMarket();
+    const first = await createTestUser(tm, 0, 10_000);
+    const second = await createTestUser(tm, 0, 10_000);
+
+    for (const user of [first, second]) {
+      await initializePortfolioIn(tm, user);
+      await addPortfolioCollateralIn(tm, user, tm.collateralMint, tm.collateralOracle);
+      await depositPortfolioCollateralIn(
+        tm,
+        user,
+        tm.collateralMint,
+        user.collateralAccount,
+        10_000
+      );
+      const vault = await getAccount(
+        provider.connection,
+        portfolioVaultPda(portfolioPda(tm, user), tm.collateralMint)
+      );
+      expect(Number(vault.amount)).to.equal(10_000);
+    }
+
+    // Registering the same mint twice in one portfolio is still rejected
+    await expectError(
+      addPortfolioCollateralIn(tm, first, tm.collateralMint, tm.collateralOracle),
+      "already in use"
+    );
+  });
+
+  const setInterestFreeSlots = (tm: TestMarket, slots: number) =>
+    program.methods
+      .setInterestFreeSlots(new anchor.BN(slots))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+  it("Charges no interest on a borrow repaid inside the interest-free window", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await setInterestFreeSlots(tm, 10_000);
+
+    await borrowFrom(tm, borrower, 1 * 1e9, 500 * 1e6);
+    await sleep(2000);
+
+    await repayTo(tm, borrower, 100 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(400 * 1e6);
+  });
+
+  it("Charges interest once the interest-free window has passed", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await setInterestFreeSlots(tm, 2);
+
+    await borrowFrom(tm, borrower, 1 * 1e9, 500 * 1e6);
+    // Several slots past the 2-slot window
+    await sleep(4000);
+
+    await repayTo(tm, borrower, 100 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.greaterThan(400 * 1e6);
+  });
+
+  // SPL Memo v1 ignores its accounts, so it serves as a no-op flash loan callback
+  const MEMO_V1_PROGRAM_ID = new PublicKey(
+    "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo"
+  );
+
+  // Protocol fees go to token accounts owned by the fee recipient, so point it at a test user
+  const setFeeRecipientTo = (recipient: TestUser) =>
+    program.methods
+      .setFeeRecipient(recipient.keypair.publicKey)
+      .accounts({ protocolState, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+  const flashLoanIn = (
+    tm: TestMarket,
+    user: TestUser,
+    amount: number,
+    feeRecipient: TestUser
+  ) =>
+    program.methods
+      .flashLoan(tm.id, new anchor.BN(amount), Buffer.from("flash"))
+      .accounts({
+        protocolState,
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: user.supplyAccount,
+        feeRecipientAccount: feeRecipient.supplyAccount,
+        user: user.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: MEMO_V1_PROGRAM_ID, isSigner: false, isWritable: false },
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+      ])
+      .signers([user.keypair])
+      .rpc();
+
+  it("Pulls back exactly amount + fee on flash loans, leaving any excess with the borrower", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1_000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1_000 * 1e6);
+
+    // The borrower holds far more than the fee, as if the callback returned a profit
+    const borrower = await createTestUser(tm, 50 * 1e6, 0);
+    const recipient = await createTestUser(tm, 0, 0);
+    await setFeeRecipientTo(recipient);
+    const market = await program.account.market.fetch(tm.market);
+    const fee = Math.floor((100 * 1e6 * market.flashLoanFeeBps.toNumber()) / 10000);
+    const vaultBefore = await getAccount(provider.connection, tm.supplyVault);
+
+    await flashLoanIn(tm, borrower, 100 * 1e6, recipient);
+
+    const vaultAfter = await getAccount(provider.connection, tm.supplyVault);
+    const borrowerAfter = await getAccount(
+      provider.connection,
+      borrower.supplyAccount
+    );
+    const marketAfter = await program.account.market.fetch(tm.market);
+    expect(Number(vaultAfter.amount - vaultBefore.amount)).to.equal(fee);
+    expect(Number(borrowerAfter.amount)).to.equal(50 * 1e6 - fee);
+    // No protocol share configured: the whole fee goes to suppliers
+    expect(marketAfter.totalSupplyDeposits.toNumber()).to.equal(
+      market.totalSupplyDeposits.toNumber() + fee
+    );
+
+    // A borrower who can't cover the fee can't repay
+    const broke = await createTestUser(tm, 0, 0);
+    await expectError(
+      flashLoanIn(tm, broke, 100 * 1e6, recipient),
+      "FlashLoanNotRepaid"
+    );
+  });
+
+  it("Blocks oracle updates outside the deviation band unless overridden", async () => {
+    const tm = await createTestMarket();
+    const oracle = tm.collateralOracle;
+    const setBreaker = (bps: number, slots: number) =>
+      program.methods
+        .setOracleCircuitBreaker(new anchor.BN(bps), new anchor.BN(slots))
+        .accounts({ oracle, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    await expectError(setBreaker(10001, 1_000), "InvalidBasisPoints");
+    await setBreaker(1000, 1_000_000);
+    const base = (await program.account.oracle.fetch(oracle)).price.toNumber();
+
+    // A 5% move is inside the 10% band
+    await setOraclePrice(oracle, Math.floor(base * 1.05));
+    let price = (await program.account.oracle.fetch(oracle)).price.toNumber();
+    expect(price).to.equal(Math.floor(base * 1.05));
+
+    // A 50% jump right after is blocked
+    await expectError(
+      setOraclePrice(oracle, Math.floor(price * 1.5)),
+      "PriceDeviationTooLarge"
+    );
+
+    // The authority can push it through explicitly, which is recorded
+    const sig = await program.methods
+      .updateOraclePrice(new anchor.BN(Math.floor(price * 1.5)), true)
+      .accounts({ oracle, authority: admin.publicKey })
+      .signers([admin])
+      .rpc({ commitment: "confirmed" });
+    const override = (await getEvents(sig)).find(
+      (e) => e.name === "oraclePriceOverrideEvent"
+    );
+    expect(override).to.not.be.undefined;
+    expect(override!.data.deviationBps.toNumber()).to.equal(5000);
+    expect(override!.data.previousPrice.toNumber()).to.equal(price);
+  });
+
+  it("Reports market TVL in a common quote unit", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 250 * 1e6);
+
+    const tvl = await program.methods
+      .getMarketTvl(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        collateralVault: tm.collateralVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        supplyOracle: tm.supplyOracle,
+        collateralOracle: tm.collateralOracle,
+      })
+      .view();
+
+    // Oracle prices are $1 and $3000 with 6 decimals, normalized to 9 decimals;
+    // USDC has 6 decimals and ETH 9, so each value is in 1e-9 dollars
+    const supplyVault = await getAccount(provider.connection, tm.supplyVault);
+    const collateralVault = await getAccount(provider.connection, tm.collateralVault);
+    const market = await program.account.market.fetch(tm.market);
+    expect(tvl.supplyValue.toNumber()).to.equal(
+      (Number(supplyVault.amount) / 1e6) * 1e9
+    );
+    expect(tvl.supplyValue.toNumber()).to.equal(750 * 1e9);
+    expect(tvl.collateralValue.toNumber()).to.equal(
+      (Number(collateralVault.amount) / 1e9) * 3000 * 1e9
+    );
+    expect(tvl.collateralValue.toNumber()).to.equal(3000 * 1e9);
+    expect(tvl.borrowedValue.toNumber()).to.equal(
+      (market.totalBorrows.toNumber() / 1e6) * 1e9
+    );
+    expect(tvl.borrowedValue.toNumber()).to.equal(250 * 1e9);
+  });
+
+  it("Seizes collateral using the market's configurable liquidation bonus", async () => {
+    const setBonus = (tm: TestMarket, bps: number) =>
+      program.methods
+        .setLiquidationBonusBps(new anchor.BN(bps))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    for (const [bonusBps, expectedSeized] of [
+      // $50 of debt plus the bonus, paid out in $2000 ETH
+      [500, 26_250_000],
+      [1500, 28_750_000],
+    ]) {
+      const tm = await createTestMarket();
+      let market = await program.account.market.fetch(tm.market);
+      expect(market.liquidationBonusBps.toNumber()).to.equal(1000);
+      await setBonus(tm, bonusBps);
+      market = await program.account.market.fetch(tm.market);
+      expect(market.liquidationBonusBps.toNumber()).to.equal(bonusBps);
+
+      const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+      const borrower = await createTestUser(tm, 0, 1e8);
+      const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+      await supplyTo(tm, supplier, 1000 * 1e6);
+      await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+      await setOraclePrice(tm.collateralOracle, 2000_000_000);
+
+      await liquidateIn(tm, liquidatorUser, borrower, 50 * 1e6);
+      const seized = await getAccount(
+        provider.connection,
+        liquidatorUser.collateralAccount
+      );
+      expect(Number(seized.amount)).to.equal(expectedSeized);
+    }
+
+    // Bonuses above the 20% cap are rejected
+    const tm = await createTestMarket();
+    await setBonus(tm, 2000);
+    await expectError(setBonus(tm, 2001), "LiquidationBonusTooHigh");
+  });
+
+  it("Liquidates several unhealthy positions in one batch and skips healthy ones", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const risky1 = await createTestUser(tm, 0, 1e8);
+    const risky2 = await createTestUser(tm, 0, 1e8);
+    const safe = await createTestUser(tm, 0, 1e8);
+    // Interest-free, so the debts each liquidation books stay round
+    await setInterestFreeSlots(tm, 1_000_000);
+    await borrowFrom(tm, risky1, 1e8, 200 * 1e6);
+    await borrowFrom(tm, risky2, 1e8, 200 * 1e6);
+    await borrowFrom(tm, safe, 1e8, 50 * 1e6);
+
+    // ETH to $2000: 0.1 ETH backs $170 at the 85% threshold, below the $200 loans
+    await setOraclePrice(tm.collateralOracle, 2000_000_000);
+
+    const batch = program.methods
+      .liquidateBatch(tm.id, [
+        new anchor.BN(40 * 1e6),
+        new anchor.BN(40 * 1e6),
+        new anchor.BN(40 * 1e6),
+      ])
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        collateralVault: tm.collateralVault,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        liquidatorSupplyAccount: liquidatorUser.supplyAccount,
+        liquidatorCollateralAccount: liquidatorUser.collateralAccount,
+        liquidator: liquidatorUser.keypair.publicKey,
+        collateralOracle: tm.collateralOracle,
+        borrowOracle: tm.supplyOracle,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(
+        [risky1, safe, risky2].map((u) => ({
+          pubkey: u.deposit,
+          isSigner: false,
+          isWritable: true,
+        }))
+      )
+      .signers([liquidatorUser.keypair]);
+
+    // liquidateBatch writes accounts, so it can't be a view: read its return data from a simulation
+    const simulated = await batch.simulate();
+    const returnLog = simulated.raw.find((log) =>
+      log.startsWith(`Program return: ${program.programId} `)
+    );
+    const summary = program.coder.types.decode(
+      "liquidationBatchSummary",
+      Buffer.from(returnLog.split(" ")[3], "base64")
+    );
+    expect(summary.liquidated).to.equal(2);
+    expect(summary.skipped).to.equal(1);
+    expect(summary.debtRepaid.toNumber()).to.equal(80 * 1e6);
+    // $40 plus the 10% default bonus, paid out in $2000 ETH: 0.022 ETH each
+    expect(summary.collateralSeized.toNumber()).to.equal(44 * 1e6);
+
+    await batch.rpc();
+
+    for (const risky of [risky1, risky2]) {
+      const deposit = await program.account.userDeposit.fetch(risky.deposit);
+      expect(deposit.borrowedAmount.toNumber()).to.equal(160 * 1e6);
+      expect(deposit.collateralDeposited.toNumber()).to.equal(1e8 - 22 * 1e6);
+    }
+    const untouched = await program.account.userDeposit.fetch(safe.deposit);
+    expect(untouched.borrowedAmount.toNumber()).to.equal(50 * 1e6);
+    expect(untouched.collateralDeposited.toNumber()).to.equal(1e8);
+
+    const seized = await getAccount(
+      provider.connection,
+      liquidatorUser.collateralAccount
+    );
+    expect(Number(seized.amount)).to.equal(44 * 1e6);
+  });
+
+  it("Checks a new borrow against debt including accrued interest", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    // 0.1 collateral at $3000 with an 80% factor caps debt at 240 supply tokens
+    const borrower = await createTestUser(tm, 0, 1e8);
+    await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+
+    // Let interest build up on the stored (stale) debt
+    await sleep(3000);
+    const stale = (await program.account.userDeposit.fetch(borrower.deposit))
+      .borrowedAmount.toNumber();
+    expect(stale).to.equal(200 * 1e6);
+
+    // Exactly fills the limit against the stale debt, but not once interest is booked
+    await expectError(
+      borrowFrom(tm, borrower, 0, 240 * 1e6 - stale),
+      "InsufficientCollateral"
+    );
+
+    // Leaving room for the accrued interest still works
+    await borrowFrom(tm, borrower, 0, 240 * 1e6 - stale - 1_000);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.greaterThan(240 * 1e6 - 1_000);
+    expect(deposit.borrowedAmount.toNumber()).to.be.at.most(240 * 1e6);
+  });
+
+  it("Blocks new debt everywhere while borrows are paused, but still takes repayments", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1100 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const borrower = await createTestUser(tm, 0, 1 * 1e9);
+    await borrowFrom(tm, borrower, 1 * 1e9, 100 * 1e6);
+
+    await program.methods
+      .setBorrowsPaused(true)
+      .accounts({ protocolState, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    const state = await program.account.protocolState.fetch(protocolState);
+    expect(state.borrowsPaused).to.be.true;
+
+    try {
+      await expectError(borrowFrom(tm, borrower, 0, 10 * 1e6), "MarketPaused");
+      await expectError(flashLoanIn(tm, supplier, 10 * 1e6), "MarketPaused");
+
+      // Existing debt can still be paid down and the market still takes deposits
+      await repayTo(tm, borrower, 40 * 1e6);
+      await supplyTo(tm, supplier, 100 * 1e6);
+      const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+      expect(deposit.borrowedAmount.toNumber()).to.be.lessThan(61 * 1e6);
+    } finally {
+      await program.methods
+        .setBorrowsPaused(false)
+        .accounts({ protocolState, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    }
+
+    await borrowFrom(tm, borrower, 0, 10 * 1e6);
+  });
+
+  it("Names the stale oracle when one borrow feed falls behind", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    const borrower = await createTestUser(tm, 0, 1e9);
+
+    // Tighten the window so feeds go stale within a few seconds
+    await program.methods
+      .setMaxOracleStalenessSlots(new anchor.BN(10))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    const waitUntilStale = async () => {
+      const oracles = await program.account.oracle.fetchMultiple([
+        tm.supplyOracle,
+        tm.collateralOracle,
+      ]);
+      const newest = Math.max(...oracles.map((o) => o.validSlot.toNumber()));
+      while ((await provider.connection.getSlot()) <= newest + 10) {
+        await sleep(500);
+      }
+    };
+
+    // Only the borrow feed is fresh
+    await waitUntilStale();
+    await setOraclePrice(tm.supplyOracle, 1e6);
+    await expectError(
+      borrowFrom(tm, borrower, 1e9, 10 * 1e6),
+      "CollateralOracleStale"
+    );
+
+    // Only the collateral feed is fresh
+    await waitUntilStale();
+    await setOraclePrice(tm.collateralOracle, 3000 * 1e6);
+    await expectError(
+      borrowFrom(tm, borrower, 1e9, 10 * 1e6),
+      "BorrowOracleStale"
+    );
+
+    // Nothing moved on either rejection
+    const collateral = await getAccount(
+      provider.connection,
+      borrower.collateralAccount
+    );
+    expect(Number(collateral.amount)).to.equal(1e9);
+
+    await setOraclePrice(tm.supplyOracle, 1e6);
+    await borrowFrom(tm, borrower, 1e9, 10 * 1e6);
+  });
+
+  it("Reports the largest cToken withdrawal the protocol will accept", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    // Supplies and borrows in the same market
+    const user = await createTestUser(tm, 100 * 1e6, 1e8);
+    await supplyTo(tm, user, 100 * 1e6);
+    await borrowFrom(tm, user, 1e8, 20 * 1e6);
+
+    // Another borrower drains liquidity so the vault, not the balance, is the limit
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await borrowFrom(tm, borrower, 1e9, 500 * 1e6);
+
+    const maxWithdraw = await program.methods
+      .getMaxWithdraw(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        userDeposit: user.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        user: user.keypair.publicKey,
+        supplyOracle: tm.supplyOracle,
+        collateralOracle: tm.collateralOracle,
+      })
+      .view();
+    const deposit = await program.account.userDeposit.fetch(user.deposit);
+    expect(maxWithdraw.toNumber()).to.be.greaterThan(0);
+    expect(maxWithdraw.toNumber()).to.be.lessThan(deposit.ctokenBalance.toNumber());
+
+    const withdrawCTokens = (ctokens: number) =>
+      program.methods
+        .withdraw(tm.id, new anchor.BN(ctokens), new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: user.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: user.supplyAccount,
+          user: user.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.keypair])
+        .rpc();
+
+    await expectError(
+      withdrawCTokens(maxWithdraw.toNumber() + 1),
+      "InsufficientLiquidity"
+    );
+    await withdrawCTokens(maxWithdraw.toNumber());
+
+    const after = await program.account.userDeposit.fetch(user.deposit);
+    expect(after.ctokenBalance.toNumber()).to.equal(
+      deposit.ctokenBalance.toNumber() - maxWithdraw.toNumber()
+    );
+  });
+
+  it("Prices collateral and debt in whole tokens when mint decimals differ", async () => {
+    // 9-decimal supply token at $1 against a 6-decimal collateral token at $3000
+    const tm = await createTestMarket(1_000_000, 3000_000_000, 0, 9, 6);
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.supplyDecimals).to.equal(9);
+    expect(market.collateralDecimals).to.equal(6);
+
+    const supplier = await createTestUser(tm, 3000 * 1e9, 0);
+    await supplyTo(tm, supplier, 3000 * 1e9);
+
+    // 1 collateral token ($3000) at an 80% factor backs exactly 2400 supply tokens
+    const borrower = await createTestUser(tm, 0, 1e6);
+    await expectError(
+      borrowFrom(tm, borrower, 1e6, 2400 * 1e9 + 1),
+      "InsufficientCollateral"
+    );
+    await borrowFrom(tm, borrower, 1e6, 2400 * 1e9);
+
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toString()).to.equal((2400 * 1e9).toString());
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e6);
+  });
+
+  it("Emits InterestAccruedEvent with the rate math when interest accrues", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 200 * 1e6, 0);
+    await supplyTo(tm, supplier, 100 * 1e6);
+    const before = await program.account.market.fetch(tm.market);
+
+    await sleep(2000);
+    const sig = await supplyTo(tm, supplier, 100 * 1e6);
+    const after = await program.account.market.fetch(tm.market);
+
+    const accrued = (await getEvents(sig)).find(
+      (e) => e.name === "interestAccruedEvent"
+    );
+    expect(accrued).to.not.be.undefined;
+    const slots = after.lastUpdateSlot.sub(before.lastUpdateSlot);
+    expect(slots.toNumber()).to.be.greaterThan(0);
+    expect(accrued.data.marketId.toString()).to.equal(tm.id.toString());
+    expect(accrued.data.slotsElapsed.toString()).to.equal(slots.toString());
+    // Flat per-slot rates: 25 for borrows, 12 for supply
+    expect(accrued.data.borrowIncrement.toString()).to.equal(
+      slots.muln(25).toString()
+    );
+    expect(accrued.data.supplyIncrement.toString()).to.equal(
+      slots.muln(12).toString()
+    );
+    expect(accrued.data.newBorrowRate.toString()).to.equal(
+      before.cumulativeBorrowRate.add(slots.muln(25)).toString()
+    );
+    expect(accrued.data.newSupplyRate.toString()).to.equal(
+      before.cumulativeSupplyRate.add(slots.muln(12)).toString()
+    );
+    expect(after.cumulativeBorrowRate.toString()).to.equal(
+      accrued.data.newBorrowRate.toString()
+    );
+  });
+
+  it("Rejects a supply vault the market doesn't own", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // A supply-mint token account owned by the borrower instead of the market
+    try {
+      await program.methods
+        .repay(tm.id, new anchor.BN(10 * 1e6))
+        .accounts({
+          market: tm.market,
+          supplyVault: borrower.supplyAccount,
+          userDeposit: borrower.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: borrower.supplyAccount,
+          user: borrower.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([borrower.keypair])
+        .rpc();
+      expect.fail("Repay into a foreign vault should have been rejected");
+    } catch (error) {
+      // Anchor checks the PDA seeds before token::authority, either rejects it
+      expect(error.toString()).to.match(/ConstraintSeeds|ConstraintTokenOwner/);
+    }
+
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.at.least(100 * 1e6);
+  });
+
+  it("Accrues the user's debt on every supply so market and debt interest stay in step", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await borrowFrom(tm, borrower, 1e9, 500 * 1e6);
+
+    // Interleave supplies (which used to advance only the market) with waiting
+    let previous = await program.account.userDeposit.fetch(borrower.deposit);
+    for (let i = 0; i < 3; i++) {
+      await sleep(1500);
+      await supplyTo(tm, borrower, 1e6);
+
+      const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+      const market = await program.account.market.fetch(tm.market);
+      // The debt moved forward together with the market
+      expect(deposit.borrowedAmount.gt(previous.borrowedAmount)).to.be.true;
+      expect(deposit.lastUpdateSlot.toNumber()).to.equal(
+        market.lastUpdateSlot.toNumber()
+      );
+      previous = deposit;
+    }
+
+    // A later borrow starts from the debt already booked, nothing was skipped
+    await borrowFrom(tm, borrower, 0, 1e6);
+    const after = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(after.borrowedAmount.toNumber()).to.be.at.least(
+      previous.borrowedAmount.toNumber() + 1e6
+    );
+    const market = await program.account.market.fetch(tm.market);
+    expect(after.lastUpdateSlot.toNumber()).to.be.at.most(
+      market.lastUpdateSlot.toNumber()
+    );
+  });
+
+  it("Rejects borrows when the collateral/borrow price ratio leaves the market band", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 2 * 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    // Collateral normally trades around 3000x the borrow asset
+    await program.methods
+      .setPriceRatioBand(new anchor.BN(25_000_000), new anchor.BN(35_000_000))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.minPriceRatioBps.toNumber()).to.equal(25_000_000);
+    expect(market.maxPriceRatioBps.toNumber()).to.equal(35_000_000);
+
+    // A normal ratio borrows as usual
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // The borrow asset depegs to $2: both feeds are fresh, but the ratio halves
+    await setOraclePrice(tm.supplyOracle, 2_000_000);
+    await expectError(
+      borrowFrom(tm, borrower, 1e9, 100 * 1e6),
+      "InvalidOracleData"
+    );
+
+    // An inverted band is refused outright
+    await expectError(
+      program.methods
+        .setPriceRatioBand(new anchor.BN(2), new anchor.BN(1))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc(),
+      "InvalidPriceRatioBand"
+    );
+  });
+
+  it("Never lets total supply deposits exceed the vault plus outstanding borrows", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 100 * 1e6, 1e9);
+
+    const expectBacked = async () => {
+      const market = await program.account.market.fetch(tm.market);
+      const vault = await getAccount(provider.connection, tm.supplyVault);
+      expect(
+        market.totalSupplyDeposits.lte(
+          new anchor.BN(vault.amount.toString()).add(market.totalBorrows)
+        )
+      ).to.be.true;
+      return market;
+    };
+
+    await supplyTo(tm, supplier, 500 * 1e6);
+    const start = await expectBacked();
+
+    // Repeated supplies used to mint interest out of thin air on every call
+    await supplyTo(tm, supplier, 100 * 1e6);
+    await supplyTo(tm, supplier, 100 * 1e6);
+    const noBorrows = await expectBacked();
+    expect(noBorrows.totalSupplyDeposits.toNumber()).to.equal(
+      start.totalSupplyDeposits.toNumber() + 200 * 1e6
+    );
+
+    // With a borrow outstanding, suppliers only earn interest the borrower actually owes
+    await borrowFrom(tm, borrower, 1e9, 300 * 1e6);
+    await sleep(1500);
+    await supplyTo(tm, borrower, 10 * 1e6);
+    await expectBacked();
+    await sleep(1500);
+    await repayTo(tm, borrower, 50 * 1e6);
+    await expectBacked();
+    await supplyTo(tm, supplier, 100 * 1e6);
+    const end = await expectBacked();
+    expect(end.totalSupplyDeposits.toNumber()).to.be.greaterThan(
+      noBorrows.totalSupplyDeposits.toNumber() + 110 * 1e6
+    );
+  });
+
+  it("get_exchange_rate returns the live cToken rate including pending borrow interest", async () => {
+    const tm = await createTestMarket();
+    const getExchangeRate = () =>
+      program.methods
+        .getExchangeRate(tm.id)
+        .accounts({
+          market: tm.market,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+        })
+        .view();
+
+    // An empty market starts at 1:1
+    const SCALING_FACTOR = 1e9;
+    expect((await getExchangeRate()).toNumber()).to.equal(SCALING_FACTOR);
+
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 500 * 1e6);
+    const before = await getExchangeRate();
+
+    // Let the borrow accrue for a few slots without touching the market
+    await sleep(2000);
+    const after = await getExchangeRate();
+    expect(after.gt(before)).to.be.true;
+  });
+
+  it("Forgives sub-threshold dust left by a repayment so the position can close", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.dustThreshold.toNumber()).to.equal(100);
+
+    // Repay all but a few units; the interest accrued meanwhile stays under the threshold
+    const owed = await program.account.userDeposit.fetch(borrower.deposit);
+    await repayTo(tm, borrower, owed.borrowedAmount.toNumber() - 20);
+
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(0);
+    const after = await program.account.market.fetch(tm.market);
+    expect(after.totalBorrows.toNumber()).to.equal(0);
+
+    // With the debt settled the collateral comes back and the account closes
+    await program.methods
+      .withdrawCollateral(tm.id, new anchor.BN(1e9))
+      .accounts({
+        market: tm.market,
+        collateralVault: tm.collateralVault,
+        userDeposit: borrower.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userCollateralAccount: borrower.collateralAccount,
+        user: borrower.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([borrower.keypair])
+      .rpc();
+    await program.methods
+      .closeUserDeposit()
+      .accounts({
+        userDeposit: borrower.deposit,
+        user: borrower.keypair.publicKey,
+      })
+      .signers([borrower.keypair])
+      .rpc();
+    expect(await program.account.userDeposit.fetchNullable(borrower.deposit)).to.be.null;
+  });
+
+  it("Blocks borrows on a frozen deposit while still allowing repayment", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    const setFrozen = (frozen: boolean, authority = admin) =>
+      program.methods
+        .setDepositFrozen(frozen)
+        .accounts({
+          market: tm.market,
+          userDeposit: borrower.deposit,
+          authority: authority.publicKey,
+        })
+        .signers([authority])
+        .rpc();
+
+    // Only the market admin can freeze a position
+    await expectError(setFrozen(true, borrower.keypair), "Unauthorized");
+    await setFrozen(true);
+    expect((await program.account.userDeposit.fetch(borrower.deposit)).frozen).to.be.true;
+
+    await expectError(borrowFrom(tm, borrower, 0, 10 * 1e6), "DepositFrozen");
+
+    // Repaying only reduces risk, so it goes through
+    await repayTo(tm, borrower, 50 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.lessThan(60 * 1e6);
+
+    // Unfreezing restores borrowing
+    await setFrozen(false);
+    await borrowFrom(tm, borrower, 0, 10 * 1e6);
+  });
+
+  it("Converts seized collateral by value on a cheap collateral and caps it at the deposit", async () => {
+    // Collateral at $0.01 with 9 decimals: each unit of debt is worth far more
+    // collateral units than the old 1:1-plus-bonus math paid out
+    const tm = await createTestMarket(1_000_000, 10_000);
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 100_000 * 1e9);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 100_000 * 1e9, 500 * 1e6);
+
+    // $0.005: $500 of collateral backs $425 at the 85% threshold, below the $500 loan
+    await setOraclePrice(tm.collateralOracle, 5_000);
+    await liquidateIn(tm, liquidatorUser, borrower, 200 * 1e6);
+
+    // $220 of collateral at $0.005 is 44,000 tokens
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(56_000 * 1e9);
+    let seized = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    expect(Number(seized.amount)).to.equal(44_000 * 1e9);
+
+    // At $0.001 the next $110 is worth more than the 56,000 tokens left: take them all
+    await setOraclePrice(tm.collateralOracle, 1_000);
+    await liquidateIn(tm, liquidatorUser, borrower, 100 * 1e6);
+
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(0);
+    seized = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    expect(Number(seized.amount)).to.equal(100_000 * 1e9);
+  });
+
+  it("Rejects dust supplies that would mint zero cTokens and supplies below the market minimum", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 100 * 1e6, 1e9);
+    const dustSupplier = await createTestUser(tm, 100 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 800 * 1e6);
+
+    // Booking the borrower's interest lifts the cToken rate above 1:1
+    await sleep(2000);
+    await repayTo(tm, borrower, 1 * 1e6);
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.totalSupplyDeposits.gt(market.totalCtokenSupply)).to.equal(true);
+
+    // One unit now floors to zero cTokens: rejected instead of silently absorbed
+    await expectError(supplyTo(tm, dustSupplier, 1), "SupplyTooSmall");
+    let deposit = await program.account.userDeposit.fetch(dustSupplier.deposit);
+    expect(deposit.ctokenBalance.toNumber()).to.equal(0);
+
+    await program.methods
+      .setMinSupply(new anchor.BN(10 * 1e6))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    await expectError(supplyTo(tm, dustSupplier, 10 * 1e6 - 1), "SupplyTooSmall");
+
+    await supplyTo(tm, dustSupplier, 10 * 1e6);
+    deposit = await program.account.userDeposit.fetch(dustSupplier.deposit);
+    expect(deposit.ctokenBalance.toNumber()).to.be.greaterThan(0);
+  });
+
+  it("Sends the protocol's share of flash-loan fees to the fee recipient", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1_000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 50 * 1e6, 0);
+    const recipient = await createTestUser(tm, 0, 0);
+    await supplyTo(tm, supplier, 1_000 * 1e6);
+
+    // Only the protocol admin picks the recipient
+    await expectError(
+      program.methods
+        .setFeeRecipient(recipient.keypair.publicKey)
+        .accounts({ protocolState, admin: recipient.keypair.publicKey })
+        .signers([recipient.keypair])
+        .rpc(),
+      "Unauthorized"
+    );
+    await setFeeRecipientTo(recipient);
+    const state = await program.account.protocolState.fetch(protocolState);
+    expect(state.feeRecipient.toString()).to.equal(
+      recipient.keypair.publicKey.toString()
+    );
+
+    const setShare = (bps: number) =>
+      program.methods
+        .setProtocolFeeShareBps(new anchor.BN(bps))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    await expectError(setShare(10001), "InvalidBasisPoints");
+    await setShare(4000);
+
+    const market = await program.account.market.fetch(tm.market);
+    const fee = Math.floor((100 * 1e6 * market.flashLoanFeeBps.toNumber()) / 10000);
+    const protocolFee = Math.floor((fee * 4000) / 10000);
+    expect(protocolFee).to.be.greaterThan(0);
+    const vaultBefore = await getAccount(provider.connection, tm.supplyVault);
+
+    await flashLoanIn(tm, borrower, 100 * 1e6, recipient);
+
+    const recipientAfter = await getAccount(provider.connection, recipient.supplyAccount);
+    expect(Number(recipientAfter.amount)).to.equal(protocolFee);
+    // The remainder stays in the vault for suppliers
+    const vaultAfter = await getAccount(provider.connection, tm.supplyVault);
+    expect(Number(vaultAfter.amount - vaultBefore.amount)).to.equal(fee - protocolFee);
+    const marketAfter = await program.account.market.fetch(tm.market);
+    expect(marketAfter.totalSupplyDeposits.toNumber()).to.equal(
+      market.totalSupplyDeposits.toNumber() + fee - protocolFee
+    );
+
+    // A token account owned by anyone else is rejected
+    await expectError(
+      flashLoanIn(tm, borrower, 100 * 1e6, supplier),
+      "ConstraintTokenOwner"
+    );
+  });
+
+  it("Liquidates exactly when am_i_safe reports the position unsafe at the threshold", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    // No interest, so the debt sits exactly on the boundary
+    await setInterestFreeSlots(tm, 1_000_000);
+    await borrowFrom(tm, borrower, 1e8, 170 * 1e6);
+
+    const checkSafety = async () => {
+      const sig = await program.methods
+        .amISafe(tm.id)
+        .accounts({
+          market: tm.market,
+          userDeposit: borrower.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          user: borrower.keypair.publicKey,
+          collateralOracle: tm.collateralOracle,
+          borrowOracle: tm.supplyOracle,
+        })
+        .signers([borrower.keypair])
+        .rpc({ commitment: "confirmed" });
+      return (await getEvents(sig)).find((e) => e.name === "safetyEvent").data;
+    };
+
+    // 0.1 ETH at $2000 backs exactly $170 at the 85% threshold: still safe
+    await setOraclePrice(tm.collateralOracle, 2000_000_000);
+    let safety = await checkSafety();
+    expect(safety.isSafe).to.be.true;
+    expect(safety.healthFactor.toNumber()).to.equal(1e9);
+    await expectError(
+      liquidateIn(tm, liquidatorUser, borrower, 10 * 1e6),
+      "PositionHealthy"
+    );
+
+    // The smallest price step below puts it over: both paths flip together
+    await setOraclePrice(tm.collateralOracle, 1999_999_999);
+    safety = await checkSafety();
+    expect(safety.isSafe).to.be.false;
+    expect(safety.healthFactor.toNumber()).to.be.lessThan(1e9);
+    await liquidateIn(tm, liquidatorUser, borrower, 10 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(160 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.be.lessThan(1e8);
+  });
+
+  it("Creates 18-decimal oracles with a price-relative confidence and rejects bad parameters", async () => {
+    const oracleFor = async () => {
+      const mint = await createMint(provider.connection, admin, admin.publicKey, null, 18);
+      const [oracle] = PublicKey.findProgramAddressSync(
+        [Buffer.from("oracle"), mint.toBuffer()],
+        program.programId
+      );
+      return { mint, oracle };
+    };
+    const create = (
+      target: { mint: PublicKey; oracle: PublicKey },
+      price: anchor.BN,
+      decimals: number
+    ) =>
+      program.methods
+        .createOracle(
+          Buffer.from("mock_pyth_source_data"),
+          price,
+          decimals,
+          new anchor.BN(0)
+        )
+        .accounts({
+          oracle: target.oracle,
+          mint: target.mint,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+    // $1 and then $1.50 at 18 decimals: confidence stays 1% of the price
+    const eighteen = await oracleFor();
+    await create(eighteen, new anchor.BN("1000000000000000000"), 18);
+    let oracle = await program.account.oracle.fetch(eighteen.oracle);
+    expect(oracle.decimals).to.equal(18);
+    expect(oracle.confidence.toString()).to.equal("10000000000000000");
+
+    await program.methods
+      .updateOraclePrice(new anchor.BN("1500000000000000000"), false)
+      .accounts({ oracle: eighteen.oracle, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    oracle = await program.account.oracle.fetch(eighteen.oracle);
+    expect(oracle.price.toString()).to.equal("1500000000000000000");
+    expect(oracle.confidence.toString()).to.equal("15000000000000000");
+
+    const nineteen = await oracleFor();
+    await expectError(
+      create(nineteen, new anchor.BN("1000000000000000000"), 19),
+      "InvalidOracleDecimals"
+    );
+    await expectError(create(nineteen, new anchor.BN(0), 6), "InvalidOracleData");
+  });
+
+  it("borrow_to deposits collateral and sends the borrowed tokens to a third party in one instruction", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    const recipient = await createTestUser(tm, 0, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const borrowTo = (
+      collateralAmount: number,
+      borrowAmount: number,
+      destination: PublicKey,
+      account: PublicKey
+    ) =>
+      program.methods
+        .borrowTo(
+          tm.id,
+          new anchor.BN(collateralAmount),
+          new anchor.BN(borrowAmount),
+          destination
+        )
+        .accounts({
+          protocolState,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          collateralVault: tm.collateralVault,
+          userDeposit: borrower.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: account,
+          userCollateralAccount: borrower.collateralAccount,
+          user: borrower.keypair.publicKey,
+          collateralOracle: tm.collateralOracle,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([borrower.keypair])
+        .rpc({ commitment: "confirmed" });
+
+    // The destination must be the account passed, and it must hold the supply mint
+    await expectError(
+      borrowTo(1e9, 100 * 1e6, recipient.supplyAccount, supplier.supplyAccount),
+      "InvalidBorrowDestination"
+    );
+    await expectError(
+      borrowTo(1e9, 100 * 1e6, recipient.collateralAccount, recipient.collateralAccount),
+      "InvalidBorrowDestination"
+    );
+    // Same collateral checks as borrow: $3000 of ETH can't back $2500 at 80%
+    await expectError(
+      borrowTo(1e9, 2500 * 1e6, recipient.supplyAccount, recipient.supplyAccount),
+      "InsufficientCollateral"
+    );
+
+    const sig = await borrowTo(1e9, 100 * 1e6, recipient.supplyAccount, recipient.supplyAccount);
+
+    const received = await getAccount(provider.connection, recipient.supplyAccount);
+    expect(Number(received.amount)).to.equal(100 * 1e6);
+    const borrowerSupply = await getAccount(provider.connection, borrower.supplyAccount);
+    expect(Number(borrowerSupply.amount)).to.equal(0);
+
+    // The debt and collateral sit on the borrower's position
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e9);
+
+    const event = (await getEvents(sig)).find((e) => e.name === "borrowToEvent");
+    expect(event.data.destination.toString()).to.equal(recipient.supplyAccount.toString());
+    expect(event.data.amount.toNumber()).to.equal(100 * 1e6);
+  });
+
+  it("Rejects a withdraw that returns fewer tokens than min_tokens_out after the rate drops", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const withdrawWithMin = (ctokens: number, minTokensOut: number) =>
+      program.methods
+        .withdraw(tm.id, new anchor.BN(ctokens), new anchor.BN(minTokensOut))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: supplier.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: supplier.supplyAccount,
+          user: supplier.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([supplier.keypair])
+        .rpc();
+
+    // Quoted at 1:1, then socialized bad debt lowers the rate before the withdraw lands
+    const deposit = await program.account.userDeposit.fetch(supplier.deposit);
+    expect(deposit.ctokenBalance.toNumber()).to.equal(1000 * 1e6);
+    await liquidateIntoShortfall(tm);
+
+    await expectError(withdrawWithMin(100 * 1e6, 100 * 1e6), "SlippageExceeded");
+
+    // 0 skips the check and takes whatever the current rate gives
+    await withdrawWithMin(100 * 1e6, 0);
+    const received = await getAccount(provider.connection, supplier.supplyAccount);
+    expect(Number(received.amount)).to.be.lessThan(100 * 1e6);
+    expect(Number(received.amount)).to.be.greaterThan(90 * 1e6);
+  });
+
+  it("Rejects a liquidation that would seize less than min_collateral_out after a price move", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+
+    // Quoted at $2000: $50 plus the 10% bonus is 0.0275 ETH
+    await setOraclePrice(tm.collateralOracle, 2000_000_000);
+    const quoted = 27_500_000;
+
+    // ETH recovers to $2100 before the transaction lands: still unhealthy,
+    // but the same repayment now only buys ~0.02619 ETH
+    await setOraclePrice(tm.collateralOracle, 2100_000_000);
+    await expectError(
+      liquidateIn(tm, liquidatorUser, borrower, 50 * 1e6, quoted),
+      "SlippageExceeded"
+    );
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e8);
+
+    // A floor the new price still clears goes through
+    await liquidateIn(tm, liquidatorUser, borrower, 50 * 1e6, 26_000_000);
+    const seized = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    expect(Number(seized.amount)).to.equal(26_190_476);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e8 - 26_190_476);
+  });
+
+  it("Accrues the borrower's interest before deciding a liquidation", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e8, 170 * 1e6);
+
+    // 0.1 ETH at $2000 backs exactly the $170 principal at the 85% threshold,
+    // so only the interest accrued since the borrow makes the position unhealthy
+    await sleep(2000);
+    await setOraclePrice(tm.collateralOracle, 2000_000_000);
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(170 * 1e6);
+
+    await liquidateIn(tm, liquidatorUser, borrower, 10 * 1e6);
+
+    // The interest was booked before the repayment came off the debt
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.greaterThan(160 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.be.lessThan(1e8);
+    const seized = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    expect(Number(seized.amount)).to.equal(5_500_000);
+  });
+
+  it("Rejects creating a market wired to oracles for the wrong mints", async () => {
+    // Reuse an existing market's mints and oracles for a market on the reversed pair
+    const tm = await createTestMarket();
+    const supplyMint = tm.collateralMint;
+    const collateralMint = tm.supplyMint;
+    const id = await nextMarketId();
+    const idBytes = id.toArrayLike(Buffer, "le", 8);
+    const [market] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("market"),
+        idBytes,
+        supplyMint.toBuffer(),
+        collateralMint.toBuffer(),
+      ],
+      program.programId
+    );
+    const [supplyVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("supply_vault"), idBytes, supplyMint.toBuffer()],
+      program.programId
+    );
+    const [collateralVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("collateral_vault"), idBytes, collateralMint.toBuffer()],
+      program.programId
+    );
+
+    const createWith = async (supplyOracle: PublicKey, collateralOracle: PublicKey) =>
+      program.methods
+        .createMarket(id, new anchor.BN(8000), new anchor.BN(8500), new anchor.BN(0))
+        .accounts({
+          market,
+          protocolState,
+          marketIndex: await nextMarketIndexPda(),
+          marketPointer: marketPointerPda(supplyMint, collateralMint),
+          supplyMint,
+          collateralMint,
+          supplyOracle,
+          collateralOracle,
+          supplyVault,
+          collateralVault,
+          creator: admin.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+
+    // The supply feed can't price the collateral side
+    await expectError(
+      createWith(tm.collateralOracle, tm.collateralOracle),
+      "InvalidOracleData"
+    );
+    // An account that isn't an oracle at all
+    await expectError(
+      createWith(tm.collateralOracle, tm.collateralVault),
+      "InvalidOracleData"
+    );
+
+    await createWith(tm.collateralOracle, tm.supplyOracle);
+    const created = await program.account.market.fetch(market);
+    expect(created.collateralOracle.toString()).to.equal(tm.supplyOracle.toString());
+  });
+
+  it("get_position returns a user's balances, current debt and health in one market", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // Let interest accrue
+    await sleep(3000);
+
+    const positionOf = (user: TestUser) =>
+      program.methods
+        .getPosition(tm.id)
+        .accounts({
+          market: tm.market,
+          userDeposit: user.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          user: user.keypair.publicKey,
+          collateralOracle: tm.collateralOracle,
+          borrowOracle: tm.supplyOracle,
+        })
+        .view();
+
+    const position = await positionOf(borrower);
+    const deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(position.supplyDeposited.toString()).to.equal(deposit.supplyDeposited.toString());
+    expect(position.collateralDeposited.toString()).to.equal(
+      deposit.collateralDeposited.toString()
+    );
+    expect(position.ctokenBalance.toString()).to.equal(deposit.ctokenBalance.toString());
+    // Debt includes interest pending since the borrow, which wasn't persisted
+    expect(position.borrowedAmount.toNumber()).to.be.greaterThan(100 * 1e6);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6);
+    // Comfortably collateralized, so above 1.0 (1e9)
+    expect(position.healthFactor.gt(new anchor.BN(1e9))).to.be.true;
+
+    // A pure supplier has no debt and maximal health
+    const supplierPosition = await positionOf(supplier);
+    expect(supplierPosition.borrowedAmount.toNumber()).to.equal(0);
+    expect(supplierPosition.supplyDeposited.toNumber()).to.equal(1000 * 1e6);
+    expect(supplierPosition.healthFactor.toString()).to.equal(
+      "340282366920938463463374607431768211455"
+    );
+  });
+
+  it("Books exactly one borrow's worth of debt and collateral per call", async () => {
+    const tm = await createTestMarket();
+    // Keep interest out of the picture so balances are exact
+    await setInterestFreeSlots(tm, 1_000_000);
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 2e9);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    let market = await program.account.market.fetch(tm.market);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(100 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e9);
+    expect(market.totalBorrows.toNumber()).to.equal(100 * 1e6);
+    expect(market.totalCollateralDeposits.toNumber()).to.equal(1e9);
+
+    // A second borrow adds exactly its amount on top of the first
+    await borrowFrom(tm, borrower, 1e9, 50 * 1e6);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    market = await program.account.market.fetch(tm.market);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(150 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(2e9);
+    expect(market.totalBorrows.toNumber()).to.equal(150 * 1e6);
+    expect(market.totalCollateralDeposits.toNumber()).to.equal(2e9);
+
+    const received = await getAccount(provider.connection, borrower.supplyAccount);
+    expect(Number(received.amount)).to.equal(150 * 1e6);
+  });
+
+  it("Keeps the sum of user debts equal to market total_borrows across borrows and repays", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrowers = [
+      await createTestUser(tm, 50 * 1e6, 2e9),
+      await createTestUser(tm, 50 * 1e6, 2e9),
+      await createTestUser(tm, 50 * 1e6, 2e9),
+    ];
+    await supplyTo(tm, supplier, 1000 * 1e6);
+
+    const expectDebtsMatchMarket = async () => {
+      let sum = new anchor.BN(0);
+      for (const borrower of borrowers) {
+        const deposit = await program.account.userDeposit.fetchNullable(borrower.deposit);
+        if (deposit) sum = sum.add(deposit.borrowedAmount);
+      }
+      const market = await program.account.market.fetch(tm.market);
+      expect(sum.toString()).to.equal(market.totalBorrows.toString());
+    };
+
+    await borrowFrom(tm, borrowers[0], 1e9, 100 * 1e6);
+    await borrowFrom(tm, borrowers[1], 1e9, 60 * 1e6);
+    await expectDebtsMatchMarket();
+
+    // Interest accrued by one borrower is booked on both sides
+    await sleep(2000);
+    await borrowFrom(tm, borrowers[0], 1e9, 20 * 1e6);
+    await borrowFrom(tm, borrowers[2], 1e9, 80 * 1e6);
+    await expectDebtsMatchMarket();
+
+    await sleep(2000);
+    await repayTo(tm, borrowers[1], 30 * 1e6);
+    await repayTo(tm, borrowers[2], 1000 * 1e6);
+    await expectDebtsMatchMarket();
+  });
+
+  it("Locks supplied cTokens backing debt the collateral no longer covers", async () => {
+    const tm = await createTestMarket();
+    // Keep interest out of the picture so the lock is exact
+    await setInterestFreeSlots(tm, 1_000_000);
+    const supplier = await createTestUser(tm, 10_000 * 1e6, 0);
+    await supplyTo(tm, supplier, 10_000 * 1e6);
+
+    // Supplies 100 tokens and borrows the full limit of 1 ETH at $3000 and 80%
+    const user = await createTestUser(tm, 100 * 1e6, 1e9);
+    await supplyTo(tm, user, 100 * 1e6);
+    await borrowFrom(tm, user, 1e9, 2400 * 1e6);
+
+    // At $2950 the collateral backs 2360, leaving 40 tokens of debt on the cTokens
+    await setOraclePrice(tm.collateralOracle, 2950 * 1e6);
+
+    const withdrawCTokens = (ctokens: number) =>
+      program.methods
+        .withdraw(tm.id, new anchor.BN(ctokens), new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: user.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: user.supplyAccount,
+          user: user.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.keypair])
+        .rpc();
+
+    const deposit = await program.account.userDeposit.fetch(user.deposit);
+    const balance = deposit.ctokenBalance.toNumber();
+    expect(balance).to.equal(100 * 1e6);
+
+    await expectError(withdrawCTokens(balance), "CTokensLocked");
+    await expectError(withdrawCTokens(60 * 1e6 + 1), "CTokensLocked");
+    await withdrawCTokens(60 * 1e6);
+
+    const after = await program.account.userDeposit.fetch(user.deposit);
+    expect(after.lockedCtokens.toNumber()).to.equal(40 * 1e6);
+    expect(after.ctokenBalance.toNumber()).to.equal(40 * 1e6);
+    await expectError(withdrawCTokens(1), "CTokensLocked");
+  });
+
+  it("Rejects oracle updates that come sooner than the oracle's update interval", async () => {
+    const mint = await createMint(provider.connection, admin, admin.publicKey, null, 6);
+    const [oracle] = PublicKey.findProgramAddressSync(
+      [Buffer.from("oracle"), mint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .createOracle(
+        Buffer.from("mock_pyth_source_data"),
+        new anchor.BN(1_000_000),
+        6,
+        new anchor.BN(5)
+      )
+      .accounts({
+        oracle,
+        mint,
+        authority: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+    let data = await program.account.oracle.fetch(oracle);
+    expect(data.minUpdateIntervalSlots.toNumber()).to.equal(5);
+
+    // Right after creation the interval hasn't elapsed
+    await expectError(setOraclePrice(oracle, 1_010_000), "OracleUpdateTooFrequent");
+    data = await program.account.oracle.fetch(oracle);
+    expect(data.price.toNumber()).to.equal(1_000_000);
+
+    // Comfortably more than 5 slots later it goes through
+    await sleep(4000);
+    await setOraclePrice(oracle, 1_010_000);
+    data = await program.account.oracle.fetch(oracle);
+    expect(data.price.toNumber()).to.equal(1_010_000);
+    expect(data.updateCount.toNumber()).to.equal(1);
+  });
+
+  it("Settles each supplier's interest against the supply index they entered at", async () => {
+    const tm = await createTestMarket();
+    const early = await createTestUser(tm, 100 * 1e6, 0);
+    const late = await createTestUser(tm, 100 * 1e6, 0);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+
+    // Only booked borrow interest moves the index: the early supplier alone earns the
+    // first stretch, both share the second
+    await supplyTo(tm, early, 100 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 50 * 1e6);
+    await sleep(4000);
+    await repayTo(tm, borrower, 1e6);
+    await supplyTo(tm, late, 100 * 1e6);
+    await sleep(4000);
+    await repayTo(tm, borrower, 1e6);
+
+    const earlyDeposit = await program.account.userDeposit.fetch(early.deposit);
+    const lateDeposit = await program.account.userDeposit.fetch(late.deposit);
+    expect(earlyDeposit.supplyIndex.lt(lateDeposit.supplyIndex)).to.be.true;
+
+    const withdrawHalf = async (user: TestUser) => {
+      const deposit = await program.account.userDeposit.fetch(user.deposit);
+      const before = await getAccount(provider.connection, user.supplyAccount);
+      const sig = await program.methods
+        .withdraw(tm.id, deposit.ctokenBalance.divn(2), new anchor.BN(0))
+        .accounts({
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          userDeposit: user.deposit,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: user.supplyAccount,
+          user: user.keypair.publicKey,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user.keypair])
+        .rpc({ commitment: "confirmed" });
+      const settled = (await getEvents(sig)).find(
+        (e) => e.name === "supplyInterestSettledEvent"
+      );
+      expect(settled).to.not.be.undefined;
+      // The settled interest is paid: the withdrawal is principal plus interest, give or
+      // take rounding
+      const after = await getAccount(provider.connection, user.supplyAccount);
+      const received = Number(after.amount - before.amount);
+      const settledTotal = settled!.data.principal.add(settled!.data.interest).toNumber();
+      expect(Math.abs(received - settledTotal)).to.be.at.most(1);
+      return settled!.data;
+    };
+
+    const earlySettled = await withdrawHalf(early);
+    const lateSettled = await withdrawHalf(late);
+    expect(earlySettled.principal.toNumber()).to.equal(50 * 1e6);
+    expect(lateSettled.principal.toNumber()).to.equal(50 * 1e6);
+    expect(earlySettled.userSupplyIndex.toString()).to.equal(
+      earlyDeposit.supplyIndex.toString()
+    );
+    expect(earlySettled.marketSupplyIndex.toString()).to.equal(
+      (await program.account.market.fetch(tm.market)).supplyIndex.toString()
+    );
+    // Same principal, but the earlier entry has been earning for longer
+    expect(lateSettled.interest.toNumber()).to.be.greaterThan(0);
+    expect(earlySettled.interest.toNumber()).to.be.greaterThan(
+      lateSettled.interest.toNumber()
+    );
+  });
+
+  it("Rejects flash loans whose callback is the lending program or the token program", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1_000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1_000 * 1e6);
+    const borrower = await createTestUser(tm, 50 * 1e6, 0);
+    const recipient = await createTestUser(tm, 0, 0);
+    await setFeeRecipientTo(recipient);
+
+    const flashLoanWithCallback = (callbackProgram: PublicKey) =>
+      program.methods
+        .flashLoan(tm.id, new anchor.BN(100 * 1e6), Buffer.from("flash"))
+        .accounts({
+          protocolState,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: borrower.supplyAccount,
+          feeRecipientAccount: recipient.supplyAccount,
+          user: borrower.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([
+          { pubkey: callbackProgram, isSigner: false, isWritable: false },
+          { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+        ])
+        .signers([borrower.keypair])
+        .rpc();
+
+    const vaultBefore = await getAccount(provider.connection, tm.supplyVault);
+    await expectError(
+      flashLoanWithCallback(program.programId),
+      "InvalidCallbackProgram"
+    );
+    await expectError(
+      flashLoanWithCallback(TOKEN_PROGRAM_ID),
+      "InvalidCallbackProgram"
+    );
+    const vaultAfter = await getAccount(provider.connection, tm.supplyVault);
+    expect(vaultAfter.amount.toString()).to.equal(vaultBefore.amount.toString());
+
+    // An unrelated callback program still works
+    await flashLoanWithCallback(MEMO_V1_PROGRAM_ID);
+  });
+
+  it("Fails flash loans cleanly when the callback accounts are missing or not a program", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1_000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1_000 * 1e6);
+    const borrower = await createTestUser(tm, 50 * 1e6, 0);
+    const recipient = await createTestUser(tm, 0, 0);
+    await setFeeRecipientTo(recipient);
+
+    const flashLoanWith = (remaining: PublicKey[]) =>
+      program.methods
+        .flashLoan(tm.id, new anchor.BN(100 * 1e6), Buffer.from("flash"))
+        .accounts({
+          protocolState,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          userSupplyAccount: borrower.supplyAccount,
+          feeRecipientAccount: recipient.supplyAccount,
+          user: borrower.keypair.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(
+          remaining.map((pubkey) => ({ pubkey, isSigner: false, isWritable: false }))
+        )
+        .signers([borrower.keypair])
+        .rpc();
+
+    await expectError(flashLoanWith([]), "MissingCallbackAccounts");
+    await expectError(flashLoanWith([MEMO_V1_PROGRAM_ID]), "MissingCallbackAccounts");
+    // A wallet is not executable
+    await expectError(
+      flashLoanWith([recipient.keypair.publicKey, TOKEN_PROGRAM_ID]),
+      "InvalidCallbackProgram"
+    );
+  });
+
+  it("Rejects a repay the user's token balance can't cover with InsufficientBalance", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    // Spend most of the borrowed tokens so the debt exceeds what the wallet holds
+    await transfer(
+      provider.connection,
+      borrower.keypair,
+      borrower.supplyAccount,
+      supplier.supplyAccount,
+      borrower.keypair,
+      60 * 1e6
+    );
+
+    await expectError(repayTo(tm, borrower, 100 * 1e6), "InsufficientBalance");
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.at.least(100 * 1e6);
+
+    // Whatever the wallet does hold can still be repaid
+    await repayTo(tm, borrower, 40 * 1e6);
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.be.lessThan(61 * 1e6);
+  });
+
+  it("Resolves a market from its mint pair and allows only one market per pair", async () => {
+    const tm = await createTestMarket();
+
+    const pointer = await program.account.marketPointer.fetch(
+      marketPointerPda(tm.supplyMint, tm.collateralMint)
+    );
+    expect(pointer.market.toBase58()).to.equal(tm.market.toBase58());
+    expect(pointer.marketId.toString()).to.equal(tm.id.toString());
+
+    // A second market id on the same pair can't claim the pointer
+    const id = await nextMarketId();
+    const idBytes = id.toArrayLike(Buffer, "le", 8);
+    const [market] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("market"),
+        idBytes,
+        tm.supplyMint.toBuffer(),
+        tm.collateralMint.toBuffer(),
+      ],
+      program.programId
+    );
+    const [supplyVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("supply_vault"), idBytes, tm.supplyMint.toBuffer()],
+      program.programId
+    );
+    const [collateralVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("collateral_vault"), idBytes, tm.collateralMint.toBuffer()],
+      program.programId
+    );
+    await expectError(
+      program.methods
+        .createMarket(id, new anchor.BN(8000), new anchor.BN(8500), new anchor.BN(0))
+        .accounts({
+          market,
+          protocolState,
+          marketIndex: await nextMarketIndexPda(),
+          marketPointer: marketPointerPda(tm.supplyMint, tm.collateralMint),
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+          supplyVault,
+          collateralVault,
+          creator: admin.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc(),
+      "already in use"
+    );
+    expect(await provider.connection.getAccountInfo(market)).to.be.null;
+  });
+
+  it("Stores the vault PDA bumps on the market at creation", async () => {
+    const tm = await createTestMarket();
+    const market = await program.account.market.fetch(tm.market);
+    const idBytes = tm.id.toArrayLike(Buffer, "le", 8);
+
+    const [supplyVault, supplyBump] = PublicKey.findProgramAddressSync(
+      [Buffer.from("supply_vault"), idBytes, tm.supplyMint.toBuffer()],
+      program.programId
+    );
+    const [collateralVault, collateralBump] = PublicKey.findProgramAddressSync(
+      [Buffer.from("collateral_vault"), idBytes, tm.collateralMint.toBuffer()],
+      program.programId
+    );
+    expect(supplyVault.toBase58()).to.equal(tm.supplyVault.toBase58());
+    expect(collateralVault.toBase58()).to.equal(tm.collateralVault.toBase58());
+    expect(market.supplyVaultBump).to.equal(supplyBump);
+    expect(market.collateralVaultBump).to.equal(collateralBump);
+
+    // Instructions validating the vaults against the stored bumps go through
+    const supplier = await createTestUser(tm, 100 * 1e6, 0);
+    await supplyTo(tm, supplier, 100 * 1e6);
+    const borrower = await createTestUser(tm, 0, 1e9);
+    await borrowFrom(tm, borrower, 1e9, 10 * 1e6);
+  });
+
+  it("Lets debt-free suppliers exit through emergency_withdraw while oracles are stale", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 100 * 1e6, 0);
+    await supplyTo(tm, supplier, 100 * 1e6);
+    const borrower = await createTestUser(tm, 10 * 1e6, 1e9);
+    await supplyTo(tm, borrower, 10 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 20 * 1e6);
+
+    // Tighten the window and let both feeds fall behind it
+    await program.methods
+      .setMaxOracleStalenessSlots(new anchor.BN(10))
+      .accounts({ market: tm.market, authority: admin.publicKey })
+      .signers([admin])
+      .rpc();
+    const oracles = await program.account.oracle.fetchMultiple([
+      tm.supplyOracle,
+      tm.collateralOracle,
+    ]);
+    const newest = Math.max(...oracles.map((o) => o.validSlot.toNumber()));
+    while ((await provider.connection.getSlot()) <= newest + 10) {
+      await sleep(500);
+    }
+
+    const accountsFor = (user: TestUser) => ({
+      market: tm.market,
+      supplyVault: tm.supplyVault,
+      userDeposit: user.deposit,
+      supplyMint: tm.supplyMint,
+      collateralMint: tm.collateralMint,
+      userSupplyAccount: user.supplyAccount,
+      user: user.keypair.publicKey,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    });
+
+    // The regular path needs prices and fails
+    await expectError(
+      program.methods
+        .withdraw(tm.id, new anchor.BN(50 * 1e6), new anchor.BN(0))
+        .accounts({
+          ...accountsFor(supplier),
+          supplyOracle: tm.supplyOracle,
+          collateralOracle: tm.collateralOracle,
+        })
+        .signers([supplier.keypair])
+        .rpc(),
+      "InvalidOracleData"
+    );
+
+    const emergencyWithdraw = (user: TestUser, ctokens: number) =>
+      program.methods
+        .emergencyWithdraw(tm.id, new anchor.BN(ctokens))
+        .accounts(accountsFor(user))
+        .signers([user.keypair])
+        .rpc();
+
+    // Anyone with debt still needs the priced path
+    await expectError(emergencyWithdraw(borrower, 1e6), "HasBorrows");
+
+    const before = await getAccount(provider.connection, supplier.supplyAccount);
+    await emergencyWithdraw(supplier, 50 * 1e6);
+    const after = await getAccount(provider.connection, supplier.supplyAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(50 * 1e6);
+    const deposit = await program.account.userDeposit.fetch(supplier.deposit);
+    expect(deposit.ctokenBalance.toNumber()).to.equal(50 * 1e6);
+  });
+
+  it("Lets a supplier fully exit through emergency_withdraw after interest has been booked", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 100 * 1e6, 0);
+    await supplyTo(tm, supplier, 100 * 1e6);
+    // The borrower's own supply keeps enough liquidity for the supplier's full exit
+    const borrower = await createTestUser(tm, 1010 * 1e6, 1e9);
+    await supplyTo(tm, borrower, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 500 * 1e6);
+    await sleep(4000);
+    await repayTo(tm, borrower, 10 * 1e6);
+
+    // Booked interest lifted the exchange rate: the cTokens now redeem for more than
+    // the principal behind them
+    const deposit = await program.account.userDeposit.fetch(supplier.deposit);
+    const market = await program.account.market.fetch(tm.market);
+    const redeemable = deposit.ctokenBalance
+      .mul(market.totalSupplyDeposits)
+      .div(market.totalCtokenSupply);
+    expect(redeemable.gt(deposit.supplyDeposited)).to.be.true;
+
+    const before = await getAccount(provider.connection, supplier.supplyAccount);
+    await program.methods
+      .emergencyWithdraw(tm.id, deposit.ctokenBalance)
+      .accounts({
+        market: tm.market,
+        supplyVault: tm.supplyVault,
+        userDeposit: supplier.deposit,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+        userSupplyAccount: supplier.supplyAccount,
+        user: supplier.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([supplier.keypair])
+      .rpc();
+    const after = await getAccount(provider.connection, supplier.supplyAccount);
+
+    expect(Number(after.amount - before.amount)).to.be.greaterThan(100 * 1e6);
+    const exited = await program.account.userDeposit.fetch(supplier.deposit);
+    expect(exited.ctokenBalance.toNumber()).to.equal(0);
+    expect(exited.supplyDeposited.toNumber()).to.equal(0);
+  });
+
+  it("Clamps seized collateral to what the borrower has and never wraps balances", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e8);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await setInterestFreeSlots(tm, 1_000_000);
+    await borrowFrom(tm, borrower, 1e8, 200 * 1e6);
+
+    // At $500, repaying 100 tokens plus the bonus is worth 0.21 ETH, more than the 0.1 posted
+    await setOraclePrice(tm.collateralOracle, 500 * 1e6);
+
+    // Repaying more than the whole debt is a clean error, not a wrapped balance
+    await expectError(
+      liquidateIn(tm, liquidatorUser, borrower, 300 * 1e6),
+      "MathOverflow"
+    );
+    let deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.borrowedAmount.toNumber()).to.equal(200 * 1e6);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(1e8);
+
+    const before = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    await liquidateIn(tm, liquidatorUser, borrower, 100 * 1e6);
+    const after = await getAccount(provider.connection, liquidatorUser.collateralAccount);
+    expect(Number(after.amount) - Number(before.amount)).to.equal(1e8);
+
+    deposit = await program.account.userDeposit.fetch(borrower.deposit);
+    expect(deposit.collateralDeposited.toNumber()).to.equal(0);
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.totalCollateralDeposits.toNumber()).to.equal(0);
+  });
+
+  it("Requires market ids to be allocated in order from the protocol state", async () => {
+    const supplyMint = await createMint(provider.connection, admin, admin.publicKey, null, 6);
+    const collateralMint = await createMint(provider.connection, admin, admin.publicKey, null, 9);
+    const oracleFor = (mint: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("oracle"), mint.toBuffer()],
+        program.programId
+      )[0];
+    for (const mint of [supplyMint, collateralMint]) {
+      await program.methods
+        .createOracle(
+          Buffer.from("mock_pyth_source_data"),
+          new anchor.BN(1_000_000),
+          6,
+          new anchor.BN(0)
+        )
+        .accounts({
+          oracle: oracleFor(mint),
+          mint,
+          authority: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+    }
+
+    const createWithId = async (id: anchor.BN) => {
+      const idBytes = id.toArrayLike(Buffer, "le", 8);
+      return program.methods
+        .createMarket(id, new anchor.BN(8000), new anchor.BN(8500), new anchor.BN(0))
+        .accounts({
+          market: PublicKey.findProgramAddressSync(
+            [
+              Buffer.from("market"),
+              idBytes,
+              supplyMint.toBuffer(),
+              collateralMint.toBuffer(),
+            ],
+            program.programId
+          )[0],
+          protocolState,
+          marketIndex: await nextMarketIndexPda(),
+          marketPointer: marketPointerPda(supplyMint, collateralMint),
+          supplyMint,
+          collateralMint,
+          supplyOracle: oracleFor(supplyMint),
+          collateralOracle: oracleFor(collateralMint),
+          supplyVault: PublicKey.findProgramAddressSync(
+            [Buffer.from("supply_vault"), idBytes, supplyMint.toBuffer()],
+            program.programId
+          )[0],
+          collateralVault: PublicKey.findProgramAddressSync(
+            [Buffer.from("collateral_vault"), idBytes, collateralMint.toBuffer()],
+            program.programId
+          )[0],
+          creator: admin.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+    };
+
+    const expectedId = await nextMarketId();
+    await expectError(createWithId(expectedId.addn(1)), "InvalidMarketId");
+    expect((await nextMarketId()).toString()).to.equal(expectedId.toString());
+
+    await createWithId(expectedId);
+    expect((await nextMarketId()).toString()).to.equal(expectedId.addn(1).toString());
+  });
+
+  it("Mints a large deposit's cTokens after accrual so it can't dilute earlier suppliers", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 100 * 1e6, 1e9);
+    const whale = await createTestUser(tm, 1_000_000 * 1e6, 0);
+
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 500 * 1e6);
+    await sleep(2000);
+    // A repayment books the borrower's interest, so the exchange rate is above 1:1
+    await repayTo(tm, borrower, 1e6);
+
+    const redeemable = async (user: TestUser) => {
+      const market = await program.account.market.fetch(tm.market);
+      const deposit = await program.account.userDeposit.fetch(user.deposit);
+      return deposit.ctokenBalance
+        .mul(market.totalSupplyDeposits)
+        .div(market.totalCtokenSupply);
+    };
+
+    const withoutWhale = await redeemable(supplier);
+    expect(withoutWhale.gtn(1000 * 1e6)).to.be.true;
+
+    await supplyTo(tm, whale, 1_000_000 * 1e6);
+    const withWhale = await redeemable(supplier);
+
+    // The earlier supplier keeps all the interest booked before the whale arrived,
+    // and the whale can't redeem more than it put in
+    expect(withWhale.gte(withoutWhale)).to.be.true;
+    expect(withWhale.sub(withoutWhale).lten(1)).to.be.true;
+    expect((await redeemable(whale)).lten(1_000_000 * 1e6)).to.be.true;
+  });
+
+  it("Clamps the borrow rate to the market's max_borrow_rate_bps", async () => {
+    const tm = await createTestMarket();
+    const supplier = await createTestUser(tm, 1000 * 1e6, 0);
+    const borrower = await createTestUser(tm, 0, 1e9);
+
+    const setMaxBorrowRate = (bps: number) =>
+      program.methods
+        .setMaxBorrowRateBps(new anchor.BN(bps))
+        .accounts({ market: tm.market, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    expect(
+      (await program.account.market.fetch(tm.market)).maxBorrowRateBps.toNumber()
+    ).to.equal(200); // the flat 2% model rate
+    await expectError(setMaxBorrowRate(0), "InvalidBorrowRateCap");
+    await expectError(setMaxBorrowRate(100_001), "InvalidBorrowRateCap");
+
+    // Cap the 2% flat rate at 1%; the rate doesn't depend on utilization, so any open
+    // debt shows the clamp
+    await setMaxBorrowRate(100);
+    await supplyTo(tm, supplier, 1000 * 1e6);
+    await borrowFrom(tm, borrower, 1e9, 100 * 1e6);
+
+    const stats = await program.methods
+      .getMarketStats(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+      })
+      .view();
+    // 12 per slot compounded over 800,000 slots a year: about 0.9646% scaled by 1e9
+    expect(stats.borrowApy.toNumber()).to.equal(9_645_883);
+
+    const before = await program.account.market.fetch(tm.market);
+    await sleep(2000);
+    const sig = await repayTo(tm, borrower, 1e6);
+    const after = await program.account.market.fetch(tm.market);
+
+    const accrued = (await getEvents(sig)).find(
+      (e) => e.name === "interestAccruedEvent"
+    );
+    expect(accrued).to.not.be.undefined;
+    const slots = after.lastUpdateSlot.sub(before.lastUpdateSlot);
+    // 12 per slot is the 1% ceiling rounded down, not the 25 per slot of the 2% flat rate
+    expect(accrued.data.borrowIncrement.toString()).to.equal(
+      slots.muln(12).toString()
+    );
+
+    // A ceiling above the model rate only lifts the clamp, it doesn't raise the rate
+    await setMaxBorrowRate(10_000);
+    const lifted = await program.methods
+      .getMarketStats(tm.id)
+      .accounts({
+        market: tm.market,
+        supplyMint: tm.supplyMint,
+        collateralMint: tm.collateralMint,
+      })
+      .view();
+    expect(lifted.borrowApy.toNumber()).to.equal(20_200_564); // the uncapped 2% compounded
+  });
+
+  it("Books portfolio interest on the market and blocks borrows on a frozen portfolio", async () => {
+    const tm = await createTestMarket();
+    const borrower = await openPortfolioLoan(tm);
+    const portfolio = portfolioPda(tm, borrower);
+    const before = await program.account.market.fetch(tm.market);
+
+    await sleep(2000);
+    await repayPortfolioIn(tm, borrower, 1e6);
+
+    // The interest the debt picked up is on the market's books, for borrows and deposits
+    const market = await program.account.market.fetch(tm.market);
+    const state = await program.account.userPortfolio.fetch(portfolio);
+    expect(market.totalBorrows.toString()).to.equal(state.borrowedAmount.toString());
+    const interest = state.borrowedAmount.sub(new anchor.BN(399 * 1e6));
+    expect(interest.toNumber()).to.be.greaterThan(0);
+    expect(market.totalSupplyDeposits.sub(before.totalSupplyDeposits).toString()).to.equal(
+      interest.toString()
+    );
+
+    const setFrozen = (frozen: boolean) =>
+      program.methods
+        .setPortfolioFrozen(frozen)
+        .accounts({ market: tm.market, portfolio, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    await setFrozen(true);
+    await expectError(
+      borrowAgainstPortfolioIn(tm, borrower, 1e6, [tm.collateralOracle]),
+      "DepositFrozen"
+    );
+    // Repayments stay open on a frozen portfolio
+    await repayPortfolioIn(tm, borrower, 1e6);
+    await setFrozen(false);
+    await borrowAgainstPortfolioIn(tm, borrower, 1e6, [tm.collateralOracle]);
+  });
+
+  it("Withdraws portfolio collateral only while the combined borrow limit holds", async () => {
+    const tm = await createTestMarket();
+    const borrower = await openPortfolioLoan(tm);
+    const portfolio = portfolioPda(tm, borrower);
+
+    // Leaving 0.1 token ($300) behind 400 of debt breaks the limit
+    await expectError(
+      withdrawPortfolioCollateralIn(tm, borrower, 0.9 * 1e9, [tm.collateralOracle]),
+      "InsufficientCollateral"
+    );
+    // Withdrawing while in debt still needs every entry's oracle
+    await expectError(
+      withdrawPortfolioCollateralIn(tm, borrower, 0.1 * 1e9, []),
+      "InvalidOracleData"
+    );
+    await withdrawPortfolioCollateralIn(tm, borrower, 0.1 * 1e9, [tm.collateralOracle]);
+
+    // Once the debt is repaid the rest comes out without any oracle
+    const state = await program.account.userPortfolio.fetch(portfolio);
+    await repayPortfolioIn(tm, borrower, state.borrowedAmount.toNumber() + 1e6);
+    const before = await getAccount(provider.connection, borrower.collateralAccount);
+    await withdrawPortfolioCollateralIn(tm, borrower, 0.9 * 1e9, []);
+    const after = await getAccount(provider.connection, borrower.collateralAccount);
+    expect(Number(after.amount - before.amount)).to.equal(0.9 * 1e9);
+    const emptied = await program.account.userPortfolio.fetch(portfolio);
+    expect(emptied.collaterals[0].amount.toNumber()).to.equal(0);
+  });
+
+  it("Liquidates a portfolio once its combined collateral falls below the threshold", async () => {
+    const tm = await createTestMarket();
+    const borrower = await openPortfolioLoan(tm);
+    const liquidatorUser = await createTestUser(tm, 1000 * 1e6, 0);
+    const portfolio = portfolioPda(tm, borrower);
+
+    const liquidatePortfolioIn = (amount: number, minCollateralOut = 0) =>
+      program.methods
+        .liquidatePortfolio(tm.id, new anchor.BN(amount), new anchor.BN(minCollateralOut))
+        .accounts({
+          portfolio,
+          market: tm.market,
+          supplyVault: tm.supplyVault,
+          supplyMint: tm.supplyMint,
+          collateralMint: tm.collateralMint,
+          assetMint: tm.collateralMint,
+          assetVault: portfolioVaultPda(portfolio, tm.collateralMint),
+          liquidatorSupplyAccount: liquidatorUser.supplyAccount,
+          liquidatorAssetAccount: liquidatorUser.collateralAccount,
+          liquidator: liquidatorUser.keypair.publicKey,
+          borrowOracle: tm.supplyOracle,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(oracleMetas([tm.collateralOracle]))
+        .signers([liquidatorUser.keypair])
+        .rpc();
+
+    await expectError(liquidatePortfolioIn(100 * 1e6), "PositionHealthy");
+
+    // $3000 → $400: 1 token no longer covers 400 of debt at the threshold
+    await setOraclePrice(tm.collateralOracle, 400_000_000);
+    await expectError(liquidatePortfolioIn(100 * 1e6, 1e9), "SlippageExceeded");
+
+    const before = await program.account.userPortfolio.fetch(portfolio);
+    await liquidatePortfolioIn(100 * 1e6);
+    const after = await program.account.userPortfolio.fetch(portfolio);
+    const seized = before.collaterals[0].amount.sub(after.collaterals[0].amount);
+    // 100 of debt plus the bonus, at $400 a token
+    expect(seized.toNumber()).to.be.greaterThan(0.25 * 1e9);
+    const liquidatorCollateral = await getAccount(
+      provider.connection,
+      liquidatorUser.collateralAccount
+    );
+    expect(liquidatorCollateral.amount.toString()).to.equal(seized.toString());
+    expect(after.borrowedAmount.toNumber()).to.be.lessThan(
+      before.borrowedAmount.toNumber() - 99 * 1e6
+    );
+  });
+
+  it("Blocks portfolio borrows while borrows are paused, but still takes repayments", async () => {
+    const tm = await createTestMarket();
+    const borrower = await openPortfolioLoan(tm);
+
+    await program.methods
+      .setBorrowsPaused(true)
+      .accounts({ protocolState, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    try {
+      await expectError(
+        borrowAgainstPortfolioIn(tm, borrower, 10 * 1e6, [tm.collateralOracle]),
+        "MarketPaused"
+      );
+      await repayPortfolioIn(tm, borrower, 40 * 1e6);
+    } finally {
+      await program.methods
+        .setBorrowsPaused(false)
+        .accounts({ protocolState, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    }
+
+    await borrowAgainstPortfolioIn(tm, borrower, 10 * 1e6, [tm.collateralOracle]);
+  });
+
+  it("Only lets the market admin update market params, within the grace period bound", async () => {
+    const tm = await createTestMarket();
+    const outsider = await createTestUser(tm, 0, 0);
+    const updateParams = (graceSlots: number, authority: Keypair) =>
+      program.methods
+        .updateMarketParams(
+          new anchor.BN(8000),
+          new anchor.BN(8500),
+          new anchor.BN(graceSlots),
+          new anchor.BN(30),
+          new anchor.BN(500)
+        )
+        .accounts({ market: tm.market, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    await expectError(updateParams(5, outsider.keypair), "Unauthorized");
+    // MAX_GRACE_PERIOD_SLOTS is 9_000
+    await expectError(updateParams(9_001, admin), "InvalidGracePeriod");
+    await updateParams(9_000, admin);
+
+    const market = await program.account.market.fetch(tm.market);
+    expect(market.gracePeriodSlots.toNumber()).to.equal(9_000);
+  });
 });
@@ -16,6 +16,13 @@ pub mod account_1 {
         pub is_active: bool,
     }
 
+    impl UserProfile {
+        /// Account size for a profile holding a `username_len`-byte username
+        pub fn space_for(username_len: usize) -> usize {
+            8 + 32 + (4 + username_len) + 8 + 8 + 1
+        }
+    }
+
     // Context structures
     #[derive(Accounts)]
     pub struct InitUserProfile<'info> {
@@ -41,6 +48,25 @@ pub mod account_1 {
         pub authority: Signer<'info>,
     }
 
+    #[derive(Accounts)]
+    #[instruction(new_username: String)]
+    pub struct UpdateUsername<'info> {
+        #[account(
+            mut,
+            has_one = authority @ MarketplaceError::Unauthorized,
+            constraint = new_username.len() <= MAX_USERNAME_LEN @ MarketplaceError::UsernameTooLong,
+            realloc = UserProfile::space_for(new_username.len()),
+            realloc::payer = authority,
+            realloc::zero = false
+        )]
+        pub user_profile: Account<'info, UserProfile>,
+
+        #[account(mut)]
+        pub authority: Signer<'info>,
+
+        pub system_program: Program<'info, System>,
+    }
+
     // Program instructions
     pub fn initialize_user_profile(ctx: Context<InitUserProfile>, username: String) -> Result<()> {
         msg!("Initializing user profile: {}", username);
@@ -71,10 +97,25 @@ pub mod account_1 {
         msg!("NFT {} listed for {} lamports", nft_mint, price);
         Ok(())
     }
+
+    pub fn update_username(ctx: Context<UpdateUsername>, new_username: String) -> Result<()> {
+        // Ownership and length are checked by the context, which also resizes the
+        // account to fit: the authority pays rent to grow it and is refunded on shrink
+        let profile = &mut ctx.accounts.user_profile;
+
+        msg!("Username changed from {} to {}", profile.username, new_username);
+        profile.username = new_username;
+        Ok(())
+    }
 }
 
+/// Longest username (in bytes) a profile can be resized to hold
+pub const MAX_USERNAME_LEN: usize = 64;
+
 #[error_code]
 pub enum MarketplaceError {
     #[msg("Unauthorized")]
     Unauthorized,
+    #[msg("Username is longer than 64 bytes")]
+    UsernameTooLong,
 }
@@ -47,6 +47,29 @@ pub mod account_2 {
         pub authority: Signer<'info>,
     }
 
+    #[derive(Accounts)]
+    pub struct CloseProfile<'info> {
+        #[account(
+            mut,
+            has_one = authority,
+            close = authority
+        )]
+        pub user_profile: Account<'info, UserProfile>,
+        // Receives the reclaimed rent
+        #[account(mut)]
+        pub authority: Signer<'info>,
+    }
+
+    #[derive(Accounts)]
+    pub struct SetProfileActive<'info> {
+        #[account(
+            mut,
+            has_one = authority
+        )]
+        pub user_profile: Account<'info, UserProfile>,
+        pub authority: Signer<'info>,
+    }
+
     #[derive(Accounts)]
     pub struct ListNft<'info> {
         #[account(mut)]
@@ -84,6 +107,28 @@ pub mod account_2 {
         Ok(())
     }
 
+    pub fn close_profile(ctx: Context<CloseProfile>) -> Result<()> {
+        // Anchor's close constraint returns the rent to the authority
+        msg!(
+            "Profile {} closed, rent returned to {}",
+            ctx.accounts.user_profile.username,
+            ctx.accounts.authority.key()
+        );
+        Ok(())
+    }
+
+    pub fn set_profile_active(ctx: Context<SetProfileActive>, active: bool) -> Result<()> {
+        let profile = &mut ctx.accounts.user_profile;
+
+        profile.is_active = active;
+        msg!(
+            "Profile {} is now {}",
+            profile.username,
+            if active { "active" } else { "inactive" }
+        );
+        Ok(())
+    }
+
     pub fn list_nft(ctx: Context<ListNft>, price: u64, nft_mint: Pubkey) -> Result<()> {
         let profile = &mut ctx.accounts.user_profile;
 
@@ -92,6 +137,8 @@ pub mod account_2 {
             profile.authority == ctx.accounts.authority.key(),
             MarketplaceError::Unauthorized
         );
+        // Deactivated profiles can't list
+        require!(profile.is_active, MarketplaceError::ProfileInactive);
 
         profile.nft_count += 1;
         msg!("NFT {} listed for {} lamports", nft_mint, price);
@@ -103,4 +150,6 @@ pub mod account_2 {
 pub enum MarketplaceError {
     #[msg("Unauthorized")]
     Unauthorized,
+    #[msg("Profile is inactive")]
+    ProfileInactive,
 }
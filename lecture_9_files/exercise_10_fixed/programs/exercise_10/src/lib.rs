@@ -1,5 +1,9 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::{
+    program::{invoke, invoke_signed},
+    system_instruction,
+};
+use anchor_spl::token::{self, Approve, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("4CQhKWx8yrV8Jp5yJ55NubNKTqUoFsd4CuakRzyVka3W");
 
@@ -15,9 +19,13 @@ pub mod exercise_10 {
     pub struct NftListing {
         pub seller: Pubkey,
         pub seller_token_account: Pubkey,
-        pub price: u64,        // Price in tokens
+        pub price: u64,        // Price in tokens, or lamports for SOL listings
         pub is_active: bool,
         pub bump: u8,
+        pub is_settled: bool,
+        pub nft_mint: Pubkey,
+        pub seller_nft_account: Pubkey,
+        pub payment_mint: Option<Pubkey>, // None = priced in native SOL
     }
 
     // ============================================================================
@@ -32,7 +40,7 @@ pub mod exercise_10 {
         #[account(
             init,
             payer = seller,
-            space = 8 + 32 + 32 + 8 + 1 + 1, // discriminator + seller + seller_token_account + price + is_active + bump
+            space = 8 + 32 + 32 + 8 + 1 + 1 + 1 + 32 + 32 + (1 + 32), // discriminator + seller + seller_token_account + price + is_active + bump + is_settled + nft_mint + seller_nft_account + payment_mint
             seeds = [b"listing", seller.key().as_ref(), seller_token_account.key().as_ref()],
             bump
         )]
@@ -43,9 +51,46 @@ pub mod exercise_10 {
         )]
         pub seller_token_account: Account<'info, TokenAccount>,
 
+        #[account(
+            address = seller_token_account.mint @ MarketplaceError::InvalidPaymentMint
+        )]
+        pub payment_mint: Account<'info, Mint>,
+
+        /// Holds this listing's sale proceeds, and only this listing's
+        #[account(
+            init,
+            payer = seller,
+            seeds = [b"escrow", listing.key().as_ref()],
+            bump,
+            token::mint = payment_mint,
+            token::authority = escrow_authority
+        )]
+        pub escrow_token_account: Account<'info, TokenAccount>,
+
+        /// CHECK: PDA that owns every listing's escrow token account
+        #[account(
+            seeds = [b"escrow_authority"],
+            bump
+        )]
+        pub escrow_authority: UncheckedAccount<'info>,
+
+        #[account(
+            constraint = nft_mint.decimals == 0 && nft_mint.supply == 1 @ MarketplaceError::InvalidNftMint
+        )]
+        pub nft_mint: Account<'info, Mint>,
+
+        #[account(
+            mut,
+            constraint = seller_nft_account.owner == seller.key(),
+            constraint = seller_nft_account.mint == nft_mint.key() @ MarketplaceError::InvalidNftMint,
+            constraint = seller_nft_account.amount == 1 @ MarketplaceError::InvalidNftMint
+        )]
+        pub seller_nft_account: Account<'info, TokenAccount>,
+
         #[account(mut)]
         pub seller: Signer<'info>,
         
+        pub token_program: Program<'info, Token>,
         pub system_program: Program<'info, System>,
     }
 
@@ -61,20 +106,206 @@ pub mod exercise_10 {
 
         #[account(
             mut,
-            constraint = buyer_token_account.owner == buyer.key()
+            constraint = buyer_token_account.owner == buyer.key(),
+            constraint = listing.payment_mint == Some(buyer_token_account.mint) @ MarketplaceError::InvalidPaymentMint
         )]
         pub buyer_token_account: Account<'info, TokenAccount>,
 
         #[account(
             mut,
-            constraint = escrow_token_account.mint == buyer_token_account.mint
+            seeds = [b"escrow", listing.key().as_ref()],
+            bump,
+            constraint = escrow_token_account.owner == escrow_authority.key() @ MarketplaceError::InvalidEscrowAccount,
+            constraint = listing.payment_mint == Some(escrow_token_account.mint) @ MarketplaceError::InvalidPaymentMint
         )]
         pub escrow_token_account: Account<'info, TokenAccount>,
 
+        /// CHECK: PDA that must own the escrow token account the payment goes into
+        #[account(
+            seeds = [b"escrow_authority"],
+            bump
+        )]
+        pub escrow_authority: UncheckedAccount<'info>,
+
+        #[account(
+            mut,
+            address = listing.seller_nft_account,
+            constraint = seller_nft_account.mint == listing.nft_mint @ MarketplaceError::InvalidNftMint
+        )]
+        pub seller_nft_account: Account<'info, TokenAccount>,
+
+        #[account(
+            mut,
+            constraint = buyer_nft_account.owner == buyer.key(),
+            constraint = buyer_nft_account.mint == listing.nft_mint @ MarketplaceError::InvalidNftMint
+        )]
+        pub buyer_nft_account: Account<'info, TokenAccount>,
+
+        #[account(mut)]
+        pub buyer: Signer<'info>,
+
+        pub token_program: Program<'info, Token>,
+    }
+
+    #[derive(Accounts)]
+    pub struct UpdateListingPrice<'info> {
+        #[account(
+            mut,
+            seeds = [b"listing", listing.seller.as_ref(), listing.seller_token_account.as_ref()],
+            bump = listing.bump,
+            has_one = seller,
+            constraint = listing.is_active @ MarketplaceError::ListingNotActive
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        pub seller: Signer<'info>,
+    }
+
+    #[derive(Accounts)]
+    pub struct UpdateSolListingPrice<'info> {
+        #[account(
+            mut,
+            seeds = [b"sol_listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump,
+            has_one = seller,
+            constraint = listing.payment_mint.is_none() @ MarketplaceError::InvalidPaymentMint,
+            constraint = listing.is_active @ MarketplaceError::ListingNotActive
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        pub seller: Signer<'info>,
+    }
+
+    #[derive(Accounts)]
+    pub struct SettleSale<'info> {
+        #[account(
+            mut,
+            seeds = [b"listing", listing.seller.as_ref(), listing.seller_token_account.as_ref()],
+            bump = listing.bump,
+            has_one = seller_token_account,
+            constraint = !listing.is_active @ MarketplaceError::ListingStillActive,
+            constraint = !listing.is_settled @ MarketplaceError::SaleAlreadySettled
+        )]
+        pub listing: Box<Account<'info, NftListing>>,
+
+        #[account(mut)]
+        pub seller_token_account: Account<'info, TokenAccount>,
+
+        /// The listing's own escrow, so a settlement can only pay out what this sale paid in
+        #[account(
+            mut,
+            seeds = [b"escrow", listing.key().as_ref()],
+            bump,
+            constraint = escrow_token_account.owner == escrow_authority.key(),
+            constraint = escrow_token_account.mint == seller_token_account.mint
+        )]
+        pub escrow_token_account: Account<'info, TokenAccount>,
+
+        /// CHECK: PDA that owns the escrow token account, only used as a signer
+        #[account(
+            seeds = [b"escrow_authority"],
+            bump
+        )]
+        pub escrow_authority: UncheckedAccount<'info>,
+
+        pub token_program: Program<'info, Token>,
+    }
+
+    #[derive(Accounts)]
+    pub struct CreateSolListing<'info> {
+        #[account(
+            init,
+            payer = seller,
+            space = 8 + 32 + 32 + 8 + 1 + 1 + 1 + 32 + 32 + (1 + 32), // same layout as token listings
+            seeds = [b"sol_listing", seller.key().as_ref(), nft_mint.key().as_ref()],
+            bump
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        #[account(
+            constraint = nft_mint.decimals == 0 && nft_mint.supply == 1 @ MarketplaceError::InvalidNftMint
+        )]
+        pub nft_mint: Account<'info, Mint>,
+
+        #[account(
+            mut,
+            constraint = seller_nft_account.owner == seller.key(),
+            constraint = seller_nft_account.mint == nft_mint.key() @ MarketplaceError::InvalidNftMint,
+            constraint = seller_nft_account.amount == 1 @ MarketplaceError::InvalidNftMint
+        )]
+        pub seller_nft_account: Account<'info, TokenAccount>,
+
+        #[account(mut)]
+        pub seller: Signer<'info>,
+
+        pub token_program: Program<'info, Token>,
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct PurchaseNftWithSol<'info> {
+        #[account(
+            mut,
+            seeds = [b"sol_listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump,
+            constraint = listing.is_active @ MarketplaceError::ListingNotActive,
+            constraint = listing.payment_mint.is_none() @ MarketplaceError::InvalidPaymentMint
+        )]
+        pub listing: Box<Account<'info, NftListing>>,
+
+        /// Lamport-only PDA holding sale proceeds until the seller settles
+        #[account(
+            mut,
+            seeds = [b"sol_escrow", listing.key().as_ref()],
+            bump
+        )]
+        pub sol_escrow: SystemAccount<'info>,
+
+        #[account(
+            mut,
+            address = listing.seller_nft_account,
+            constraint = seller_nft_account.mint == listing.nft_mint @ MarketplaceError::InvalidNftMint
+        )]
+        pub seller_nft_account: Account<'info, TokenAccount>,
+
+        #[account(
+            mut,
+            constraint = buyer_nft_account.owner == buyer.key(),
+            constraint = buyer_nft_account.mint == listing.nft_mint @ MarketplaceError::InvalidNftMint
+        )]
+        pub buyer_nft_account: Account<'info, TokenAccount>,
+
         #[account(mut)]
         pub buyer: Signer<'info>,
 
         pub token_program: Program<'info, Token>,
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct SettleSolSale<'info> {
+        #[account(
+            mut,
+            seeds = [b"sol_listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump,
+            has_one = seller,
+            constraint = listing.payment_mint.is_none() @ MarketplaceError::InvalidPaymentMint,
+            constraint = !listing.is_active @ MarketplaceError::ListingStillActive,
+            constraint = !listing.is_settled @ MarketplaceError::SaleAlreadySettled
+        )]
+        pub listing: Box<Account<'info, NftListing>>,
+
+        #[account(
+            mut,
+            seeds = [b"sol_escrow", listing.key().as_ref()],
+            bump
+        )]
+        pub sol_escrow: SystemAccount<'info>,
+
+        #[account(mut)]
+        pub seller: SystemAccount<'info>,
+
+        pub system_program: Program<'info, System>,
     }
 
     // ============================================================================
@@ -94,11 +325,96 @@ pub mod exercise_10 {
         listing.price = price;
         listing.is_active = true;
         listing.bump = ctx.bumps.listing;
+        listing.is_settled = false;
+        listing.nft_mint = ctx.accounts.nft_mint.key();
+        listing.seller_nft_account = ctx.accounts.seller_nft_account.key();
+        listing.payment_mint = Some(ctx.accounts.seller_token_account.mint);
+
+        // Delegate the NFT to the listing PDA so the sale can deliver it without the seller signing
+        let approve_accounts = Approve {
+            to: ctx.accounts.seller_nft_account.to_account_info(),
+            delegate: ctx.accounts.listing.to_account_info(),
+            authority: ctx.accounts.seller.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            approve_accounts,
+        );
+
+        token::approve(cpi_ctx, 1)?;
 
         msg!("Created listing at price {} tokens", price);
         Ok(())
     }
 
+    /// List an NFT priced in lamports instead of an SPL token
+    pub fn create_sol_listing(ctx: Context<CreateSolListing>, price: u64) -> Result<()> {
+        // The escrow is a plain system account, so the first payment into it must cover rent
+        require!(
+            price >= Rent::get()?.minimum_balance(0),
+            MarketplaceError::InvalidPrice
+        );
+
+        let listing = &mut ctx.accounts.listing;
+
+        listing.seller = ctx.accounts.seller.key();
+        listing.seller_token_account = Pubkey::default();
+        listing.price = price;
+        listing.is_active = true;
+        listing.bump = ctx.bumps.listing;
+        listing.is_settled = false;
+        listing.nft_mint = ctx.accounts.nft_mint.key();
+        listing.seller_nft_account = ctx.accounts.seller_nft_account.key();
+        listing.payment_mint = None;
+
+        let approve_accounts = Approve {
+            to: ctx.accounts.seller_nft_account.to_account_info(),
+            delegate: ctx.accounts.listing.to_account_info(),
+            authority: ctx.accounts.seller.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            approve_accounts,
+        );
+
+        token::approve(cpi_ctx, 1)?;
+
+        msg!("Created listing at price {} lamports", price);
+        Ok(())
+    }
+
+    /// Reprice an active listing without closing it
+    pub fn update_listing_price(ctx: Context<UpdateListingPrice>, new_price: u64) -> Result<()> {
+        require!(new_price > 0, MarketplaceError::InvalidPrice);
+
+        let listing = &mut ctx.accounts.listing;
+        let old_price = listing.price;
+        listing.price = new_price;
+
+        msg!("Updated listing price from {} to {} tokens", old_price, new_price);
+        Ok(())
+    }
+
+    /// Reprice an active SOL listing, keeping the same rent floor as `create_sol_listing`
+    pub fn update_sol_listing_price(
+        ctx: Context<UpdateSolListingPrice>,
+        new_price: u64,
+    ) -> Result<()> {
+        require!(
+            new_price >= Rent::get()?.minimum_balance(0),
+            MarketplaceError::InvalidPrice
+        );
+
+        let listing = &mut ctx.accounts.listing;
+        let old_price = listing.price;
+        listing.price = new_price;
+
+        msg!("Updated listing price from {} to {} lamports", old_price, new_price);
+        Ok(())
+    }
+
     /// Purchase NFT with token transfer
     pub fn purchase_nft_vulnerable(ctx: Context<PurchaseNft>) -> Result<()> {
         let listing = &mut ctx.accounts.listing;
@@ -163,10 +479,175 @@ pub mod exercise_10 {
 
         // Complete the sale
         listing.is_active = false;
+
+        // Deliver the NFT from the seller to the buyer, signed by the listing PDA as delegate
+        let seller_key = listing.seller;
+        let seller_token_account_key = listing.seller_token_account;
+        let listing_bump = listing.bump;
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"listing",
+            seller_key.as_ref(),
+            seller_token_account_key.as_ref(),
+            &[listing_bump],
+        ]];
+
+        let nft_transfer_accounts = Transfer {
+            from: ctx.accounts.seller_nft_account.to_account_info(),
+            to: ctx.accounts.buyer_nft_account.to_account_info(),
+            authority: ctx.accounts.listing.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            nft_transfer_accounts,
+            signer_seeds,
+        );
+
+        token::transfer(cpi_ctx, 1)?;
+
+        msg!("NFT {} transferred to buyer", ctx.accounts.listing.nft_mint);
         
         msg!("=== PURCHASE COMPLETED ===");
         Ok(())
     }
+
+    /// Purchase a SOL-priced NFT, paying lamports into the listing's SOL escrow
+    pub fn purchase_nft_with_sol(ctx: Context<PurchaseNftWithSol>) -> Result<()> {
+        let price = ctx.accounts.listing.price;
+
+        msg!("=== SOL PURCHASE STARTING ===");
+        msg!("Purchase price: {} lamports", price);
+
+        let buyer_info = ctx.accounts.buyer.to_account_info();
+        let escrow_info = ctx.accounts.sol_escrow.to_account_info();
+        let escrow_balance_before = escrow_info.lamports();
+
+        require!(
+            buyer_info.lamports() >= price,
+            MarketplaceError::InsufficientFunds
+        );
+
+        invoke(
+            &system_instruction::transfer(buyer_info.key, escrow_info.key, price),
+            &[
+                buyer_info.clone(),
+                escrow_info.clone(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let escrow_received = escrow_info.lamports() - escrow_balance_before;
+        if escrow_received != price {
+            msg!("Validation: Expected {} lamports, but escrow received {}", price, escrow_received);
+            return Err(error!(MarketplaceError::TransferAmountMismatch));
+        }
+
+        let listing = &mut ctx.accounts.listing;
+        listing.is_active = false;
+
+        // Deliver the NFT, signed by the listing PDA as delegate
+        let seller_key = listing.seller;
+        let nft_mint_key = listing.nft_mint;
+        let listing_bump = listing.bump;
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"sol_listing",
+            seller_key.as_ref(),
+            nft_mint_key.as_ref(),
+            &[listing_bump],
+        ]];
+
+        let nft_transfer_accounts = Transfer {
+            from: ctx.accounts.seller_nft_account.to_account_info(),
+            to: ctx.accounts.buyer_nft_account.to_account_info(),
+            authority: ctx.accounts.listing.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            nft_transfer_accounts,
+            signer_seeds,
+        );
+
+        token::transfer(cpi_ctx, 1)?;
+
+        msg!("=== SOL PURCHASE COMPLETED ===");
+        Ok(())
+    }
+
+    /// Pay SOL sale proceeds out of the listing's escrow to the seller
+    pub fn settle_sol_sale(ctx: Context<SettleSolSale>) -> Result<()> {
+        let price = ctx.accounts.listing.price;
+        let escrow_info = ctx.accounts.sol_escrow.to_account_info();
+
+        require!(
+            escrow_info.lamports() >= price,
+            MarketplaceError::EscrowBalanceMismatch
+        );
+
+        let listing_key = ctx.accounts.listing.key();
+        let signer_seeds: &[&[&[u8]]] =
+            &[&[b"sol_escrow", listing_key.as_ref(), &[ctx.bumps.sol_escrow]]];
+
+        invoke_signed(
+            &system_instruction::transfer(escrow_info.key, ctx.accounts.seller.key, price),
+            &[
+                escrow_info.clone(),
+                ctx.accounts.seller.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        ctx.accounts.listing.is_settled = true;
+
+        msg!("Settled sale: {} lamports released to seller", price);
+        Ok(())
+    }
+
+    /// Pay the sale proceeds out of escrow to the seller
+    pub fn settle_sale(ctx: Context<SettleSale>) -> Result<()> {
+        let price = ctx.accounts.listing.price;
+
+        let seller_token_balance_before = ctx.accounts.seller_token_account.amount;
+        let escrow_token_balance_before = ctx.accounts.escrow_token_account.amount;
+
+        require!(
+            escrow_token_balance_before >= price,
+            MarketplaceError::EscrowBalanceMismatch
+        );
+
+        let signer_seeds: &[&[&[u8]]] = &[&[b"escrow_authority", &[ctx.bumps.escrow_authority]]];
+
+        let transfer_accounts = Transfer {
+            from: ctx.accounts.escrow_token_account.to_account_info(),
+            to: ctx.accounts.seller_token_account.to_account_info(),
+            authority: ctx.accounts.escrow_authority.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_accounts,
+            signer_seeds,
+        );
+
+        token::transfer(cpi_ctx, price)?;
+
+        ctx.accounts.seller_token_account.reload()?;
+        ctx.accounts.escrow_token_account.reload()?;
+
+        let seller_received = ctx.accounts.seller_token_account.amount - seller_token_balance_before;
+        let escrow_sent = escrow_token_balance_before - ctx.accounts.escrow_token_account.amount;
+
+        if seller_received != price || escrow_sent != price {
+            msg!("Validation: Expected {} tokens, but seller received {}", price, seller_received);
+            return Err(error!(MarketplaceError::TransferAmountMismatch));
+        }
+
+        ctx.accounts.listing.is_settled = true;
+
+        msg!("Settled sale: {} tokens released to seller", price);
+        Ok(())
+    }
 }
 
 // ============================================================================
@@ -186,4 +667,22 @@ pub enum MarketplaceError {
     
     #[msg("Listing is not active")]
     ListingNotActive,
+
+    #[msg("Listing price must be greater than zero")]
+    InvalidPrice,
+
+    #[msg("Listing is still active")]
+    ListingStillActive,
+
+    #[msg("Sale has already been settled")]
+    SaleAlreadySettled,
+
+    #[msg("NFT mint does not match the listing")]
+    InvalidNftMint,
+
+    #[msg("Listing is not priced in this currency")]
+    InvalidPaymentMint,
+
+    #[msg("Escrow token account is not owned by the escrow authority")]
+    InvalidEscrowAccount,
 }
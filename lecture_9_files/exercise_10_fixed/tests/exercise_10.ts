@@ -5,6 +5,7 @@ import { expect } from "chai";
 import { 
   TOKEN_PROGRAM_ID,
   createMint,
+  createAccount,
   getOrCreateAssociatedTokenAccount,
   mintTo,
   getAccount,
@@ -18,6 +19,12 @@ describe("exercise_10", () => {
   const program = anchor.workspace.Exercise10 as Program<Exercise10>;
   const provider = anchor.AnchorProvider.env();
 
+  // PDA that owns every escrow token account
+  const [escrowAuthority] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("escrow_authority")],
+    program.programId
+  );
+
   // Test accounts
   let seller: anchor.web3.Keypair;
   let buyer: anchor.web3.Keypair;
@@ -28,6 +35,11 @@ describe("exercise_10", () => {
   let buyerTokenAccount: anchor.web3.PublicKey;
   let escrowTokenAccount: anchor.web3.PublicKey;
 
+  // NFT being sold
+  let nftMint: anchor.web3.PublicKey;
+  let sellerNftAccount: anchor.web3.PublicKey;
+  let buyerNftAccount: anchor.web3.PublicKey;
+
   // Program accounts
   let listingPda: anchor.web3.PublicKey;
 
@@ -89,22 +101,6 @@ describe("exercise_10", () => {
     );
     buyerTokenAccount = buyerATA.address;
 
-    // For escrow, we'll use a PDA-derived token account or another ATA
-    // Let's create an ATA for a PDA as the escrow
-    const [escrowAuthority] = anchor.web3.PublicKey.findProgramAddressSync(
-      [Buffer.from("escrow_authority")],
-      program.programId
-    );
-
-    const escrowATA = await getOrCreateAssociatedTokenAccount(
-      provider.connection,
-      seller, // Payer
-      mint,
-      escrowAuthority, // Owner is the PDA
-      true // Allow PDA owner
-    );
-    escrowTokenAccount = escrowATA.address;
-
     console.log("💎 Minting tokens to buyer...");
 
     // Mint tokens to buyer for testing
@@ -117,12 +113,52 @@ describe("exercise_10", () => {
       10000 // Amount
     );
 
+    console.log("🖼️  Minting NFT to seller...");
+
+    // A 0-decimal mint with a supply of 1 stands in for the NFT
+    nftMint = await createMint(
+      provider.connection,
+      seller,
+      seller.publicKey,
+      null,
+      0
+    );
+
+    sellerNftAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      seller,
+      nftMint,
+      seller.publicKey
+    )).address;
+
+    buyerNftAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      buyer,
+      nftMint,
+      buyer.publicKey
+    )).address;
+
+    await mintTo(
+      provider.connection,
+      seller,
+      nftMint,
+      sellerNftAccount,
+      seller,
+      1
+    );
+
     // Derive listing PDA
     [listingPda] = anchor.web3.PublicKey.findProgramAddressSync(
       [Buffer.from("listing"), seller.publicKey.toBuffer(), sellerTokenAccount.toBuffer()],
       program.programId
     );
 
+    // Each listing gets its own escrow, created with the listing
+    [escrowTokenAccount] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), listingPda.toBuffer()],
+      program.programId
+    );
+
     console.log("📋 Creating listing...");
 
     // Create a listing
@@ -131,7 +167,13 @@ describe("exercise_10", () => {
       .accounts({
         listing: listingPda,
         sellerTokenAccount: sellerTokenAccount,
+        paymentMint: mint,
+        escrowTokenAccount: escrowTokenAccount,
+        escrowAuthority: escrowAuthority,
+        nftMint: nftMint,
+        sellerNftAccount: sellerNftAccount,
         seller: seller.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: anchor.web3.SystemProgram.programId,
       })
       .signers([seller])
@@ -140,7 +182,7 @@ describe("exercise_10", () => {
     console.log("✅ Setup completed successfully");
     console.log(`   Seller ATA: ${sellerTokenAccount}`);
     console.log(`   Buyer ATA: ${buyerTokenAccount}`);
-    console.log(`   Escrow ATA: ${escrowTokenAccount}`);
+    console.log(`   Escrow: ${escrowTokenAccount}`);
   });
 
   it("FIXED: purchase_nft_vulnerable now succeeds with fresh data", async () => {
@@ -158,6 +200,9 @@ describe("exercise_10", () => {
           listing: listingPda,
           buyerTokenAccount: buyerTokenAccount,
           escrowTokenAccount: escrowTokenAccount,
+          escrowAuthority: escrowAuthority,
+          sellerNftAccount: sellerNftAccount,
+          buyerNftAccount: buyerNftAccount,
           buyer: buyer.publicKey,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
@@ -225,5 +270,428 @@ describe("exercise_10", () => {
       expect.fail("❌ TEST FAILED: Transaction failed for reasons other than TransferAmountMismatch");
     }
   });
+
+  it("Seller can update the price of an active listing", async () => {
+    const newPrice = TOKEN_PRICE * 2;
+
+    await program.methods
+      .updateListingPrice(new anchor.BN(newPrice))
+      .accounts({
+        listing: listingPda,
+        seller: seller.publicKey,
+      })
+      .signers([seller])
+      .rpc();
+
+    const listing = await program.account.nftListing.fetch(listingPda);
+    expect(listing.price.toNumber()).to.equal(newPrice);
+    expect(listing.isActive).to.be.true;
+  });
+
+  it("Rejects a zero listing price", async () => {
+    try {
+      await program.methods
+        .updateListingPrice(new anchor.BN(0))
+        .accounts({
+          listing: listingPda,
+          seller: seller.publicKey,
+        })
+        .signers([seller])
+        .rpc();
+      expect.fail("Zero price should have been rejected");
+    } catch (error: any) {
+      expect(error.message).to.include("InvalidPrice");
+    }
+
+    const listing = await program.account.nftListing.fetch(listingPda);
+    expect(listing.price.toNumber()).to.equal(TOKEN_PRICE);
+  });
+
+  it("Only the seller can update the listing price", async () => {
+    try {
+      await program.methods
+        .updateListingPrice(new anchor.BN(1))
+        .accounts({
+          listing: listingPda,
+          seller: buyer.publicKey,
+        })
+        .signers([buyer])
+        .rpc();
+      expect.fail("Non-seller should not be able to reprice the listing");
+    } catch (error: any) {
+      expect(error.message).to.include("ConstraintHasOne");
+    }
+  });
+
+  it("Settles a completed sale by releasing escrowed tokens to the seller", async () => {
+    await program.methods
+      .purchaseNftVulnerable()
+      .accounts({
+        listing: listingPda,
+        buyerTokenAccount: buyerTokenAccount,
+        escrowTokenAccount: escrowTokenAccount,
+        escrowAuthority: escrowAuthority,
+        sellerNftAccount: sellerNftAccount,
+        buyerNftAccount: buyerNftAccount,
+        buyer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([buyer])
+      .rpc();
+
+    const sellerBalanceBefore = (await getAccount(provider.connection, sellerTokenAccount)).amount;
+
+    await program.methods
+      .settleSale()
+      .accounts({
+        listing: listingPda,
+        sellerTokenAccount: sellerTokenAccount,
+        escrowTokenAccount: escrowTokenAccount,
+        escrowAuthority: escrowAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const sellerBalanceAfter = (await getAccount(provider.connection, sellerTokenAccount)).amount;
+    expect(Number(sellerBalanceAfter - sellerBalanceBefore)).to.equal(TOKEN_PRICE);
+
+    const listing = await program.account.nftListing.fetch(listingPda);
+    expect(listing.isSettled).to.be.true;
+
+    // A second settlement must not pay the seller twice
+    try {
+      await program.methods
+        .settleSale()
+        .accounts({
+          listing: listingPda,
+          sellerTokenAccount: sellerTokenAccount,
+          escrowTokenAccount: escrowTokenAccount,
+          escrowAuthority: escrowAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      expect.fail("Sale should not be settled twice");
+    } catch (error: any) {
+      expect(error.message).to.include("SaleAlreadySettled");
+    }
+  });
+
+  it("Purchase delivers the NFT to the buyer", async () => {
+    await program.methods
+      .purchaseNftVulnerable()
+      .accounts({
+        listing: listingPda,
+        buyerTokenAccount: buyerTokenAccount,
+        escrowTokenAccount: escrowTokenAccount,
+        escrowAuthority: escrowAuthority,
+        sellerNftAccount: sellerNftAccount,
+        buyerNftAccount: buyerNftAccount,
+        buyer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([buyer])
+      .rpc();
+
+    expect(Number((await getAccount(provider.connection, buyerNftAccount)).amount)).to.equal(1);
+    expect(Number((await getAccount(provider.connection, sellerNftAccount)).amount)).to.equal(0);
+
+    const listing = await program.account.nftListing.fetch(listingPda);
+    expect(listing.nftMint.toBase58()).to.equal(nftMint.toBase58());
+    expect(listing.isActive).to.be.false;
+  });
+
+  it("Rejects a buyer NFT account for a different mint", async () => {
+    const otherMint = await createMint(provider.connection, buyer, buyer.publicKey, null, 0);
+    const otherAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      buyer,
+      otherMint,
+      buyer.publicKey
+    )).address;
+
+    try {
+      await program.methods
+        .purchaseNftVulnerable()
+        .accounts({
+          listing: listingPda,
+          buyerTokenAccount: buyerTokenAccount,
+          escrowTokenAccount: escrowTokenAccount,
+          escrowAuthority: escrowAuthority,
+          sellerNftAccount: sellerNftAccount,
+          buyerNftAccount: otherAccount,
+          buyer: buyer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+      expect.fail("Purchase should reject an NFT account for the wrong mint");
+    } catch (error: any) {
+      expect(error.message).to.include("InvalidNftMint");
+    }
+  });
+
+  it("Sells an NFT for native SOL through the SOL escrow end to end", async () => {
+    const SOL_PRICE = anchor.web3.LAMPORTS_PER_SOL / 2;
+
+    const [solListingPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("sol_listing"), seller.publicKey.toBuffer(), nftMint.toBuffer()],
+      program.programId
+    );
+    const [solEscrowPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("sol_escrow"), solListingPda.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createSolListing(new anchor.BN(SOL_PRICE * 2))
+      .accounts({
+        listing: solListingPda,
+        nftMint: nftMint,
+        sellerNftAccount: sellerNftAccount,
+        seller: seller.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([seller])
+      .rpc();
+
+    let listing = await program.account.nftListing.fetch(solListingPda);
+    expect(listing.paymentMint).to.be.null;
+    expect(listing.price.toNumber()).to.equal(SOL_PRICE * 2);
+
+    // SOL listings are repriced through their own instruction, under the same rent floor
+    const repriceSol = (price: number) =>
+      program.methods
+        .updateSolListingPrice(new anchor.BN(price))
+        .accounts({ listing: solListingPda, seller: seller.publicKey })
+        .signers([seller])
+        .rpc();
+    const rentFloor = await provider.connection.getMinimumBalanceForRentExemption(0);
+    try {
+      await repriceSol(rentFloor - 1);
+      expect.fail("A SOL price below the escrow's rent floor should be rejected");
+    } catch (error: any) {
+      expect(error.message).to.include("InvalidPrice");
+    }
+    try {
+      await program.methods
+        .updateListingPrice(new anchor.BN(SOL_PRICE))
+        .accounts({ listing: solListingPda, seller: seller.publicKey })
+        .signers([seller])
+        .rpc();
+      expect.fail("The token reprice path should not accept a SOL listing");
+    } catch (error: any) {
+      expect(error.message).to.include("ConstraintSeeds");
+    }
+    await repriceSol(SOL_PRICE);
+    listing = await program.account.nftListing.fetch(solListingPda);
+    expect(listing.price.toNumber()).to.equal(SOL_PRICE);
+
+    // Token listings record the mint they're priced in
+    const tokenListing = await program.account.nftListing.fetch(listingPda);
+    expect(tokenListing.paymentMint.toBase58()).to.equal(mint.toBase58());
+
+    const buyerLamportsBefore = await provider.connection.getBalance(buyer.publicKey);
+
+    await program.methods
+      .purchaseNftWithSol()
+      .accounts({
+        listing: solListingPda,
+        solEscrow: solEscrowPda,
+        sellerNftAccount: sellerNftAccount,
+        buyerNftAccount: buyerNftAccount,
+        buyer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([buyer])
+      .rpc();
+
+    // Lamports sit in escrow, the NFT has moved, the buyer paid price plus the tx fee
+    expect(await provider.connection.getBalance(solEscrowPda)).to.equal(SOL_PRICE);
+    expect(Number((await getAccount(provider.connection, buyerNftAccount)).amount)).to.equal(1);
+    expect(Number((await getAccount(provider.connection, sellerNftAccount)).amount)).to.equal(0);
+    const buyerSpent = buyerLamportsBefore - (await provider.connection.getBalance(buyer.publicKey));
+    expect(buyerSpent).to.be.at.least(SOL_PRICE);
+    expect(buyerSpent).to.be.lessThan(SOL_PRICE + 10_000);
+
+    listing = await program.account.nftListing.fetch(solListingPda);
+    expect(listing.isActive).to.be.false;
+
+    const sellerLamportsBefore = await provider.connection.getBalance(seller.publicKey);
+
+    await program.methods
+      .settleSolSale()
+      .accounts({
+        listing: solListingPda,
+        solEscrow: solEscrowPda,
+        seller: seller.publicKey,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    expect(await provider.connection.getBalance(seller.publicKey)).to.equal(
+      sellerLamportsBefore + SOL_PRICE
+    );
+    expect(await provider.connection.getBalance(solEscrowPda)).to.equal(0);
+    listing = await program.account.nftListing.fetch(solListingPda);
+    expect(listing.isSettled).to.be.true;
+
+    // The SOL listing can't be bought through the token path
+    try {
+      await program.methods
+        .purchaseNftVulnerable()
+        .accounts({
+          listing: solListingPda,
+          buyerTokenAccount: buyerTokenAccount,
+          escrowTokenAccount: escrowTokenAccount,
+          escrowAuthority: escrowAuthority,
+          sellerNftAccount: sellerNftAccount,
+          buyerNftAccount: buyerNftAccount,
+          buyer: buyer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+      expect.fail("Token purchase of a SOL listing should fail");
+    } catch (error: any) {
+      expect(error.message).to.match(/ConstraintSeeds|ListingNotActive/);
+    }
+  });
+
+  it("Rejects payment in a mint other than the listing's, or into an escrow the program doesn't own", async () => {
+    // The buyer's own worthless mint, paid into an account the buyer also owns
+    const junkMint = await createMint(provider.connection, buyer, buyer.publicKey, null, 9);
+    const buyerJunkAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      buyer,
+      junkMint,
+      buyer.publicKey
+    )).address;
+    await mintTo(provider.connection, buyer, junkMint, buyerJunkAccount, buyer, 10000);
+
+    const purchase = (paidFrom: anchor.web3.PublicKey, paidInto: anchor.web3.PublicKey) =>
+      program.methods
+        .purchaseNftVulnerable()
+        .accounts({
+          listing: listingPda,
+          buyerTokenAccount: paidFrom,
+          escrowTokenAccount: paidInto,
+          escrowAuthority: escrowAuthority,
+          sellerNftAccount: sellerNftAccount,
+          buyerNftAccount: buyerNftAccount,
+          buyer: buyer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([buyer])
+        .rpc();
+
+    try {
+      await purchase(buyerJunkAccount, escrowTokenAccount);
+      expect.fail("Payment in the wrong mint should be rejected");
+    } catch (error: any) {
+      expect(error.message).to.include("InvalidPaymentMint");
+    }
+
+    // Right mint, but paid into an account the buyer controls
+    const buyerSecondAccount = await createAccount(
+      provider.connection,
+      buyer,
+      mint,
+      buyer.publicKey,
+      anchor.web3.Keypair.generate()
+    );
+    try {
+      await purchase(buyerTokenAccount, buyerSecondAccount);
+      expect.fail("Payment into an escrow outside the program's control should be rejected");
+    } catch (error: any) {
+      expect(error.message).to.match(/ConstraintSeeds|InvalidEscrowAccount/);
+    }
+
+    // Nothing moved
+    expect(Number((await getAccount(provider.connection, sellerNftAccount)).amount)).to.equal(1);
+    const listing = await program.account.nftListing.fetch(listingPda);
+    expect(listing.isActive).to.be.true;
+  });
+
+  it("Settles each sale only from its own listing's escrow", async () => {
+    // A second listing by the same seller, paid into a second token account
+    const secondSellerTokenAccount = await createAccount(
+      provider.connection,
+      seller,
+      mint,
+      seller.publicKey,
+      anchor.web3.Keypair.generate()
+    );
+    const [secondListingPda] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), seller.publicKey.toBuffer(), secondSellerTokenAccount.toBuffer()],
+      program.programId
+    );
+    const [secondEscrow] = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), secondListingPda.toBuffer()],
+      program.programId
+    );
+    const secondNftMint = await createMint(provider.connection, seller, seller.publicKey, null, 0);
+    const secondSellerNftAccount = (await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      seller,
+      secondNftMint,
+      seller.publicKey
+    )).address;
+    await mintTo(provider.connection, seller, secondNftMint, secondSellerNftAccount, seller, 1);
+
+    await program.methods
+      .createListing(new anchor.BN(TOKEN_PRICE))
+      .accounts({
+        listing: secondListingPda,
+        sellerTokenAccount: secondSellerTokenAccount,
+        paymentMint: mint,
+        escrowTokenAccount: secondEscrow,
+        escrowAuthority: escrowAuthority,
+        nftMint: secondNftMint,
+        sellerNftAccount: secondSellerNftAccount,
+        seller: seller.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      })
+      .signers([seller])
+      .rpc();
+
+    await program.methods
+      .purchaseNftVulnerable()
+      .accounts({
+        listing: listingPda,
+        buyerTokenAccount: buyerTokenAccount,
+        escrowTokenAccount: escrowTokenAccount,
+        escrowAuthority: escrowAuthority,
+        sellerNftAccount: sellerNftAccount,
+        buyerNftAccount: buyerNftAccount,
+        buyer: buyer.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([buyer])
+      .rpc();
+
+    // The proceeds sit in the first listing's escrow alone
+    expect(Number((await getAccount(provider.connection, escrowTokenAccount)).amount)).to.equal(TOKEN_PRICE);
+    expect(Number((await getAccount(provider.connection, secondEscrow)).amount)).to.equal(0);
+
+    // Settling the sold listing out of the other listing's escrow is rejected
+    try {
+      await program.methods
+        .settleSale()
+        .accounts({
+          listing: listingPda,
+          sellerTokenAccount: sellerTokenAccount,
+          escrowTokenAccount: secondEscrow,
+          escrowAuthority: escrowAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+      expect.fail("Settlement should only draw on the listing's own escrow");
+    } catch (error: any) {
+      expect(error.message).to.include("ConstraintSeeds");
+    }
+  });
 });
 
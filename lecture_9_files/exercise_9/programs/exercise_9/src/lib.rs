@@ -28,6 +28,18 @@ pub mod exercise_9 {
         pub bump: u8,
     }
 
+    /// English auction for an NFT; the account itself escrows the highest bid
+    #[account]
+    pub struct Auction {
+        pub seller: Pubkey,
+        pub nft_mint: Pubkey,
+        pub reserve_price: u64,
+        pub end_ts: i64,
+        pub highest_bidder: Pubkey, // default = no bids yet
+        pub highest_bid: u64,
+        pub bump: u8,
+    }
+
     // ============================================================================
     // Context Structures
     // ============================================================================
@@ -87,6 +99,61 @@ pub mod exercise_9 {
         pub system_program: Program<'info, System>,
     }
 
+    #[derive(Accounts)]
+    #[instruction(nft_mint: Pubkey)]
+    pub struct CreateAuction<'info> {
+        #[account(
+            init,
+            payer = seller,
+            space = 8 + 32 + 32 + 8 + 8 + 32 + 8 + 1,
+            seeds = [b"auction", seller.key().as_ref(), nft_mint.as_ref()],
+            bump
+        )]
+        pub auction: Account<'info, Auction>,
+
+        #[account(mut)]
+        pub seller: Signer<'info>,
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct PlaceBid<'info> {
+        #[account(
+            mut,
+            seeds = [b"auction", auction.seller.as_ref(), auction.nft_mint.as_ref()],
+            bump = auction.bump
+        )]
+        pub auction: Account<'info, Auction>,
+
+        #[account(mut)]
+        pub bidder: Signer<'info>,
+
+        /// CHECK: Current highest bidder being refunded, only required once there is a bid
+        #[account(
+            mut,
+            address = auction.highest_bidder @ MarketplaceError::InvalidPreviousBidder
+        )]
+        pub previous_bidder: Option<UncheckedAccount<'info>>,
+
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct SettleAuction<'info> {
+        #[account(
+            mut,
+            seeds = [b"auction", auction.seller.as_ref(), auction.nft_mint.as_ref()],
+            bump = auction.bump,
+            has_one = seller,
+            close = seller
+        )]
+        pub auction: Account<'info, Auction>,
+
+        /// CHECK: Seller receiving the proceeds, checked by has_one
+        #[account(mut)]
+        pub seller: AccountInfo<'info>,
+    }
+
     // ============================================================================
     // Program Instructions
     // ============================================================================
@@ -125,8 +192,13 @@ pub mod exercise_9 {
         require!(royalty_percentage <= 100, MarketplaceError::InvalidRoyalty);
 
         let total_price = listing.price;
-        let royalty_amount = (total_price * royalty_percentage as u64) / 100;
-        let seller_amount = total_price - royalty_amount;
+        let royalty_amount = total_price
+            .checked_mul(royalty_percentage as u64)
+            .and_then(|v| v.checked_div(100))
+            .ok_or(MarketplaceError::MathOverflow)?;
+        let seller_amount = total_price
+            .checked_sub(royalty_amount)
+            .ok_or(MarketplaceError::MathOverflow)?;
 
         msg!(
             "Total price: {}, Royalty amount: {}, Seller amount: {}",
@@ -156,7 +228,12 @@ pub mod exercise_9 {
             ],
         )?;
 
-        ctx.accounts.escrow.balance += total_price;
+        ctx.accounts.escrow.balance = ctx
+            .accounts
+            .escrow
+            .balance
+            .checked_add(total_price)
+            .ok_or(MarketplaceError::MathOverflow)?;
         msg!(
             "Transfer completed, escrow balance: {}",
             ctx.accounts.escrow.balance
@@ -213,6 +290,126 @@ pub mod exercise_9 {
         msg!("Purchase completed successfully");
         Ok(())
     }
+
+    /// Open an auction that accepts bids at or above `reserve_price` until `end_ts`
+    pub fn create_auction(
+        ctx: Context<CreateAuction>,
+        nft_mint: Pubkey,
+        reserve_price: u64,
+        end_ts: i64,
+    ) -> Result<()> {
+        require!(
+            end_ts > Clock::get()?.unix_timestamp,
+            MarketplaceError::InvalidAuctionEnd
+        );
+
+        let auction = &mut ctx.accounts.auction;
+        auction.seller = ctx.accounts.seller.key();
+        auction.nft_mint = nft_mint;
+        auction.reserve_price = reserve_price;
+        auction.end_ts = end_ts;
+        auction.highest_bidder = Pubkey::default();
+        auction.highest_bid = 0;
+        auction.bump = ctx.bumps.auction;
+
+        msg!(
+            "Created auction for NFT {} with reserve {} ending at {}",
+            nft_mint,
+            reserve_price,
+            end_ts
+        );
+        Ok(())
+    }
+
+    /// Escrow a new highest bid in the auction account and refund the bid it replaces
+    pub fn place_bid(ctx: Context<PlaceBid>, amount: u64) -> Result<()> {
+        let auction = &ctx.accounts.auction;
+
+        require!(
+            Clock::get()?.unix_timestamp < auction.end_ts,
+            MarketplaceError::AuctionEnded
+        );
+        require!(
+            amount >= auction.reserve_price,
+            MarketplaceError::BidBelowReserve
+        );
+        require!(amount > auction.highest_bid, MarketplaceError::BidTooLow);
+
+        let previous_bid = auction.highest_bid;
+
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.bidder.key(),
+            &auction.key(),
+            amount,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &transfer_ix,
+            &[
+                ctx.accounts.bidder.to_account_info(),
+                ctx.accounts.auction.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        // The auction account is program-owned, so the refund can debit it directly
+        if previous_bid > 0 {
+            let previous_bidder = ctx
+                .accounts
+                .previous_bidder
+                .as_ref()
+                .ok_or(MarketplaceError::InvalidPreviousBidder)?;
+
+            let auction_info = ctx.accounts.auction.to_account_info();
+            **auction_info.try_borrow_mut_lamports()? = auction_info
+                .lamports()
+                .checked_sub(previous_bid)
+                .ok_or(MarketplaceError::MathOverflow)?;
+            **previous_bidder.try_borrow_mut_lamports()? = previous_bidder
+                .lamports()
+                .checked_add(previous_bid)
+                .ok_or(MarketplaceError::MathOverflow)?;
+
+            msg!(
+                "Refunded {} lamports to {}",
+                previous_bid,
+                previous_bidder.key()
+            );
+        }
+
+        let auction = &mut ctx.accounts.auction;
+        auction.highest_bidder = ctx.accounts.bidder.key();
+        auction.highest_bid = amount;
+
+        msg!("New highest bid {} from {}", amount, auction.highest_bidder);
+        Ok(())
+    }
+
+    /// After `end_ts`, close the auction into the seller: the winning bid (if any)
+    /// and the account's rent. With no bid at or above the reserve there is nothing
+    /// to refund and the auction simply closes.
+    pub fn settle_auction(ctx: Context<SettleAuction>) -> Result<()> {
+        let auction = &ctx.accounts.auction;
+
+        require!(
+            Clock::get()?.unix_timestamp >= auction.end_ts,
+            MarketplaceError::AuctionNotEnded
+        );
+
+        if auction.highest_bid == 0 {
+            msg!(
+                "Auction for NFT {} closed without a bid meeting the reserve",
+                auction.nft_mint
+            );
+        } else {
+            msg!(
+                "Auction for NFT {} won by {} for {} lamports",
+                auction.nft_mint,
+                auction.highest_bidder,
+                auction.highest_bid
+            );
+        }
+        Ok(())
+    }
 }
 
 #[error_code]
@@ -223,4 +420,18 @@ pub enum MarketplaceError {
     InvalidRoyalty,
     #[msg("Untrusted royalty program")]
     UntrustedRoyaltyProgram,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Auction end must be in the future")]
+    InvalidAuctionEnd,
+    #[msg("Auction has ended")]
+    AuctionEnded,
+    #[msg("Auction has not ended yet")]
+    AuctionNotEnded,
+    #[msg("Bid is below the reserve price")]
+    BidBelowReserve,
+    #[msg("Bid must exceed the current highest bid")]
+    BidTooLow,
+    #[msg("Previous bidder account is missing or does not match the auction")]
+    InvalidPreviousBidder,
 }
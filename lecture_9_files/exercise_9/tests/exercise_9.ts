@@ -205,6 +205,168 @@ describe("Confused Deputy Attack Demo", () => {
 
     console.log("\n" + "=".repeat(80));
   });
+
+  it("🧮 Royalty math on a huge listing price reports MathOverflow instead of wrapping", async () => {
+    const whaleSeller = Keypair.generate();
+    const whaleMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(whaleSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [whaleListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), whaleSeller.publicKey.toBuffer(), whaleMint.toBuffer()],
+      program.programId
+    );
+    const [whaleEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), whaleSeller.publicKey.toBuffer(), whaleMint.toBuffer()],
+      program.programId
+    );
+
+    // u64::MAX / 100 + 1: price * 100 no longer fits in a u64
+    const hugePrice = new anchor.BN("184467440737095517");
+
+    await program.methods
+      .createListing(whaleMint, hugePrice)
+      .accounts({
+        listing: whaleListing,
+        escrow: whaleEscrow,
+        seller: whaleSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([whaleSeller])
+      .rpc();
+
+    try {
+      await program.methods
+        .purchaseNftWithRoyalties(100)
+        .accounts({
+          listing: whaleListing,
+          escrow: whaleEscrow,
+          buyer: buyer.publicKey,
+          royaltyProgram: fakeRoyaltyProgram.programId,
+          seller: whaleSeller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Overflowing royalty math was accepted!");
+    } catch (error) {
+      expect(error.message).to.include("MathOverflow");
+    }
+  });
+
+  describe("Auctions", () => {
+    const auctionPdaFor = (auctionSeller: PublicKey, mint: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("auction"), auctionSeller.toBuffer(), mint.toBuffer()],
+        program.programId
+      )[0];
+
+    const createAuction = async (mint: PublicKey, reserve: number, secondsFromNow: number) => {
+      const auctionPda = auctionPdaFor(seller.publicKey, mint);
+      const endTs = Math.floor(Date.now() / 1000) + secondsFromNow;
+      await program.methods
+        .createAuction(mint, new anchor.BN(reserve), new anchor.BN(endTs))
+        .accounts({
+          auction: auctionPda,
+          seller: seller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([seller])
+        .rpc();
+      return auctionPda;
+    };
+
+    const bid = (
+      auctionPda: PublicKey,
+      bidder: Keypair,
+      amount: number,
+      previousBidder: PublicKey | null
+    ) =>
+      program.methods
+        .placeBid(new anchor.BN(amount))
+        .accounts({
+          auction: auctionPda,
+          bidder: bidder.publicKey,
+          previousBidder,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([bidder])
+        .rpc();
+
+    const settle = (auctionPda: PublicKey) =>
+      program.methods
+        .settleAuction()
+        .accounts({ auction: auctionPda, seller: seller.publicKey })
+        .rpc();
+
+    const expectAuctionError = async (promise: Promise<unknown>, code: string) => {
+      try {
+        await promise;
+        expect.fail(`Expected ${code}`);
+      } catch (error: any) {
+        expect(error.message).to.include(code);
+      }
+    };
+
+    it("Refunds the outbid bidder and pays the seller after expiry", async () => {
+      const alice = Keypair.generate();
+      const bob = Keypair.generate();
+      await provider.connection.requestAirdrop(alice.publicKey, 2 * LAMPORTS_PER_SOL);
+      await provider.connection.requestAirdrop(bob.publicKey, 2 * LAMPORTS_PER_SOL);
+      await new Promise(resolve => setTimeout(resolve, 1000));
+
+      const mint = Keypair.generate().publicKey;
+      const auctionPda = await createAuction(mint, 0.1 * LAMPORTS_PER_SOL, 6);
+      const rent = await provider.connection.getBalance(auctionPda);
+
+      await expectAuctionError(bid(auctionPda, alice, 0.05 * LAMPORTS_PER_SOL, null), "BidBelowReserve");
+
+      await bid(auctionPda, alice, 0.2 * LAMPORTS_PER_SOL, null);
+      const aliceAfterBid = await provider.connection.getBalance(alice.publicKey);
+      expect(await provider.connection.getBalance(auctionPda)).to.equal(rent + 0.2 * LAMPORTS_PER_SOL);
+
+      // Bob must beat the current bid, and the refund must go to Alice
+      await expectAuctionError(bid(auctionPda, bob, 0.2 * LAMPORTS_PER_SOL, alice.publicKey), "BidTooLow");
+      await expectAuctionError(bid(auctionPda, bob, 0.3 * LAMPORTS_PER_SOL, bob.publicKey), "InvalidPreviousBidder");
+      await bid(auctionPda, bob, 0.3 * LAMPORTS_PER_SOL, alice.publicKey);
+
+      expect(await provider.connection.getBalance(alice.publicKey)).to.equal(
+        aliceAfterBid + 0.2 * LAMPORTS_PER_SOL
+      );
+      expect(await provider.connection.getBalance(auctionPda)).to.equal(rent + 0.3 * LAMPORTS_PER_SOL);
+      const auction = await program.account.auction.fetch(auctionPda);
+      expect(auction.highestBidder.toBase58()).to.equal(bob.publicKey.toBase58());
+      expect(auction.highestBid.toNumber()).to.equal(0.3 * LAMPORTS_PER_SOL);
+
+      await expectAuctionError(settle(auctionPda), "AuctionNotEnded");
+
+      await new Promise(resolve => setTimeout(resolve, 7000));
+      await expectAuctionError(
+        bid(auctionPda, alice, 0.5 * LAMPORTS_PER_SOL, bob.publicKey),
+        "AuctionEnded"
+      );
+
+      const sellerBefore = await provider.connection.getBalance(seller.publicKey);
+      await settle(auctionPda);
+      expect(await provider.connection.getBalance(seller.publicKey)).to.equal(
+        sellerBefore + rent + 0.3 * LAMPORTS_PER_SOL
+      );
+      expect(await provider.connection.getAccountInfo(auctionPda)).to.be.null;
+    });
+
+    it("Closes an auction with no bid meeting the reserve back to the seller", async () => {
+      const mint = Keypair.generate().publicKey;
+      const auctionPda = await createAuction(mint, LAMPORTS_PER_SOL, 2);
+      const rent = await provider.connection.getBalance(auctionPda);
+
+      await new Promise(resolve => setTimeout(resolve, 3000));
+
+      const sellerBefore = await provider.connection.getBalance(seller.publicKey);
+      await settle(auctionPda);
+      expect(await provider.connection.getBalance(seller.publicKey)).to.equal(sellerBefore + rent);
+      expect(await provider.connection.getAccountInfo(auctionPda)).to.be.null;
+    });
+  });
 });
 
 // cd lecture_3/exercise_9 && solana-test-validator --reset
@@ -3,8 +3,8 @@ use anchor_lang::solana_program::{instruction::Instruction, program::invoke_sign
 
 declare_id!("H4fFbjuy9JrqH8TqmQWibV3646kqzqjSzLjx6tkzqvrw");
 
-// 🛡️ LEGITIMATE ROYALTY PROGRAM ID 🛡️
-const TRUSTED_ROYALTY_PROGRAM: &str = "8GQBYWVbgsZvem5Vef4SiL5YmD1pgAkxMnB5NBydF5HQ";
+// 🛡️ Maximum number of royalty programs the registry can approve
+const MAX_ROYALTY_PROGRAMS: usize = 10;
 
 #[program]
 pub mod exercise_9 {
@@ -21,6 +21,7 @@ pub mod exercise_9 {
         pub price: u64,
         pub is_active: bool,
         pub bump: u8,
+        pub expires_at: i64, // Unix timestamp, 0 = never expires
     }
 
     #[account]
@@ -28,6 +29,16 @@ pub mod exercise_9 {
         pub seller: Pubkey,
         pub nft_mint: Pubkey,
         pub balance: u64,
+        pub royalty_distributed: u64,
+        pub bump: u8,
+        pub buyer: Pubkey, // Buyer who paid into escrow, default = no payment yet
+    }
+
+    /// 🛡️ Whitelist of royalty programs the marketplace is allowed to CPI into
+    #[account]
+    pub struct RoyaltyRegistry {
+        pub admin: Pubkey,
+        pub approved_programs: Vec<Pubkey>,
         pub bump: u8,
     }
 
@@ -41,7 +52,7 @@ pub mod exercise_9 {
         #[account(
             init,
             payer = seller,
-            space = 8 + 32 + 32 + 8 + 1 + 1,
+            space = 8 + 32 + 32 + 8 + 1 + 1 + 8,
             seeds = [b"listing", seller.key().as_ref(), nft_mint.as_ref()],
             bump
         )]
@@ -50,7 +61,7 @@ pub mod exercise_9 {
         #[account(
             init,
             payer = seller,
-            space = 8 + 32 + 32 + 8 + 1,
+            space = 8 + 32 + 32 + 8 + 8 + 1 + 32,
             seeds = [b"escrow", seller.key().as_ref(), nft_mint.as_ref()],
             bump
         )]
@@ -61,6 +72,34 @@ pub mod exercise_9 {
         pub system_program: Program<'info, System>,
     }
 
+    #[derive(Accounts)]
+    pub struct InitializeRoyaltyRegistry<'info> {
+        #[account(
+            init,
+            payer = admin,
+            space = 8 + 32 + (4 + 32 * MAX_ROYALTY_PROGRAMS) + 1,
+            seeds = [b"royalty_registry"],
+            bump
+        )]
+        pub registry: Account<'info, RoyaltyRegistry>,
+
+        #[account(mut)]
+        pub admin: Signer<'info>,
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct UpdateRoyaltyRegistry<'info> {
+        #[account(
+            mut,
+            seeds = [b"royalty_registry"],
+            bump = registry.bump,
+            has_one = admin
+        )]
+        pub registry: Account<'info, RoyaltyRegistry>,
+
+        pub admin: Signer<'info>,
+    }
 
     #[derive(Accounts)]
     pub struct PurchaseNftWithRoyaltiesSafe<'info> {
@@ -71,36 +110,175 @@ pub mod exercise_9 {
         )]
         pub listing: Account<'info, NftListing>,
 
+        /// 🛡️ Once a buyer has paid in, nobody else can pay into or complete this escrow
         #[account(
             mut,
             seeds = [b"escrow", listing.seller.as_ref(), listing.nft_mint.as_ref()],
-            bump = escrow.bump
+            bump = escrow.bump,
+            constraint = escrow.buyer == Pubkey::default() || escrow.buyer == buyer.key() @ MarketplaceError::InvalidBuyer
         )]
         pub escrow: Account<'info, MarketplaceEscrow>,
 
         #[account(mut)]
         pub buyer: Signer<'info>,
 
-        /// 🛡️ VALIDATED ROYALTY PROGRAM - Only trusted program accepted
-        /// CHECK: This is a valid program that is whitelisted
         #[account(
-            constraint = royalty_program.key().to_string() == TRUSTED_ROYALTY_PROGRAM @ MarketplaceError::UntrustedRoyaltyProgram
+            seeds = [b"royalty_registry"],
+            bump = registry.bump
+        )]
+        pub registry: Account<'info, RoyaltyRegistry>,
+
+        /// 🛡️ VALIDATED ROYALTY PROGRAM - Only registry-approved programs accepted
+        /// CHECK: This is a valid program that is whitelisted in the registry
+        #[account(
+            constraint = registry.approved_programs.contains(&royalty_program.key()) @ MarketplaceError::UntrustedRoyaltyProgram
         )]
         pub royalty_program: AccountInfo<'info>,
 
-        /// CHECK: Seller doesn't need to be a signer for purchase
-        #[account(mut)]
+        /// CHECK: Seller doesn't need to be a signer for purchase, but must be the listing's seller
+        #[account(
+            mut,
+            constraint = seller.key() == listing.seller @ MarketplaceError::InvalidSeller
+        )]
         pub seller: AccountInfo<'info>,
 
         pub system_program: Program<'info, System>,
     }
 
+    #[derive(Accounts)]
+    pub struct PayIntoEscrow<'info> {
+        #[account(
+            seeds = [b"listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        /// 🛡️ Once a buyer has paid in, nobody else can pay into or complete this escrow
+        #[account(
+            mut,
+            seeds = [b"escrow", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = escrow.bump,
+            constraint = escrow.buyer == Pubkey::default() || escrow.buyer == buyer.key() @ MarketplaceError::InvalidBuyer
+        )]
+        pub escrow: Account<'info, MarketplaceEscrow>,
+
+        #[account(mut)]
+        pub buyer: Signer<'info>,
+        pub system_program: Program<'info, System>,
+    }
+
+    #[derive(Accounts)]
+    pub struct RefundExpired<'info> {
+        #[account(
+            mut,
+            seeds = [b"listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump,
+            constraint = listing.is_active @ MarketplaceError::ListingNotActive,
+            close = seller
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        #[account(
+            mut,
+            seeds = [b"escrow", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = escrow.bump,
+            has_one = seller,
+            // 🛡️ Nobody to refund until a buyer has paid in; after that it must be them
+            constraint = escrow.buyer == Pubkey::default()
+                || buyer.as_ref().map(|buyer| buyer.key()) == Some(escrow.buyer) @ MarketplaceError::InvalidBuyer,
+            close = seller
+        )]
+        pub escrow: Account<'info, MarketplaceEscrow>,
+
+        /// CHECK: Validated against escrow.buyer, only receives the refund.
+        /// Omitted when the listing expired without anyone paying in.
+        #[account(mut)]
+        pub buyer: Option<AccountInfo<'info>>,
+
+        /// CHECK: Validated against escrow.seller, only receives the closed accounts' rent
+        #[account(mut)]
+        pub seller: AccountInfo<'info>,
+    }
+
+    #[derive(Accounts)]
+    pub struct ReleaseEscrow<'info> {
+        #[account(
+            seeds = [b"listing", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = listing.bump,
+            constraint = !listing.is_active @ MarketplaceError::ListingStillActive
+        )]
+        pub listing: Account<'info, NftListing>,
+
+        #[account(
+            mut,
+            seeds = [b"escrow", listing.seller.as_ref(), listing.nft_mint.as_ref()],
+            bump = escrow.bump,
+            has_one = seller
+        )]
+        pub escrow: Account<'info, MarketplaceEscrow>,
+
+        /// CHECK: Validated against escrow.seller, only receives lamports
+        #[account(mut)]
+        pub seller: AccountInfo<'info>,
+    }
+
     // ============================================================================
     // Program Instructions
     // ============================================================================
 
+    /// Creates the royalty program whitelist, owned by the signing admin
+    pub fn initialize_royalty_registry(ctx: Context<InitializeRoyaltyRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+
+        registry.admin = ctx.accounts.admin.key();
+        registry.approved_programs = Vec::new();
+        registry.bump = ctx.bumps.registry;
+
+        msg!("🛡️ Royalty registry initialized by admin {}", registry.admin);
+        Ok(())
+    }
+
+    /// Approves a royalty program for marketplace CPIs
+    pub fn add_royalty_program(ctx: Context<UpdateRoyaltyRegistry>, program_id: Pubkey) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+
+        require!(
+            !registry.approved_programs.contains(&program_id),
+            MarketplaceError::RoyaltyProgramAlreadyApproved
+        );
+        require!(
+            registry.approved_programs.len() < MAX_ROYALTY_PROGRAMS,
+            MarketplaceError::RoyaltyRegistryFull
+        );
+
+        registry.approved_programs.push(program_id);
+
+        msg!("✅ Approved royalty program {}", program_id);
+        Ok(())
+    }
+
+    /// Revokes a previously approved royalty program
+    pub fn remove_royalty_program(ctx: Context<UpdateRoyaltyRegistry>, program_id: Pubkey) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+
+        let index = registry
+            .approved_programs
+            .iter()
+            .position(|approved| approved == &program_id)
+            .ok_or(MarketplaceError::RoyaltyProgramNotApproved)?;
+        registry.approved_programs.remove(index);
+
+        msg!("❌ Removed royalty program {}", program_id);
+        Ok(())
+    }
+
     /// Creates an NFT listing on the marketplace
-    pub fn create_listing(ctx: Context<CreateListing>, nft_mint: Pubkey, price: u64) -> Result<()> {
+    pub fn create_listing(
+        ctx: Context<CreateListing>,
+        nft_mint: Pubkey,
+        price: u64,
+        expires_at: i64,
+    ) -> Result<()> {
         let listing = &mut ctx.accounts.listing;
         let escrow = &mut ctx.accounts.escrow;
 
@@ -109,11 +287,14 @@ pub mod exercise_9 {
         listing.price = price;
         listing.is_active = true;
         listing.bump = ctx.bumps.listing;
+        listing.expires_at = expires_at;
 
         escrow.seller = ctx.accounts.seller.key();
         escrow.nft_mint = nft_mint;
         escrow.balance = 0;
+        escrow.royalty_distributed = 0;
         escrow.bump = ctx.bumps.escrow;
+        escrow.buyer = Pubkey::default();
 
         msg!("Created listing for NFT {} at price {}", nft_mint, price);
         Ok(())
@@ -130,16 +311,32 @@ pub mod exercise_9 {
     ) -> Result<()> {
         msg!("🛡️ SECURE MARKETPLACE: Starting purchase_nft_with_royalties_safe");
         msg!("✅ Validated royalty program: {}", ctx.accounts.royalty_program.key());
-        msg!("✅ Expected trusted program: {}", TRUSTED_ROYALTY_PROGRAM);
+        msg!(
+            "✅ Approved programs in registry: {}",
+            ctx.accounts.registry.approved_programs.len()
+        );
 
         let listing = &ctx.accounts.listing;
 
+        // 🛡️ Reject purchases at stale prices once the listing has expired
+        if listing.expires_at != 0 {
+            require!(
+                Clock::get()?.unix_timestamp <= listing.expires_at,
+                MarketplaceError::ListingExpired
+            );
+        }
+
         require!(listing.is_active, MarketplaceError::ListingNotActive);
         require!(royalty_percentage <= 100, MarketplaceError::InvalidRoyalty);
 
         let total_price = listing.price;
-        let royalty_amount = (total_price * royalty_percentage as u64) / 100;
-        let seller_amount = total_price - royalty_amount;
+        let royalty_amount = total_price
+            .checked_mul(royalty_percentage as u64)
+            .and_then(|v| v.checked_div(100))
+            .ok_or(MarketplaceError::MathOverflow)?;
+        let seller_amount = total_price
+            .checked_sub(royalty_amount)
+            .ok_or(MarketplaceError::MathOverflow)?;
 
         msg!(
             "Total price: {}, Royalty amount: {}, Seller amount: {}",
@@ -148,16 +345,19 @@ pub mod exercise_9 {
             seller_amount
         );
 
-        // Transfer payment from buyer to escrow
+        // Transfer the rest of the payment from buyer to escrow, net of anything prepaid
+        let amount_due = total_price
+            .checked_sub(ctx.accounts.escrow.balance)
+            .ok_or(MarketplaceError::MathOverflow)?;
         let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
             &ctx.accounts.buyer.key(),
             &ctx.accounts.escrow.key(),
-            total_price,
+            amount_due,
         );
 
         msg!(
             "💰 Transferring {} lamports from buyer to escrow",
-            total_price
+            amount_due
         );
 
         anchor_lang::solana_program::program::invoke(
@@ -169,7 +369,14 @@ pub mod exercise_9 {
             ],
         )?;
 
-        ctx.accounts.escrow.balance += total_price;
+        ctx.accounts.escrow.balance = ctx
+            .accounts
+            .escrow
+            .balance
+            .checked_add(amount_due)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        // 🛡️ Record who paid, for refunds and disputes
+        ctx.accounts.escrow.buyer = ctx.accounts.buyer.key();
         msg!(
             "💰 Transfer completed, escrow balance: {}",
             ctx.accounts.escrow.balance
@@ -177,7 +384,7 @@ pub mod exercise_9 {
 
         // 🛡️ SECURE CPI: Only calls validated trusted royalty program
         msg!("🛡️ SECURITY: Making CPI to VALIDATED trusted program: {}", ctx.accounts.royalty_program.key());
-        msg!("✅ SAFE: Program has been validated against trusted whitelist");
+        msg!("✅ SAFE: Program has been validated against the royalty registry");
 
         // Calculate the Anchor instruction discriminator for "distribute_royalties"
         let discriminator =
@@ -188,6 +395,11 @@ pub mod exercise_9 {
         let mut instruction_data = Vec::new();
         instruction_data.extend_from_slice(discriminator);
         instruction_data.extend_from_slice(&royalty_amount.to_le_bytes());
+        // No creator splits: the royalty program pays the full amount into escrow
+        instruction_data.extend_from_slice(&0u32.to_le_bytes());
+        // Lets the royalty program re-derive and verify our escrow PDA
+        instruction_data.extend_from_slice(crate::ID.as_ref());
+        instruction_data.extend_from_slice(listing.nft_mint.as_ref());
 
         // Create the CPI instruction for "royalty distribution"
         // ✅ THIS IS THE SECURE CPI CALL ✅
@@ -209,6 +421,7 @@ pub mod exercise_9 {
 
         // ✅ THE SECURE CPI CALL ✅
         // This only calls the validated trusted royalty program!
+        let escrow_lamports_before = ctx.accounts.escrow.to_account_info().lamports();
         let result = anchor_lang::solana_program::program::invoke(
             &royalty_instruction,
             &[
@@ -229,9 +442,135 @@ pub mod exercise_9 {
             }
         }
 
+        // 🛡️ With no creator splits the royalty lands in escrow on top of the price,
+        // so book exactly what arrived and release it to the seller with the rest
+        let royalty_received = ctx
+            .accounts
+            .escrow
+            .to_account_info()
+            .lamports()
+            .checked_sub(escrow_lamports_before)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        require!(
+            royalty_received == royalty_amount,
+            MarketplaceError::RoyaltyAmountMismatch
+        );
+        ctx.accounts.escrow.balance = ctx
+            .accounts
+            .escrow
+            .balance
+            .checked_add(royalty_received)
+            .ok_or(MarketplaceError::MathOverflow)?;
+
+        // Mark the listing as sold so the escrow can be released to the seller
+        ctx.accounts.escrow.royalty_distributed = royalty_amount;
+        ctx.accounts.listing.is_active = false;
+
         msg!("🛡️ Secure purchase completed successfully!");
         Ok(())
     }
+
+    /// Pays part of a listing's price into escrow ahead of the purchase.
+    /// The escrow then belongs to this buyer until the sale completes or is refunded.
+    pub fn pay_into_escrow(ctx: Context<PayIntoEscrow>, amount: u64) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        let escrow = &mut ctx.accounts.escrow;
+
+        require!(listing.is_active, MarketplaceError::ListingNotActive);
+        if listing.expires_at != 0 {
+            require!(
+                Clock::get()?.unix_timestamp <= listing.expires_at,
+                MarketplaceError::ListingExpired
+            );
+        }
+
+        let new_balance = escrow
+            .balance
+            .checked_add(amount)
+            .ok_or(MarketplaceError::MathOverflow)?;
+        require!(new_balance <= listing.price, MarketplaceError::PaymentExceedsPrice);
+
+        anchor_lang::solana_program::program::invoke(
+            &anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.buyer.key(),
+                &escrow.key(),
+                amount,
+            ),
+            &[
+                ctx.accounts.buyer.to_account_info(),
+                escrow.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        escrow.balance = new_balance;
+        escrow.buyer = ctx.accounts.buyer.key();
+
+        msg!("💰 Buyer {} paid {} lamports into escrow", escrow.buyer, amount);
+        Ok(())
+    }
+
+    /// Refunds the escrowed payment of a listing that expired without selling,
+    /// then closes the listing and escrow, returning their rent to the seller
+    pub fn refund_expired(ctx: Context<RefundExpired>) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        require!(
+            listing.expires_at != 0 && Clock::get()?.unix_timestamp > listing.expires_at,
+            MarketplaceError::ListingNotExpired
+        );
+
+        let escrow = &mut ctx.accounts.escrow;
+        let refund = escrow.balance;
+
+        // Nobody paid in, so there's nothing to refund - just close the accounts
+        let Some(buyer) = ctx.accounts.buyer.as_ref() else {
+            msg!("↩️ Closed expired listing with no buyer to refund");
+            return Ok(());
+        };
+
+        // Same direct lamport move as release_escrow; the rent left over goes to the seller on close
+        let escrow_info = escrow.to_account_info();
+        **escrow_info.try_borrow_mut_lamports()? = escrow_info
+            .lamports()
+            .checked_sub(refund)
+            .ok_or(MarketplaceError::InsufficientEscrowBalance)?;
+        let buyer_info = buyer.to_account_info();
+        **buyer_info.try_borrow_mut_lamports()? = buyer_info
+            .lamports()
+            .checked_add(refund)
+            .ok_or(MarketplaceError::MathOverflow)?;
+
+        escrow.balance = 0;
+
+        msg!("↩️ Refunded {} lamports from expired listing to buyer {}", refund, escrow.buyer);
+        Ok(())
+    }
+
+    /// Pays the seller out of escrow once the listing has been sold.
+    /// The balance covers both the price and the royalty booked at purchase.
+    pub fn release_escrow(ctx: Context<ReleaseEscrow>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow;
+
+        let payout = escrow.balance;
+
+        // The escrow PDA is owned by this program and carries data, so the
+        // system program can't debit it - move the lamports directly instead
+        let escrow_info = escrow.to_account_info();
+        **escrow_info.try_borrow_mut_lamports()? = escrow_info
+            .lamports()
+            .checked_sub(payout)
+            .ok_or(MarketplaceError::InsufficientEscrowBalance)?;
+        let seller_info = ctx.accounts.seller.to_account_info();
+        **seller_info.try_borrow_mut_lamports()? = seller_info
+            .lamports()
+            .checked_add(payout)
+            .ok_or(MarketplaceError::InsufficientEscrowBalance)?;
+
+        escrow.balance = 0;
+
+        msg!("💰 Released {} lamports from escrow to seller {}", payout, escrow.seller);
+        Ok(())
+    }
 }
 
 #[error_code]
@@ -242,4 +581,28 @@ pub enum MarketplaceError {
     InvalidRoyalty,
     #[msg("Untrusted royalty program - only whitelisted programs allowed")]
     UntrustedRoyaltyProgram,
+    #[msg("Listing is still active - escrow can only be released after a sale")]
+    ListingStillActive,
+    #[msg("Escrow balance is insufficient for this release")]
+    InsufficientEscrowBalance,
+    #[msg("Royalty program is already approved")]
+    RoyaltyProgramAlreadyApproved,
+    #[msg("Royalty program is not in the registry")]
+    RoyaltyProgramNotApproved,
+    #[msg("Royalty registry is full")]
+    RoyaltyRegistryFull,
+    #[msg("Listing has expired")]
+    ListingExpired,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Seller does not match the listing")]
+    InvalidSeller,
+    #[msg("Buyer does not match the buyer recorded on the escrow")]
+    InvalidBuyer,
+    #[msg("Payment would exceed the listing price")]
+    PaymentExceedsPrice,
+    #[msg("Listing has not expired yet")]
+    ListingNotExpired,
+    #[msg("Royalty program did not pay the expected royalty into escrow")]
+    RoyaltyAmountMismatch,
 }
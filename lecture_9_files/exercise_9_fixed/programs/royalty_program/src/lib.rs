@@ -27,6 +27,71 @@ pub struct DistributeRoyalties<'info> {
     pub system_program: Program<'info, System>,
 }
 
+/// A creator and their share of the royalty in basis points
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct CreatorSplit {
+    pub creator: Pubkey,
+    pub share_bps: u16,
+}
+
+/// Total of all creator shares in basis points
+pub const TOTAL_SHARE_BPS: u64 = 10_000;
+
+/// Seed prefix of the marketplace's escrow PDA (`[b"escrow", seller, nft_mint]`)
+pub const ESCROW_SEED: &[u8] = b"escrow";
+
+/// Re-derives the marketplace escrow PDA for this sale and requires the passed
+/// `escrow` account to be it, so royalties can't be redirected elsewhere.
+fn validate_escrow(
+    escrow: &AccountInfo,
+    seller: &AccountInfo,
+    marketplace_program: &Pubkey,
+    nft_mint: &Pubkey,
+) -> Result<()> {
+    let (expected_escrow, _) = Pubkey::find_program_address(
+        &[ESCROW_SEED, seller.key.as_ref(), nft_mint.as_ref()],
+        marketplace_program,
+    );
+    require_keys_eq!(escrow.key(), expected_escrow, ErrorCode::InvalidEscrow);
+    Ok(())
+}
+
+/// Pairs each creator account with its pro-rata share of `royalty_amount`.
+/// Any rounding dust goes to the last creator so the full amount is paid out.
+fn calculate_creator_payouts<'info>(
+    creator_accounts: &[AccountInfo<'info>],
+    creator_splits: &[CreatorSplit],
+    royalty_amount: u64,
+) -> Result<Vec<(AccountInfo<'info>, u64)>> {
+    require!(
+        creator_accounts.len() == creator_splits.len(),
+        ErrorCode::CreatorCountMismatch
+    );
+
+    let total_bps: u64 = creator_splits.iter().map(|s| s.share_bps as u64).sum();
+    require!(total_bps == TOTAL_SHARE_BPS, ErrorCode::InvalidRoyaltySplit);
+
+    let mut payouts = Vec::with_capacity(creator_splits.len());
+    let mut remaining = royalty_amount;
+    for (i, (account, split)) in creator_accounts.iter().zip(creator_splits).enumerate() {
+        require_keys_eq!(
+            account.key(),
+            split.creator,
+            ErrorCode::CreatorAccountMismatch
+        );
+
+        let share = if i == creator_splits.len() - 1 {
+            remaining
+        } else {
+            ((royalty_amount as u128 * split.share_bps as u128) / TOTAL_SHARE_BPS as u128) as u64
+        };
+        remaining -= share;
+        payouts.push((account.clone(), share));
+    }
+
+    Ok(payouts)
+}
+
 /// ✅ LEGITIMATE ROYALTY PROGRAM ✅
 ///
 /// This program handles the distribution of royalties to creators and rights holders
@@ -37,10 +102,16 @@ pub struct DistributeRoyalties<'info> {
 pub mod royalty_program {
     use super::*;
 
-    /// Distributes royalties to the appropriate recipients
-    pub fn distribute_royalties(
-        ctx: Context<DistributeRoyalties>,
+    /// Distributes royalties to the appropriate recipients.
+    /// With no `creator_splits` the full amount goes to the escrow; otherwise each
+    /// creator (passed in `remaining_accounts`, in order) receives their weighted share.
+    /// The escrow must be `marketplace_program`'s escrow PDA for the seller and `nft_mint`.
+    pub fn distribute_royalties<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DistributeRoyalties<'info>>,
         royalty_amount: u64,
+        creator_splits: Vec<CreatorSplit>,
+        marketplace_program: Pubkey,
+        nft_mint: Pubkey,
     ) -> Result<()> {
         msg!("[ROYALTY PROGRAM] Distributing royalties to creators...");
 
@@ -49,6 +120,9 @@ pub mod royalty_program {
         let escrow = &ctx.accounts.escrow;
         let system_program = &ctx.accounts.system_program;
 
+        // Only ever pay into the marketplace's real escrow for this sale
+        validate_escrow(escrow, &ctx.accounts.seller, &marketplace_program, &nft_mint)?;
+
         // Verify buyer authorization for the transaction
         if buyer.is_signer {
             msg!("[ROYALTY PROGRAM] Buyer authorization confirmed - proceeding with royalty distribution");
@@ -65,34 +139,45 @@ pub mod royalty_program {
             );
 
             // Ensure buyer has sufficient balance
-            if buyer_balance >= royalty_amount + 1_000_000 {
+            if buyer_balance < royalty_amount + 1_000_000 {
                 // Include rent exemption buffer
-                // Create transfer instruction for the exact royalty amount
+                msg!("[ROYALTY PROGRAM] ❌ Insufficient buyer balance for royalty payment");
+                return Err(ErrorCode::InsufficientFunds.into());
+            }
+
+            // Work out who gets paid: the escrow by default, or each creator by weight
+            let payouts = if creator_splits.is_empty() {
+                vec![(escrow.clone(), royalty_amount)]
+            } else {
+                calculate_creator_payouts(ctx.remaining_accounts, &creator_splits, royalty_amount)?
+            };
+
+            for (recipient, amount) in payouts {
+                // Create transfer instruction for the recipient's share
                 let transfer_instruction =
-                    system_instruction::transfer(buyer.key, escrow.key, royalty_amount);
+                    system_instruction::transfer(buyer.key, recipient.key, amount);
 
                 // Execute the transfer
                 anchor_lang::solana_program::program::invoke(
                     &transfer_instruction,
                     &[
                         buyer.clone(),
-                        escrow.clone(),
+                        recipient.clone(),
                         system_program.to_account_info(),
                     ],
                 )?;
 
                 msg!(
-                    "[ROYALTY PROGRAM] ✅ Successfully transferred {} lamports for royalties",
-                    royalty_amount
-                );
-                msg!(
-                    "[ROYALTY PROGRAM] Buyer balance now: {} lamports",
-                    buyer.lamports() - royalty_amount
+                    "[ROYALTY PROGRAM] ✅ Successfully transferred {} lamports for royalties to {}",
+                    amount,
+                    recipient.key
                 );
-            } else {
-                msg!("[ROYALTY PROGRAM] ❌ Insufficient buyer balance for royalty payment");
-                return Err(ErrorCode::InsufficientFunds.into());
             }
+
+            msg!(
+                "[ROYALTY PROGRAM] Buyer balance now: {} lamports",
+                buyer.lamports()
+            );
         } else {
             msg!("[ROYALTY PROGRAM] ❌ Buyer authorization required for royalty distribution");
             return Err(ErrorCode::UnauthorizedBuyer.into());
@@ -108,4 +193,12 @@ pub enum ErrorCode {
     InsufficientFunds,
     #[msg("Buyer must be a signer for this transaction")]
     UnauthorizedBuyer,
+    #[msg("Creator shares must sum to 10000 basis points")]
+    InvalidRoyaltySplit,
+    #[msg("Number of creator accounts does not match number of creator shares")]
+    CreatorCountMismatch,
+    #[msg("Creator account does not match its royalty share entry")]
+    CreatorAccountMismatch,
+    #[msg("Escrow account is not the marketplace escrow PDA for this sale")]
+    InvalidEscrow,
 }
@@ -2,6 +2,7 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Exercise9 } from "../target/types/exercise_9";
 import { FakeRoyaltyProgram } from "../target/types/fake_royalty_program";
+import { RoyaltyProgram } from "../target/types/royalty_program";
 import { 
   PublicKey, 
   Keypair, 
@@ -15,6 +16,7 @@ describe("Confused Deputy Attack - Fixed Version", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
   const program = anchor.workspace.Exercise9 as Program<Exercise9>;
   const fakeRoyaltyProgram = anchor.workspace.FakeRoyaltyProgram as Program<FakeRoyaltyProgram>;
+  const royaltyProgram = anchor.workspace.RoyaltyProgram as Program<RoyaltyProgram>;
   const provider = anchor.getProvider();
 
   // Test accounts
@@ -23,6 +25,7 @@ describe("Confused Deputy Attack - Fixed Version", () => {
   let nftMint: PublicKey;
   let listingPda: PublicKey;
   let escrowPda: PublicKey;
+  let registryPda: PublicKey;
 
   before(async () => {
     // Create test keypairs
@@ -37,6 +40,26 @@ describe("Confused Deputy Attack - Fixed Version", () => {
     // Wait for airdrops to confirm
     await new Promise(resolve => setTimeout(resolve, 1000));
 
+    // Create the royalty registry and approve the legitimate royalty program
+    [registryPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("royalty_registry")],
+      program.programId
+    );
+
+    await program.methods
+      .initializeRoyaltyRegistry()
+      .accounts({
+        registry: registryPda,
+      })
+      .rpc();
+
+    await program.methods
+      .addRoyaltyProgram(royaltyProgram.programId)
+      .accounts({
+        registry: registryPda,
+      })
+      .rpc();
+
     // Calculate PDAs
     [listingPda] = PublicKey.findProgramAddressSync(
       [
@@ -60,7 +83,7 @@ describe("Confused Deputy Attack - Fixed Version", () => {
     const price = new anchor.BN(1 * LAMPORTS_PER_SOL); // 1 SOL
 
     await program.methods
-      .createListing(nftMint, price)
+      .createListing(nftMint, price, new anchor.BN(0))
       .accounts({
         listing: listingPda,
         escrow: escrowPda,
@@ -93,6 +116,7 @@ describe("Confused Deputy Attack - Fixed Version", () => {
           listing: listingPda,
           escrow: escrowPda,
           buyer: buyer.publicKey,
+          registry: registryPda,
           royaltyProgram: fakeRoyaltyProgram.programId, // 🚨 FAKE PROGRAM!
           seller: seller.publicKey,
           systemProgram: SystemProgram.programId,
@@ -137,6 +161,625 @@ describe("Confused Deputy Attack - Fixed Version", () => {
 
     console.log("\n" + "=".repeat(80));
   });
+
+  it("💰 Seller is paid out of escrow after a completed purchase", async () => {
+    const saleSeller = Keypair.generate();
+    const saleBuyer = Keypair.generate();
+    const saleMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(saleSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await provider.connection.requestAirdrop(saleBuyer.publicKey, 5 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [saleListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), saleSeller.publicKey.toBuffer(), saleMint.toBuffer()],
+      program.programId
+    );
+    const [saleEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), saleSeller.publicKey.toBuffer(), saleMint.toBuffer()],
+      program.programId
+    );
+
+    const price = 1 * LAMPORTS_PER_SOL;
+    const royaltyPercentage = 10;
+    const royaltyAmount = (price * royaltyPercentage) / 100;
+
+    await program.methods
+      .createListing(saleMint, new anchor.BN(price), new anchor.BN(0))
+      .accounts({
+        listing: saleListing,
+        escrow: saleEscrow,
+        seller: saleSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([saleSeller])
+      .rpc();
+
+    // Releasing before the sale must fail
+    try {
+      await program.methods
+        .releaseEscrow()
+        .accounts({
+          listing: saleListing,
+          escrow: saleEscrow,
+          seller: saleSeller.publicKey,
+        })
+        .rpc();
+      throw new Error("Escrow should not be released while the listing is active");
+    } catch (error) {
+      expect(error.message).to.include("ListingStillActive");
+    }
+
+    // Fees are paid by the provider wallet, so every delta below is exact
+    const escrowRent = await provider.connection.getBalance(saleEscrow);
+    const buyerBalanceBefore = await provider.connection.getBalance(saleBuyer.publicKey);
+
+    await program.methods
+      .purchaseNftWithRoyaltiesSafe(royaltyPercentage)
+      .accounts({
+        listing: saleListing,
+        escrow: saleEscrow,
+        buyer: saleBuyer.publicKey,
+        registry: registryPda,
+        royaltyProgram: royaltyProgram.programId,
+        seller: saleSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([saleBuyer])
+      .rpc();
+
+    const listingAccount = await program.account.nftListing.fetch(saleListing);
+    expect(listingAccount.isActive).to.equal(false);
+
+    // The buyer pays the price plus the royalty, and all of it sits in escrow
+    const buyerBalanceAfter = await provider.connection.getBalance(saleBuyer.publicKey);
+    expect(buyerBalanceBefore - buyerBalanceAfter).to.equal(price + royaltyAmount);
+    expect(await provider.connection.getBalance(saleEscrow)).to.equal(escrowRent + price + royaltyAmount);
+    const paidEscrow = await program.account.marketplaceEscrow.fetch(saleEscrow);
+    expect(paidEscrow.balance.toNumber()).to.equal(price + royaltyAmount);
+
+    const sellerBalanceBefore = await provider.connection.getBalance(saleSeller.publicKey);
+
+    await program.methods
+      .releaseEscrow()
+      .accounts({
+        listing: saleListing,
+        escrow: saleEscrow,
+        seller: saleSeller.publicKey,
+      })
+      .rpc();
+
+    const sellerBalanceAfter = await provider.connection.getBalance(saleSeller.publicKey);
+    const escrowAccount = await program.account.marketplaceEscrow.fetch(saleEscrow);
+
+    console.log(`💰 Seller received ${(sellerBalanceAfter - sellerBalanceBefore) / LAMPORTS_PER_SOL} SOL`);
+    expect(sellerBalanceAfter - sellerBalanceBefore).to.equal(price + royaltyAmount);
+    expect(escrowAccount.balance.toNumber()).to.equal(0);
+    // Nothing is stranded: only the escrow's rent is left behind
+    expect(await provider.connection.getBalance(saleEscrow)).to.equal(escrowRent);
+  });
+
+  it("🛡️ Royalty programs removed from the registry are rejected", async () => {
+    const removedSeller = Keypair.generate();
+    const removedMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(removedSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [removedListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), removedSeller.publicKey.toBuffer(), removedMint.toBuffer()],
+      program.programId
+    );
+    const [removedEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), removedSeller.publicKey.toBuffer(), removedMint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createListing(removedMint, new anchor.BN(LAMPORTS_PER_SOL / 10), new anchor.BN(0))
+      .accounts({
+        listing: removedListing,
+        escrow: removedEscrow,
+        seller: removedSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([removedSeller])
+      .rpc();
+
+    await program.methods
+      .removeRoyaltyProgram(royaltyProgram.programId)
+      .accounts({ registry: registryPda })
+      .rpc();
+
+    let registry = await program.account.royaltyRegistry.fetch(registryPda);
+    expect(registry.approvedPrograms.map((p) => p.toString())).to.not.include(
+      royaltyProgram.programId.toString()
+    );
+
+    try {
+      await program.methods
+        .purchaseNftWithRoyaltiesSafe(10)
+        .accounts({
+          listing: removedListing,
+          escrow: removedEscrow,
+          buyer: buyer.publicKey,
+          registry: registryPda,
+          royaltyProgram: royaltyProgram.programId,
+          seller: removedSeller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Removed royalty program was accepted!");
+    } catch (error) {
+      expect(error.message).to.include("UntrustedRoyaltyProgram");
+    }
+
+    // Re-approve so the program is usable again
+    await program.methods
+      .addRoyaltyProgram(royaltyProgram.programId)
+      .accounts({ registry: registryPda })
+      .rpc();
+
+    registry = await program.account.royaltyRegistry.fetch(registryPda);
+    expect(registry.approvedPrograms.map((p) => p.toString())).to.include(
+      royaltyProgram.programId.toString()
+    );
+  });
+
+  it("💸 Royalty program splits royalties 30/70 between two creators", async () => {
+    const creatorA = Keypair.generate().publicKey;
+    const creatorB = Keypair.generate().publicKey;
+    const royaltyAmount = LAMPORTS_PER_SOL / 10;
+
+    const distributeAccounts = {
+      buyer: buyer.publicKey,
+      escrow: escrowPda,
+      seller: seller.publicKey,
+      listing: listingPda,
+      systemProgram: SystemProgram.programId,
+    };
+    const creatorAccounts = [creatorA, creatorB].map((pubkey) => ({
+      pubkey,
+      isWritable: true,
+      isSigner: false,
+    }));
+
+    // Weights must line up with the creator accounts passed in
+    try {
+      await royaltyProgram.methods
+        .distributeRoyalties(new anchor.BN(royaltyAmount), [
+          { creator: creatorA, shareBps: 3000 },
+          { creator: creatorB, shareBps: 7000 },
+        ], program.programId, nftMint)
+        .accounts(distributeAccounts)
+        .remainingAccounts(creatorAccounts.slice(0, 1))
+        .signers([buyer])
+        .rpc();
+      throw new Error("Mismatched creator accounts were accepted!");
+    } catch (error) {
+      expect(error.message).to.include("CreatorCountMismatch");
+    }
+
+    await royaltyProgram.methods
+      .distributeRoyalties(new anchor.BN(royaltyAmount), [
+        { creator: creatorA, shareBps: 3000 },
+        { creator: creatorB, shareBps: 7000 },
+      ], program.programId, nftMint)
+      .accounts(distributeAccounts)
+      .remainingAccounts(creatorAccounts)
+      .signers([buyer])
+      .rpc();
+
+    expect(await provider.connection.getBalance(creatorA)).to.equal((royaltyAmount * 3000) / 10000);
+    expect(await provider.connection.getBalance(creatorB)).to.equal((royaltyAmount * 7000) / 10000);
+  });
+
+  it("⏰ Expired listings cannot be purchased", async () => {
+    const expiringSeller = Keypair.generate();
+    const expiringMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(expiringSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [expiringListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), expiringSeller.publicKey.toBuffer(), expiringMint.toBuffer()],
+      program.programId
+    );
+    const [expiringEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), expiringSeller.publicKey.toBuffer(), expiringMint.toBuffer()],
+      program.programId
+    );
+
+    const clusterTime = async () =>
+      provider.connection.getBlockTime(await provider.connection.getSlot());
+    const expiresAt = (await clusterTime()) + 2;
+
+    await program.methods
+      .createListing(expiringMint, new anchor.BN(LAMPORTS_PER_SOL / 10), new anchor.BN(expiresAt))
+      .accounts({
+        listing: expiringListing,
+        escrow: expiringEscrow,
+        seller: expiringSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([expiringSeller])
+      .rpc();
+
+    const listing = await program.account.nftListing.fetch(expiringListing);
+    expect(listing.expiresAt.toNumber()).to.equal(expiresAt);
+
+    // Fast-forward: wait until the cluster clock is past the expiry
+    while ((await clusterTime()) <= expiresAt) {
+      await new Promise(resolve => setTimeout(resolve, 500));
+    }
+
+    try {
+      await program.methods
+        .purchaseNftWithRoyaltiesSafe(10)
+        .accounts({
+          listing: expiringListing,
+          escrow: expiringEscrow,
+          buyer: buyer.publicKey,
+          registry: registryPda,
+          royaltyProgram: royaltyProgram.programId,
+          seller: expiringSeller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Expired listing was purchased!");
+    } catch (error) {
+      expect(error.message).to.include("ListingExpired");
+    }
+  });
+
+  it("🧮 Royalty math on a huge listing price reports MathOverflow instead of wrapping", async () => {
+    const whaleSeller = Keypair.generate();
+    const whaleMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(whaleSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [whaleListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), whaleSeller.publicKey.toBuffer(), whaleMint.toBuffer()],
+      program.programId
+    );
+    const [whaleEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), whaleSeller.publicKey.toBuffer(), whaleMint.toBuffer()],
+      program.programId
+    );
+
+    // u64::MAX / 100 + 1: price * 100 no longer fits in a u64
+    const hugePrice = new anchor.BN("184467440737095517");
+
+    await program.methods
+      .createListing(whaleMint, hugePrice, new anchor.BN(0))
+      .accounts({
+        listing: whaleListing,
+        escrow: whaleEscrow,
+        seller: whaleSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([whaleSeller])
+      .rpc();
+
+    try {
+      await program.methods
+        .purchaseNftWithRoyaltiesSafe(100)
+        .accounts({
+          listing: whaleListing,
+          escrow: whaleEscrow,
+          buyer: buyer.publicKey,
+          registry: registryPda,
+          royaltyProgram: royaltyProgram.programId,
+          seller: whaleSeller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Overflowing royalty math was accepted!");
+    } catch (error) {
+      expect(error.message).to.include("MathOverflow");
+    }
+  });
+
+  it("🛡️ Purchases with a spoofed seller account are rejected", async () => {
+    const realSeller = Keypair.generate();
+    const spoofedSeller = Keypair.generate();
+    const mint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(realSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [listing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), realSeller.publicKey.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const [escrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), realSeller.publicKey.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .createListing(mint, new anchor.BN(LAMPORTS_PER_SOL / 10), new anchor.BN(0))
+      .accounts({
+        listing,
+        escrow,
+        seller: realSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([realSeller])
+      .rpc();
+
+    try {
+      await program.methods
+        .purchaseNftWithRoyaltiesSafe(10)
+        .accounts({
+          listing,
+          escrow,
+          buyer: buyer.publicKey,
+          registry: registryPda,
+          royaltyProgram: royaltyProgram.programId,
+          seller: spoofedSeller.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Purchase accepted a spoofed seller!");
+    } catch (error) {
+      expect(error.message).to.include("InvalidSeller");
+    }
+
+    const listingAccount = await program.account.nftListing.fetch(listing);
+    expect(listingAccount.isActive).to.be.true;
+  });
+
+  it("🛡️ Royalty program rejects an escrow that isn't the marketplace escrow PDA", async () => {
+    const royaltyAmount = LAMPORTS_PER_SOL / 10;
+    const attackerEscrow = Keypair.generate().publicKey;
+
+    try {
+      await royaltyProgram.methods
+        .distributeRoyalties(new anchor.BN(royaltyAmount), [], program.programId, nftMint)
+        .accounts({
+          buyer: buyer.publicKey,
+          escrow: attackerEscrow,
+          seller: seller.publicKey,
+          listing: listingPda,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([buyer])
+        .rpc();
+      throw new Error("🚨 Royalties were redirected to a foreign escrow!");
+    } catch (error) {
+      expect(error.message).to.include("InvalidEscrow");
+    }
+
+    expect(await provider.connection.getBalance(attackerEscrow)).to.equal(0);
+  });
+
+  it("↩️ Buyers are refunded their escrow payment once a listing expires unsold", async () => {
+    const expiringSeller = Keypair.generate();
+    const refundBuyer = Keypair.generate();
+    const expiringMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(expiringSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await provider.connection.requestAirdrop(refundBuyer.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [expiringListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), expiringSeller.publicKey.toBuffer(), expiringMint.toBuffer()],
+      program.programId
+    );
+    const [expiringEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), expiringSeller.publicKey.toBuffer(), expiringMint.toBuffer()],
+      program.programId
+    );
+
+    const clusterTime = async () =>
+      provider.connection.getBlockTime(await provider.connection.getSlot());
+    const expiresAt = (await clusterTime()) + 3;
+
+    await program.methods
+      .createListing(expiringMint, new anchor.BN(LAMPORTS_PER_SOL / 10), new anchor.BN(expiresAt))
+      .accounts({
+        listing: expiringListing,
+        escrow: expiringEscrow,
+        seller: expiringSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([expiringSeller])
+      .rpc();
+
+    // The buyer pays half the price up front
+    const deposit = LAMPORTS_PER_SOL / 20;
+    await program.methods
+      .payIntoEscrow(new anchor.BN(deposit))
+      .accounts({
+        listing: expiringListing,
+        escrow: expiringEscrow,
+        buyer: refundBuyer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([refundBuyer])
+      .rpc();
+
+    const escrowAccount = await program.account.marketplaceEscrow.fetch(expiringEscrow);
+    expect(escrowAccount.balance.toNumber()).to.equal(deposit);
+    expect(escrowAccount.buyer.toString()).to.equal(refundBuyer.publicKey.toString());
+
+    const refundAccounts = {
+      listing: expiringListing,
+      escrow: expiringEscrow,
+      buyer: refundBuyer.publicKey,
+      seller: expiringSeller.publicKey,
+    };
+
+    // No refunds while the listing can still sell
+    try {
+      await program.methods.refundExpired().accounts(refundAccounts).rpc();
+      throw new Error("🚨 Refunded a listing that hasn't expired!");
+    } catch (error) {
+      expect(error.message).to.include("ListingNotExpired");
+    }
+
+    // Fast-forward: wait until the cluster clock is past the expiry
+    while ((await clusterTime()) <= expiresAt) {
+      await new Promise(resolve => setTimeout(resolve, 500));
+    }
+
+    const buyerBefore = await provider.connection.getBalance(refundBuyer.publicKey);
+    await program.methods.refundExpired().accounts(refundAccounts).rpc();
+
+    expect(await provider.connection.getBalance(refundBuyer.publicKey)).to.equal(
+      buyerBefore + deposit
+    );
+    expect(await program.account.nftListing.fetchNullable(expiringListing)).to.be.null;
+    expect(await program.account.marketplaceEscrow.fetchNullable(expiringEscrow)).to.be.null;
+  });
+
+  it("↩️ Expired listings nobody paid into close without a buyer account", async () => {
+    const idleSeller = Keypair.generate();
+    const idleMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(idleSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [idleListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), idleSeller.publicKey.toBuffer(), idleMint.toBuffer()],
+      program.programId
+    );
+    const [idleEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), idleSeller.publicKey.toBuffer(), idleMint.toBuffer()],
+      program.programId
+    );
+
+    const clusterTime = async () =>
+      provider.connection.getBlockTime(await provider.connection.getSlot());
+    const expiresAt = (await clusterTime()) + 3;
+
+    await program.methods
+      .createListing(idleMint, new anchor.BN(LAMPORTS_PER_SOL / 10), new anchor.BN(expiresAt))
+      .accounts({
+        listing: idleListing,
+        escrow: idleEscrow,
+        seller: idleSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([idleSeller])
+      .rpc();
+
+    while ((await clusterTime()) <= expiresAt) {
+      await new Promise(resolve => setTimeout(resolve, 500));
+    }
+
+    // The escrow never recorded a buyer, so there is no one to pass or refund
+    const rent =
+      (await provider.connection.getBalance(idleListing)) +
+      (await provider.connection.getBalance(idleEscrow));
+    const sellerBefore = await provider.connection.getBalance(idleSeller.publicKey);
+    await program.methods
+      .refundExpired()
+      .accounts({
+        listing: idleListing,
+        escrow: idleEscrow,
+        buyer: null,
+        seller: idleSeller.publicKey,
+      })
+      .rpc();
+
+    expect(await provider.connection.getBalance(idleSeller.publicKey)).to.equal(sellerBefore + rent);
+    expect(await program.account.nftListing.fetchNullable(idleListing)).to.be.null;
+    expect(await program.account.marketplaceEscrow.fetchNullable(idleEscrow)).to.be.null;
+  });
+
+  it("🛡️ Escrow records the paying buyer and rejects payments from anyone else", async () => {
+    const escrowSeller = Keypair.generate();
+    const firstBuyer = Keypair.generate();
+    const secondBuyer = Keypair.generate();
+    const escrowMint = Keypair.generate().publicKey;
+    await provider.connection.requestAirdrop(escrowSeller.publicKey, 2 * LAMPORTS_PER_SOL);
+    await provider.connection.requestAirdrop(firstBuyer.publicKey, 2 * LAMPORTS_PER_SOL);
+    await provider.connection.requestAirdrop(secondBuyer.publicKey, 2 * LAMPORTS_PER_SOL);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+
+    const [escrowListing] = PublicKey.findProgramAddressSync(
+      [Buffer.from("listing"), escrowSeller.publicKey.toBuffer(), escrowMint.toBuffer()],
+      program.programId
+    );
+    const [buyerEscrow] = PublicKey.findProgramAddressSync(
+      [Buffer.from("escrow"), escrowSeller.publicKey.toBuffer(), escrowMint.toBuffer()],
+      program.programId
+    );
+
+    const price = LAMPORTS_PER_SOL / 10;
+    await program.methods
+      .createListing(escrowMint, new anchor.BN(price), new anchor.BN(0))
+      .accounts({
+        listing: escrowListing,
+        escrow: buyerEscrow,
+        seller: escrowSeller.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([escrowSeller])
+      .rpc();
+
+    // The first buyer funds part of the escrow and is recorded on it
+    await program.methods
+      .payIntoEscrow(new anchor.BN(price / 2))
+      .accounts({
+        listing: escrowListing,
+        escrow: buyerEscrow,
+        buyer: firstBuyer.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([firstBuyer])
+      .rpc();
+    let escrowAccount = await program.account.marketplaceEscrow.fetch(buyerEscrow);
+    expect(escrowAccount.buyer.toString()).to.equal(firstBuyer.publicKey.toString());
+
+    // A second buyer can neither add to that escrow nor complete the purchase
+    try {
+      await program.methods
+        .payIntoEscrow(new anchor.BN(price / 2))
+        .accounts({
+          listing: escrowListing,
+          escrow: buyerEscrow,
+          buyer: secondBuyer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([secondBuyer])
+        .rpc();
+      throw new Error("🚨 A second buyer paid into an escrow funded by someone else!");
+    } catch (error) {
+      expect(error.message).to.include("InvalidBuyer");
+    }
+
+    const purchaseAccounts = (purchaser: Keypair) => ({
+      listing: escrowListing,
+      escrow: buyerEscrow,
+      buyer: purchaser.publicKey,
+      registry: registryPda,
+      royaltyProgram: royaltyProgram.programId,
+      seller: escrowSeller.publicKey,
+      systemProgram: SystemProgram.programId,
+    });
+    try {
+      await program.methods
+        .purchaseNftWithRoyaltiesSafe(10)
+        .accounts(purchaseAccounts(secondBuyer))
+        .signers([secondBuyer])
+        .rpc();
+      throw new Error("🚨 A second buyer completed a purchase funded by someone else!");
+    } catch (error) {
+      expect(error.message).to.include("InvalidBuyer");
+    }
+
+    // The recorded buyer completes the purchase and stays on the escrow
+    await program.methods
+      .purchaseNftWithRoyaltiesSafe(10)
+      .accounts(purchaseAccounts(firstBuyer))
+      .signers([firstBuyer])
+      .rpc();
+    escrowAccount = await program.account.marketplaceEscrow.fetch(buyerEscrow);
+    expect(escrowAccount.buyer.toString()).to.equal(firstBuyer.publicKey.toString());
+    // The price plus the 10% royalty booked on top of it
+    expect(escrowAccount.balance.toNumber()).to.equal(price + price / 10);
+  });
 });
 
 // cd lecture_3/exercise_9_fixed && solana-test-validator --reset